@@ -0,0 +1,133 @@
+use mbg_hook_shared::{
+    protocol::{Error, Message},
+    Breakpoint, Channel, HostLostPolicy,
+};
+
+mod command;
+
+static mut CHANNEL: *mut Channel = std::ptr::null_mut::<Channel>();
+
+static mut PREVIOUS_ACTION: Option<libc::sigaction> = None;
+
+// run initialize when the shared object is loaded (e.g. using LD_PRELOAD)
+#[used]
+#[link_section = ".init_array"]
+static INITIALIZE: extern "C" fn() = initialize;
+
+extern "C" fn initialize() {
+    unsafe {
+        // open the channel, stay inactive if the host speaks a different
+        // protocol version
+        let Ok(channel) = Channel::open() else {
+            return;
+        };
+        CHANNEL = channel;
+
+        // handle commands of the host on a dedicated thread
+        std::thread::spawn(|| {
+            if let Err(Error::PeerLost) = (*CHANNEL).serve(command::handle) {
+                host_lost(&mut *CHANNEL);
+            }
+        });
+
+        // add signal handler for handling breakpoints
+        let mut action = std::mem::zeroed::<libc::sigaction>();
+        action.sa_sigaction = signal_handler as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut previous_action = std::mem::zeroed::<libc::sigaction>();
+        libc::sigaction(libc::SIGTRAP, &action, &mut previous_action);
+        PREVIOUS_ACTION = Some(previous_action);
+    }
+}
+
+unsafe extern "C" fn signal_handler(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    let ucontext = &mut *(context as *mut libc::ucontext_t);
+    // rip points behind the int3
+    let address = ucontext.uc_mcontext.gregs[libc::REG_RIP as usize] as u64 - 1;
+
+    // find the breakpoint
+    let channel = &mut *CHANNEL;
+    if channel
+        .breakpoints
+        .iter()
+        .any(|breakpoint| breakpoint.address == address)
+    {
+        // notify the host process and wait until it lets the thread continue,
+        // requests are encoded in place and only wait on futexes, which is
+        // async-signal-safe (a breakpoint might be hit inside of malloc)
+        let thread_id = libc::syscall(libc::SYS_gettid) as u32;
+        match channel.request(thread_id, Message::BreakpointHit { address, thread_id }) {
+            Ok(Message::Resume) => {
+                // continue at the breakpoint like it is done on Windows
+                ucontext.uc_mcontext.gregs[libc::REG_RIP as usize] = address as i64;
+                return;
+            }
+            Err(Error::PeerLost) => {
+                host_lost(channel);
+                ucontext.uc_mcontext.gregs[libc::REG_RIP as usize] = address as i64;
+                return;
+            }
+            _ => {}
+        }
+    }
+
+    // pass the signal on
+    let Some(previous_action) = PREVIOUS_ACTION else {
+        return;
+    };
+    match previous_action.sa_sigaction {
+        libc::SIG_IGN => {}
+        libc::SIG_DFL => {
+            // the signal is blocked while handling it, therefore it is raised
+            // again as soon as the handler returns
+            libc::sigaction(signal, &previous_action, std::ptr::null_mut());
+            libc::raise(signal);
+        }
+        handler if previous_action.sa_flags & libc::SA_SIGINFO != 0 => {
+            std::mem::transmute::<
+                usize,
+                extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void),
+            >(handler)(signal, info, context)
+        }
+        handler => std::mem::transmute::<usize, extern "C" fn(libc::c_int)>(handler)(signal),
+    }
+}
+
+/// applies the policy set by the host, only async-signal-safe functions are
+/// used as this is also called by the signal handler
+unsafe fn host_lost(channel: &mut Channel) {
+    match channel.host_lost_policy() {
+        HostLostPolicy::Resume => {
+            // restore the original code and forget about the breakpoints,
+            // /proc/self/mem ignores the protection
+            let memory = libc::open(
+                c"/proc/self/mem".as_ptr(),
+                libc::O_WRONLY | libc::O_CLOEXEC,
+            );
+            for breakpoint in channel
+                .breakpoints
+                .iter_mut()
+                .filter(|breakpoint| breakpoint.address != 0)
+            {
+                if memory != -1 {
+                    libc::pwrite(
+                        memory,
+                        &breakpoint.original as *const u8 as *const libc::c_void,
+                        1,
+                        breakpoint.address as libc::off_t,
+                    );
+                }
+                *breakpoint = Breakpoint::default();
+            }
+            if memory != -1 {
+                libc::close(memory);
+            }
+        }
+        HostLostPolicy::Terminate => libc::_exit(1),
+    }
+}
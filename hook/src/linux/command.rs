@@ -0,0 +1,180 @@
+use std::{collections::BTreeMap, os::unix::fs::FileExt, sync::Mutex};
+
+use mbg_hook_shared::protocol::{Message, ModuleEntry, RegionEntry};
+
+/// sizes of the regions allocated by the host, needed for munmap
+static ALLOCATIONS: Mutex<BTreeMap<u64, u64>> = Mutex::new(BTreeMap::new());
+
+/// handles a single command of the host
+pub fn handle(message: Message) -> Message {
+    unsafe {
+        match message {
+            Message::ReadMemory { address, size } => read_memory(address, size),
+            Message::WriteMemory { address, data } => write_memory(address, &data),
+            Message::Allocate { size, protection } => allocate(size, protection),
+            Message::Free { address } => free(address),
+            Message::ListThreads => list_threads(),
+            Message::ListModules => list_modules(),
+            Message::ListRegions => list_regions(),
+            Message::Call { address, arguments } => call(address, &arguments),
+            _ => Message::Failure(libc::EINVAL as u32),
+        }
+    }
+}
+
+fn last_error() -> Message {
+    Message::Failure(std::io::Error::last_os_error().raw_os_error().unwrap_or(0) as u32)
+}
+
+unsafe fn read_memory(address: u64, size: u32) -> Message {
+    // process_vm_readv is used instead of dereferencing to not crash on
+    // inaccessible memory
+    let mut data = vec![0u8; size as usize];
+    let local = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut _,
+        iov_len: data.len(),
+    };
+    let remote = libc::iovec {
+        iov_base: address as *mut _,
+        iov_len: data.len(),
+    };
+    let data_length = libc::process_vm_readv(libc::getpid(), &local, 1, &remote, 1, 0);
+    if data_length == -1 {
+        return last_error();
+    }
+    data.truncate(data_length as usize);
+    Message::Data(data)
+}
+
+fn write_memory(address: u64, data: &[u8]) -> Message {
+    // /proc/self/mem ignores the protection, which allows patching code
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .open("/proc/self/mem")
+        .and_then(|file| file.write_all_at(data, address))
+    {
+        Ok(()) => Message::Done,
+        Err(error) => Message::Failure(error.raw_os_error().unwrap_or(0) as u32),
+    }
+}
+
+unsafe fn allocate(size: u64, protection: u32) -> Message {
+    let address = libc::mmap(
+        std::ptr::null_mut(),
+        size as usize,
+        protection as libc::c_int,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+        -1,
+        0,
+    );
+    if address == libc::MAP_FAILED {
+        return last_error();
+    }
+    ALLOCATIONS.lock().unwrap().insert(address as u64, size);
+    Message::Value(address as u64)
+}
+
+unsafe fn free(address: u64) -> Message {
+    let Some(size) = ALLOCATIONS.lock().unwrap().remove(&address) else {
+        return Message::Failure(libc::EINVAL as u32);
+    };
+    if libc::munmap(address as *mut _, size as usize) == -1 {
+        return last_error();
+    }
+    Message::Done
+}
+
+fn list_threads() -> Message {
+    let Ok(tasks) = std::fs::read_dir("/proc/self/task") else {
+        return last_error();
+    };
+    Message::Threads(
+        tasks
+            .filter_map(|task| task.ok()?.file_name().to_str()?.parse().ok())
+            .collect(),
+    )
+}
+
+unsafe fn list_modules() -> Message {
+    unsafe extern "C" fn callback(
+        info: *mut libc::dl_phdr_info,
+        _size: libc::size_t,
+        modules: *mut libc::c_void,
+    ) -> libc::c_int {
+        let info = &*info;
+        let modules = &mut *(modules as *mut Vec<ModuleEntry>);
+
+        // the module spans from the lowest to the highest loadable segment
+        let headers = std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize);
+        let segments = headers
+            .iter()
+            .filter(|header| header.p_type == libc::PT_LOAD)
+            .map(|header| (header.p_vaddr, header.p_vaddr + header.p_memsz));
+        let (Some(start), Some(end)) = (
+            segments.clone().map(|segment| segment.0).min(),
+            segments.map(|segment| segment.1).max(),
+        ) else {
+            return 0;
+        };
+
+        // the name of the executable is empty
+        let path = if info.dlpi_name.is_null() || *info.dlpi_name == 0 {
+            std::env::current_exe().unwrap_or_default()
+        } else {
+            std::ffi::CStr::from_ptr(info.dlpi_name)
+                .to_string_lossy()
+                .into_owned()
+                .into()
+        };
+        modules.push(ModuleEntry {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            base: info.dlpi_addr + start,
+            size: end - start,
+        });
+        0
+    }
+
+    let mut modules = Vec::<ModuleEntry>::new();
+    libc::dl_iterate_phdr(Some(callback), &mut modules as *mut _ as *mut _);
+    Message::Modules(modules)
+}
+
+fn list_regions() -> Message {
+    match std::fs::read_to_string("/proc/self/maps") {
+        Ok(maps) => Message::Regions(RegionEntry::parse_maps(&maps)),
+        Err(error) => Message::Failure(error.raw_os_error().unwrap_or(0) as u32),
+    }
+}
+
+unsafe fn call(address: u64, arguments: &[u64]) -> Message {
+    type A = u64;
+    let address = address as usize;
+    let value = match *arguments {
+        [] => std::mem::transmute::<usize, extern "C" fn() -> A>(address)(),
+        [a] => std::mem::transmute::<usize, extern "C" fn(A) -> A>(address)(a),
+        [a, b] => std::mem::transmute::<usize, extern "C" fn(A, A) -> A>(address)(a, b),
+        [a, b, c] => std::mem::transmute::<usize, extern "C" fn(A, A, A) -> A>(address)(a, b, c),
+        [a, b, c, d] => {
+            std::mem::transmute::<usize, extern "C" fn(A, A, A, A) -> A>(address)(a, b, c, d)
+        }
+        [a, b, c, d, e] => {
+            std::mem::transmute::<usize, extern "C" fn(A, A, A, A, A) -> A>(address)(a, b, c, d, e)
+        }
+        [a, b, c, d, e, f] => std::mem::transmute::<usize, extern "C" fn(A, A, A, A, A, A) -> A>(
+            address,
+        )(a, b, c, d, e, f),
+        [a, b, c, d, e, f, g] => std::mem::transmute::<
+            usize,
+            extern "C" fn(A, A, A, A, A, A, A) -> A,
+        >(address)(a, b, c, d, e, f, g),
+        [a, b, c, d, e, f, g, h] => std::mem::transmute::<
+            usize,
+            extern "C" fn(A, A, A, A, A, A, A, A) -> A,
+        >(address)(a, b, c, d, e, f, g, h),
+        _ => return Message::Failure(libc::EINVAL as u32),
+    };
+    Message::Value(value)
+}
@@ -0,0 +1,104 @@
+use windows::Win32::{
+    Foundation::HMODULE,
+    System::{
+        Diagnostics::Debug::{AddVectoredExceptionHandler, EXCEPTION_POINTERS},
+        LibraryLoader::DisableThreadLibraryCalls,
+        SystemServices::DLL_PROCESS_ATTACH,
+        Threading::{GetCurrentProcess, GetCurrentThreadId, TerminateProcess},
+    },
+};
+
+use mbg_hook_shared::{
+    protocol::{Error, Message},
+    Breakpoint, Channel, HostLostPolicy,
+};
+
+mod command;
+
+static mut CHANNEL: *mut Channel = std::ptr::null_mut::<Channel>();
+
+#[no_mangle]
+unsafe extern "system" fn DllMain(
+    module: HMODULE,
+    reason: u32,
+    _reserved: *const std::ffi::c_void,
+) -> bool {
+    if reason == DLL_PROCESS_ATTACH {
+        // disable DLL_THREAD_ATTACH and DLL_THREAD_DETACH calls, this is only
+        // an optimization and panicking inside of DllMain is not an option
+        let _ = DisableThreadLibraryCalls(module);
+
+        // open the channel, refuse to load if the host speaks a different
+        // protocol version
+        let Ok(channel) = Channel::open() else {
+            return false;
+        };
+        CHANNEL = channel;
+
+        // handle commands of the host on a dedicated thread
+        std::thread::spawn(|| {
+            if let Err(Error::PeerLost) = (*CHANNEL).serve(command::handle) {
+                host_lost(&mut *CHANNEL);
+            }
+        });
+
+        // add vectored exception handler for handling breakpoints
+        AddVectoredExceptionHandler(1, Some(vectored_exception_handler));
+    }
+
+    true
+}
+
+const EXCEPTION_CONTINUE_EXECUTION: i32 = -1;
+const EXCEPTION_CONTINUE_SEARCH: i32 = 0;
+const _EXCEPTION_EXECUTE_HANDLER: i32 = 1;
+
+unsafe extern "system" fn vectored_exception_handler(
+    exception_pointers: *mut EXCEPTION_POINTERS,
+) -> i32 {
+    let exception_pointers = *exception_pointers;
+    let exception = *exception_pointers.ExceptionRecord;
+    /* let context = *exception_pointers.ContextRecord; */
+
+    // find the breakpoint
+    let channel = &mut *CHANNEL;
+    let address = exception.ExceptionAddress as u64;
+    if !channel
+        .breakpoints
+        .iter()
+        .any(|breakpoint| breakpoint.address == address)
+    {
+        return EXCEPTION_CONTINUE_SEARCH;
+    }
+
+    // notify the host process and wait until it lets the thread continue
+    let thread_id = GetCurrentThreadId();
+    match channel.request(thread_id, Message::BreakpointHit { address, thread_id }) {
+        Ok(Message::Resume) => EXCEPTION_CONTINUE_EXECUTION,
+        Err(Error::PeerLost) => {
+            host_lost(channel);
+            EXCEPTION_CONTINUE_EXECUTION
+        }
+        _ => EXCEPTION_CONTINUE_SEARCH,
+    }
+}
+
+/// applies the policy set by the host
+unsafe fn host_lost(channel: &mut Channel) {
+    match channel.host_lost_policy() {
+        HostLostPolicy::Resume => {
+            // restore the original code and forget about the breakpoints
+            for breakpoint in channel
+                .breakpoints
+                .iter_mut()
+                .filter(|breakpoint| breakpoint.address != 0)
+            {
+                command::write_memory(breakpoint.address, &[breakpoint.original]);
+                *breakpoint = Breakpoint::default();
+            }
+        }
+        HostLostPolicy::Terminate => {
+            TerminateProcess(GetCurrentProcess(), 1);
+        }
+    }
+}
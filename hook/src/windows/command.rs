@@ -0,0 +1,307 @@
+use windows::Win32::{
+    Foundation::{CloseHandle, GetLastError, ERROR_INVALID_PARAMETER, HMODULE},
+    System::{
+        Diagnostics::{
+            Debug::{ReadProcessMemory, WriteProcessMemory},
+            ToolHelp::{
+                CreateToolhelp32Snapshot, Module32FirstW, Module32NextW, Thread32First,
+                Thread32Next, MODULEENTRY32W, TH32CS_SNAPMODULE, TH32CS_SNAPTHREAD, THREADENTRY32,
+            },
+        },
+        LibraryLoader::GetModuleFileNameW,
+        Memory::{
+            VirtualAlloc, VirtualFree, VirtualQuery, MEMORY_BASIC_INFORMATION, MEM_COMMIT,
+            MEM_IMAGE, MEM_MAPPED, MEM_RELEASE, MEM_RESERVE, PAGE_PROTECTION_FLAGS,
+        },
+        ProcessStatus::GetMappedFileNameW,
+        Threading::{GetCurrentProcess, GetCurrentProcessId},
+    },
+};
+
+use mbg_hook_shared::protocol::{Message, ModuleEntry, RegionEntry, RegionKind};
+
+/// handles a single command of the host
+pub fn handle(message: Message) -> Message {
+    unsafe {
+        match message {
+            Message::ReadMemory { address, size } => read_memory(address, size),
+            Message::WriteMemory { address, data } => write_memory(address, &data),
+            Message::Allocate { size, protection } => allocate(size, protection),
+            Message::Free { address } => free(address),
+            Message::ListThreads => list_threads(),
+            Message::ListModules => list_modules(),
+            Message::ListRegions => list_regions(),
+            Message::Call { address, arguments } => call(address, &arguments),
+            _ => Message::Failure(ERROR_INVALID_PARAMETER.0),
+        }
+    }
+}
+
+unsafe fn last_error() -> Message {
+    Message::Failure(GetLastError().0)
+}
+
+unsafe fn read_memory(address: u64, size: u32) -> Message {
+    // ReadProcessMemory is used instead of dereferencing to not crash on
+    // inaccessible memory
+    let mut data = vec![0; size as usize];
+    let mut data_length = 0;
+    if !ReadProcessMemory(
+        GetCurrentProcess(),
+        address as *const std::ffi::c_void,
+        data.as_mut_ptr() as *mut _,
+        data.len(),
+        Some(&mut data_length),
+    )
+    .as_bool()
+    {
+        return last_error();
+    }
+    data.truncate(data_length);
+    Message::Data(data)
+}
+
+/// reads all of data at the address like [read_memory]
+unsafe fn read_exact(address: u64, data: &mut [u8]) -> bool {
+    let mut data_length = 0;
+    ReadProcessMemory(
+        GetCurrentProcess(),
+        address as *const std::ffi::c_void,
+        data.as_mut_ptr() as *mut _,
+        data.len(),
+        Some(&mut data_length),
+    )
+    .as_bool()
+        && data_length == data.len()
+}
+
+pub unsafe fn write_memory(address: u64, data: &[u8]) -> Message {
+    if !WriteProcessMemory(
+        GetCurrentProcess(),
+        address as *const std::ffi::c_void,
+        data.as_ptr() as *const _,
+        data.len(),
+        None,
+    )
+    .as_bool()
+    {
+        return last_error();
+    }
+    Message::Done
+}
+
+unsafe fn allocate(size: u64, protection: u32) -> Message {
+    let address = VirtualAlloc(
+        None,
+        size as usize,
+        MEM_COMMIT | MEM_RESERVE,
+        PAGE_PROTECTION_FLAGS(protection),
+    );
+    if address.is_null() {
+        return last_error();
+    }
+    Message::Value(address as u64)
+}
+
+unsafe fn free(address: u64) -> Message {
+    if !VirtualFree(address as *mut std::ffi::c_void, 0, MEM_RELEASE).as_bool() {
+        return last_error();
+    }
+    Message::Done
+}
+
+unsafe fn list_threads() -> Message {
+    let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) else {
+        return last_error();
+    };
+    let process_id = GetCurrentProcessId();
+    let mut threads = vec![];
+    let mut entry = THREADENTRY32 {
+        dwSize: std::mem::size_of::<THREADENTRY32>() as u32,
+        ..Default::default()
+    };
+    let mut next = Thread32First(snapshot, &mut entry).as_bool();
+    while next {
+        if entry.th32OwnerProcessID == process_id {
+            threads.push(entry.th32ThreadID);
+        }
+        next = Thread32Next(snapshot, &mut entry).as_bool();
+    }
+    CloseHandle(snapshot);
+    Message::Threads(threads)
+}
+
+unsafe fn list_modules() -> Message {
+    let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPMODULE, 0) else {
+        return last_error();
+    };
+    let mut modules = vec![];
+    let mut entry = MODULEENTRY32W {
+        dwSize: std::mem::size_of::<MODULEENTRY32W>() as u32,
+        ..Default::default()
+    };
+    let mut next = Module32FirstW(snapshot, &mut entry).as_bool();
+    while next {
+        modules.push(ModuleEntry {
+            name: String::from_utf16_lossy(entry.szModule.split(|&elem| elem == 0).next().unwrap()),
+            base: entry.modBaseAddr as u64,
+            size: entry.modBaseSize as u64,
+        });
+        next = Module32NextW(snapshot, &mut entry).as_bool();
+    }
+    CloseHandle(snapshot);
+    Message::Modules(modules)
+}
+
+unsafe fn list_regions() -> Message {
+    let mut regions = vec![];
+    let mut address = 0;
+    let mut info = MEMORY_BASIC_INFORMATION::default();
+    while VirtualQuery(
+        Some(address as *const std::ffi::c_void),
+        &mut info,
+        std::mem::size_of::<MEMORY_BASIC_INFORMATION>(),
+    ) != 0
+    {
+        let base = info.BaseAddress as u64;
+        let size = info.RegionSize as u64;
+        address = base + size;
+        if info.State != MEM_COMMIT {
+            continue;
+        }
+        let (read, write, execute) = match info.Protect.0 & 0xFF {
+            0x02 => (true, false, false),
+            0x04 | 0x08 => (true, true, false),
+            0x10 => (false, false, true),
+            0x20 => (true, false, true),
+            0x40 | 0x80 => (true, true, true),
+            _ => (false, false, false),
+        };
+        let mut region = RegionEntry {
+            base,
+            size,
+            read,
+            write,
+            execute,
+            kind: RegionKind::Private,
+            owner: String::new(),
+        };
+        if info.Type == MEM_IMAGE {
+            // split at the sections, which can share the protection
+            let module = info.AllocationBase as u64;
+            let mut name = [0; 260];
+            let length = GetModuleFileNameW(HMODULE(module as isize), &mut name) as usize;
+            let name = String::from_utf16_lossy(&name[..length]);
+            let name = name.rsplit('\\').next().unwrap().to_owned();
+            region.kind = RegionKind::Image;
+            let mut start = base;
+            for (section, range) in sections(module) {
+                let (section_start, section_end) =
+                    (range.start.max(start), range.end.min(base + size));
+                if section_start >= section_end {
+                    continue;
+                }
+                if section_start > start {
+                    regions.push(RegionEntry {
+                        base: start,
+                        size: section_start - start,
+                        owner: name.clone(),
+                        ..region.clone()
+                    });
+                }
+                regions.push(RegionEntry {
+                    base: section_start,
+                    size: section_end - section_start,
+                    owner: format!("{} {}", name, section),
+                    ..region.clone()
+                });
+                start = section_end;
+            }
+            if start < base + size {
+                regions.push(RegionEntry {
+                    base: start,
+                    size: base + size - start,
+                    owner: name,
+                    ..region
+                });
+            }
+            continue;
+        }
+        if info.Type == MEM_MAPPED {
+            let mut name = [0; 260];
+            let length = GetMappedFileNameW(GetCurrentProcess(), info.BaseAddress, &mut name);
+            region.kind = RegionKind::Mapped;
+            region.owner = String::from_utf16_lossy(&name[..length as usize]);
+        }
+        regions.push(region);
+    }
+    Message::Regions(regions)
+}
+
+/// names and address ranges of the sections of a loaded module, the headers
+/// are read like [read_memory] as they can be inaccessible, in which case
+/// there are no sections
+unsafe fn sections(module: u64) -> Vec<(String, std::ops::Range<u64>)> {
+    let mut dos_header = [0; 0x40];
+    if !read_exact(module, &mut dos_header) {
+        return vec![];
+    }
+    let nt_headers = module + u32::from_le_bytes(dos_header[0x3C..].try_into().unwrap()) as u64;
+    // signature, file header and optional header up to the section alignment
+    let mut headers = [0; 60];
+    if !read_exact(nt_headers, &mut headers) {
+        return vec![];
+    }
+    let section_count = u16::from_le_bytes(headers[6..8].try_into().unwrap()) as usize;
+    let optional_header_size = u16::from_le_bytes(headers[20..22].try_into().unwrap()) as u64;
+    let section_alignment = u32::from_le_bytes(headers[56..60].try_into().unwrap()) as u64;
+    let mut section_table = vec![0; section_count * 40];
+    if !read_exact(nt_headers + 24 + optional_header_size, &mut section_table) {
+        return vec![];
+    }
+    section_table
+        .chunks_exact(40)
+        .map(|section| {
+            let name =
+                String::from_utf8_lossy(section[..8].split(|&elem| elem == 0).next().unwrap());
+            let size = (u32::from_le_bytes(section[8..12].try_into().unwrap()) as u64)
+                .next_multiple_of(section_alignment.max(1));
+            let address = module + u32::from_le_bytes(section[12..16].try_into().unwrap()) as u64;
+            (name.into_owned(), address..address + size)
+        })
+        .collect()
+}
+
+unsafe fn call(address: u64, arguments: &[u64]) -> Message {
+    type A = u64;
+    let address = address as usize;
+    let value = match *arguments {
+        [] => std::mem::transmute::<usize, extern "system" fn() -> A>(address)(),
+        [a] => std::mem::transmute::<usize, extern "system" fn(A) -> A>(address)(a),
+        [a, b] => std::mem::transmute::<usize, extern "system" fn(A, A) -> A>(address)(a, b),
+        [a, b, c] => {
+            std::mem::transmute::<usize, extern "system" fn(A, A, A) -> A>(address)(a, b, c)
+        }
+        [a, b, c, d] => {
+            std::mem::transmute::<usize, extern "system" fn(A, A, A, A) -> A>(address)(a, b, c, d)
+        }
+        [a, b, c, d, e] => std::mem::transmute::<usize, extern "system" fn(A, A, A, A, A) -> A>(
+            address,
+        )(a, b, c, d, e),
+        [a, b, c, d, e, f] => {
+            std::mem::transmute::<usize, extern "system" fn(A, A, A, A, A, A) -> A>(address)(
+                a, b, c, d, e, f,
+            )
+        }
+        [a, b, c, d, e, f, g] => std::mem::transmute::<
+            usize,
+            extern "system" fn(A, A, A, A, A, A, A) -> A,
+        >(address)(a, b, c, d, e, f, g),
+        [a, b, c, d, e, f, g, h] => std::mem::transmute::<
+            usize,
+            extern "system" fn(A, A, A, A, A, A, A, A) -> A,
+        >(address)(a, b, c, d, e, f, g, h),
+        _ => return Message::Failure(ERROR_INVALID_PARAMETER.0),
+    };
+    Message::Value(value)
+}
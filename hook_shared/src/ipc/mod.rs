@@ -0,0 +1,15 @@
+//! shared memory and events used by the channel, the events are stored as u64
+//! slots inside of the shared memory
+
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+pub use self::windows::*;
+
+#[cfg(target_os = "linux")]
+mod posix;
+#[cfg(target_os = "linux")]
+pub use self::posix::*;
+
+#[cfg(not(any(windows, target_os = "linux")))]
+compile_error!("shared memory and events are only implemented for Windows and Linux");
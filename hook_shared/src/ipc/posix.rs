@@ -0,0 +1,197 @@
+use std::{
+    ffi::CString,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+/// process the events are shared with, unused as the events live inside of
+/// the shared memory
+pub type ProcessHandle = libc::pid_t;
+
+pub struct SharedMemory {
+    name: Option<CString>,
+    data: *mut u8,
+    size: usize,
+}
+
+impl SharedMemory {
+    /// creates a new named shared memory object and maps it, the object is
+    /// unlinked when dropped
+    pub fn create(name: &str, size: usize) -> std::io::Result<Self> {
+        let name = CString::new(format!("/{name}")).unwrap();
+        unsafe {
+            let fd = libc::shm_open(
+                name.as_ptr(),
+                libc::O_CREAT | libc::O_RDWR | libc::O_TRUNC,
+                0o600,
+            );
+            if fd == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if libc::ftruncate(fd, size as libc::off_t) == -1 {
+                let error = std::io::Error::last_os_error();
+                libc::close(fd);
+                libc::shm_unlink(name.as_ptr());
+                return Err(error);
+            }
+            Self::map(fd, Some(name), size)
+        }
+    }
+
+    /// opens an existing named shared memory object and maps it
+    pub fn open(name: &str, size: usize) -> std::io::Result<Self> {
+        let name = CString::new(format!("/{name}")).unwrap();
+        unsafe {
+            let fd = libc::shm_open(name.as_ptr(), libc::O_RDWR, 0);
+            if fd == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Self::map(fd, None, size)
+        }
+    }
+
+    unsafe fn map(fd: libc::c_int, name: Option<CString>, size: usize) -> std::io::Result<Self> {
+        let data = libc::mmap(
+            std::ptr::null_mut(),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        );
+        let error = std::io::Error::last_os_error();
+        libc::close(fd);
+        if data == libc::MAP_FAILED {
+            return Err(error);
+        }
+        Ok(Self {
+            name,
+            data: data as *mut u8,
+            size,
+        })
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.data
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.data as *mut _, self.size);
+            if let Some(name) = &self.name {
+                libc::shm_unlink(name.as_ptr());
+            }
+        }
+    }
+}
+
+/// auto-reset event backed by a futex word inside of the shared memory (the
+/// lower half of the slot)
+pub struct Event(*const AtomicU32);
+
+impl Event {
+    /// initializes the event in the slot
+    pub fn create(slot: &mut u64, _target_process: ProcessHandle) -> std::io::Result<Self> {
+        *slot = 0;
+        Ok(Self::open(slot))
+    }
+
+    /// opens an event created by the host
+    pub fn open(slot: &u64) -> Self {
+        Self(slot as *const u64 as *const AtomicU32)
+    }
+
+    fn word(&self) -> &AtomicU32 {
+        unsafe { &*self.0 }
+    }
+
+    pub fn signal(&self) {
+        self.word().store(1, Ordering::Release);
+        unsafe {
+            libc::syscall(libc::SYS_futex, self.0, libc::FUTEX_WAKE, libc::c_int::MAX);
+        }
+    }
+
+    /// waits until the event is signaled, returns false if the timeout elapsed
+    pub fn wait(&self, timeout: Option<Duration>) -> bool {
+        let timeout = timeout.map(|timeout| libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        });
+        loop {
+            if self
+                .word()
+                .compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return true;
+            }
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_futex,
+                    self.0,
+                    libc::FUTEX_WAIT,
+                    0,
+                    timeout
+                        .as_ref()
+                        .map_or(std::ptr::null(), |timeout| timeout as *const _),
+                )
+            };
+            if result == -1
+                && std::io::Error::last_os_error().raw_os_error() == Some(libc::ETIMEDOUT)
+            {
+                return self
+                    .word()
+                    .compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn shared_memory() {
+        let name = format!("mbg_test_shared_memory_{}", std::process::id());
+        let created = SharedMemory::create(&name, 0x2000).unwrap();
+        let opened = SharedMemory::open(&name, 0x2000).unwrap();
+        unsafe {
+            *created.as_ptr().add(0x1FFF) = 0x42;
+            assert_eq!(*opened.as_ptr().add(0x1FFF), 0x42);
+        }
+
+        // the object is unlinked together with the creator
+        drop(created);
+        assert!(SharedMemory::open(&name, 0x2000).is_err());
+    }
+
+    #[test]
+    fn event() {
+        let mut slot = 0u64;
+        let event = Event::create(&mut slot, 0).unwrap();
+        assert!(!event.wait(Some(Duration::from_millis(10))));
+
+        // auto-reset
+        event.signal();
+        assert!(event.wait(Some(Duration::from_millis(10))));
+        assert!(!event.wait(Some(Duration::from_millis(10))));
+
+        // signaled from another thread
+        let slot_address = &slot as *const u64 as usize;
+        let start = Instant::now();
+        let signaler = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            Event::open(unsafe { &*(slot_address as *const u64) }).signal();
+        });
+        assert!(event.wait(None));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        signaler.join().unwrap();
+    }
+}
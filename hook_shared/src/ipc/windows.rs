@@ -0,0 +1,142 @@
+use std::{ffi::CString, time::Duration};
+
+use windows::{
+    core::PCSTR,
+    Win32::{
+        Foundation::{
+            CloseHandle, DuplicateHandle, DUPLICATE_HANDLE_OPTIONS, DUPLICATE_SAME_ACCESS, FALSE,
+            HANDLE, INVALID_HANDLE_VALUE, WAIT_OBJECT_0,
+        },
+        System::{
+            Memory::{
+                CreateFileMappingA, MapViewOfFile, OpenFileMappingA, UnmapViewOfFile,
+                FILE_MAP_READ, FILE_MAP_WRITE, MEMORYMAPPEDVIEW_HANDLE, PAGE_READWRITE,
+            },
+            Threading::{CreateEventA, GetCurrentProcess, SetEvent, WaitForSingleObject, INFINITE},
+        },
+    },
+};
+
+/// process the events are shared with
+pub type ProcessHandle = HANDLE;
+
+pub struct SharedMemory {
+    file: HANDLE,
+    data: *mut u8,
+}
+
+impl SharedMemory {
+    /// creates a new named file mapping and maps it
+    pub fn create(name: &str, size: usize) -> std::io::Result<Self> {
+        let name = CString::new(name).unwrap();
+        unsafe {
+            let file = CreateFileMappingA(
+                INVALID_HANDLE_VALUE,
+                None,
+                PAGE_READWRITE,
+                0,
+                size as u32,
+                PCSTR(name.as_ptr() as *const u8),
+            )?;
+            Self::map(file, size)
+        }
+    }
+
+    /// opens an existing named file mapping and maps it
+    pub fn open(name: &str, size: usize) -> std::io::Result<Self> {
+        let name = CString::new(name).unwrap();
+        unsafe {
+            let file = OpenFileMappingA(
+                (FILE_MAP_READ | FILE_MAP_WRITE).0,
+                FALSE,
+                PCSTR(name.as_ptr() as *const u8),
+            )?;
+            Self::map(file, size)
+        }
+    }
+
+    unsafe fn map(file: HANDLE, size: usize) -> std::io::Result<Self> {
+        let data = MapViewOfFile(file, FILE_MAP_READ | FILE_MAP_WRITE, 0, 0, size)?;
+        Ok(Self {
+            file,
+            data: data.0 as *mut u8,
+        })
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.data
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            UnmapViewOfFile(MEMORYMAPPEDVIEW_HANDLE(self.data as isize));
+            CloseHandle(self.file);
+        }
+    }
+}
+
+/// auto-reset event
+pub struct Event {
+    handle: HANDLE,
+    /// whether the handle was created by this event and is closed on drop
+    owned: bool,
+}
+
+impl Event {
+    /// creates a new event and stores a handle which is valid in the target
+    /// process into the slot
+    pub fn create(slot: &mut u64, target_process: ProcessHandle) -> std::io::Result<Self> {
+        unsafe {
+            let event = CreateEventA(None, FALSE, FALSE, PCSTR::null())?;
+            let mut target_event = HANDLE::default();
+            DuplicateHandle(
+                GetCurrentProcess(),
+                event,
+                target_process,
+                &mut target_event,
+                DUPLICATE_SAME_ACCESS.0,
+                FALSE,
+                DUPLICATE_HANDLE_OPTIONS::default(),
+            )
+            .ok()?;
+            *slot = target_event.0 as u64;
+            Ok(Self {
+                handle: event,
+                owned: true,
+            })
+        }
+    }
+
+    /// opens an event created by the host, the handle in the slot stays
+    /// open when the event is dropped
+    pub fn open(slot: &u64) -> Self {
+        Self {
+            handle: HANDLE(*slot as isize),
+            owned: false,
+        }
+    }
+
+    pub fn signal(&self) {
+        unsafe {
+            SetEvent(self.handle);
+        }
+    }
+
+    /// waits until the event is signaled, returns false if the timeout elapsed
+    pub fn wait(&self, timeout: Option<Duration>) -> bool {
+        let timeout = timeout.map_or(INFINITE, |timeout| timeout.as_millis() as u32);
+        unsafe { WaitForSingleObject(self.handle, timeout) == WAIT_OBJECT_0 }
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe {
+                CloseHandle(self.handle);
+            }
+        }
+    }
+}
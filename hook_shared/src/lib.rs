@@ -1,144 +1,749 @@
-use windows::{
-    core::PCSTR,
-    s,
-    Win32::{
-        Foundation::{
-            CloseHandle, DuplicateHandle, DUPLICATE_HANDLE_OPTIONS, DUPLICATE_SAME_ACCESS, FALSE,
-            HANDLE, INVALID_HANDLE_VALUE,
-        },
-        System::{
-            Memory::{
-                CreateFileMappingA, MapViewOfFile, OpenFileMappingA, UnmapViewOfFile,
-                FILE_MAP_READ, FILE_MAP_WRITE, MEMORYMAPPEDVIEW_HANDLE, PAGE_READWRITE,
-            },
-            Threading::{CreateEventA, GetCurrentProcess},
-        },
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc,
     },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
-pub struct BreakpointListOwner {
-    pub trigger_event: HANDLE,
-    pub resolve_event: HANDLE,
-    pub file: HANDLE,
-    pub data: &'static mut BreakpointList,
+use crate::{
+    ipc::{Event, ProcessHandle, SharedMemory},
+    protocol::{
+        Error, Frame, Message, ModuleEntry, RegionEntry, Result, FRAME_HEADER_SIZE, MAGIC, VERSION,
+    },
+};
+
+pub mod ipc;
+pub mod protocol;
+
+/// name of the shared memory
+const CHANNEL_NAME: &str = "mbg_hook_channel";
+
+/// maximum size of a single encoded frame
+pub const MAILBOX_SIZE: usize = 0x10000;
+
+/// maximum number of bytes transferred by a single memory request
+pub const MAX_TRANSFER_SIZE: usize = MAILBOX_SIZE - FRAME_HEADER_SIZE - 12;
+
+/// error code replied when the reply doesn't fit into the mailbox
+#[cfg(windows)]
+pub const REPLY_TOO_LARGE: u32 = windows::Win32::Foundation::ERROR_INSUFFICIENT_BUFFER.0;
+#[cfg(target_os = "linux")]
+pub const REPLY_TOO_LARGE: u32 = libc::E2BIG as u32;
+
+/// maximum number of breakpoints
+pub const BREAKPOINT_COUNT: usize = 256;
+
+/// how often both sides update their heartbeat
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// after which time without a heartbeat the other side is considered dead
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// what the hook does when the host disappeared
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HostLostPolicy {
+    /// restores all breakpoints and lets the target continue
+    Resume = 0,
+    /// terminates the target
+    Terminate = 1,
 }
 
-impl BreakpointListOwner {
-    pub fn new(target_process: HANDLE) -> Self {
-        unsafe {
-            // create new file mapping with the name BreakpointList and map it
-            let file = CreateFileMappingA(
-                INVALID_HANDLE_VALUE,
-                None,
-                PAGE_READWRITE,
-                0,
-                std::mem::size_of::<BreakpointList>() as u32,
-                s!("BreakpointList"),
-            )
-            .unwrap();
-            let data = &mut *(MapViewOfFile(
-                file,
-                FILE_MAP_READ | FILE_MAP_WRITE,
-                0,
-                0,
-                std::mem::size_of::<BreakpointList>(),
-            )
-            .unwrap()
-            .0 as *mut BreakpointList);
-
-            // create trigger and resolve events which are used for IPC
-            let process = GetCurrentProcess();
-            let trigger_event = CreateEventA(None, FALSE, FALSE, PCSTR::null()).unwrap();
-            let mut target_trigger_event = HANDLE::default();
-            DuplicateHandle(
-                process,
-                trigger_event,
-                target_process,
-                &mut target_trigger_event,
-                DUPLICATE_SAME_ACCESS.0,
-                FALSE,
-                DUPLICATE_HANDLE_OPTIONS::default(),
-            )
-            .ok()
-            .unwrap();
-            let resolve_event = CreateEventA(None, FALSE, FALSE, PCSTR::null()).unwrap();
-            let mut target_resolve_event = HANDLE::default();
-            DuplicateHandle(
-                process,
-                resolve_event,
-                target_process,
-                &mut target_resolve_event,
-                DUPLICATE_SAME_ACCESS.0,
-                FALSE,
-                DUPLICATE_HANDLE_OPTIONS::default(),
-            )
-            .ok()
-            .unwrap();
-
-            // store the duplicated events into the shared memory
-            *data = BreakpointList {
-                trigger_event: target_trigger_event,
-                resolve_event: target_resolve_event,
-                entries: [BreakpointEntry::default(); 256],
-            };
-            Self {
-                trigger_event,
-                resolve_event,
-                file,
-                data,
+/// shared memory between the host and the hook, every field has a fixed size
+/// and offset, handles are stored as u64 and are only valid inside the hook
+/// process
+#[repr(C)]
+pub struct Channel {
+    pub magic: u32,
+    pub version: u16,
+    pub reserved: u16,
+    pub size: u32,
+    pub host_process_id: u32,
+    /// counter incremented by the host every [HEARTBEAT_INTERVAL]
+    pub host_heartbeat: AtomicU64,
+    /// counter incremented by the hook every [HEARTBEAT_INTERVAL]
+    pub hook_heartbeat: AtomicU64,
+    /// [HostLostPolicy]
+    pub host_lost_policy: u32,
+    pub reserved2: u32,
+    pub breakpoints: [Breakpoint; BREAKPOINT_COUNT],
+    /// requests sent by the hook (e.g. breakpoint hits)
+    pub hook_to_host: Mailbox,
+    /// responses sent by the host
+    pub host_to_hook: Mailbox,
+    /// commands sent by the host (e.g. memory reads)
+    pub command: Mailbox,
+    /// replies to commands sent by the hook
+    pub reply: Mailbox,
+}
+
+/// breakpoint, the original byte is needed to restore the code when the host
+/// is lost
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct Breakpoint {
+    pub address: u64,
+    pub original: u8,
+    pub reserved: [u8; 7],
+}
+
+/// single-slot buffer for one frame, guarded by a spin lock
+#[repr(C)]
+pub struct Mailbox {
+    pub event: u64,
+    pub lock: AtomicU32,
+    pub length: AtomicU32,
+    pub data: [u8; MAILBOX_SIZE],
+}
+
+// layout drift between mbg and mbg_hook has to fail at compile time
+const _: () = assert!(std::mem::size_of::<Breakpoint>() == 16);
+const _: () = assert!(std::mem::size_of::<Mailbox>() == 16 + MAILBOX_SIZE);
+const _: () =
+    assert!(std::mem::size_of::<Channel>() == 40 + 16 * BREAKPOINT_COUNT + 4 * (16 + MAILBOX_SIZE));
+
+/// observes the heartbeat of the peer, the peer is considered dead if the
+/// counter didn't change for [HEARTBEAT_TIMEOUT] of the local monotonic clock
+/// (wall clock jumps don't matter)
+#[derive(Copy, Clone)]
+struct Watchdog {
+    last: u64,
+    changed: Instant,
+}
+
+impl Watchdog {
+    fn new(heartbeat: &AtomicU64) -> Self {
+        Self {
+            last: heartbeat.load(Ordering::Relaxed),
+            changed: Instant::now(),
+        }
+    }
+
+    /// checks if the heartbeat has changed recently
+    fn alive(&mut self, heartbeat: &AtomicU64) -> bool {
+        let value = heartbeat.load(Ordering::Relaxed);
+        if value != self.last {
+            self.last = value;
+            self.changed = Instant::now();
+        }
+        self.changed.elapsed() < HEARTBEAT_TIMEOUT
+    }
+}
+
+/// increments the heartbeat every [HEARTBEAT_INTERVAL] until stopped
+fn spawn_heartbeat(heartbeat: &'static AtomicU64, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+            heartbeat.fetch_add(1, Ordering::Relaxed);
+            std::thread::sleep(HEARTBEAT_INTERVAL);
+        }
+    })
+}
+
+impl Mailbox {
+    fn lock(&self) {
+        while self
+            .lock
+            .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            std::hint::spin_loop();
+        }
+    }
+
+    fn unlock(&self) {
+        self.lock.store(0, Ordering::Release);
+    }
+
+    /// writes the frame into the mailbox and signals the event, fails if the
+    /// peer died before the previous frame has been taken
+    fn post(&mut self, event: &Event, peer: &AtomicU64, frame: &Frame) -> Result<()> {
+        // wait until the previous frame has been taken
+        let mut watchdog = Watchdog::new(peer);
+        loop {
+            self.lock();
+            if self.length.load(Ordering::Acquire) == 0 {
+                break;
+            }
+            self.unlock();
+            if !watchdog.alive(peer) {
+                return Err(Error::PeerLost);
             }
+            std::thread::yield_now();
+        }
+        // encoded in place, posting doesn't allocate as it is used inside of
+        // signal handlers
+        let length = frame.encode_into(&mut self.data);
+        if let Ok(length) = length {
+            self.length.store(length as u32, Ordering::Release);
+        }
+        self.unlock();
+        length?;
+        event.signal();
+        Ok(())
+    }
+
+    /// waits until a frame has been posted and takes it out of the mailbox,
+    /// only frames with the sequence number are taken (if specified), fails if
+    /// the timeout elapsed or the peer died (if specified)
+    fn take(
+        &mut self,
+        event: &Event,
+        sequence: Option<u32>,
+        peer: Option<&AtomicU64>,
+        timeout: Option<Duration>,
+    ) -> Result<Frame> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut watchdog = peer.map(|peer| (peer, Watchdog::new(peer)));
+        loop {
+            if self.length.load(Ordering::Acquire) != 0 {
+                self.lock();
+                let length = self.length.load(Ordering::Acquire) as usize;
+                // the sequence number is at the same offset in every frame
+                let matches = length >= FRAME_HEADER_SIZE
+                    && sequence.is_none_or(|sequence| self.data[8..12] == sequence.to_le_bytes());
+                if matches {
+                    let frame = Frame::decode(&self.data[..length]);
+                    self.length.store(0, Ordering::Release);
+                    self.unlock();
+                    return Ok(frame?.0);
+                }
+                self.unlock();
+                if length != 0 {
+                    // the frame belongs to another thread waiting on this
+                    // mailbox, pass the wakeup on
+                    event.signal();
+                    std::thread::yield_now();
+                }
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(Error::Timeout);
+            }
+            if watchdog
+                .as_mut()
+                .is_some_and(|(peer, watchdog)| !watchdog.alive(peer))
+            {
+                return Err(Error::PeerLost);
+            }
+            event.wait(Some(HEARTBEAT_INTERVAL));
         }
     }
 }
 
-impl Drop for BreakpointListOwner {
-    fn drop(&mut self) {
-        unsafe {
-            UnmapViewOfFile(MEMORYMAPPEDVIEW_HANDLE(self.data as *mut _ as isize));
-            CloseHandle(self.file).ok().unwrap();
+impl Channel {
+    /// opens the channel created by the host, fails if the host speaks a
+    /// different version, and announces the hook
+    pub fn open() -> Result<&'static mut Channel> {
+        // the mapping stays alive as long as the hook is loaded
+        let shared_memory = SharedMemory::open(CHANNEL_NAME, std::mem::size_of::<Channel>())?;
+        let channel = unsafe { &mut *(shared_memory.as_ptr() as *mut Channel) };
+        std::mem::forget(shared_memory);
+        channel.validate()?;
+
+        // the heartbeat of the hook runs as long as the hook is loaded
+        spawn_heartbeat(
+            unsafe { &*(&channel.hook_heartbeat as *const AtomicU64) },
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        // announce the hook, the host validates the version on its side
+        let process_id = std::process::id();
+        channel.hook_to_host.post(
+            &Event::open(&channel.hook_to_host.event),
+            &channel.host_heartbeat,
+            &Frame {
+                sequence: 0,
+                message: Message::Hello {
+                    version: VERSION,
+                    process_id,
+                },
+            },
+        )?;
+        Ok(channel)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.magic != MAGIC {
+            return Err(Error::BadMagic(self.magic));
+        }
+        if self.version != VERSION {
+            return Err(Error::VersionMismatch {
+                host: self.version,
+                hook: VERSION,
+            });
+        }
+        if self.size as usize != std::mem::size_of::<Channel>() {
+            return Err(Error::LayoutMismatch {
+                expected: std::mem::size_of::<Channel>() as u32,
+                actual: self.size,
+            });
+        }
+        Ok(())
+    }
+
+    /// what to do when the host disappeared
+    pub fn host_lost_policy(&self) -> HostLostPolicy {
+        if self.host_lost_policy == HostLostPolicy::Terminate as u32 {
+            HostLostPolicy::Terminate
+        } else {
+            HostLostPolicy::Resume
+        }
+    }
+
+    /// sends a request to the host and waits for the response, the sequence
+    /// number has to be unique among the threads sending requests at the same
+    /// time (e.g. the thread id) as they share the mailbox, fails with
+    /// [Error::PeerLost] if the host disappeared
+    pub fn request(&mut self, sequence: u32, message: Message) -> Result<Message> {
+        self.hook_to_host.post(
+            &Event::open(&self.hook_to_host.event),
+            &self.host_heartbeat,
+            &Frame { sequence, message },
+        )?;
+        let response = self.host_to_hook.take(
+            &Event::open(&self.host_to_hook.event),
+            Some(sequence),
+            Some(&self.host_heartbeat),
+            None,
+        )?;
+        Ok(response.message)
+    }
+
+    /// handles commands of the host until the channel fails (e.g. the host
+    /// disappeared), has to be called from a dedicated thread
+    pub fn serve(&mut self, mut handler: impl FnMut(Message) -> Message) -> Result<()> {
+        loop {
+            let request = self.command.take(
+                &Event::open(&self.command.event),
+                None,
+                Some(&self.host_heartbeat),
+                None,
+            )?;
+            let response = handler(request.message);
+            let event = Event::open(&self.reply.event);
+            let result = self.reply.post(
+                &event,
+                &self.host_heartbeat,
+                &Frame {
+                    sequence: request.sequence,
+                    message: response,
+                },
+            );
+            // the host still waits for a reply
+            if let Err(Error::FrameTooLarge(_)) = result {
+                self.reply.post(
+                    &event,
+                    &self.host_heartbeat,
+                    &Frame {
+                        sequence: request.sequence,
+                        message: Message::Failure(REPLY_TOO_LARGE),
+                    },
+                )?;
+            } else {
+                result?;
+            }
         }
     }
 }
 
-pub struct BreakpointList {
-    pub trigger_event: HANDLE,
-    pub resolve_event: HANDLE,
-    pub entries: [BreakpointEntry; 256],
+pub struct ChannelOwner {
+    hook_to_host_event: Event,
+    host_to_hook_event: Event,
+    command_event: Event,
+    reply_event: Event,
+    sequence: u32,
+    heartbeat: Option<JoinHandle<()>>,
+    heartbeat_stop: Arc<AtomicBool>,
+    hook_watchdog: Cell<Watchdog>,
+    _shared_memory: SharedMemory,
+    pub data: &'static mut Channel,
 }
 
-impl BreakpointList {
-    pub fn new() -> &'static mut BreakpointList {
-        unsafe {
-            let file = OpenFileMappingA(
-                (FILE_MAP_READ | FILE_MAP_WRITE).0,
-                FALSE,
-                s!("BreakpointList"),
-            )
-            .unwrap();
-            &mut *(MapViewOfFile(
-                file,
-                FILE_MAP_READ | FILE_MAP_WRITE,
-                0,
-                0,
-                std::mem::size_of::<BreakpointList>(),
-            )
-            .unwrap()
-            .0 as *mut BreakpointList)
+impl ChannelOwner {
+    pub fn new(target_process: ProcessHandle) -> Result<Self> {
+        // create new shared memory with the name mbg_hook_channel and map it
+        let shared_memory = SharedMemory::create(CHANNEL_NAME, std::mem::size_of::<Channel>())?;
+        let data = unsafe {
+            let data = shared_memory.as_ptr() as *mut Channel;
+            std::ptr::write_bytes(data, 0, 1);
+            &mut *data
+        };
+
+        // write the header and create an event per mailbox which are used for
+        // IPC
+        data.magic = MAGIC;
+        data.version = VERSION;
+        data.size = std::mem::size_of::<Channel>() as u32;
+        data.host_process_id = std::process::id();
+        data.host_lost_policy = HostLostPolicy::Resume as u32;
+        let hook_to_host_event = Event::create(&mut data.hook_to_host.event, target_process)?;
+        let host_to_hook_event = Event::create(&mut data.host_to_hook.event, target_process)?;
+        let command_event = Event::create(&mut data.command.event, target_process)?;
+        let reply_event = Event::create(&mut data.reply.event, target_process)?;
+
+        // spawned last, nothing can fail anymore which would leave the thread
+        // running on unmapped memory
+        let heartbeat_stop = Arc::new(AtomicBool::new(false));
+        let heartbeat = spawn_heartbeat(
+            unsafe { &*(&data.host_heartbeat as *const AtomicU64) },
+            heartbeat_stop.clone(),
+        );
+        Ok(Self {
+            hook_to_host_event,
+            host_to_hook_event,
+            command_event,
+            reply_event,
+            sequence: 0,
+            heartbeat: Some(heartbeat),
+            heartbeat_stop,
+            hook_watchdog: Cell::new(Watchdog::new(&data.hook_heartbeat)),
+            _shared_memory: shared_memory,
+            data,
+        })
+    }
+
+    /// sets what the hook does when the host disappeared
+    pub fn set_host_lost_policy(&mut self, policy: HostLostPolicy) {
+        self.data.host_lost_policy = policy as u32;
+    }
+
+    /// checks if the hook is still alive
+    pub fn alive(&self) -> bool {
+        let mut watchdog = self.hook_watchdog.get();
+        let alive = watchdog.alive(&self.data.hook_heartbeat);
+        self.hook_watchdog.set(watchdog);
+        alive
+    }
+
+    /// waits for the hello of the hook and checks its version
+    pub fn accept(&mut self, timeout: Duration) -> Result<u32> {
+        match self
+            .data
+            .hook_to_host
+            .take(&self.hook_to_host_event, None, None, Some(timeout))?
+            .message
+        {
+            Message::Hello {
+                version,
+                process_id,
+            } => {
+                if version != VERSION {
+                    return Err(Error::VersionMismatch {
+                        host: VERSION,
+                        hook: version,
+                    });
+                }
+                Ok(process_id)
+            }
+            _ => Err(Error::Unexpected),
+        }
+    }
+
+    /// waits for the next request of the hook, fails with [Error::PeerLost] if
+    /// the hook disappeared
+    pub fn receive(&mut self) -> Result<Frame> {
+        self.receive_timeout(None)
+    }
+
+    /// waits for the next request of the hook for at most the specified time
+    pub fn receive_timeout(&mut self, timeout: Option<Duration>) -> Result<Frame> {
+        self.data.hook_to_host.take(
+            &self.hook_to_host_event,
+            None,
+            Some(&self.data.hook_heartbeat),
+            timeout,
+        )
+    }
+
+    /// responds to a request of the hook
+    pub fn respond(&mut self, sequence: u32, message: Message) -> Result<()> {
+        self.data.host_to_hook.post(
+            &self.host_to_hook_event,
+            &self.data.hook_heartbeat,
+            &Frame { sequence, message },
+        )
+    }
+
+    /// sends a command to the hook and waits for the reply
+    pub fn command(&mut self, message: Message) -> Result<Message> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let sequence = self.sequence;
+        self.data.command.post(
+            &self.command_event,
+            &self.data.hook_heartbeat,
+            &Frame { sequence, message },
+        )?;
+        let reply = self.data.reply.take(
+            &self.reply_event,
+            None,
+            Some(&self.data.hook_heartbeat),
+            None,
+        )?;
+        if reply.sequence != sequence {
+            return Err(Error::Unexpected);
+        }
+        match reply.message {
+            Message::Failure(code) => Err(Error::Remote(code)),
+            message => Ok(message),
         }
     }
+
+    /// reads memory of the target in-process
+    pub fn read_memory(&mut self, address: u64, size: usize) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(size);
+        while data.len() < size {
+            let chunk_size = (size - data.len()).min(MAX_TRANSFER_SIZE);
+            let Message::Data(chunk) = self.command(Message::ReadMemory {
+                address: address + data.len() as u64,
+                size: chunk_size as u32,
+            })?
+            else {
+                return Err(Error::Unexpected);
+            };
+            // the hook stops at the first unreadable byte
+            if chunk.is_empty() {
+                return Err(Error::Unreadable(address + data.len() as u64));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    /// writes memory of the target in-process
+    pub fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<()> {
+        for (i, chunk) in data.chunks(MAX_TRANSFER_SIZE).enumerate() {
+            let Message::Done = self.command(Message::WriteMemory {
+                address: address + (i * MAX_TRANSFER_SIZE) as u64,
+                data: chunk.to_vec(),
+            })?
+            else {
+                return Err(Error::Unexpected);
+            };
+        }
+        Ok(())
+    }
+
+    /// allocates memory in the target
+    pub fn allocate(&mut self, size: u64, protection: u32) -> Result<u64> {
+        let Message::Value(address) = self.command(Message::Allocate { size, protection })? else {
+            return Err(Error::Unexpected);
+        };
+        Ok(address)
+    }
+
+    /// frees memory allocated with [ChannelOwner::allocate]
+    pub fn free(&mut self, address: u64) -> Result<()> {
+        let Message::Done = self.command(Message::Free { address })? else {
+            return Err(Error::Unexpected);
+        };
+        Ok(())
+    }
+
+    /// ids of all threads of the target
+    pub fn threads(&mut self) -> Result<Vec<u32>> {
+        let Message::Threads(threads) = self.command(Message::ListThreads)? else {
+            return Err(Error::Unexpected);
+        };
+        Ok(threads)
+    }
+
+    /// all modules known to the loader of the target
+    pub fn modules(&mut self) -> Result<Vec<ModuleEntry>> {
+        let Message::Modules(modules) = self.command(Message::ListModules)? else {
+            return Err(Error::Unexpected);
+        };
+        Ok(modules)
+    }
+
+    /// all committed regions of the address space of the target
+    pub fn regions(&mut self) -> Result<Vec<RegionEntry>> {
+        let Message::Regions(regions) = self.command(Message::ListRegions)? else {
+            return Err(Error::Unexpected);
+        };
+        Ok(regions)
+    }
+
+    /// calls a function in the target on the command thread of the hook
+    pub fn call(&mut self, address: u64, arguments: Vec<u64>) -> Result<u64> {
+        let Message::Value(value) = self.command(Message::Call { address, arguments })? else {
+            return Err(Error::Unexpected);
+        };
+        Ok(value)
+    }
 }
 
-#[derive(Copy, Clone)]
-pub struct BreakpointEntry {
-    pub address: usize,
-    pub trigger: bool,
+impl Drop for ChannelOwner {
+    fn drop(&mut self) {
+        // stop the heartbeat before the shared memory is unmapped
+        self.heartbeat_stop.store(true, Ordering::Relaxed);
+        if let Some(heartbeat) = self.heartbeat.take() {
+            let _ = heartbeat.join();
+        }
+    }
 }
 
-impl Default for BreakpointEntry {
-    fn default() -> Self {
-        Self {
-            address: 0,
-            trigger: false,
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, MutexGuard};
+
+    use super::*;
+
+    /// the channel has a fixed name, therefore only one test can use it at a
+    /// time
+    static CHANNEL_LOCK: Mutex<()> = Mutex::new(());
+
+    /// creates the channel and opens it like the hook would in the same
+    /// process
+    fn connect() -> (MutexGuard<'static, ()>, ChannelOwner, &'static mut Channel) {
+        let guard = CHANNEL_LOCK
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        #[cfg(windows)]
+        let process = unsafe { windows::Win32::System::Threading::GetCurrentProcess() };
+        #[cfg(target_os = "linux")]
+        let process = std::process::id() as ProcessHandle;
+        let mut owner = ChannelOwner::new(process).unwrap();
+        let channel = Channel::open().unwrap();
+        assert_eq!(
+            owner.accept(Duration::from_secs(5)).unwrap(),
+            std::process::id()
+        );
+        (guard, owner, channel)
+    }
+
+    #[test]
+    fn watchdog() {
+        let heartbeat = AtomicU64::new(5);
+        let mut watchdog = Watchdog::new(&heartbeat);
+        assert!(watchdog.alive(&heartbeat));
+
+        // only the change of the counter matters, not its value
+        watchdog.changed -= HEARTBEAT_TIMEOUT;
+        assert!(!watchdog.alive(&heartbeat));
+        heartbeat.store(0, Ordering::Relaxed);
+        assert!(watchdog.alive(&heartbeat));
+    }
+
+    #[test]
+    fn validate() {
+        let layout = std::alloc::Layout::new::<Channel>();
+        let channel = unsafe { &mut *(std::alloc::alloc_zeroed(layout) as *mut Channel) };
+        channel.magic = MAGIC;
+        channel.version = VERSION;
+        channel.size = std::mem::size_of::<Channel>() as u32;
+        assert!(channel.validate().is_ok());
+
+        channel.magic = 0x1234;
+        assert!(matches!(channel.validate(), Err(Error::BadMagic(0x1234))));
+        channel.magic = MAGIC;
+
+        channel.version = VERSION + 1;
+        assert!(matches!(
+            channel.validate(),
+            Err(Error::VersionMismatch { host, hook }) if host == VERSION + 1 && hook == VERSION
+        ));
+        channel.version = VERSION;
+
+        channel.size -= 1;
+        assert!(matches!(
+            channel.validate(),
+            Err(Error::LayoutMismatch { .. })
+        ));
+        unsafe { std::alloc::dealloc(channel as *mut Channel as *mut u8, layout) };
+    }
+
+    #[test]
+    fn concurrent_requests() {
+        let (_guard, mut owner, channel) = connect();
+
+        // both threads share the channel like the threads of the hook do
+        let channel = channel as *mut Channel as usize;
+        let threads = [1u32, 2].map(|thread_id| {
+            std::thread::spawn(move || {
+                let channel = unsafe { &mut *(channel as *mut Channel) };
+                channel.request(
+                    thread_id,
+                    Message::BreakpointHit {
+                        address: 0x1000,
+                        thread_id,
+                    },
+                )
+            })
+        });
+
+        // respond in reverse order, every thread has to get its own response
+        let mut requests = [
+            owner.receive().unwrap().sequence,
+            owner.receive().unwrap().sequence,
+        ];
+        requests.sort();
+        for &sequence in requests.iter().rev() {
+            owner
+                .respond(sequence, Message::Value(sequence as u64))
+                .unwrap();
+        }
+        for (thread, thread_id) in threads.into_iter().zip([1u64, 2]) {
+            assert_eq!(thread.join().unwrap().unwrap(), Message::Value(thread_id));
         }
     }
+
+    #[test]
+    fn loopback() {
+        let (_guard, mut owner, channel) = connect();
+        assert!(owner.alive());
+        std::thread::spawn(move || {
+            channel.serve(|message| match message {
+                Message::ReadMemory { address, size } => {
+                    Message::Data((address..address + size as u64).map(|x| x as u8).collect())
+                }
+                Message::WriteMemory { .. } => Message::Done,
+                Message::ListThreads => Message::Threads(vec![1, 2, 3]),
+                Message::Call { address, arguments } => {
+                    Message::Value(address + arguments.iter().sum::<u64>())
+                }
+                _ => Message::Failure(1),
+            })
+        });
+
+        // larger than a single transfer
+        let size = MAX_TRANSFER_SIZE * 2 + 3;
+        let data = owner.read_memory(0x1000, size).unwrap();
+        assert_eq!(data.len(), size);
+        assert!(data
+            .iter()
+            .enumerate()
+            .all(|(offset, &byte)| byte == (0x1000 + offset) as u8));
+        owner.write_memory(0x1000, &data).unwrap();
+        assert_eq!(owner.threads().unwrap(), vec![1, 2, 3]);
+        assert_eq!(owner.call(0x1000, vec![1, 2]).unwrap(), 0x1003);
+        assert!(matches!(owner.free(0x1000), Err(Error::Remote(1))));
+    }
+
+    #[test]
+    fn serve_failures() {
+        let (_guard, mut owner, channel) = connect();
+        std::thread::spawn(move || {
+            channel.serve(|message| match message {
+                // too large for the mailbox
+                Message::ReadMemory { address: 0, .. } => Message::Data(vec![0; MAILBOX_SIZE]),
+                // nothing readable
+                Message::ReadMemory { .. } => Message::Data(vec![]),
+                _ => Message::Done,
+            })
+        });
+
+        assert!(matches!(
+            owner.read_memory(0, 0x10),
+            Err(Error::Remote(REPLY_TOO_LARGE))
+        ));
+        // the hook keeps serving after a failed reply
+        assert!(matches!(
+            owner.read_memory(0x1000, 0x10),
+            Err(Error::Unreadable(0x1000))
+        ));
+        owner.free(0x1000).unwrap();
+    }
 }
@@ -0,0 +1,730 @@
+use thiserror::Error;
+
+/// identifies a mapping created by the host
+pub const MAGIC: u32 = u32::from_le_bytes(*b"MBGH");
+
+/// has to be bumped whenever the layout or a message changes
+pub const VERSION: u16 = 4;
+
+/// size of the frame header (length, kind, sequence)
+pub const FRAME_HEADER_SIZE: usize = 12;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Bad magic {0:#x}")]
+    BadMagic(u32),
+    #[error("Version mismatch (host {host}, hook {hook})")]
+    VersionMismatch { host: u16, hook: u16 },
+    #[error("Layout mismatch (expected {expected} bytes, got {actual})")]
+    LayoutMismatch { expected: u32, actual: u32 },
+    #[error("Truncated frame")]
+    Truncated,
+    #[error("Frame too large ({0} bytes)")]
+    FrameTooLarge(usize),
+    #[error("Unknown message kind {0}")]
+    UnknownMessage(u16),
+    #[error("Unexpected message")]
+    Unexpected,
+    #[error("Invalid string")]
+    InvalidString,
+    #[error("Memory not readable at {0:#x}")]
+    Unreadable(u64),
+    #[error("Remote error {0}")]
+    Remote(u32),
+    #[error("Timeout")]
+    Timeout,
+    #[error("Peer lost")]
+    PeerLost,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// first message sent by the hook after it validated the header
+    Hello { version: u16, process_id: u32 },
+    /// sent by the hook when a breakpoint has been hit, the thread waits until
+    /// it gets a response
+    BreakpointHit { address: u64, thread_id: u32 },
+    /// lets the thread which hit the breakpoint continue
+    Resume,
+
+    /// reads memory of the target
+    ReadMemory { address: u64, size: u32 },
+    /// writes memory of the target
+    WriteMemory { address: u64, data: Vec<u8> },
+    /// allocates memory in the target, the protection is either PAGE_* on
+    /// Windows or PROT_* on Linux
+    Allocate { size: u64, protection: u32 },
+    /// frees memory allocated by [Message::Allocate]
+    Free { address: u64 },
+    /// lists the ids of all threads of the target
+    ListThreads,
+    /// lists all modules known to the loader of the target
+    ListModules,
+    /// lists all committed regions of the address space of the target
+    ListRegions,
+    /// calls a function in the target (win64 calling convention on Windows,
+    /// sysv on Linux)
+    Call { address: u64, arguments: Vec<u64> },
+
+    /// generic success response
+    Done,
+    /// integer or address response
+    Value(u64),
+    /// response to [Message::ReadMemory]
+    Data(Vec<u8>),
+    /// response to [Message::ListThreads]
+    Threads(Vec<u32>),
+    /// response to [Message::ListModules]
+    Modules(Vec<ModuleEntry>),
+    /// response to [Message::ListRegions]
+    Regions(Vec<RegionEntry>),
+    /// the request failed with the specified error code
+    Failure(u32),
+}
+
+/// module as seen by the loader of the target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleEntry {
+    pub name: String,
+    pub base: u64,
+    pub size: u64,
+}
+
+/// kind of a memory region
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegionKind {
+    /// mapped from an executable image by the loader
+    Image = 0,
+    /// view of a file or shared memory
+    Mapped = 1,
+    /// private to the process (e.g. heap, stack or allocations)
+    Private = 2,
+}
+
+/// committed region of the address space, adjacent pages belong to the same
+/// region if they share protection, kind and owner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionEntry {
+    pub base: u64,
+    pub size: u64,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub kind: RegionKind,
+    /// module and section (e.g. kernel32.dll .text), file or description
+    /// (e.g. [heap]) the region belongs to, empty if unknown
+    pub owner: String,
+}
+
+impl RegionEntry {
+    /// parses the regions of /proc/<pid>/maps, each mapping is a region and
+    /// all mappings of a file with an executable mapping are considered
+    /// images, malformed lines are skipped
+    pub fn parse_maps(maps: &str) -> Vec<Self> {
+        let mut entries = vec![];
+        for line in maps.lines() {
+            // start-end perms offset device inode path
+            let mut fields = line.splitn(6, ' ');
+            let (Some(range), Some(permissions)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let path = fields.nth(3).unwrap_or_default().trim_start();
+            let Some((Ok(start), Ok(end))) = range
+                .split_once('-')
+                .map(|(start, end)| (u64::from_str_radix(start, 16), u64::from_str_radix(end, 16)))
+            else {
+                continue;
+            };
+            let Some(size) = end.checked_sub(start) else {
+                continue;
+            };
+            let permissions = permissions.as_bytes();
+            if permissions.len() < 4 {
+                continue;
+            }
+            let file = path.starts_with('/');
+            entries.push(RegionEntry {
+                base: start,
+                size,
+                read: permissions[0] == b'r',
+                write: permissions[1] == b'w',
+                execute: permissions[2] == b'x',
+                kind: if file || permissions[3] == b's' {
+                    RegionKind::Mapped
+                } else {
+                    RegionKind::Private
+                },
+                owner: path.to_owned(),
+            });
+        }
+
+        // files which are executed are images
+        let images: Vec<_> = entries
+            .iter()
+            .filter(|entry| entry.execute && entry.kind == RegionKind::Mapped)
+            .map(|entry| entry.owner.clone())
+            .collect();
+        for entry in &mut entries {
+            if entry.kind == RegionKind::Mapped && images.contains(&entry.owner) {
+                entry.kind = RegionKind::Image;
+            }
+        }
+        entries
+    }
+}
+
+impl Message {
+    fn kind(&self) -> u16 {
+        match self {
+            Message::Hello { .. } => 0x0001,
+            Message::BreakpointHit { .. } => 0x0100,
+            Message::Resume => 0x0101,
+            Message::ReadMemory { .. } => 0x0200,
+            Message::WriteMemory { .. } => 0x0201,
+            Message::Allocate { .. } => 0x0202,
+            Message::Free { .. } => 0x0203,
+            Message::ListThreads => 0x0204,
+            Message::ListModules => 0x0205,
+            Message::Call { .. } => 0x0206,
+            Message::ListRegions => 0x0207,
+            Message::Done => 0x0300,
+            Message::Value(..) => 0x0301,
+            Message::Data(..) => 0x0302,
+            Message::Threads(..) => 0x0303,
+            Message::Modules(..) => 0x0304,
+            Message::Regions(..) => 0x0305,
+            Message::Failure(..) => 0x03FF,
+        }
+    }
+
+    fn encode(&self, writer: &mut Writer) {
+        match self {
+            Message::Hello {
+                version,
+                process_id,
+            } => {
+                writer.u16(*version);
+                writer.u32(*process_id);
+            }
+            Message::BreakpointHit { address, thread_id } => {
+                writer.u64(*address);
+                writer.u32(*thread_id);
+            }
+            Message::Resume => {}
+            Message::ReadMemory { address, size } => {
+                writer.u64(*address);
+                writer.u32(*size);
+            }
+            Message::WriteMemory { address, data } => {
+                writer.u64(*address);
+                writer.bytes(data);
+            }
+            Message::Allocate { size, protection } => {
+                writer.u64(*size);
+                writer.u32(*protection);
+            }
+            Message::Free { address } => writer.u64(*address),
+            Message::ListThreads => {}
+            Message::ListModules => {}
+            Message::ListRegions => {}
+            Message::Call { address, arguments } => {
+                writer.u64(*address);
+                writer.u32(arguments.len() as u32);
+                for &argument in arguments {
+                    writer.u64(argument);
+                }
+            }
+            Message::Done => {}
+            Message::Value(value) => writer.u64(*value),
+            Message::Data(data) => writer.bytes(data),
+            Message::Threads(threads) => {
+                writer.u32(threads.len() as u32);
+                for &thread in threads {
+                    writer.u32(thread);
+                }
+            }
+            Message::Modules(modules) => {
+                writer.u32(modules.len() as u32);
+                for module in modules {
+                    writer.bytes(module.name.as_bytes());
+                    writer.u64(module.base);
+                    writer.u64(module.size);
+                }
+            }
+            Message::Regions(regions) => {
+                writer.u32(regions.len() as u32);
+                for region in regions {
+                    writer.u64(region.base);
+                    writer.u64(region.size);
+                    writer.u8(region.read as u8
+                        | (region.write as u8) << 1
+                        | (region.execute as u8) << 2);
+                    writer.u8(region.kind as u8);
+                    writer.bytes(region.owner.as_bytes());
+                }
+            }
+            Message::Failure(code) => writer.u32(*code),
+        }
+    }
+
+    fn decode(kind: u16, reader: &mut Reader) -> Result<Self> {
+        Ok(match kind {
+            0x0001 => Message::Hello {
+                version: reader.u16()?,
+                process_id: reader.u32()?,
+            },
+            0x0100 => Message::BreakpointHit {
+                address: reader.u64()?,
+                thread_id: reader.u32()?,
+            },
+            0x0101 => Message::Resume,
+            0x0200 => Message::ReadMemory {
+                address: reader.u64()?,
+                size: reader.u32()?,
+            },
+            0x0201 => Message::WriteMemory {
+                address: reader.u64()?,
+                data: reader.prefixed_bytes()?.to_vec(),
+            },
+            0x0202 => Message::Allocate {
+                size: reader.u64()?,
+                protection: reader.u32()?,
+            },
+            0x0203 => Message::Free {
+                address: reader.u64()?,
+            },
+            0x0204 => Message::ListThreads,
+            0x0205 => Message::ListModules,
+            0x0207 => Message::ListRegions,
+            0x0206 => Message::Call {
+                address: reader.u64()?,
+                arguments: (0..reader.u32()?)
+                    .map(|_| reader.u64())
+                    .collect::<Result<_>>()?,
+            },
+            0x0300 => Message::Done,
+            0x0301 => Message::Value(reader.u64()?),
+            0x0302 => Message::Data(reader.prefixed_bytes()?.to_vec()),
+            0x0303 => Message::Threads(
+                (0..reader.u32()?)
+                    .map(|_| reader.u32())
+                    .collect::<Result<_>>()?,
+            ),
+            0x0304 => Message::Modules(
+                (0..reader.u32()?)
+                    .map(|_| {
+                        Ok(ModuleEntry {
+                            name: String::from_utf8(reader.prefixed_bytes()?.to_vec())
+                                .map_err(|_| Error::InvalidString)?,
+                            base: reader.u64()?,
+                            size: reader.u64()?,
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            0x0305 => Message::Regions(
+                (0..reader.u32()?)
+                    .map(|_| {
+                        let base = reader.u64()?;
+                        let size = reader.u64()?;
+                        let protection = reader.u8()?;
+                        Ok(RegionEntry {
+                            base,
+                            size,
+                            read: protection & 1 != 0,
+                            write: protection & 2 != 0,
+                            execute: protection & 4 != 0,
+                            kind: match reader.u8()? {
+                                0 => RegionKind::Image,
+                                1 => RegionKind::Mapped,
+                                _ => RegionKind::Private,
+                            },
+                            owner: String::from_utf8(reader.prefixed_bytes()?.to_vec())
+                                .map_err(|_| Error::InvalidString)?,
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            0x03FF => Message::Failure(reader.u32()?),
+            kind => return Err(Error::UnknownMessage(kind)),
+        })
+    }
+}
+
+/// message together with a sequence number, responses carry the sequence
+/// number of their request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub sequence: u32,
+    pub message: Message,
+}
+
+impl Frame {
+    /// encodes the frame, the layout is (all little-endian)
+    /// `length: u32, kind: u16, reserved: u16, sequence: u32, payload`
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![0; self.write(&mut []).1];
+        self.write(&mut data);
+        data
+    }
+
+    /// encodes the frame into the buffer without allocating (e.g. inside of a
+    /// signal handler) and returns its length, fails if the buffer is too
+    /// small
+    pub fn encode_into(&self, buffer: &mut [u8]) -> Result<usize> {
+        match self.write(buffer) {
+            (true, length) => Ok(length),
+            (false, length) => Err(Error::FrameTooLarge(length)),
+        }
+    }
+
+    /// writes as much as fits into the buffer, returns if everything fitted
+    /// and the length of the frame
+    fn write(&self, buffer: &mut [u8]) -> (bool, usize) {
+        let capacity = buffer.len();
+        let mut writer = Writer {
+            data: buffer,
+            length: FRAME_HEADER_SIZE,
+        };
+        self.message.encode(&mut writer);
+        let length = writer.length;
+        let mut header = Writer {
+            data: writer.data,
+            length: 0,
+        };
+        header.u32((length - FRAME_HEADER_SIZE) as u32);
+        header.u16(self.message.kind());
+        header.u16(0);
+        header.u32(self.sequence);
+        (length <= capacity, length)
+    }
+
+    /// decodes a frame and returns it together with the number of bytes
+    /// consumed
+    pub fn decode(data: &[u8]) -> Result<(Self, usize)> {
+        let mut reader = Reader(data);
+        let length = reader.u32()? as usize;
+        let kind = reader.u16()?;
+        reader.u16()?;
+        let sequence = reader.u32()?;
+        let mut payload = Reader(reader.bytes(length)?);
+        let message = Message::decode(kind, &mut payload)?;
+        if !payload.0.is_empty() {
+            return Err(Error::LayoutMismatch {
+                expected: (length - payload.0.len()) as u32,
+                actual: length as u32,
+            });
+        }
+        Ok((Self { sequence, message }, FRAME_HEADER_SIZE + length))
+    }
+}
+
+/// writes into a fixed buffer, the length keeps counting past its end to
+/// report the required size
+pub(crate) struct Writer<'a> {
+    data: &'a mut [u8],
+    length: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, value: &[u8]) {
+        if let Some(data) = self.data.get_mut(self.length..self.length + value.len()) {
+            data.copy_from_slice(value);
+        }
+        self.length += value.len();
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.put(&[value]);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.put(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.put(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.put(&value.to_le_bytes());
+    }
+
+    /// length-prefixed byte array
+    pub(crate) fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.put(value);
+    }
+}
+
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.0.len() < N {
+            return Err(Error::Truncated);
+        }
+        let (value, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(value.try_into().unwrap())
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    /// fixed-size byte array
+    pub(crate) fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.0.len() < length {
+            return Err(Error::Truncated);
+        }
+        let (value, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(value)
+    }
+
+    /// length-prefixed byte array
+    pub(crate) fn prefixed_bytes(&mut self) -> Result<&'a [u8]> {
+        let length = self.u32()? as usize;
+        self.bytes(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::Hello {
+                version: VERSION,
+                process_id: 1234,
+            },
+            Message::BreakpointHit {
+                address: 0x7FF6_1234_5678,
+                thread_id: 42,
+            },
+            Message::Resume,
+            Message::ReadMemory {
+                address: 0x1000,
+                size: 0x20,
+            },
+            Message::WriteMemory {
+                address: 0x2000,
+                data: vec![0xCC, 0x90, 0xC3],
+            },
+            Message::Allocate {
+                size: 0x1000,
+                protection: 0x40,
+            },
+            Message::Free { address: 0x3000 },
+            Message::ListThreads,
+            Message::ListModules,
+            Message::ListRegions,
+            Message::Call {
+                address: 0x4000,
+                arguments: vec![1, 2, u64::MAX],
+            },
+            Message::Done,
+            Message::Value(u64::MAX),
+            Message::Data(vec![]),
+            Message::Threads(vec![1, 2, 3]),
+            Message::Modules(vec![ModuleEntry {
+                name: "kernel32.dll".to_owned(),
+                base: 0x7FF8_0000_0000,
+                size: 0xC0000,
+            }]),
+            Message::Regions(vec![
+                RegionEntry {
+                    base: 0x10000,
+                    size: 0x1000,
+                    read: true,
+                    write: false,
+                    execute: true,
+                    kind: RegionKind::Image,
+                    owner: "kernel32.dll .text".to_owned(),
+                },
+                RegionEntry {
+                    base: 0x20000,
+                    size: 0x2000,
+                    read: true,
+                    write: true,
+                    execute: false,
+                    kind: RegionKind::Private,
+                    owner: String::new(),
+                },
+            ]),
+            Message::Failure(5),
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        for (sequence, message) in messages().into_iter().enumerate() {
+            let frame = Frame {
+                sequence: sequence as u32,
+                message,
+            };
+            let data = frame.encode();
+            let (decoded, length) = Frame::decode(&data).unwrap();
+            assert_eq!(decoded, frame);
+            assert_eq!(length, data.len());
+        }
+    }
+
+    #[test]
+    fn encode_into() {
+        for message in messages() {
+            let frame = Frame {
+                sequence: 7,
+                message,
+            };
+            let data = frame.encode();
+            let mut buffer = [0xAA; 256];
+            assert_eq!(frame.encode_into(&mut buffer).unwrap(), data.len());
+            assert_eq!(&buffer[..data.len()], &data[..]);
+            assert!(matches!(
+                frame.encode_into(&mut buffer[..data.len() - 1]),
+                Err(Error::FrameTooLarge(length)) if length == data.len()
+            ));
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        for message in messages() {
+            let data = Frame {
+                sequence: 1,
+                message,
+            }
+            .encode();
+            for length in 0..data.len() {
+                assert!(matches!(
+                    Frame::decode(&data[..length]),
+                    Err(Error::Truncated)
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_kind() {
+        let mut data = Frame {
+            sequence: 1,
+            message: Message::Done,
+        }
+        .encode();
+        data[4..6].copy_from_slice(&0x7777u16.to_le_bytes());
+        assert!(matches!(
+            Frame::decode(&data),
+            Err(Error::UnknownMessage(0x7777))
+        ));
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        // trailing bytes inside of the frame are an error
+        let mut data = Frame {
+            sequence: 1,
+            message: Message::Value(1),
+        }
+        .encode();
+        data.push(0);
+        data[0..4].copy_from_slice(&9u32.to_le_bytes());
+        assert!(matches!(
+            Frame::decode(&data),
+            Err(Error::LayoutMismatch { .. })
+        ));
+
+        // bytes behind the frame are left to the caller
+        let mut data = Frame {
+            sequence: 1,
+            message: Message::Value(1),
+        }
+        .encode();
+        let length = data.len();
+        data.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(Frame::decode(&data).unwrap().1, length);
+    }
+
+    #[test]
+    fn parse_maps() {
+        let maps = "\
+55d0c0a00000-55d0c0a01000 r--p 00000000 08:01 1234                       /usr/bin/target
+55d0c0a01000-55d0c0a02000 r-xp 00001000 08:01 1234                       /usr/bin/target
+7f0000000000-7f0000001000 rw-s 00000000 00:01 42                         /dev/shm/data
+7f0000001000-7f0000002000 r--p 00000000 08:01 99                         /usr/share/locale
+7ffd00000000-7ffd00021000 rw-p 00000000 00:00 0                          [stack]
+7ffd00030000-7ffd00031000 rw-p 00000000 00:00 0
+7ffd00040000-7ffd0003f000 rw-p 00000000 00:00 0
+invalid line
+";
+        let region = |base, size, permissions: &str, kind, owner: &str| RegionEntry {
+            base,
+            size,
+            read: permissions.contains('r'),
+            write: permissions.contains('w'),
+            execute: permissions.contains('x'),
+            kind,
+            owner: owner.to_owned(),
+        };
+        assert_eq!(
+            RegionEntry::parse_maps(maps),
+            [
+                region(
+                    0x55D0C0A00000,
+                    0x1000,
+                    "r",
+                    RegionKind::Image,
+                    "/usr/bin/target"
+                ),
+                region(
+                    0x55D0C0A01000,
+                    0x1000,
+                    "rx",
+                    RegionKind::Image,
+                    "/usr/bin/target"
+                ),
+                region(
+                    0x7F0000000000,
+                    0x1000,
+                    "rw",
+                    RegionKind::Mapped,
+                    "/dev/shm/data"
+                ),
+                region(
+                    0x7F0000001000,
+                    0x1000,
+                    "r",
+                    RegionKind::Mapped,
+                    "/usr/share/locale"
+                ),
+                region(
+                    0x7FFD00000000,
+                    0x21000,
+                    "rw",
+                    RegionKind::Private,
+                    "[stack]"
+                ),
+                region(0x7FFD00030000, 0x1000, "rw", RegionKind::Private, ""),
+            ]
+        );
+    }
+}
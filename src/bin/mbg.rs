@@ -0,0 +1,75 @@
+use std::io::Write;
+
+use mbg::{memory::RegionEntry, process::Process, Result};
+
+fn main() -> Result<()> {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("usage: mbg <executable>");
+        std::process::exit(1);
+    };
+    let process = Process::new(path)?;
+
+    let stdin = std::io::stdin();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            break;
+        }
+        let result = match line.split_whitespace().next() {
+            None => Ok(()),
+            Some("q" | "quit") => break,
+            #[cfg(windows)]
+            Some("lm" | "list-modules") => list_modules(&process),
+            Some("lr" | "list-regions") => list_regions(&process),
+            Some(command) => {
+                println!("unknown command: {}", command);
+                Ok(())
+            }
+        };
+        if let Err(error) = result {
+            println!("{}", error);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn list_modules(process: &Process) -> Result<()> {
+    for module in process.modules()? {
+        println!(
+            "{:016X} {:08X} {}",
+            module.base(),
+            module.size(),
+            module.name()
+        );
+    }
+    Ok(())
+}
+
+/// base, size, protection, kind and owner of each committed region
+fn list_regions(process: &Process) -> Result<()> {
+    for RegionEntry {
+        base,
+        size,
+        read,
+        write,
+        execute,
+        kind,
+        owner,
+    } in process.regions()?
+    {
+        println!(
+            "{:016X} {:08X} {}{}{} {:<7?} {}",
+            base,
+            size,
+            if read { 'r' } else { '-' },
+            if write { 'w' } else { '-' },
+            if execute { 'x' } else { '-' },
+            kind,
+            owner
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,301 @@
+use iced_x86::code_asm::*;
+
+use crate::{Error, Result};
+
+/// argument of a remote call
+#[derive(Debug, Clone)]
+pub enum Argument {
+    /// passed as is
+    Integer(u64),
+    /// address in the target, passed as is
+    Pointer(usize),
+    /// copied into the target and passed as pointer, the content is read back
+    /// after the call
+    Buffer(Vec<u8>),
+    /// copied into the target nul-terminated and passed as pointer
+    String(String),
+    /// copied into the target nul-terminated and passed as pointer
+    WideString(Vec<u16>),
+}
+
+impl Argument {
+    /// data which has to be copied into the target
+    pub fn data(&self) -> Option<Vec<u8>> {
+        match self {
+            Argument::Integer(_) | Argument::Pointer(_) => None,
+            Argument::Buffer(data) => Some(data.clone()),
+            Argument::String(value) => {
+                let mut data = value.as_bytes().to_vec();
+                data.push(0);
+                Some(data)
+            }
+            Argument::WideString(value) => Some(
+                value
+                    .iter()
+                    .chain(std::iter::once(&0))
+                    .flat_map(|elem| elem.to_le_bytes())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CallingConvention {
+    /// rcx, rdx, r8, r9, stack with 32 bytes shadow space
+    Win64,
+    /// rdi, rsi, rdx, rcx, r8, r9, stack
+    SysV,
+    /// 32-bit, stack, caller cleans up
+    Cdecl,
+    /// 32-bit, stack, callee cleans up
+    Stdcall,
+}
+
+impl CallingConvention {
+    pub fn bitness(self) -> u32 {
+        match self {
+            CallingConvention::Win64 | CallingConvention::SysV => 64,
+            CallingConvention::Cdecl | CallingConvention::Stdcall => 32,
+        }
+    }
+}
+
+/// assembles a thread procedure which calls the function with the specified
+/// (already resolved) arguments and stores the return value at
+/// result_address, for 32-bit conventions edx:eax is stored and the function,
+/// arguments and result address have to fit in 32 bits, the code doesn't
+/// depend on ip but its size may depend on the operands
+pub fn assemble(
+    convention: CallingConvention,
+    function: u64,
+    arguments: &[u64],
+    result_address: u64,
+    ip: u64,
+) -> Result<Vec<u8>> {
+    let mut a = CodeAssembler::new(convention.bitness())?;
+    match convention {
+        CallingConvention::Win64 | CallingConvention::SysV => {
+            let (registers, shadow_space): (&[AsmRegister64], i32) =
+                if convention == CallingConvention::Win64 {
+                    (&[rcx, rdx, r8, r9], 32)
+                } else {
+                    (&[rdi, rsi, rdx, rcx, r8, r9], 0)
+                };
+            let stack_arguments = arguments.len().saturating_sub(registers.len()) as i32;
+
+            // rdi and rsi are non-volatile for the caller (win64)
+            a.push(rbp)?;
+            a.mov(rbp, rsp)?;
+            a.push(rdi)?;
+            a.push(rsi)?;
+            a.and(rsp, -16)?;
+            a.sub(rsp, (shadow_space + stack_arguments * 8 + 15) & !15)?;
+            for (i, &argument) in arguments.iter().enumerate().skip(registers.len()) {
+                a.mov(rax, argument)?;
+                a.mov(
+                    qword_ptr(rsp + shadow_space + (i - registers.len()) as i32 * 8),
+                    rax,
+                )?;
+            }
+            for (&register, &argument) in registers.iter().zip(arguments) {
+                a.mov(register, argument)?;
+            }
+            a.mov(rax, function)?;
+            a.call(rax)?;
+            a.mov(rcx, result_address)?;
+            a.mov(qword_ptr(rcx), rax)?;
+            a.lea(rsp, qword_ptr(rbp - 16))?;
+            a.pop(rsi)?;
+            a.pop(rdi)?;
+            a.pop(rbp)?;
+            a.ret()?;
+        }
+        CallingConvention::Cdecl | CallingConvention::Stdcall => {
+            let narrow = |value: u64| {
+                u32::try_from(value).map_err(|_| Error::Unsupported("value exceeds 32 bits"))
+            };
+            for &argument in arguments.iter().rev() {
+                a.push(narrow(argument)? as i32)?;
+            }
+            a.mov(eax, narrow(function)?)?;
+            a.call(eax)?;
+            if convention == CallingConvention::Cdecl && !arguments.is_empty() {
+                a.add(esp, arguments.len() as i32 * 4)?;
+            }
+            a.mov(ecx, narrow(result_address)?)?;
+            a.mov(dword_ptr(ecx), eax)?;
+            a.mov(dword_ptr(ecx + 4), edx)?;
+            // thread procedures are stdcall with one argument
+            a.ret_1(4)?;
+        }
+    }
+    Ok(a.assemble(ip)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use iced_x86::{Decoder, DecoderOptions};
+
+    use super::*;
+    use crate::emulator::{
+        Access, Emulator, Exception, Protection, PAGE_SIZE, R8, R9, RAX, RBP, RCX, RDI, RDX, RSI,
+        RSP,
+    };
+
+    const CODE: u64 = 0x1000;
+    const FUNCTION: u64 = 0x2000;
+    const RESULT: u64 = 0x3000;
+    const RETURN_ADDRESS: u64 = 0xDEAD_0000;
+
+    /// runs the thread procedure in the emulator until it enters the function
+    /// (int3 / ret), stack is rsp of the thread before the return address
+    /// is pushed
+    fn enter(convention: CallingConvention, arguments: &[u64], stack: u64) -> Emulator {
+        let code = assemble(convention, FUNCTION, arguments, RESULT, CODE).unwrap();
+        let mut emulator = Emulator::new();
+        emulator
+            .memory
+            .map(CODE, PAGE_SIZE, Protection::READ_EXECUTE);
+        emulator.memory.write_raw(CODE, &code).unwrap();
+        emulator
+            .memory
+            .map(FUNCTION, PAGE_SIZE, Protection::READ_EXECUTE);
+        emulator.memory.write_raw(FUNCTION, &[0xCC, 0xC3]).unwrap();
+        emulator
+            .memory
+            .map(RESULT, PAGE_SIZE, Protection::READ_WRITE);
+        emulator.map_stack(0x10000, 0x10000);
+        emulator.cpu.gpr[RSP] = stack - 8;
+        emulator
+            .memory
+            .write_raw(stack - 8, &RETURN_ADDRESS.to_le_bytes())
+            .unwrap();
+        for register in [RBP, RSI, RDI] {
+            emulator.cpu.gpr[register] = 0x5555_0000 + register as u64;
+        }
+        emulator.cpu.rip = CODE;
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+        assert_eq!(emulator.cpu.rip, FUNCTION + 1);
+        emulator
+    }
+
+    /// returns from the function and runs the procedure to its end
+    fn leave(mut emulator: Emulator, stack: u64) {
+        emulator.cpu.gpr[RAX] = 0x1234_5678_9ABC;
+        assert_eq!(
+            emulator.run(None),
+            Some(Exception::PageFault {
+                address: RETURN_ADDRESS,
+                access: Access::Execute,
+            })
+        );
+        assert_eq!(emulator.read_u64(RESULT).unwrap(), 0x1234_5678_9ABC);
+        assert_eq!(emulator.cpu.gpr[RSP], stack);
+        for register in [RBP, RSI, RDI] {
+            assert_eq!(emulator.cpu.gpr[register], 0x5555_0000 + register as u64);
+        }
+    }
+
+    /// stack argument of a function which was just entered
+    fn stack_argument(emulator: &Emulator, offset: u64) -> u64 {
+        emulator
+            .read_u64(emulator.cpu.gpr[RSP] + 8 + offset)
+            .unwrap()
+    }
+
+    #[test]
+    fn win64() {
+        for stack in [0x1FF00, 0x1FF08] {
+            for count in [0, 3, 4, 7] {
+                let arguments: Vec<_> = (1..=count).collect();
+                let emulator = enter(CallingConvention::Win64, &arguments, stack);
+                // rsp + 8 is aligned on entry
+                assert_eq!((emulator.cpu.gpr[RSP] + 8) % 16, 0);
+                for (&register, &argument) in [RCX, RDX, R8, R9].iter().zip(&arguments) {
+                    assert_eq!(emulator.cpu.gpr[register], argument);
+                }
+                // after the shadow space
+                for (i, &argument) in arguments.iter().enumerate().skip(4) {
+                    assert_eq!(stack_argument(&emulator, 32 + (i as u64 - 4) * 8), argument);
+                }
+                leave(emulator, stack);
+            }
+        }
+    }
+
+    #[test]
+    fn sysv() {
+        for stack in [0x1FF00, 0x1FF08] {
+            for count in [0, 6, 7, 8] {
+                let arguments: Vec<_> = (1..=count).collect();
+                let emulator = enter(CallingConvention::SysV, &arguments, stack);
+                assert_eq!((emulator.cpu.gpr[RSP] + 8) % 16, 0);
+                for (&register, &argument) in [RDI, RSI, RDX, RCX, R8, R9].iter().zip(&arguments) {
+                    assert_eq!(emulator.cpu.gpr[register], argument);
+                }
+                for (i, &argument) in arguments.iter().enumerate().skip(6) {
+                    assert_eq!(stack_argument(&emulator, (i as u64 - 6) * 8), argument);
+                }
+                leave(emulator, stack);
+            }
+        }
+    }
+
+    #[test]
+    fn x86() {
+        for convention in [CallingConvention::Cdecl, CallingConvention::Stdcall] {
+            let code = assemble(convention, 0x7000_1000, &[1, 2, 3], 0x7000_2000, 0).unwrap();
+            let instructions: Vec<_> = Decoder::with_ip(32, &code, 0, DecoderOptions::NONE)
+                .into_iter()
+                .map(|instruction| instruction.to_string())
+                .collect();
+            // pushed right to left
+            let mut expected = vec![
+                "push 3",
+                "push 2",
+                "push 1",
+                "mov eax,70001000h",
+                "call eax",
+            ];
+            // the caller cleans up
+            if convention == CallingConvention::Cdecl {
+                expected.push("add esp,0Ch");
+            }
+            expected.extend([
+                "mov ecx,70002000h",
+                "mov [ecx],eax",
+                "mov [ecx+4],edx",
+                "ret 4",
+            ]);
+            assert_eq!(instructions, expected);
+
+            // values which would be truncated
+            assert!(assemble(convention, 0x1_0000_0000, &[], 0x7000_2000, 0).is_err());
+            assert!(assemble(convention, 0x7000_1000, &[1 << 32], 0x7000_2000, 0).is_err());
+            assert!(assemble(convention, 0x7000_1000, &[], 0x1_0000_0000, 0).is_err());
+        }
+    }
+
+    #[test]
+    fn position_independent() {
+        for convention in [
+            CallingConvention::Win64,
+            CallingConvention::SysV,
+            CallingConvention::Cdecl,
+            CallingConvention::Stdcall,
+        ] {
+            let arguments = [1, 2, 3, 4, 5, 6, 7];
+            let code = assemble(convention, 0x7FFF_1000, &arguments, 0x7FFF_2000, 0).unwrap();
+            let moved = assemble(
+                convention,
+                0x7FFF_1000,
+                &arguments,
+                0x7FFF_2000,
+                0x7FFF_3000,
+            )
+            .unwrap();
+            assert_eq!(code, moved);
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::{collections::BTreeMap, io::Write, ops::Range};
+
+use crate::{module::Module, Result};
+
+/// executed basic block
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub size: u64,
+    pub hits: u64,
+}
+
+/// module the coverage is attributed to
+#[derive(Debug, Clone)]
+pub struct CoverageModule {
+    pub name: String,
+    pub base: u64,
+    pub size: u64,
+    /// functions of the module (see [Module::functions])
+    pub functions: Vec<(String, Range<u64>)>,
+}
+
+/// coverage of a single function
+#[derive(Debug, Clone)]
+pub struct FunctionCoverage {
+    pub module: String,
+    pub name: String,
+    pub range: Range<u64>,
+    /// number of times the function was entered
+    pub hits: u64,
+    /// number of distinct blocks starting in the function
+    pub blocks: usize,
+    /// number of distinct bytes executed
+    pub covered: u64,
+}
+
+/// basic-block coverage, blocks are attributed to modules when exported
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    modules: Vec<CoverageModule>,
+    /// by start address
+    blocks: BTreeMap<u64, Block>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds a module to attribute blocks to
+    pub fn add_module(&mut self, module: &Module) -> Result<()> {
+        self.modules.push(CoverageModule {
+            name: module.name().to_owned(),
+            base: module.base() as u64,
+            size: module.size() as u64,
+            functions: module
+                .functions()?
+                .into_iter()
+                .map(|(name, range)| (name, range.start as u64..range.end as u64))
+                .collect(),
+        });
+        Ok(())
+    }
+
+    pub fn modules(&self) -> &[CoverageModule] {
+        &self.modules
+    }
+
+    /// records an execution of a block (e.g. from a breakpoint per block on a
+    /// live target), blocks with the same start keep the largest size
+    pub fn add_block(&mut self, start: u64, size: u64) {
+        let block = self.blocks.entry(start).or_insert(Block { size, hits: 0 });
+        block.size = block.size.max(size);
+        block.hits += 1;
+    }
+
+    /// executed blocks by start address
+    pub fn blocks(&self) -> &BTreeMap<u64, Block> {
+        &self.blocks
+    }
+
+    /// blocks executed in this but not in the other run, this is how code
+    /// handling a specific feature is found
+    pub fn difference(&self, other: &Coverage) -> Coverage {
+        Coverage {
+            modules: self.modules.clone(),
+            blocks: self
+                .blocks
+                .iter()
+                .filter(|(start, _)| !other.blocks.contains_key(start))
+                .map(|(&start, &block)| (start, block))
+                .collect(),
+        }
+    }
+
+    /// per-function summary of all modules, ordered by module and address
+    pub fn functions(&self) -> Vec<FunctionCoverage> {
+        let mut functions = vec![];
+        for module in &self.modules {
+            for (name, range) in &module.functions {
+                let blocks = self
+                    .blocks
+                    .range(range.clone())
+                    .map(|(&start, block)| start..(start + block.size).min(range.end))
+                    .collect::<Vec<_>>();
+                // blocks can overlap when jumping into the middle of another
+                let mut covered = 0;
+                let mut end = range.start;
+                for block in &blocks {
+                    if block.end > end {
+                        covered += block.end - block.start.max(end);
+                        end = block.end;
+                    }
+                }
+                functions.push(FunctionCoverage {
+                    module: module.name.clone(),
+                    name: name.clone(),
+                    range: range.clone(),
+                    hits: self.blocks.get(&range.start).map_or(0, |block| block.hits),
+                    blocks: blocks.len(),
+                    covered,
+                });
+            }
+        }
+        functions
+    }
+
+    /// writes the blocks of all modules in drcov format (version 2), which is
+    /// read by Lighthouse, Cutter and Binary Ninja
+    pub fn write_drcov(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "DRCOV VERSION: 2")?;
+        writeln!(writer, "DRCOV FLAVOR: drcov")?;
+        writeln!(
+            writer,
+            "Module Table: version 2, count {}",
+            self.modules.len()
+        )?;
+        writeln!(
+            writer,
+            "Columns: id, base, end, entry, checksum, timestamp, path"
+        )?;
+        for (id, module) in self.modules.iter().enumerate() {
+            writeln!(
+                writer,
+                "{:3}, {:#018x}, {:#018x}, {:#018x}, {:#010x}, {:#010x}, {}",
+                id,
+                module.base,
+                module.base + module.size,
+                0,
+                0,
+                0,
+                module.name
+            )?;
+        }
+
+        // bb_entry_t: start relative to the module, size and module id
+        let mut entries = vec![];
+        for (id, module) in self.modules.iter().enumerate() {
+            for (&start, block) in self.blocks.range(module.base..module.base + module.size) {
+                entries.extend(((start - module.base) as u32).to_le_bytes());
+                entries.extend((block.size.min(u16::MAX as u64) as u16).to_le_bytes());
+                entries.extend((id as u16).to_le_bytes());
+            }
+        }
+        writeln!(writer, "BB Table: {} bbs", entries.len() / 8)?;
+        writer.write_all(&entries)?;
+        Ok(())
+    }
+
+    /// writes the per-function summary as lcov tracefile, there are no
+    /// source lines so each module is a source file and functions and blocks
+    /// are identified by their offset in the module
+    pub fn write_lcov(&self, mut writer: impl Write) -> Result<()> {
+        let functions = self.functions();
+        for module in &self.modules {
+            writeln!(writer, "TN:")?;
+            writeln!(writer, "SF:{}", module.name)?;
+            let module_functions = functions
+                .iter()
+                .filter(|function| function.module == module.name)
+                .collect::<Vec<_>>();
+            for function in &module_functions {
+                writeln!(
+                    writer,
+                    "FN:{},{}",
+                    function.range.start - module.base,
+                    function.name
+                )?;
+            }
+            for function in &module_functions {
+                writeln!(writer, "FNDA:{},{}", function.hits, function.name)?;
+            }
+            writeln!(writer, "FNF:{}", module_functions.len())?;
+            writeln!(
+                writer,
+                "FNH:{}",
+                module_functions
+                    .iter()
+                    .filter(|function| function.blocks != 0)
+                    .count()
+            )?;
+            let blocks = self
+                .blocks
+                .range(module.base..module.base + module.size)
+                .collect::<Vec<_>>();
+            for (&start, block) in &blocks {
+                writeln!(writer, "DA:{},{}", start - module.base, block.hits)?;
+            }
+            writeln!(writer, "LF:{}", blocks.len())?;
+            writeln!(writer, "LH:{}", blocks.len())?;
+            writeln!(writer, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// module at 0x1000 with two functions and blocks in both of them, the
+    /// blocks of the first one overlap
+    fn coverage() -> Coverage {
+        let mut coverage = Coverage::new();
+        coverage.modules.push(CoverageModule {
+            name: "test.exe".to_owned(),
+            base: 0x1000,
+            size: 0x1000,
+            functions: vec![
+                ("first".to_owned(), 0x1100..0x1140),
+                ("second".to_owned(), 0x1200..0x1210),
+            ],
+        });
+        coverage.add_block(0x1100, 0x10);
+        coverage.add_block(0x1100, 0x10);
+        coverage.add_block(0x1108, 0x10);
+        coverage.add_block(0x1120, 0x8);
+        // outside of the module
+        coverage.add_block(0x3000, 0x4);
+        coverage
+    }
+
+    #[test]
+    fn blocks() {
+        let mut coverage = coverage();
+        coverage.add_block(0x1120, 0x4);
+        coverage.add_block(0x1120, 0xC);
+        assert_eq!(coverage.blocks().len(), 4);
+        assert_eq!(
+            coverage.blocks()[&0x1100],
+            Block {
+                size: 0x10,
+                hits: 2
+            }
+        );
+        // the largest size is kept
+        assert_eq!(coverage.blocks()[&0x1120], Block { size: 0xC, hits: 3 });
+    }
+
+    #[test]
+    fn difference() {
+        let mut other = Coverage::new();
+        other.add_block(0x1100, 0x10);
+        other.add_block(0x3000, 0x4);
+        let difference = coverage().difference(&other);
+        assert_eq!(
+            difference.blocks().keys().copied().collect::<Vec<_>>(),
+            [0x1108, 0x1120]
+        );
+        assert_eq!(difference.modules().len(), 1);
+    }
+
+    #[test]
+    fn functions() {
+        let functions = coverage().functions();
+        assert_eq!(functions.len(), 2);
+        assert_eq!(functions[0].name, "first");
+        assert_eq!(functions[0].hits, 2);
+        assert_eq!(functions[0].blocks, 3);
+        // 0x1100..0x1118 and 0x1120..0x1128
+        assert_eq!(functions[0].covered, 0x20);
+        assert_eq!(functions[1].hits, 0);
+        assert_eq!(functions[1].blocks, 0);
+        assert_eq!(functions[1].covered, 0);
+    }
+
+    #[test]
+    fn drcov() {
+        let mut data = vec![];
+        coverage().write_drcov(&mut data).unwrap();
+        let header = "DRCOV VERSION: 2\n\
+            DRCOV FLAVOR: drcov\n\
+            Module Table: version 2, count 1\n\
+            Columns: id, base, end, entry, checksum, timestamp, path\n  \
+            0, 0x0000000000001000, 0x0000000000002000, 0x0000000000000000, 0x00000000, \
+            0x00000000, test.exe\n\
+            BB Table: 3 bbs\n";
+        assert_eq!(&data[..header.len()], header.as_bytes());
+        let entries = &data[header.len()..];
+        assert_eq!(entries.len(), 3 * 8);
+        assert_eq!(entries[..8], [0x00, 0x01, 0, 0, 0x10, 0, 0, 0]);
+        assert_eq!(entries[16..], [0x20, 0x01, 0, 0, 0x08, 0, 0, 0]);
+    }
+
+    #[test]
+    fn lcov() {
+        let mut data = vec![];
+        coverage().write_lcov(&mut data).unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "TN:\nSF:test.exe\nFN:256,first\nFN:512,second\nFNDA:2,first\nFNDA:0,second\n\
+             FNF:2\nFNH:1\nDA:256,2\nDA:264,1\nDA:288,1\nLF:3\nLH:3\nend_of_record\n"
+        );
+    }
+}
@@ -0,0 +1,503 @@
+use std::{collections::BTreeMap, io::Write};
+
+use iced_x86::{Decoder, DecoderError, DecoderOptions, FlowControl, Instruction};
+use thiserror::Error;
+
+pub use self::{
+    cpu::*, elf::*, hook::*, linux::*, loader::*, memory::*, peb::*, seh::*, snapshot::*, stub::*,
+    symbolic::*, taint::*, win32::*,
+};
+use crate::{
+    call::CallingConvention,
+    coverage::Coverage,
+    memory::{RegionEntry, RegionKind},
+    module::Module,
+    trace::{self, MemoryAccess, Registers, TraceWriter},
+};
+
+mod cpu;
+mod elf;
+mod execute;
+mod hook;
+mod linux;
+mod loader;
+mod memory;
+mod peb;
+mod seh;
+mod snapshot;
+mod sse;
+mod stub;
+mod symbolic;
+mod taint;
+mod unpack;
+mod win32;
+
+/// kind of memory access
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// exception raised by the guest, faults leave rip at the faulting
+/// instruction, traps (breakpoint, interrupt, syscall) after it
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Exception {
+    #[error("Page fault at {address:#x} ({access:?})")]
+    PageFault { address: u64, access: Access },
+    #[error("Invalid opcode")]
+    InvalidOpcode,
+    #[error("Divide error")]
+    DivideError,
+    #[error("General protection fault")]
+    GeneralProtection,
+    #[error("Breakpoint")]
+    Breakpoint,
+    #[error("Interrupt {0:#x}")]
+    Interrupt(u8),
+    #[error("System call")]
+    Syscall,
+    #[error("Stub without handler at {0:#x}")]
+    Stub(u64),
+    /// raised when there is no free address range for an allocation
+    #[error("Out of memory")]
+    OutOfMemory,
+    /// raised when structures in guest memory are inconsistent (e.g. a
+    /// circular loader list)
+    #[error("Corrupted {0}")]
+    Corrupted(&'static str),
+    /// raised by handlers when the guest terminates itself
+    #[error("Exited with code {0:#x}")]
+    Exit(u32),
+    /// raised when a hook stops emulation
+    #[error("Stopped by a hook")]
+    Stopped,
+}
+
+/// address returned to by functions invoked with [Emulator::call], it is
+/// never mapped
+pub const RETURN_ADDRESS: u64 = 0xFFFF_FFFF_FFFF_F000;
+
+/// user-mode x86-64 interpreter
+#[derive(Clone, Default)]
+pub struct Emulator {
+    pub cpu: Cpu,
+    pub memory: PagedMemory,
+    pub win32: Win32,
+    pub linux: Linux,
+    /// byte-level taint tracking, None if disabled
+    pub taint: Option<Taint>,
+    /// concolic execution, None if disabled
+    pub symbolic: Option<Symbolic>,
+
+    stubs: BTreeMap<u64, Stub>,
+    instruction_count: u64,
+    hooks: Hooks,
+    /// accesses of the current instruction while tracing or hooked
+    accesses: Option<Vec<MemoryAccess>>,
+}
+
+impl Emulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// number of instructions retired so far
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    /// decodes the instruction at rip
+    pub fn decode(&self) -> Result<Instruction, Exception> {
+        let rip = self.cpu.rip;
+        let mut data = [0; 15];
+        let length = self.memory.fetch(rip, &mut data);
+        if length == 0 {
+            return Err(Exception::PageFault {
+                address: rip,
+                access: Access::Execute,
+            });
+        }
+        let mut decoder = Decoder::with_ip(64, &data[..length], rip, DecoderOptions::NONE);
+        let instruction = decoder.decode();
+        match decoder.last_error() {
+            DecoderError::None => Ok(instruction),
+            // the instruction continues on a page which can't be executed
+            DecoderError::NoMoreBytes if length < data.len() => Err(Exception::PageFault {
+                address: rip + length as u64,
+                access: Access::Execute,
+            }),
+            _ => Err(Exception::InvalidOpcode),
+        }
+    }
+
+    /// executes a single instruction, hooks can stop before or after it
+    /// (Exception::Stopped), faults are dispatched to the exception handlers
+    /// of the guest if enabled (see [Win32::dispatch_exceptions])
+    pub fn step(&mut self) -> Result<(), Exception> {
+        let result = if self.symbolic.is_some() {
+            self.step_symbolic()
+        } else if self.taint.is_some() {
+            self.step_tainted()
+        } else {
+            self.step_hooked()
+        };
+        match result {
+            Err(exception) if self.win32.dispatch_exceptions => self.raise(exception),
+            result => result,
+        }
+    }
+
+    fn step_unhooked(&mut self) -> Result<(), Exception> {
+        if self.stubs.contains_key(&self.cpu.rip) {
+            self.invoke_stub(self.cpu.rip)?;
+            self.instruction_count += 1;
+            return Ok(());
+        }
+
+        let instruction = self.decode()?;
+        self.cpu.rip = instruction.next_ip();
+        match self.execute(&instruction) {
+            Ok(()) => {
+                self.instruction_count += 1;
+                Ok(())
+            }
+            Err(exception) => {
+                match exception {
+                    Exception::Breakpoint | Exception::Interrupt(_) | Exception::Syscall => {
+                        self.instruction_count += 1
+                    }
+                    _ => self.cpu.rip = instruction.ip(),
+                }
+                Err(exception)
+            }
+        }
+    }
+
+    /// executes until an exception is raised or the limit of instructions is
+    /// reached, returns None in the latter case
+    pub fn run(&mut self, limit: Option<u64>) -> Option<Exception> {
+        let mut count = 0;
+        while limit.is_none_or(|limit| count < limit) {
+            if let Err(exception) = self.step() {
+                return Some(exception);
+            }
+            count += 1;
+        }
+        None
+    }
+
+    /// like [Emulator::run] but records every retired instruction, accesses
+    /// of stub handlers are not recorded
+    pub fn run_traced(
+        &mut self,
+        limit: Option<u64>,
+        trace: &mut TraceWriter<impl Write>,
+    ) -> crate::Result<Option<Exception>> {
+        let mut count = 0;
+        while limit.is_none_or(|limit| count < limit) {
+            let registers = self.registers();
+            let instruction_count = self.instruction_count;
+            self.accesses = Some(vec![]);
+            let result = self.step();
+            let accesses = self.accesses.take().unwrap();
+            // faults don't retire the instruction
+            if self.instruction_count != instruction_count {
+                trace.record(THREAD_ID as u32, &registers, &accesses)?;
+            }
+            if let Err(exception) = result {
+                return Ok(Some(exception));
+            }
+            count += 1;
+        }
+        Ok(None)
+    }
+
+    /// like [Emulator::run] but records the executed blocks, a block ends at
+    /// any instruction which isn't followed by the next one, stubs are not
+    /// recorded
+    pub fn run_covered(
+        &mut self,
+        limit: Option<u64>,
+        coverage: &mut Coverage,
+    ) -> Option<Exception> {
+        // start and end of the current block
+        let mut block: Option<(u64, u64)> = None;
+        let mut count = 0;
+        let exception = loop {
+            if limit.is_some_and(|limit| count >= limit) {
+                break None;
+            }
+            let rip = self.cpu.rip;
+            if let Some((start, end)) = block.filter(|&(_, end)| end != rip) {
+                coverage.add_block(start, end - start);
+                block = None;
+            }
+            let instruction = if self.stubs.contains_key(&rip) {
+                None
+            } else {
+                self.decode().ok()
+            };
+            let instruction_count = self.instruction_count;
+            let result = self.step();
+            if self.instruction_count != instruction_count {
+                let start = block.map_or(rip, |(start, _)| start);
+                match instruction {
+                    Some(instruction) if instruction.flow_control() == FlowControl::Next => {
+                        block = Some((start, instruction.next_ip()))
+                    }
+                    Some(instruction) => {
+                        coverage.add_block(start, instruction.next_ip() - start);
+                        block = None;
+                    }
+                    None => {
+                        if let Some((start, end)) = block.take() {
+                            coverage.add_block(start, end - start);
+                        }
+                    }
+                }
+            }
+            if let Err(exception) = result {
+                break Some(exception);
+            }
+            count += 1;
+        };
+        if let Some((start, end)) = block {
+            coverage.add_block(start, end - start);
+        }
+        exception
+    }
+
+    /// registers in the order of the trace format
+    pub fn registers(&self) -> Registers {
+        let mut registers = [0; trace::REGISTER_COUNT];
+        registers[..16].copy_from_slice(&self.cpu.gpr);
+        registers[trace::RIP] = self.cpu.rip;
+        registers[trace::RFLAGS] = self.cpu.rflags;
+        registers[trace::FS_BASE] = self.cpu.fs_base;
+        registers[trace::GS_BASE] = self.cpu.gs_base;
+        registers
+    }
+
+    /// calls a function with the specified arguments on the current stack and
+    /// returns rax, any other exception is passed through
+    pub fn call(
+        &mut self,
+        function: u64,
+        convention: CallingConvention,
+        arguments: &[u64],
+    ) -> Result<u64, Exception> {
+        let (registers, shadow_space): (&[usize], u64) = match convention {
+            CallingConvention::Win64 => (&[RCX, RDX, R8, R9], 32),
+            CallingConvention::SysV => (&[RDI, RSI, RDX, RCX, R8, R9], 0),
+            CallingConvention::Cdecl | CallingConvention::Stdcall => {
+                return Err(Exception::GeneralProtection)
+            }
+        };
+        let stack_arguments = &arguments[arguments.len().min(registers.len())..];
+
+        // rsp + 8 has to be aligned to 16 on entry, the stack wraps around like
+        // on hardware and an invalid rsp faults on the writes below
+        let mut rsp = self.cpu.gpr[RSP]
+            .wrapping_sub(shadow_space)
+            .wrapping_sub(stack_arguments.len() as u64 * 8);
+        rsp &= !0xF;
+        for (i, &argument) in stack_arguments.iter().enumerate() {
+            self.memory.write(
+                rsp.wrapping_add(shadow_space + i as u64 * 8),
+                &argument.to_le_bytes(),
+            )?;
+        }
+        rsp = rsp.wrapping_sub(8);
+        self.memory.write(rsp, &RETURN_ADDRESS.to_le_bytes())?;
+        for (&register, &argument) in registers.iter().zip(arguments) {
+            self.cpu.gpr[register] = argument;
+        }
+        let previous_rsp = self.cpu.gpr[RSP];
+        self.cpu.gpr[RSP] = rsp;
+        self.cpu.rip = function;
+
+        loop {
+            match self.step() {
+                Ok(()) => {}
+                Err(Exception::PageFault {
+                    address: RETURN_ADDRESS,
+                    access: Access::Execute,
+                }) => {
+                    self.cpu.gpr[RSP] = previous_rsp;
+                    return Ok(self.cpu.gpr[RAX]);
+                }
+                Err(exception) => return Err(exception),
+            }
+        }
+    }
+
+    /// maps a stack of the specified size and points rsp to its top
+    pub fn map_stack(&mut self, address: u64, size: u64) {
+        self.memory.map(address, size, Protection::READ_WRITE);
+        self.cpu.gpr[RSP] = address + size;
+    }
+
+    /// mapped regions, the ones of modules known to the loader are split at
+    /// their sections, stubs and the program break are labeled as well
+    pub fn regions(&self) -> crate::Result<Vec<RegionEntry>> {
+        let regions = self
+            .memory
+            .regions()
+            .into_iter()
+            .map(|(range, protection)| RegionEntry {
+                base: range.start,
+                size: range.end - range.start,
+                read: protection.read,
+                write: protection.write,
+                execute: protection.execute,
+                kind: RegionKind::Private,
+                owner: if self.stubs.contains_key(&range.start) {
+                    "stubs".to_owned()
+                } else if self.linux.brk.contains(&range.start) {
+                    "[heap]".to_owned()
+                } else {
+                    String::new()
+                },
+            })
+            .collect();
+
+        // images loaded without process environment have no module list
+        let mut modules = vec![];
+        for (base, path) in self.modules().unwrap_or_default() {
+            let name = win32::file_name(&path);
+            if let Ok(module) = Module::from_memory(&self.memory, name, base as usize) {
+                modules.push(module);
+            }
+        }
+        crate::memory::label_regions(regions, &modules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // mov ecx, 3 / mov [rsp], rcx / dec ecx / jnz -8 / int3
+    pub(super) const LOOP: &[u8] = &[
+        0xB9, 0x03, 0x00, 0x00, 0x00, 0x48, 0x89, 0x0C, 0x24, 0xFF, 0xC9, 0x75, 0xF8, 0xCC,
+    ];
+
+    /// emulator executing the code at 0x1000 with a stack
+    pub(super) fn emulator(code: &[u8]) -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator
+            .memory
+            .map(0x1000, PAGE_SIZE, Protection::READ_WRITE_EXECUTE);
+        emulator.memory.write(0x1000, code).unwrap();
+        emulator.map_stack(0x10000, 0x10000);
+        // room for pops
+        emulator.cpu.gpr[RSP] -= 0x100;
+        emulator.cpu.rip = 0x1000;
+        emulator
+    }
+
+    #[test]
+    fn privileged_registers() {
+        // mov ds, ax / pop fs / mov rax, cr0 / mov rax, dr7
+        for code in [
+            &[0x8E, 0xD8][..],
+            &[0x0F, 0xA1],
+            &[0x0F, 0x20, 0xC0],
+            &[0x0F, 0x21, 0xF8],
+        ] {
+            let mut emulator = emulator(code);
+            assert_eq!(emulator.step(), Err(Exception::GeneralProtection));
+            assert_eq!(emulator.cpu.rip, 0x1000);
+            assert_eq!(emulator.cpu.gpr[RSP], 0x1FF00);
+        }
+    }
+
+    #[test]
+    fn regions() {
+        let mut emulator = emulator(LOOP);
+        let stub = emulator.add_stub("kernel32.dll", "Sleep");
+        let regions = emulator.regions().unwrap();
+        assert_eq!(regions.len(), 3);
+        assert_eq!(
+            regions[0],
+            RegionEntry {
+                base: 0x1000,
+                size: PAGE_SIZE,
+                read: true,
+                write: true,
+                execute: true,
+                kind: RegionKind::Private,
+                owner: String::new(),
+            }
+        );
+        assert_eq!((regions[1].base, regions[1].size), (0x10000, 0x10000));
+        assert!(regions[1].write && !regions[1].execute);
+        assert_eq!(regions[2].base, stub);
+        assert_eq!(regions[2].owner, "stubs");
+        assert!(regions[2].read && !regions[2].write);
+    }
+
+    #[test]
+    fn run_traced() {
+        let mut emulator = emulator(LOOP);
+        let rsp = emulator.cpu.gpr[RSP];
+        let mut writer = TraceWriter::new(vec![]).unwrap();
+        let exception = emulator.run_traced(None, &mut writer).unwrap();
+        assert_eq!(exception, Some(Exception::Breakpoint));
+        assert_eq!(writer.count(), 11);
+
+        let trace = trace::Trace::read(writer.finish().unwrap().as_slice()).unwrap();
+        assert_eq!(trace.len(), 11);
+        assert_eq!(trace.executions(0x1005), &[1, 4, 7]);
+        assert_eq!(trace.executions(0x100D), &[10]);
+        // registers before the first and the last decrement
+        assert_eq!(trace.registers(2)[RCX], 3);
+        assert_eq!(trace.registers(8)[RCX], 1);
+        assert_eq!(trace.registers(8)[trace::RIP], 0x1009);
+        assert_eq!(trace.accesses(rsp..rsp + 8).count(), 3);
+        assert_eq!(trace.last_write(rsp, 11), Some(7));
+        assert_eq!(trace.value(rsp, 11), Some(1));
+    }
+
+    #[test]
+    fn run_covered() {
+        let mut emulator = emulator(LOOP);
+        let mut coverage = Coverage::new();
+        let exception = emulator.run_covered(None, &mut coverage);
+        assert_eq!(exception, Some(Exception::Breakpoint));
+        assert_eq!(
+            coverage
+                .blocks()
+                .iter()
+                .map(|(&start, block)| (start, block.size, block.hits))
+                .collect::<Vec<_>>(),
+            [(0x1000, 0xD, 1), (0x1005, 0x8, 2), (0x100D, 0x1, 1)]
+        );
+    }
+
+    #[test]
+    fn call() {
+        // lea rax, [rcx + rdx] / ret
+        let mut emulator = emulator(&[0x48, 0x8D, 0x04, 0x11, 0xC3]);
+        let rsp = emulator.cpu.gpr[RSP];
+        let result = emulator.call(0x1000, CallingConvention::Win64, &[1, 2]);
+        assert_eq!(result, Ok(3));
+        assert_eq!(emulator.cpu.gpr[RSP], rsp);
+    }
+
+    #[test]
+    fn call_without_stack() {
+        let mut emulator = emulator(&[0xC3]);
+        emulator.cpu.gpr[RSP] = 0;
+        let result = emulator.call(0x1000, CallingConvention::Win64, &[1, 2, 3, 4, 5, 6]);
+        assert!(matches!(
+            result,
+            Err(Exception::PageFault {
+                access: Access::Write,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,217 @@
+use iced_x86::Register;
+
+use super::Exception;
+
+// indices into Cpu::gpr, in encoding order
+pub const RAX: usize = 0;
+pub const RCX: usize = 1;
+pub const RDX: usize = 2;
+pub const RBX: usize = 3;
+pub const RSP: usize = 4;
+pub const RBP: usize = 5;
+pub const RSI: usize = 6;
+pub const RDI: usize = 7;
+pub const R8: usize = 8;
+pub const R9: usize = 9;
+pub const R10: usize = 10;
+pub const R11: usize = 11;
+pub const R12: usize = 12;
+pub const R13: usize = 13;
+pub const R14: usize = 14;
+pub const R15: usize = 15;
+
+// bits of Cpu::rflags
+pub const CF: u64 = 1 << 0;
+pub const PF: u64 = 1 << 2;
+pub const AF: u64 = 1 << 4;
+pub const ZF: u64 = 1 << 6;
+pub const SF: u64 = 1 << 7;
+pub const TF: u64 = 1 << 8;
+pub const IF: u64 = 1 << 9;
+pub const DF: u64 = 1 << 10;
+pub const OF: u64 = 1 << 11;
+
+/// flags which can be changed by popf in user-mode
+pub const USER_FLAGS: u64 = CF | PF | AF | ZF | SF | TF | DF | OF | (1 << 18) | (1 << 21);
+
+/// user-mode register state
+#[derive(Debug, Clone)]
+pub struct Cpu {
+    /// rax, rcx, rdx, rbx, rsp, rbp, rsi, rdi, r8-r15
+    pub gpr: [u64; 16],
+    pub rip: u64,
+    pub rflags: u64,
+    pub fs_base: u64,
+    pub gs_base: u64,
+    /// es, cs, ss, ds, fs and gs, only read by instructions as the bases are
+    /// used for addressing
+    pub selectors: [u16; 6],
+    pub xmm: [u128; 16],
+    pub mxcsr: u32,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self {
+            gpr: [0; 16],
+            rip: 0,
+            // bit 1 is reserved and always set
+            rflags: IF | 2,
+            fs_base: 0,
+            gs_base: 0,
+            // flat 64-bit code and stack segments, null data segments
+            selectors: [0, 0x33, 0x2B, 0, 0, 0],
+            xmm: [0; 16],
+            mxcsr: 0x1F80,
+        }
+    }
+}
+
+impl Cpu {
+    pub fn flag(&self, flag: u64) -> bool {
+        self.rflags & flag != 0
+    }
+
+    pub fn set_flag(&mut self, flag: u64, value: bool) {
+        if value {
+            self.rflags |= flag;
+        } else {
+            self.rflags &= !flag;
+        }
+    }
+
+    /// value of a general purpose register (any size), rip or a segment base,
+    /// control and debug registers are privileged
+    pub fn register(&self, register: Register) -> Result<u64, Exception> {
+        match register {
+            Register::RIP => Ok(self.rip),
+            Register::EIP => Ok(self.rip as u32 as u64),
+            Register::ES | Register::CS | Register::SS | Register::DS => Ok(0),
+            Register::FS => Ok(self.fs_base),
+            Register::GS => Ok(self.gs_base),
+            register if register.is_gpr() => Ok(self.gpr_value(register)),
+            register if register.is_cr() || register.is_dr() => Err(Exception::GeneralProtection),
+            _ => Err(Exception::InvalidOpcode),
+        }
+    }
+
+    /// selector of a segment register
+    pub fn selector(&self, register: Register) -> u64 {
+        self.selectors[register as usize - Register::ES as usize] as u64
+    }
+
+    /// sets a general purpose register, segment selectors aren't emulated and
+    /// control and debug registers are privileged
+    pub fn set_register(&mut self, register: Register, value: u64) -> Result<(), Exception> {
+        if register.is_gpr() {
+            self.set_gpr_value(register, value);
+            Ok(())
+        } else if register.is_segment_register() || register.is_cr() || register.is_dr() {
+            Err(Exception::GeneralProtection)
+        } else {
+            Err(Exception::InvalidOpcode)
+        }
+    }
+
+    /// value of a general purpose register (any size)
+    pub(super) fn gpr_value(&self, register: Register) -> u64 {
+        let gpr = self.gpr[gpr_index(register)];
+        match register {
+            Register::AH | Register::CH | Register::DH | Register::BH => (gpr >> 8) & 0xFF,
+            register => gpr & mask(register.size()),
+        }
+    }
+
+    /// sets a general purpose register (any size), writes to 32-bit registers
+    /// clear the upper half, writes to 8- and 16-bit registers keep the
+    /// remaining bits
+    pub(super) fn set_gpr_value(&mut self, register: Register, value: u64) {
+        let gpr = &mut self.gpr[gpr_index(register)];
+        match register {
+            Register::AH | Register::CH | Register::DH | Register::BH => {
+                *gpr = (*gpr & !0xFF00) | ((value & 0xFF) << 8)
+            }
+            register => match register.size() {
+                8 => *gpr = value,
+                4 => *gpr = value as u32 as u64,
+                size => *gpr = (*gpr & !mask(size)) | (value & mask(size)),
+            },
+        }
+    }
+
+    pub fn xmm(&self, register: Register) -> u128 {
+        self.xmm[register.number()]
+    }
+
+    pub fn set_xmm(&mut self, register: Register, value: u128) {
+        self.xmm[register.number()] = value;
+    }
+}
+
+/// index of the full register in Cpu::gpr, only valid for general purpose
+/// registers
+fn gpr_index(register: Register) -> usize {
+    register.full_register() as usize - Register::RAX as usize
+}
+
+/// mask of the lowest size bytes
+pub(crate) fn mask(size: usize) -> u64 {
+    if size >= 8 {
+        u64::MAX
+    } else {
+        (1 << (size * 8)) - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers() {
+        let mut cpu = Cpu::default();
+        cpu.set_register(Register::RAX, 0x1122_3344_5566_7788)
+            .unwrap();
+        cpu.set_register(Register::AH, 0xAA).unwrap();
+        assert_eq!(cpu.register(Register::RAX), Ok(0x1122_3344_5566_AA88));
+        cpu.set_register(Register::EAX, 0xFFFF_FFFF).unwrap();
+        assert_eq!(cpu.register(Register::RAX), Ok(0xFFFF_FFFF));
+        cpu.set_register(Register::R8W, 0x1234).unwrap();
+        assert_eq!(cpu.register(Register::R8L), Ok(0x34));
+        cpu.fs_base = 0x7000;
+        assert_eq!(cpu.register(Register::FS), Ok(0x7000));
+        assert_eq!(cpu.register(Register::DS), Ok(0));
+        assert_eq!(cpu.selector(Register::CS), 0x33);
+        assert_eq!(cpu.selector(Register::FS), 0);
+    }
+
+    #[test]
+    fn unsupported_registers() {
+        let mut cpu = Cpu::default();
+        assert_eq!(
+            cpu.register(Register::CR0),
+            Err(Exception::GeneralProtection)
+        );
+        assert_eq!(
+            cpu.register(Register::DR7),
+            Err(Exception::GeneralProtection)
+        );
+        assert_eq!(cpu.register(Register::ST0), Err(Exception::InvalidOpcode));
+        assert_eq!(
+            cpu.set_register(Register::DS, 0x2B),
+            Err(Exception::GeneralProtection)
+        );
+        assert_eq!(
+            cpu.set_register(Register::FS, 0),
+            Err(Exception::GeneralProtection)
+        );
+        assert_eq!(
+            cpu.set_register(Register::CR3, 0),
+            Err(Exception::GeneralProtection)
+        );
+        assert_eq!(
+            cpu.set_register(Register::RIP, 0),
+            Err(Exception::InvalidOpcode)
+        );
+    }
+}
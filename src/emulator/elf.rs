@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+
+use object::{
+    elf::{ET_DYN, PF_W, PF_X, PT_INTERP, PT_PHDR},
+    read::elf::{ElfFile64, FileHeader, ProgramHeader},
+    Endianness, Object, ObjectSegment, SegmentFlags,
+};
+
+use super::memory::pages;
+use crate::{emulator::*, Error, Result};
+
+/// load bias of position-independent executables
+pub const ELF_BASE: u64 = 0x5555_5555_4000;
+
+/// address above the initial stack
+pub const LINUX_STACK_TOP: u64 = 0x7FFF_FFFF_F000;
+
+/// size of the initial stack
+pub const LINUX_STACK_SIZE: u64 = 0x10_0000;
+
+// auxiliary vector entries
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_BASE: u64 = 7;
+const AT_FLAGS: u64 = 8;
+const AT_ENTRY: u64 = 9;
+const AT_UID: u64 = 11;
+const AT_EUID: u64 = 12;
+const AT_GID: u64 = 13;
+const AT_EGID: u64 = 14;
+const AT_PLATFORM: u64 = 15;
+const AT_HWCAP: u64 = 16;
+const AT_CLKTCK: u64 = 17;
+const AT_SECURE: u64 = 23;
+const AT_RANDOM: u64 = 25;
+const AT_EXECFN: u64 = 31;
+
+/// static ELF64 executable mapped into the emulator
+#[derive(Debug, Clone)]
+pub struct ElfImage {
+    /// load bias, 0 for non position-independent executables
+    pub base: u64,
+    /// end of the highest segment, the program break starts at the next page
+    pub end: u64,
+    pub entry_point: u64,
+    /// address of the mapped program headers, 0 if they are not mapped
+    pub program_headers: u64,
+    pub program_header_size: u64,
+    pub program_header_count: u64,
+}
+
+impl Emulator {
+    /// maps the loadable segments of a static ELF64 executable, dynamically
+    /// linked executables are not supported as there is no dynamic loader
+    pub fn load_elf(&mut self, data: &[u8]) -> Result<ElfImage> {
+        let file = ElfFile64::<Endianness>::parse(data)?;
+        let endian = file.endian();
+        let header = file.raw_header();
+        let program_headers = file.raw_segments();
+        if program_headers
+            .iter()
+            .any(|program_header| program_header.p_type(endian) == PT_INTERP)
+        {
+            return Err(Error::Unsupported("dynamically linked executable"));
+        }
+        let base = if header.e_type(endian) == ET_DYN {
+            ELF_BASE
+        } else {
+            0
+        };
+
+        // map writable first, segments can share pages
+        let mut end = 0;
+        for segment in file.segments() {
+            let address = base + segment.address();
+            let size = segment.size();
+            self.memory.map(address, size, Protection::READ_WRITE);
+            let segment_data = segment.data()?;
+            self.memory.write_raw(address, segment_data)?;
+            // zero the bss, the first page can contain data of another segment
+            self.memory.write_raw(
+                address + segment_data.len() as u64,
+                &vec![0; (size as usize).saturating_sub(segment_data.len())],
+            )?;
+            end = end.max(address + size);
+        }
+        // a page shared by segments gets the permissions of all of them
+        let mut protections = BTreeMap::new();
+        for segment in file.segments() {
+            let SegmentFlags::Elf { p_flags } = segment.flags() else {
+                unreachable!()
+            };
+            for page in pages(base + segment.address(), segment.size()) {
+                let protection = protections.entry(page).or_insert(Protection::READ);
+                protection.write |= p_flags & PF_W != 0;
+                protection.execute |= p_flags & PF_X != 0;
+            }
+        }
+        for (page, protection) in protections {
+            self.memory.protect(page, PAGE_SIZE, protection);
+        }
+
+        // the program headers are either described by PT_PHDR or part of the
+        // first segment
+        let program_header_offset = header.e_phoff(endian);
+        let program_headers_address = match program_headers
+            .iter()
+            .find(|program_header| program_header.p_type(endian) == PT_PHDR)
+        {
+            Some(program_header) => base + program_header.p_vaddr(endian),
+            None => file
+                .segments()
+                .find_map(|segment| {
+                    let (offset, size) = segment.file_range();
+                    (offset..offset + size)
+                        .contains(&program_header_offset)
+                        .then(|| base + segment.address() + program_header_offset - offset)
+                })
+                .unwrap_or(0),
+        };
+
+        Ok(ElfImage {
+            base,
+            end,
+            entry_point: base + file.entry(),
+            program_headers: program_headers_address,
+            program_header_size: header.e_phentsize(endian) as u64,
+            program_header_count: program_headers.len() as u64,
+        })
+    }
+
+    /// maps the initial stack with the arguments, environment (NAME=value)
+    /// and auxiliary vector, and points rip to the entry point
+    pub fn setup_linux(
+        &mut self,
+        image: &ElfImage,
+        arguments: &[&str],
+        environment: &[&str],
+    ) -> Result<()> {
+        let stack = LINUX_STACK_TOP - LINUX_STACK_SIZE;
+        self.map_stack(stack, LINUX_STACK_SIZE);
+        let executable = arguments.first().copied().unwrap_or_default();
+        self.linux.executable = self.linux.resolve(executable);
+        self.linux.brk = {
+            let brk = image.end.next_multiple_of(PAGE_SIZE);
+            brk..brk
+        };
+
+        // strings and the random bytes at the top
+        let mut rsp = LINUX_STACK_TOP;
+        let mut push_data = |emulator: &mut Self, data: &[u8]| -> Result<u64> {
+            rsp -= data.len() as u64;
+            emulator.memory.write(rsp, data)?;
+            Ok(rsp)
+        };
+        let executable_address = push_data(self, &c_string(executable))?;
+        let mut argument_addresses = vec![];
+        for argument in arguments {
+            argument_addresses.push(push_data(self, &c_string(argument))?);
+        }
+        let mut environment_addresses = vec![];
+        for variable in environment {
+            environment_addresses.push(push_data(self, &c_string(variable))?);
+        }
+        let platform = push_data(self, &c_string("x86_64"))?;
+        let random = self.linux.random_bytes(16);
+        let random = push_data(self, &random)?;
+
+        let auxiliary_vector = [
+            (AT_PHDR, image.program_headers),
+            (AT_PHENT, image.program_header_size),
+            (AT_PHNUM, image.program_header_count),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_BASE, 0),
+            (AT_FLAGS, 0),
+            (AT_ENTRY, image.entry_point),
+            (AT_UID, USER_ID),
+            (AT_EUID, USER_ID),
+            (AT_GID, USER_ID),
+            (AT_EGID, USER_ID),
+            (AT_PLATFORM, platform),
+            (AT_HWCAP, 0),
+            (AT_CLKTCK, 100),
+            (AT_SECURE, 0),
+            (AT_RANDOM, random),
+            (AT_EXECFN, executable_address),
+            (AT_NULL, 0),
+        ];
+        let mut words = vec![arguments.len() as u64];
+        words.extend(&argument_addresses);
+        words.push(0);
+        words.extend(&environment_addresses);
+        words.push(0);
+        words.extend(
+            auxiliary_vector
+                .iter()
+                .flat_map(|&(key, value)| [key, value]),
+        );
+
+        // rsp points to argc and is aligned to 16
+        let rsp = (rsp - words.len() as u64 * 8) & !0xF;
+        let data = words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>();
+        self.memory.write(rsp, &data)?;
+        self.cpu.gpr[RSP] = rsp;
+        // no function to register with atexit
+        self.cpu.gpr[RDX] = 0;
+        self.cpu.rip = image.entry_point;
+        Ok(())
+    }
+}
+
+fn c_string(string: &str) -> Vec<u8> {
+    let mut data = string.as_bytes().to_vec();
+    data.push(0);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use object::elf::PF_R;
+
+    use super::*;
+
+    /// ELF64 executable with a code segment and a data segment starting on
+    /// the last page of the code
+    fn executable() -> Vec<u8> {
+        let mut data = vec![0; 0x110];
+        data[..8].copy_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0]);
+        // ET_EXEC, EM_X86_64, version, entry point, program header offset
+        data[0x10..0x12].copy_from_slice(&2u16.to_le_bytes());
+        data[0x12..0x14].copy_from_slice(&0x3Eu16.to_le_bytes());
+        data[0x14..0x18].copy_from_slice(&1u32.to_le_bytes());
+        data[0x18..0x20].copy_from_slice(&0x40_0080u64.to_le_bytes());
+        data[0x20..0x28].copy_from_slice(&0x40u64.to_le_bytes());
+        // header size, program header size and count, section header size
+        data[0x34..0x36].copy_from_slice(&0x40u16.to_le_bytes());
+        data[0x36..0x38].copy_from_slice(&0x38u16.to_le_bytes());
+        data[0x38..0x3A].copy_from_slice(&2u16.to_le_bytes());
+        data[0x3A..0x3C].copy_from_slice(&0x40u16.to_le_bytes());
+
+        // PT_LOAD with flags, offset, address, file size and memory size
+        let segments: [(u32, u64, u64, u64, u64); 2] = [
+            (PF_R | PF_X, 0, 0x40_0000, 0x100, 0x100),
+            (PF_R | PF_W, 0x100, 0x40_0100, 0x10, 0x2000),
+        ];
+        for (i, (flags, offset, address, file_size, memory_size)) in
+            segments.into_iter().enumerate()
+        {
+            let header = &mut data[0x40 + i * 0x38..0x40 + (i + 1) * 0x38];
+            header[0x00..0x04].copy_from_slice(&1u32.to_le_bytes());
+            header[0x04..0x08].copy_from_slice(&flags.to_le_bytes());
+            header[0x08..0x10].copy_from_slice(&offset.to_le_bytes());
+            header[0x10..0x18].copy_from_slice(&address.to_le_bytes());
+            header[0x18..0x20].copy_from_slice(&address.to_le_bytes());
+            header[0x20..0x28].copy_from_slice(&file_size.to_le_bytes());
+            header[0x28..0x30].copy_from_slice(&memory_size.to_le_bytes());
+            header[0x30..0x38].copy_from_slice(&PAGE_SIZE.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn shared_pages() {
+        let mut emulator = Emulator::new();
+        let image = emulator.load_elf(&executable()).unwrap();
+        assert_eq!(image.base, 0);
+        assert_eq!(image.entry_point, 0x40_0080);
+        assert_eq!(image.end, 0x40_2100);
+        assert_eq!(image.program_headers, 0x40_0040);
+
+        // the code stays executable although the data segment is mapped last
+        assert_eq!(
+            emulator.memory.protection(0x40_0000),
+            Some(Protection::READ_WRITE_EXECUTE)
+        );
+        assert_eq!(
+            emulator.memory.protection(0x40_1000),
+            Some(Protection::READ_WRITE)
+        );
+        assert_eq!(
+            emulator.memory.protection(0x40_2000),
+            Some(Protection::READ_WRITE)
+        );
+        assert_eq!(emulator.memory.protection(0x40_3000), None);
+
+        // the bss is zeroed
+        let mut bss = [0xFF; 0x10];
+        emulator.memory.read_raw(0x40_0110, &mut bss).unwrap();
+        assert_eq!(bss, [0; 0x10]);
+    }
+}
@@ -0,0 +1,1267 @@
+use iced_x86::{ConditionCode, Instruction, Mnemonic, OpKind, Register};
+
+use crate::{
+    emulator::{cpu::mask, *},
+    trace::MemoryAccess,
+};
+
+impl Emulator {
+    pub(super) fn execute(&mut self, ins: &Instruction) -> Result<(), Exception> {
+        use Mnemonic::*;
+
+        let size = operand_size(ins, 0);
+        match ins.mnemonic() {
+            Nop | Endbr64 | Endbr32 | Pause | Lfence | Mfence | Sfence | Prefetchnta
+            | Prefetcht0 | Prefetcht1 | Prefetcht2 | Prefetchw => {}
+
+            // data transfer
+            Mov | Movzx | Movnti => {
+                let value = self.read_operand(ins, 1)?;
+                self.write_operand(ins, 0, value)?;
+            }
+            Movsx | Movsxd => {
+                let value = sign_extend(self.read_operand(ins, 1)?, operand_size(ins, 1));
+                self.write_operand(ins, 0, value)?;
+            }
+            Lea => {
+                let address = self.address(ins, 1)?;
+                self.write_operand(ins, 0, address)?;
+            }
+            Xchg => {
+                let a = self.read_operand(ins, 0)?;
+                let b = self.read_operand(ins, 1)?;
+                self.write_operand(ins, 0, b)?;
+                self.write_operand(ins, 1, a)?;
+            }
+            Push => {
+                let value = self.read_operand(ins, 0)?;
+                self.push(value, -ins.stack_pointer_increment() as usize)?;
+            }
+            Pop => {
+                let rsp = self.cpu.gpr[RSP];
+                let value = self.pop(ins.stack_pointer_increment() as usize)?;
+                // faults leave rsp unchanged (e.g. pop fs)
+                if let Err(exception) = self.write_operand(ins, 0, value) {
+                    self.cpu.gpr[RSP] = rsp;
+                    return Err(exception);
+                }
+            }
+            Cbw => self.set_accumulator(2, sign_extend(self.cpu.gpr[RAX], 1)),
+            Cwde => self.set_accumulator(4, sign_extend(self.cpu.gpr[RAX], 2)),
+            Cdqe => self.set_accumulator(8, sign_extend(self.cpu.gpr[RAX], 4)),
+            Cwd | Cdq | Cqo => {
+                let size = match ins.mnemonic() {
+                    Cwd => 2,
+                    Cdq => 4,
+                    _ => 8,
+                };
+                let negative = sign_extend(self.cpu.gpr[RAX], size) as i64 >> 63;
+                self.cpu.set_gpr_value(register(RDX, size), negative as u64);
+            }
+
+            // arithmetic
+            Add | Adc => {
+                let carry = ins.mnemonic() == Adc && self.cpu.flag(CF);
+                let a = self.read_operand(ins, 0)?;
+                let b = self.read_operand(ins, 1)?;
+                let result = self.add(a, b, carry, size);
+                self.write_operand(ins, 0, result)?;
+            }
+            Sub | Sbb | Cmp => {
+                let borrow = ins.mnemonic() == Sbb && self.cpu.flag(CF);
+                let a = self.read_operand(ins, 0)?;
+                let b = self.read_operand(ins, 1)?;
+                let result = self.sub(a, b, borrow, size);
+                if ins.mnemonic() != Cmp {
+                    self.write_operand(ins, 0, result)?;
+                }
+            }
+            Inc | Dec => {
+                // the carry flag is not affected
+                let carry = self.cpu.flag(CF);
+                let a = self.read_operand(ins, 0)?;
+                let result = if ins.mnemonic() == Inc {
+                    self.add(a, 1, false, size)
+                } else {
+                    self.sub(a, 1, false, size)
+                };
+                self.cpu.set_flag(CF, carry);
+                self.write_operand(ins, 0, result)?;
+            }
+            Neg => {
+                let a = self.read_operand(ins, 0)?;
+                let result = self.sub(0, a, false, size);
+                self.write_operand(ins, 0, result)?;
+            }
+            Mul => {
+                let a = (self.cpu.gpr[RAX] & mask(size)) as u128;
+                let b = (self.read_operand(ins, 0)? & mask(size)) as u128;
+                let result = a * b;
+                let high = (result >> (size * 8)) as u64 & mask(size);
+                self.set_double(size, result as u64, high);
+                self.cpu.set_flag(CF, high != 0);
+                self.cpu.set_flag(OF, high != 0);
+            }
+            Imul => {
+                let (a, b) = match ins.op_count() {
+                    1 => (self.cpu.gpr[RAX], self.read_operand(ins, 0)?),
+                    2 => (self.read_operand(ins, 0)?, self.read_operand(ins, 1)?),
+                    _ => (self.read_operand(ins, 1)?, self.read_operand(ins, 2)?),
+                };
+                let result =
+                    sign_extend(a, size) as i64 as i128 * sign_extend(b, size) as i64 as i128;
+                let low = result as u64 & mask(size);
+                let overflow = result != sign_extend(low, size) as i64 as i128;
+                if ins.op_count() == 1 {
+                    self.set_double(size, low, (result >> (size * 8)) as u64 & mask(size));
+                } else {
+                    self.write_operand(ins, 0, low)?;
+                }
+                self.cpu.set_flag(CF, overflow);
+                self.cpu.set_flag(OF, overflow);
+            }
+            Div => {
+                let divisor = (self.read_operand(ins, 0)? & mask(size)) as u128;
+                if divisor == 0 {
+                    return Err(Exception::DivideError);
+                }
+                let dividend = self.double(size);
+                let quotient = dividend / divisor;
+                if quotient > mask(size) as u128 {
+                    return Err(Exception::DivideError);
+                }
+                self.set_quotient(size, quotient as u64, (dividend % divisor) as u64);
+            }
+            Idiv => {
+                let bits = 128 - size as u32 * 16;
+                let divisor = sign_extend(self.read_operand(ins, 0)?, size) as i64 as i128;
+                let dividend = ((self.double(size) << bits) as i128) >> bits;
+                let (Some(quotient), Some(remainder)) =
+                    (dividend.checked_div(divisor), dividend.checked_rem(divisor))
+                else {
+                    return Err(Exception::DivideError);
+                };
+                if quotient != sign_extend(quotient as u64 & mask(size), size) as i64 as i128 {
+                    return Err(Exception::DivideError);
+                }
+                self.set_quotient(size, quotient as u64, remainder as u64);
+            }
+
+            // logic
+            And | Or | Xor | Test => {
+                let a = self.read_operand(ins, 0)?;
+                let b = self.read_operand(ins, 1)?;
+                let result = match ins.mnemonic() {
+                    Or => a | b,
+                    Xor => a ^ b,
+                    _ => a & b,
+                };
+                self.set_logic_flags(result, size);
+                if ins.mnemonic() != Test {
+                    self.write_operand(ins, 0, result)?;
+                }
+            }
+            Not => {
+                let a = self.read_operand(ins, 0)?;
+                self.write_operand(ins, 0, !a)?;
+            }
+            Shl | Sal | Shr | Sar | Rol | Ror | Rcl | Rcr => self.shift(ins, size)?,
+            Shld | Shrd => {
+                let bits = size as u32 * 8;
+                let count =
+                    (self.read_operand(ins, 2)? & if size == 8 { 0x3F } else { 0x1F }) as u32;
+                if count == 0 {
+                    return self.zero_extend(ins);
+                }
+                let a = self.read_operand(ins, 0)? & mask(size);
+                let b = (self.read_operand(ins, 1)? & mask(size)) as u128;
+                let (result, carry) = if ins.mnemonic() == Shld {
+                    let combined = ((a as u128) << bits) | b;
+                    (
+                        (combined << count >> bits) as u64,
+                        a >> (bits - count.min(bits)) & 1,
+                    )
+                } else {
+                    let combined = (b << bits) | a as u128;
+                    ((combined >> count) as u64, a >> (count - 1) & 1)
+                };
+                let result = result & mask(size);
+                self.set_result_flags(result, size);
+                self.cpu.set_flag(CF, carry != 0);
+                self.cpu.set_flag(OF, (result ^ a) >> (bits - 1) & 1 != 0);
+                self.write_operand(ins, 0, result)?;
+            }
+            Bt | Bts | Btr | Btc => {
+                let bits = size as u64 * 8;
+                let offset = self.read_operand(ins, 1)?;
+                let (address, bit) = if ins.op0_kind() == OpKind::Memory {
+                    let address = self.address(ins, 0)?;
+                    if ins.op1_kind() == OpKind::Register {
+                        // the bit offset can address memory outside of the operand
+                        let offset = sign_extend(offset, size) as i64;
+                        (
+                            Some(address.wrapping_add(
+                                (offset.div_euclid(bits as i64) * size as i64) as u64,
+                            )),
+                            offset.rem_euclid(bits as i64) as u64,
+                        )
+                    } else {
+                        (Some(address), offset % bits)
+                    }
+                } else {
+                    (None, offset % bits)
+                };
+                let value = match address {
+                    Some(address) => self.read_memory(address, size)?,
+                    None => self.read_operand(ins, 0)?,
+                };
+                self.cpu.set_flag(CF, value >> bit & 1 != 0);
+                let value = match ins.mnemonic() {
+                    Bts => value | 1 << bit,
+                    Btr => value & !(1 << bit),
+                    Btc => value ^ 1 << bit,
+                    _ => return Ok(()),
+                };
+                match address {
+                    Some(address) => self.write_memory(address, size, value)?,
+                    None => self.write_operand(ins, 0, value)?,
+                }
+            }
+            Bsf | Bsr => {
+                let value = self.read_operand(ins, 1)? & mask(size);
+                self.cpu.set_flag(ZF, value == 0);
+                if value != 0 {
+                    let index = if ins.mnemonic() == Bsf {
+                        value.trailing_zeros()
+                    } else {
+                        63 - value.leading_zeros()
+                    };
+                    self.write_operand(ins, 0, index as u64)?;
+                }
+            }
+            Tzcnt | Lzcnt | Popcnt => {
+                let value = self.read_operand(ins, 1)? & mask(size);
+                let result = match ins.mnemonic() {
+                    Tzcnt => value.trailing_zeros().min(size as u32 * 8),
+                    Lzcnt => value.leading_zeros() - (64 - size as u32 * 8),
+                    _ => value.count_ones(),
+                } as u64;
+                if ins.mnemonic() == Popcnt {
+                    self.cpu.rflags &= !(CF | PF | AF | ZF | SF | OF);
+                    self.cpu.set_flag(ZF, value == 0);
+                } else {
+                    self.cpu.set_flag(CF, value == 0);
+                    self.cpu.set_flag(ZF, result == 0);
+                }
+                self.write_operand(ins, 0, result)?;
+            }
+            Bswap => {
+                let value = self.read_operand(ins, 0)?;
+                let result = if size == 8 {
+                    value.swap_bytes()
+                } else {
+                    (value as u32).swap_bytes() as u64
+                };
+                self.write_operand(ins, 0, result)?;
+            }
+            Xadd => {
+                let a = self.read_operand(ins, 0)?;
+                let b = self.read_operand(ins, 1)?;
+                let result = self.add(a, b, false, size);
+                self.write_operand(ins, 1, a)?;
+                self.write_operand(ins, 0, result)?;
+            }
+            Cmpxchg => {
+                let accumulator = self.cpu.gpr[RAX];
+                let value = self.read_operand(ins, 0)?;
+                self.sub(accumulator, value, false, size);
+                if self.cpu.flag(ZF) {
+                    let source = self.read_operand(ins, 1)?;
+                    self.write_operand(ins, 0, source)?;
+                } else {
+                    self.set_accumulator(size, value);
+                }
+            }
+            Cmpxchg8b | Cmpxchg16b => {
+                let size = if ins.mnemonic() == Cmpxchg8b { 4 } else { 8 };
+                let address = self.address(ins, 0)?;
+                let low = self.read_memory(address, size)?;
+                let high = self.read_memory(address + size as u64, size)?;
+                let equal =
+                    low == self.cpu.gpr[RAX] & mask(size) && high == self.cpu.gpr[RDX] & mask(size);
+                if equal {
+                    self.write_memory(address, size, self.cpu.gpr[RBX])?;
+                    self.write_memory(address + size as u64, size, self.cpu.gpr[RCX])?;
+                } else {
+                    self.cpu.set_gpr_value(register(RAX, size), low);
+                    self.cpu.set_gpr_value(register(RDX, size), high);
+                }
+                self.cpu.set_flag(ZF, equal);
+            }
+
+            // flags
+            Clc => self.cpu.set_flag(CF, false),
+            Stc => self.cpu.set_flag(CF, true),
+            Cmc => self.cpu.set_flag(CF, !self.cpu.flag(CF)),
+            Cld => self.cpu.set_flag(DF, false),
+            Std => self.cpu.set_flag(DF, true),
+            Lahf => {
+                let flags = (self.cpu.rflags & (CF | PF | AF | ZF | SF)) | 2;
+                self.cpu.set_gpr_value(Register::AH, flags);
+            }
+            Sahf => {
+                let flags = self.cpu.gpr_value(Register::AH) & (CF | PF | AF | ZF | SF);
+                self.cpu.rflags = (self.cpu.rflags & !(CF | PF | AF | ZF | SF)) | flags;
+            }
+            Pushf | Pushfq => {
+                self.push(self.cpu.rflags, -ins.stack_pointer_increment() as usize)?
+            }
+            Popf | Popfq => {
+                let size = ins.stack_pointer_increment() as usize;
+                let flags = self.pop(size)? & USER_FLAGS & mask(size);
+                self.cpu.rflags = (self.cpu.rflags & !(USER_FLAGS & mask(size))) | flags;
+            }
+            Seta | Setae | Setb | Setbe | Sete | Setg | Setge | Setl | Setle | Setne | Setno
+            | Setnp | Setns | Seto | Setp | Sets => {
+                let value = self.condition(ins.condition_code()) as u64;
+                self.write_operand(ins, 0, value)?;
+            }
+            Cmova | Cmovae | Cmovb | Cmovbe | Cmove | Cmovg | Cmovge | Cmovl | Cmovle | Cmovne
+            | Cmovno | Cmovnp | Cmovns | Cmovo | Cmovp | Cmovs => {
+                // 32-bit destinations are zero-extended even if the condition is false
+                let value = if self.condition(ins.condition_code()) {
+                    self.read_operand(ins, 1)?
+                } else {
+                    self.read_operand(ins, 0)?
+                };
+                self.write_operand(ins, 0, value)?;
+            }
+
+            // control flow
+            Jmp => self.cpu.rip = self.read_operand(ins, 0)?,
+            Call => {
+                let target = self.read_operand(ins, 0)?;
+                self.push(self.cpu.rip, 8)?;
+                self.cpu.rip = target;
+            }
+            Ret => {
+                self.cpu.rip = self.pop(8)?;
+                if ins.op_count() == 1 {
+                    self.cpu.gpr[RSP] = self.cpu.gpr[RSP].wrapping_add(ins.immediate(0));
+                }
+            }
+            Leave => {
+                self.cpu.gpr[RSP] = self.cpu.gpr[RBP];
+                self.cpu.gpr[RBP] = self.pop(8)?;
+            }
+            Ja | Jae | Jb | Jbe | Je | Jg | Jge | Jl | Jle | Jne | Jno | Jnp | Jns | Jo | Jp
+            | Js => {
+                if self.condition(ins.condition_code()) {
+                    self.cpu.rip = ins.near_branch_target();
+                }
+            }
+            Loop | Loope | Loopne => {
+                self.cpu.gpr[RCX] = self.cpu.gpr[RCX].wrapping_sub(1);
+                let taken = self.cpu.gpr[RCX] != 0
+                    && match ins.mnemonic() {
+                        Loope => self.cpu.flag(ZF),
+                        Loopne => !self.cpu.flag(ZF),
+                        _ => true,
+                    };
+                if taken {
+                    self.cpu.rip = ins.near_branch_target();
+                }
+            }
+            Jrcxz | Jecxz => {
+                let size = if ins.mnemonic() == Jrcxz { 8 } else { 4 };
+                if self.cpu.gpr[RCX] & mask(size) == 0 {
+                    self.cpu.rip = ins.near_branch_target();
+                }
+            }
+
+            // string
+            Movsb | Movsw | Movsq | Stosb | Stosw | Stosd | Stosq | Lodsb | Lodsw | Lodsd
+            | Lodsq | Scasb | Scasw | Scasd | Scasq | Cmpsb | Cmpsw | Cmpsq => self.string(ins)?,
+            Movsd | Cmpsd if is_string(ins) => self.string(ins)?,
+
+            // system
+            Cpuid => {
+                let (eax, ebx, ecx, edx) = match self.cpu.gpr[RAX] as u32 {
+                    // "GenuineIntel"
+                    0x0000_0000 => (0x0000_0001, 0x756E_6547, 0x6C65_746E, 0x4965_6E69),
+                    // cmpxchg16b, popcnt / tsc, cx8, cmov, sse, sse2
+                    0x0000_0001 => (
+                        0x0003_06A9,
+                        0,
+                        1 << 13 | 1 << 23,
+                        1 << 4 | 1 << 8 | 1 << 15 | 1 << 25 | 1 << 26,
+                    ),
+                    0x8000_0000 => (0x8000_0001, 0, 0, 0),
+                    // lzcnt / long mode
+                    0x8000_0001 => (0, 0, 1 << 5, 1 << 29),
+                    _ => (0, 0, 0, 0),
+                };
+                self.cpu.gpr[RAX] = eax;
+                self.cpu.gpr[RBX] = ebx;
+                self.cpu.gpr[RCX] = ecx;
+                self.cpu.gpr[RDX] = edx;
+            }
+            Rdtsc => {
+                // the instruction count is used as time stamp to stay deterministic
+                self.cpu.gpr[RAX] = self.instruction_count & 0xFFFF_FFFF;
+                self.cpu.gpr[RDX] = self.instruction_count >> 32;
+            }
+            Int3 => return Err(Exception::Breakpoint),
+            Int1 => return Err(Exception::Interrupt(1)),
+            Int => return Err(Exception::Interrupt(ins.immediate(0) as u8)),
+            Syscall => {
+                // rcx and r11 are clobbered by the cpu
+                self.cpu.gpr[RCX] = self.cpu.rip;
+                self.cpu.gpr[R11] = self.cpu.rflags;
+                return Err(Exception::Syscall);
+            }
+            Ud0 | Ud1 | Ud2 => return Err(Exception::InvalidOpcode),
+            Hlt | Cli | Sti | In | Out | Insb | Insw | Insd | Outsb | Outsw | Outsd => {
+                return Err(Exception::GeneralProtection)
+            }
+
+            _ => self.execute_sse(ins)?,
+        }
+        Ok(())
+    }
+
+    /// effective address of a memory operand, fails with the exception of an
+    /// unsupported base or index register
+    pub(super) fn address(&self, ins: &Instruction, operand: u32) -> Result<u64, Exception> {
+        let mut error = None;
+        ins.virtual_address(operand, 0, |register, _, _| {
+            self.cpu
+                .register(register)
+                .map_err(|exception| error = Some(exception))
+                .ok()
+        })
+        .ok_or_else(|| error.unwrap_or(Exception::InvalidOpcode))
+    }
+
+    pub(super) fn read_memory(&mut self, address: u64, size: usize) -> Result<u64, Exception> {
+        let mut data = [0; 8];
+        self.read_data(address, &mut data[..size])?;
+        Ok(u64::from_le_bytes(data))
+    }
+
+    pub(super) fn write_memory(
+        &mut self,
+        address: u64,
+        size: usize,
+        value: u64,
+    ) -> Result<(), Exception> {
+        self.write_data(address, &value.to_le_bytes()[..size])
+    }
+
+    /// reads memory on behalf of an instruction, all data accesses of
+    /// instructions go through here and [Emulator::write_data]
+    pub(super) fn read_data(&mut self, address: u64, data: &mut [u8]) -> Result<(), Exception> {
+        self.memory.read(address, data, Access::Read)?;
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(MemoryAccess {
+                address,
+                data: data.to_vec(),
+                write: false,
+            });
+        }
+        Ok(())
+    }
+
+    pub(super) fn write_data(&mut self, address: u64, data: &[u8]) -> Result<(), Exception> {
+        self.memory.write(address, data)?;
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(MemoryAccess {
+                address,
+                data: data.to_vec(),
+                write: true,
+            });
+        }
+        Ok(())
+    }
+
+    /// reads a general purpose register, memory or immediate operand
+    pub(super) fn read_operand(
+        &mut self,
+        ins: &Instruction,
+        operand: u32,
+    ) -> Result<u64, Exception> {
+        Ok(match ins.op_kind(operand) {
+            // segment registers are read as selectors (e.g. push fs)
+            OpKind::Register if ins.op_register(operand).is_segment_register() => {
+                self.cpu.selector(ins.op_register(operand))
+            }
+            OpKind::Register => self.cpu.register(ins.op_register(operand))?,
+            OpKind::Memory => {
+                self.read_memory(self.address(ins, operand)?, operand_size(ins, operand))?
+            }
+            OpKind::NearBranch64 | OpKind::NearBranch32 | OpKind::NearBranch16 => {
+                ins.near_branch_target()
+            }
+            _ => ins.immediate(operand),
+        })
+    }
+
+    /// writes a general purpose register or memory operand
+    pub(super) fn write_operand(
+        &mut self,
+        ins: &Instruction,
+        operand: u32,
+        value: u64,
+    ) -> Result<(), Exception> {
+        match ins.op_kind(operand) {
+            OpKind::Register => self.cpu.set_register(ins.op_register(operand), value)?,
+            OpKind::Memory => self.write_memory(
+                self.address(ins, operand)?,
+                operand_size(ins, operand),
+                value,
+            )?,
+            _ => return Err(Exception::InvalidOpcode),
+        }
+        Ok(())
+    }
+
+    pub fn push(&mut self, value: u64, size: usize) -> Result<(), Exception> {
+        let rsp = self.cpu.gpr[RSP].wrapping_sub(size as u64);
+        self.write_memory(rsp, size, value)?;
+        self.cpu.gpr[RSP] = rsp;
+        Ok(())
+    }
+
+    pub fn pop(&mut self, size: usize) -> Result<u64, Exception> {
+        let value = self.read_memory(self.cpu.gpr[RSP], size)?;
+        self.cpu.gpr[RSP] = self.cpu.gpr[RSP].wrapping_add(size as u64);
+        Ok(value)
+    }
+
+    pub(super) fn condition(&self, condition: ConditionCode) -> bool {
+        let flag = |flag| self.cpu.flag(flag);
+        match condition {
+            ConditionCode::None => true,
+            ConditionCode::o => flag(OF),
+            ConditionCode::no => !flag(OF),
+            ConditionCode::b => flag(CF),
+            ConditionCode::ae => !flag(CF),
+            ConditionCode::e => flag(ZF),
+            ConditionCode::ne => !flag(ZF),
+            ConditionCode::be => flag(CF) || flag(ZF),
+            ConditionCode::a => !flag(CF) && !flag(ZF),
+            ConditionCode::s => flag(SF),
+            ConditionCode::ns => !flag(SF),
+            ConditionCode::p => flag(PF),
+            ConditionCode::np => !flag(PF),
+            ConditionCode::l => flag(SF) != flag(OF),
+            ConditionCode::ge => flag(SF) == flag(OF),
+            ConditionCode::le => flag(ZF) || flag(SF) != flag(OF),
+            ConditionCode::g => !flag(ZF) && flag(SF) == flag(OF),
+        }
+    }
+
+    /// sets zf, sf and pf according to the result
+    pub(super) fn set_result_flags(&mut self, result: u64, size: usize) {
+        let result = result & mask(size);
+        self.cpu.set_flag(ZF, result == 0);
+        self.cpu.set_flag(SF, result >> (size * 8 - 1) & 1 != 0);
+        self.cpu
+            .set_flag(PF, (result as u8).count_ones().is_multiple_of(2));
+    }
+
+    fn set_logic_flags(&mut self, result: u64, size: usize) {
+        self.cpu.rflags &= !(CF | AF | OF);
+        self.set_result_flags(result, size);
+    }
+
+    fn add(&mut self, a: u64, b: u64, carry: bool, size: usize) -> u64 {
+        let (a, b) = (a & mask(size), b & mask(size));
+        let wide = a as u128 + b as u128 + carry as u128;
+        let result = wide as u64 & mask(size);
+        let sign = 1 << (size * 8 - 1);
+        self.cpu.set_flag(CF, wide > mask(size) as u128);
+        self.cpu
+            .set_flag(OF, (a ^ result) & (b ^ result) & sign != 0);
+        self.cpu.set_flag(AF, (a ^ b ^ result) & 0x10 != 0);
+        self.set_result_flags(result, size);
+        result
+    }
+
+    fn sub(&mut self, a: u64, b: u64, borrow: bool, size: usize) -> u64 {
+        let (a, b) = (a & mask(size), b & mask(size));
+        let result = a.wrapping_sub(b).wrapping_sub(borrow as u64) & mask(size);
+        let sign = 1 << (size * 8 - 1);
+        self.cpu
+            .set_flag(CF, (a as u128) < b as u128 + borrow as u128);
+        self.cpu.set_flag(OF, (a ^ b) & (a ^ result) & sign != 0);
+        self.cpu.set_flag(AF, (a ^ b ^ result) & 0x10 != 0);
+        self.set_result_flags(result, size);
+        result
+    }
+
+    fn shift(&mut self, ins: &Instruction, size: usize) -> Result<(), Exception> {
+        let bits = size as u32 * 8;
+        let count = (self.read_operand(ins, 1)? & if size == 8 { 0x3F } else { 0x1F }) as u32;
+        if count == 0 {
+            return self.zero_extend(ins);
+        }
+        let a = self.read_operand(ins, 0)? & mask(size);
+        let msb = |value: u64| value >> (bits - 1) & 1 != 0;
+        let (result, carry, overflow) = match ins.mnemonic() {
+            Mnemonic::Shl | Mnemonic::Sal => {
+                let wide = (a as u128) << count;
+                let result = wide as u64 & mask(size);
+                let carry = wide >> bits & 1 != 0;
+                (result, carry, msb(result) != carry)
+            }
+            Mnemonic::Shr => (a >> count, a >> (count - 1) & 1 != 0, msb(a)),
+            Mnemonic::Sar => {
+                let a = sign_extend(a, size) as i64;
+                (
+                    (a >> count) as u64 & mask(size),
+                    a >> (count - 1) & 1 != 0,
+                    false,
+                )
+            }
+            Mnemonic::Rol | Mnemonic::Ror => {
+                let count = count % bits;
+                let result = if count == 0 {
+                    a
+                } else if ins.mnemonic() == Mnemonic::Rol {
+                    (a << count | a >> (bits - count)) & mask(size)
+                } else {
+                    (a >> count | a << (bits - count)) & mask(size)
+                };
+                if ins.mnemonic() == Mnemonic::Rol {
+                    let carry = result & 1 != 0;
+                    (result, carry, msb(result) != carry)
+                } else {
+                    (
+                        result,
+                        msb(result),
+                        msb(result) != (result >> (bits - 2) & 1 != 0),
+                    )
+                }
+            }
+            _ => {
+                let mut carry = self.cpu.flag(CF);
+                let mut result = a;
+                let overflow = if ins.mnemonic() == Mnemonic::Rcl {
+                    for _ in 0..count % (bits + 1) {
+                        let next = msb(result);
+                        result = (result << 1 | carry as u64) & mask(size);
+                        carry = next;
+                    }
+                    msb(result) != carry
+                } else {
+                    let overflow = msb(a) != carry;
+                    for _ in 0..count % (bits + 1) {
+                        let next = result & 1 != 0;
+                        result = result >> 1 | (carry as u64) << (bits - 1);
+                        carry = next;
+                    }
+                    overflow
+                };
+                (result, carry, overflow)
+            }
+        };
+        if !matches!(
+            ins.mnemonic(),
+            Mnemonic::Rol | Mnemonic::Ror | Mnemonic::Rcl | Mnemonic::Rcr
+        ) {
+            self.set_result_flags(result, size);
+        }
+        self.cpu.set_flag(CF, carry);
+        self.cpu.set_flag(OF, overflow);
+        self.write_operand(ins, 0, result)
+    }
+
+    /// shifts by zero leave flags and value as they are, but 32-bit registers
+    /// are still zero-extended
+    fn zero_extend(&mut self, ins: &Instruction) -> Result<(), Exception> {
+        if ins.op0_kind() == OpKind::Register {
+            let value = self.read_operand(ins, 0)?;
+            self.write_operand(ins, 0, value)?;
+        }
+        Ok(())
+    }
+
+    /// movs, stos, lods, scas and cmps including the rep prefixes, a fault
+    /// leaves rcx, rsi and rdi at the faulting element
+    fn string(&mut self, ins: &Instruction) -> Result<(), Exception> {
+        let size = ins.memory_size().size();
+        let step = if self.cpu.flag(DF) {
+            (size as u64).wrapping_neg()
+        } else {
+            size as u64
+        };
+        let repeat = ins.has_rep_prefix() || ins.has_repne_prefix();
+        let segment = self.cpu.register(ins.memory_segment())?;
+        loop {
+            if repeat && self.cpu.gpr[RCX] == 0 {
+                break;
+            }
+            let (rsi, rdi) = (self.cpu.gpr[RSI], self.cpu.gpr[RDI]);
+            match ins.mnemonic() {
+                Mnemonic::Movsb | Mnemonic::Movsw | Mnemonic::Movsd | Mnemonic::Movsq => {
+                    let value = self.read_memory(segment.wrapping_add(rsi), size)?;
+                    self.write_memory(rdi, size, value)?;
+                    self.cpu.gpr[RSI] = rsi.wrapping_add(step);
+                    self.cpu.gpr[RDI] = rdi.wrapping_add(step);
+                }
+                Mnemonic::Stosb | Mnemonic::Stosw | Mnemonic::Stosd | Mnemonic::Stosq => {
+                    self.write_memory(rdi, size, self.cpu.gpr[RAX])?;
+                    self.cpu.gpr[RDI] = rdi.wrapping_add(step);
+                }
+                Mnemonic::Lodsb | Mnemonic::Lodsw | Mnemonic::Lodsd | Mnemonic::Lodsq => {
+                    let value = self.read_memory(segment.wrapping_add(rsi), size)?;
+                    self.set_accumulator(size, value);
+                    self.cpu.gpr[RSI] = rsi.wrapping_add(step);
+                }
+                Mnemonic::Scasb | Mnemonic::Scasw | Mnemonic::Scasd | Mnemonic::Scasq => {
+                    let value = self.read_memory(rdi, size)?;
+                    self.sub(self.cpu.gpr[RAX], value, false, size);
+                    self.cpu.gpr[RDI] = rdi.wrapping_add(step);
+                }
+                _ => {
+                    let a = self.read_memory(segment.wrapping_add(rsi), size)?;
+                    let b = self.read_memory(rdi, size)?;
+                    self.sub(a, b, false, size);
+                    self.cpu.gpr[RSI] = rsi.wrapping_add(step);
+                    self.cpu.gpr[RDI] = rdi.wrapping_add(step);
+                }
+            }
+            if !repeat {
+                break;
+            }
+            self.cpu.gpr[RCX] -= 1;
+
+            // repe and repne also stop on the comparison result
+            if matches!(
+                ins.mnemonic(),
+                Mnemonic::Scasb
+                    | Mnemonic::Scasw
+                    | Mnemonic::Scasd
+                    | Mnemonic::Scasq
+                    | Mnemonic::Cmpsb
+                    | Mnemonic::Cmpsw
+                    | Mnemonic::Cmpsd
+                    | Mnemonic::Cmpsq
+            ) && self.cpu.flag(ZF) == ins.has_repne_prefix()
+            {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// al, ax, eax or rax
+    fn set_accumulator(&mut self, size: usize, value: u64) {
+        self.cpu.set_gpr_value(register(RAX, size), value);
+    }
+
+    /// ax, dx:ax, edx:eax or rdx:rax
+    fn double(&self, size: usize) -> u128 {
+        if size == 1 {
+            return (self.cpu.gpr[RAX] & 0xFFFF) as u128;
+        }
+        ((self.cpu.gpr[RDX] & mask(size)) as u128) << (size * 8)
+            | (self.cpu.gpr[RAX] & mask(size)) as u128
+    }
+
+    fn set_double(&mut self, size: usize, low: u64, high: u64) {
+        if size == 1 {
+            self.cpu.set_gpr_value(Register::AX, high << 8 | low);
+        } else {
+            self.cpu.set_gpr_value(register(RAX, size), low);
+            self.cpu.set_gpr_value(register(RDX, size), high);
+        }
+    }
+
+    /// stores the result of div and idiv
+    fn set_quotient(&mut self, size: usize, quotient: u64, remainder: u64) {
+        if size == 1 {
+            self.cpu.set_gpr_value(Register::AL, quotient);
+            self.cpu.set_gpr_value(Register::AH, remainder);
+        } else {
+            self.set_double(size, quotient, remainder);
+        }
+    }
+}
+
+/// size of an operand in bytes
+pub(super) fn operand_size(ins: &Instruction, operand: u32) -> usize {
+    match ins.op_kind(operand) {
+        OpKind::Register => ins.op_register(operand).size(),
+        OpKind::Memory => ins.memory_size().size(),
+        _ => 8,
+    }
+}
+
+/// sign-extends the lowest size bytes
+pub(super) fn sign_extend(value: u64, size: usize) -> u64 {
+    let shift = 64 - size as u32 * 8;
+    ((value << shift) as i64 >> shift) as u64
+}
+
+/// rax or rdx of the specified size
+fn register(index: usize, size: usize) -> Register {
+    match (index, size) {
+        (RAX, 1) => Register::AL,
+        (RAX, 2) => Register::AX,
+        (RAX, 4) => Register::EAX,
+        (RAX, _) => Register::RAX,
+        (RDX, 1) => Register::DL,
+        (RDX, 2) => Register::DX,
+        (RDX, 4) => Register::EDX,
+        (RDX, _) => Register::RDX,
+        _ => unreachable!(),
+    }
+}
+
+fn is_string(ins: &Instruction) -> bool {
+    matches!(
+        ins.op0_kind(),
+        OpKind::MemorySegRSI | OpKind::MemoryESRDI | OpKind::MemorySegESI | OpKind::MemoryESEDI
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::tests::emulator;
+
+    /// status flags checked by the tests
+    const STATUS: u64 = CF | AF | ZF | SF | OF;
+
+    /// executes a single instruction with rax, rcx, rdx and the flags set
+    fn step(
+        code: &[u8],
+        rax: u64,
+        rcx: u64,
+        rdx: u64,
+        flags: u64,
+    ) -> (Emulator, Result<(), Exception>) {
+        let mut emulator = emulator(code);
+        emulator.cpu.gpr[RAX] = rax;
+        emulator.cpu.gpr[RCX] = rcx;
+        emulator.cpu.gpr[RDX] = rdx;
+        emulator.cpu.rflags |= flags;
+        let result = emulator.step();
+        (emulator, result)
+    }
+
+    #[test]
+    fn arithmetic() {
+        for (code, rax, rcx, flags, result, expected) in [
+            // add al, cl
+            (&[0x00, 0xC8][..], 0x7F, 0x01, 0, 0x80, OF | AF | SF),
+            (&[0x00, 0xC8], 0x11FF, 0x01, 0, 0x1100, CF | AF | ZF),
+            // adc al, cl
+            (&[0x10, 0xC8], 0x0F, 0x00, CF, 0x10, AF),
+            (&[0x10, 0xC8], 0xFF, 0xFF, CF, 0xFF, CF | AF | SF),
+            // sub al, cl
+            (&[0x28, 0xC8], 0x80, 0x01, 0, 0x7F, OF | AF),
+            (&[0x28, 0xC8], 0x00, 0x01, 0, 0xFF, CF | AF | SF),
+            // sbb al, cl
+            (&[0x18, 0xC8], 0x10, 0x0F, CF, 0x00, AF | ZF),
+            (&[0x18, 0xC8], 0x00, 0xFF, CF, 0x00, CF | AF | ZF),
+            // neg al
+            (&[0xF6, 0xD8], 0x00, 0, CF, 0x00, ZF),
+            (&[0xF6, 0xD8], 0x80, 0, 0, 0x80, CF | OF | SF),
+            (&[0xF6, 0xD8], 0x01, 0, 0, 0xFF, CF | AF | SF),
+            // add rax, rcx
+            (
+                &[0x48, 0x01, 0xC8],
+                i64::MAX as u64,
+                1,
+                0,
+                1 << 63,
+                OF | AF | SF,
+            ),
+            // sub eax, ecx
+            (
+                &[0x29, 0xC8],
+                0xFFFF_FFFF_0000_0000,
+                1,
+                0,
+                0xFFFF_FFFF,
+                CF | AF | SF,
+            ),
+        ] {
+            let (emulator, result_) = step(code, rax, rcx, 0, flags);
+            assert_eq!(result_, Ok(()));
+            assert_eq!(
+                emulator.cpu.gpr[RAX], result,
+                "{code:02X?} {rax:#x} {rcx:#x}"
+            );
+            assert_eq!(
+                emulator.cpu.rflags & STATUS,
+                expected,
+                "{code:02X?} {rax:#x} {rcx:#x}"
+            );
+        }
+    }
+
+    #[test]
+    fn shifts() {
+        // the overflow flag is only defined for counts of 1
+        for (code, rax, cl, flags, result, carry, overflow) in [
+            // shl al, cl / shl al, 1
+            (&[0xD2, 0xE0][..], 0x81, 0, CF | OF, 0x81, true, Some(true)),
+            (&[0xD0, 0xE0], 0x81, 0, 0, 0x02, true, Some(true)),
+            (&[0xD0, 0xE0], 0x40, 0, 0, 0x80, false, Some(true)),
+            (&[0xD2, 0xE0], 0x81, 4, 0, 0x10, false, None),
+            (&[0xD2, 0xE0], 0x01, 8, 0, 0x00, true, None),
+            // shr al, 1 / shr al, cl
+            (&[0xD0, 0xE8], 0x81, 0, 0, 0x40, true, Some(true)),
+            (&[0xD2, 0xE8], 0x81, 7, CF, 0x01, false, None),
+            // sar al, 1 / sar al, cl
+            (&[0xD0, 0xF8], 0x81, 0, OF, 0xC0, true, Some(false)),
+            (&[0xD2, 0xF8], 0x80, 7, CF, 0xFF, false, None),
+            // rol al, 1 / rol al, cl
+            (&[0xD0, 0xC0], 0x81, 0, 0, 0x03, true, Some(true)),
+            (&[0xD2, 0xC0], 0x81, 0, OF, 0x81, false, Some(true)),
+            (&[0xD2, 0xC0], 0x80, 8, CF, 0x80, false, None),
+            // rcr al, 1 / rcr al, cl
+            (&[0xD0, 0xD8], 0x01, 0, CF, 0x80, true, Some(true)),
+            (&[0xD0, 0xD8], 0x80, 0, 0, 0x40, false, Some(true)),
+            (&[0xD2, 0xD8], 0x55, 9, CF, 0x55, true, None),
+            (&[0xD2, 0xD8], 0x03, 2, 0, 0x80, true, None),
+            // shl eax, cl with a count of 0 still zero-extends
+            (&[0xD3, 0xE0], 0xFFFF_FFFF_0000_0001, 0, CF, 1, true, None),
+            // shl rax, cl masks the count to 6 bits
+            (&[0x48, 0xD3, 0xE0], 1 << 63 | 1, 65, 0, 2, true, Some(true)),
+        ] {
+            let (emulator, result_) = step(code, rax, cl, 0, flags);
+            assert_eq!(result_, Ok(()));
+            let case = format!("{code:02X?} {rax:#x} {cl}");
+            assert_eq!(emulator.cpu.gpr[RAX], result, "{case}");
+            assert_eq!(emulator.cpu.flag(CF), carry, "{case}");
+            if let Some(overflow) = overflow {
+                assert_eq!(emulator.cpu.flag(OF), overflow, "{case}");
+            }
+        }
+    }
+
+    #[test]
+    fn double_shifts() {
+        for (code, rax, rdx, cl, flags, result, carry) in [
+            // shld eax, edx, cl
+            (
+                &[0x0F, 0xA5, 0xD0][..],
+                0x1234_5678,
+                0x9ABC_DEF0,
+                4,
+                0,
+                0x2345_6789,
+                true,
+            ),
+            (
+                &[0x0F, 0xA5, 0xD0],
+                0xFFFF_FFFF_1234_5678,
+                0,
+                0,
+                CF,
+                0x1234_5678,
+                true,
+            ),
+            // shrd eax, edx, cl
+            (
+                &[0x0F, 0xAD, 0xD0],
+                0x1234_5678,
+                0x9ABC_DEF0,
+                4,
+                0,
+                0x0123_4567,
+                true,
+            ),
+            (
+                &[0x0F, 0xAD, 0xD0],
+                0x1234_5670,
+                0x9ABC_DEF1,
+                4,
+                CF,
+                0x1123_4567,
+                false,
+            ),
+        ] {
+            let (emulator, result_) = step(code, rax, cl, rdx, flags);
+            assert_eq!(result_, Ok(()));
+            assert_eq!(emulator.cpu.gpr[RAX], result, "{code:02X?} {rax:#x} {cl}");
+            assert_eq!(emulator.cpu.flag(CF), carry, "{code:02X?} {rax:#x} {cl}");
+        }
+
+        // shrd eax, edx, 1 moves the lowest bit of edx into the sign
+        let (emulator, _) = step(&[0x0F, 0xAD, 0xD0], 1, 1, 1, 0);
+        assert_eq!(emulator.cpu.gpr[RAX], 0x8000_0000);
+        assert!(emulator.cpu.flag(CF));
+        assert!(emulator.cpu.flag(OF));
+    }
+
+    #[test]
+    fn multiplication() {
+        for (code, rax, rcx, rdx, result, high, overflow) in [
+            // mul cl
+            (&[0xF6, 0xE1][..], 0x80, 2, 0, 0x100, 0, true),
+            (&[0xF6, 0xE1], 0x10, 2, 0, 0x20, 0, false),
+            // mul rcx
+            (&[0x48, 0xF7, 0xE1], u64::MAX, 2, 0, u64::MAX - 1, 1, true),
+            // imul rcx
+            (
+                &[0x48, 0xF7, 0xE9],
+                u64::MAX,
+                2,
+                0,
+                -2i64 as u64,
+                u64::MAX,
+                false,
+            ),
+            // imul eax, ecx
+            (&[0x0F, 0xAF, 0xC1], 0x4000_0000, 2, 7, 0x8000_0000, 7, true),
+            // imul eax, ecx, -3
+            (&[0x6B, 0xC1, 0xFD], 0, 5, 7, 0xFFFF_FFF1, 7, false),
+        ] {
+            let (emulator, result_) = step(code, rax, rcx, rdx, 0);
+            assert_eq!(result_, Ok(()));
+            let case = format!("{code:02X?} {rax:#x} {rcx:#x}");
+            assert_eq!(emulator.cpu.gpr[RAX], result, "{case}");
+            assert_eq!(emulator.cpu.gpr[RDX], high, "{case}");
+            assert_eq!(emulator.cpu.flag(CF), overflow, "{case}");
+            assert_eq!(emulator.cpu.flag(OF), overflow, "{case}");
+        }
+    }
+
+    #[test]
+    fn division() {
+        for (code, rax, rcx, rdx, expected) in [
+            // div cl
+            (&[0xF6, 0xF1][..], 0x0107, 0x10, 0, Ok((0x0710, 0))),
+            (&[0xF6, 0xF1], 0x1000, 0x10, 0, Err(Exception::DivideError)),
+            // div rcx
+            (&[0x48, 0xF7, 0xF1], 0, 2, 1, Ok((1 << 63, 0))),
+            (&[0x48, 0xF7, 0xF1], 0, 0, 1, Err(Exception::DivideError)),
+            (&[0x48, 0xF7, 0xF1], 0, 2, 2, Err(Exception::DivideError)),
+            // idiv ecx
+            (
+                &[0xF7, 0xF9],
+                0xFFFF_FFF9,
+                2,
+                0xFFFF_FFFF,
+                Ok((0xFFFF_FFFD, 0xFFFF_FFFF)),
+            ),
+            (
+                &[0xF7, 0xF9],
+                0x8000_0000,
+                0xFFFF_FFFF,
+                0xFFFF_FFFF,
+                Err(Exception::DivideError),
+            ),
+            // idiv rcx
+            (&[0x48, 0xF7, 0xF9], 7, 0, 0, Err(Exception::DivideError)),
+        ] {
+            let (emulator, result) = step(code, rax, rcx, rdx, 0);
+            let case = format!("{code:02X?} {rdx:#x}:{rax:#x} {rcx:#x}");
+            match expected {
+                Ok((quotient, remainder)) => {
+                    assert_eq!(result, Ok(()), "{case}");
+                    assert_eq!(emulator.cpu.gpr[RAX], quotient, "{case}");
+                    assert_eq!(emulator.cpu.gpr[RDX], remainder, "{case}");
+                }
+                Err(exception) => {
+                    // faults leave the state unchanged
+                    assert_eq!(result, Err(exception), "{case}");
+                    assert_eq!(emulator.cpu.rip, 0x1000, "{case}");
+                    assert_eq!(emulator.cpu.gpr[RAX], rax, "{case}");
+                    assert_eq!(emulator.cpu.gpr[RDX], rdx, "{case}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bits() {
+        for (code, rax, rcx, result, expected) in [
+            // bt rax, rcx, the offset wraps around for registers
+            (&[0x48, 0x0F, 0xA3, 0xC8][..], 0b100, 2, 0b100, CF),
+            (&[0x48, 0x0F, 0xA3, 0xC8], 0b100, 66, 0b100, CF),
+            // bts rax, rcx / btr rax, rcx / btc rax, rcx
+            (&[0x48, 0x0F, 0xAB, 0xC8], 0, 3, 8, 0),
+            (&[0x48, 0x0F, 0xB3, 0xC8], 0xF, 0, 0xE, CF),
+            (&[0x48, 0x0F, 0xBB, 0xC8], 0, 63, 1 << 63, 0),
+            // bsf rax, rcx / bsr rax, rcx, zero leaves the destination as is
+            (&[0x48, 0x0F, 0xBC, 0xC1], 0x1234, 0x50, 4, 0),
+            (&[0x48, 0x0F, 0xBD, 0xC1], 0x1234, 0x50, 6, 0),
+            (&[0x48, 0x0F, 0xBC, 0xC1], 0x1234, 0, 0x1234, ZF),
+            (&[0x48, 0x0F, 0xBD, 0xC1], 0x1234, 0, 0x1234, ZF),
+        ] {
+            let (emulator, result_) = step(code, rax, rcx, 0, 0);
+            assert_eq!(result_, Ok(()));
+            assert_eq!(emulator.cpu.gpr[RAX], result, "{code:02X?} {rax:#x} {rcx}");
+            assert_eq!(
+                emulator.cpu.rflags & (CF | ZF),
+                expected,
+                "{code:02X?} {rax:#x} {rcx}"
+            );
+        }
+
+        // bts [rsp], rcx addresses memory outside of the operand
+        for (rcx, address, value) in [(70, 0x1FF08, 1 << 6), (-1i64 as u64, 0x1FEF8, 1 << 63)] {
+            let (emulator, result) = step(&[0x48, 0x0F, 0xAB, 0x0C, 0x24], 0, rcx, 0, 0);
+            assert_eq!(result, Ok(()));
+            assert_eq!(emulator.read_u64(address).unwrap(), value);
+            assert_eq!(emulator.read_u64(0x1FF00).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        for (code, rax, flags, result) in [
+            // cmove rax, rcx
+            (&[0x48, 0x0F, 0x44, 0xC1][..], 1, ZF, 2),
+            (&[0x48, 0x0F, 0x44, 0xC1], 1, 0, 1),
+            // cmovl eax, ecx zero-extends even if the condition is false
+            (&[0x0F, 0x4C, 0xC1], 0xFFFF_FFFF_0000_0001, SF, 2),
+            (&[0x0F, 0x4C, 0xC1], 0xFFFF_FFFF_0000_0001, SF | OF, 1),
+            // setb al
+            (&[0x0F, 0x92, 0xC0], 0x1100, CF, 0x1101),
+            (&[0x0F, 0x92, 0xC0], 0x11FF, 0, 0x1100),
+            // setle al
+            (&[0x0F, 0x9E, 0xC0], 0, ZF, 1),
+            (&[0x0F, 0x9E, 0xC0], 0, OF, 1),
+            (&[0x0F, 0x9E, 0xC0], 0, SF | OF, 0),
+            // seta al
+            (&[0x0F, 0x97, 0xC0], 0, 0, 1),
+            (&[0x0F, 0x97, 0xC0], 0, ZF, 0),
+        ] {
+            let (emulator, result_) = step(code, rax, 2, 0, flags);
+            assert_eq!(result_, Ok(()));
+            assert_eq!(emulator.cpu.gpr[RAX], result, "{code:02X?} {flags:#x}");
+        }
+    }
+
+    #[test]
+    fn segment_registers() {
+        // push fs / push gs / mov ax, fs
+        let mut emulator = emulator(&[0x0F, 0xA0, 0x0F, 0xA8, 0x66, 0x8C, 0xE0]);
+        emulator.cpu.fs_base = 0x7000;
+        emulator.cpu.selectors = [0x2B, 0x33, 0x2B, 0x2B, 0x53, 0x2B];
+        for _ in 0..3 {
+            emulator.step().unwrap();
+        }
+        assert_eq!(emulator.read_u64(0x1FEF8).unwrap(), 0x53);
+        assert_eq!(emulator.read_u64(0x1FEF0).unwrap(), 0x2B);
+        assert_eq!(emulator.cpu.gpr[RAX], 0x53);
+    }
+
+    /// emulator with rsi at 0x18000 and rdi at 0x19000 for string instructions
+    fn strings(code: &[u8], source: &[u8], destination: &[u8], rcx: u64, flags: u64) -> Emulator {
+        let mut emulator = emulator(code);
+        emulator.memory.write(0x18000, source).unwrap();
+        emulator.memory.write(0x19000, destination).unwrap();
+        emulator.cpu.gpr[RSI] = 0x18000;
+        emulator.cpu.gpr[RDI] = 0x19000;
+        emulator.cpu.gpr[RCX] = rcx;
+        emulator.cpu.rflags |= flags;
+        emulator
+    }
+
+    #[test]
+    fn moves() {
+        // rep movsb
+        let mut emulator = strings(&[0xF3, 0xA4], &[1, 2, 3, 4, 5], &[], 5, 0);
+        emulator.step().unwrap();
+        assert_eq!(emulator.read_bytes(0x19000, 6).unwrap(), [1, 2, 3, 4, 5, 0]);
+        assert_eq!(emulator.cpu.gpr[RCX], 0);
+        assert_eq!(emulator.cpu.gpr[RSI], 0x18005);
+        assert_eq!(emulator.cpu.gpr[RDI], 0x19005);
+
+        // rep movsb backwards
+        let mut emulator = strings(&[0xF3, 0xA4], &[1, 2, 3, 4, 5], &[], 3, DF);
+        emulator.cpu.gpr[RSI] += 4;
+        emulator.cpu.gpr[RDI] += 4;
+        emulator.step().unwrap();
+        assert_eq!(emulator.read_bytes(0x19000, 5).unwrap(), [0, 0, 3, 4, 5]);
+        assert_eq!(emulator.cpu.gpr[RSI], 0x18001);
+        assert_eq!(emulator.cpu.gpr[RDI], 0x19001);
+
+        // rep movsb without a count
+        let mut emulator = strings(&[0xF3, 0xA4], &[1], &[], 0, 0);
+        emulator.step().unwrap();
+        assert_eq!(emulator.read_bytes(0x19000, 1).unwrap(), [0]);
+        assert_eq!(emulator.cpu.gpr[RSI], 0x18000);
+
+        // rep movsb faults at the end of the stack after 2 bytes
+        let mut emulator = strings(&[0xF3, 0xA4], &[1, 2, 3, 4, 5], &[], 5, 0);
+        emulator.cpu.gpr[RDI] = 0x1FFFE;
+        assert_eq!(
+            emulator.step(),
+            Err(Exception::PageFault {
+                address: 0x20000,
+                access: Access::Write
+            })
+        );
+        assert_eq!(emulator.read_bytes(0x1FFFE, 2).unwrap(), [1, 2]);
+        assert_eq!(emulator.cpu.rip, 0x1000);
+        assert_eq!(emulator.cpu.gpr[RCX], 3);
+        assert_eq!(emulator.cpu.gpr[RSI], 0x18002);
+        assert_eq!(emulator.cpu.gpr[RDI], 0x20000);
+
+        // rep stosq
+        let mut emulator = strings(&[0xF3, 0x48, 0xAB], &[], &[], 3, 0);
+        emulator.cpu.gpr[RAX] = 0x1122_3344_5566_7788;
+        emulator.step().unwrap();
+        for i in 0..3 {
+            assert_eq!(
+                emulator.read_u64(0x19000 + i * 8).unwrap(),
+                0x1122_3344_5566_7788
+            );
+        }
+        assert_eq!(emulator.cpu.gpr[RDI], 0x19018);
+
+        // rep stosd backwards
+        let mut emulator = strings(&[0xF3, 0xAB], &[], &[], 3, DF);
+        emulator.cpu.gpr[RAX] = 0x5566_7788;
+        emulator.cpu.gpr[RDI] += 8;
+        emulator.step().unwrap();
+        assert_eq!(emulator.read_u64(0x19000).unwrap(), 0x5566_7788_5566_7788);
+        assert_eq!(emulator.read_u64(0x19008).unwrap(), 0x5566_7788);
+        assert_eq!(emulator.cpu.gpr[RDI], 0x18FFC);
+    }
+
+    #[test]
+    fn comparisons() {
+        // repe cmpsb stops at the first difference
+        let mut emulator = strings(&[0xF3, 0xA6], b"abcd", b"abXd", 4, 0);
+        emulator.step().unwrap();
+        assert_eq!(emulator.cpu.gpr[RCX], 1);
+        assert_eq!(emulator.cpu.gpr[RSI], 0x18003);
+        assert_eq!(emulator.cpu.gpr[RDI], 0x19003);
+        assert!(!emulator.cpu.flag(ZF));
+
+        // repne scasb stops at the first match
+        let mut emulator = strings(&[0xF2, 0xAE], &[], b"abc\0def", 10, 0);
+        emulator.step().unwrap();
+        assert_eq!(emulator.cpu.gpr[RCX], 6);
+        assert_eq!(emulator.cpu.gpr[RDI], 0x19004);
+        assert!(emulator.cpu.flag(ZF));
+
+        // repne scasb backwards
+        let mut emulator = strings(&[0xF2, 0xAE], &[], b"abc", 10, DF);
+        emulator.cpu.gpr[RAX] = b'a' as u64;
+        emulator.cpu.gpr[RDI] += 2;
+        emulator.step().unwrap();
+        assert_eq!(emulator.cpu.gpr[RCX], 7);
+        assert_eq!(emulator.cpu.gpr[RDI], 0x18FFF);
+        assert!(emulator.cpu.flag(ZF));
+
+        // repne scasb runs out of count
+        let mut emulator = strings(&[0xF2, 0xAE], &[], b"abc", 3, 0);
+        emulator.cpu.gpr[RAX] = b'x' as u64;
+        emulator.step().unwrap();
+        assert_eq!(emulator.cpu.gpr[RCX], 0);
+        assert_eq!(emulator.cpu.gpr[RDI], 0x19003);
+        assert!(!emulator.cpu.flag(ZF));
+    }
+}
@@ -0,0 +1,310 @@
+use std::{collections::BTreeMap, ops::Range, sync::Arc};
+
+use crate::{emulator::*, trace::MemoryAccess};
+
+/// identifies a hook for [Emulator::remove_hook]
+pub type HookId = u64;
+
+/// what the emulator does after a hook ran, hooks run in the order they were
+/// added until one doesn't return Continue
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HookAction {
+    /// as if there was no hook
+    Continue,
+    /// code hooks: the instruction is skipped, the hook is expected to have
+    /// updated rip, fault hooks: the fault was resolved (e.g. by mapping the
+    /// page) and the instruction is retried, interrupt hooks: the interrupt
+    /// was handled and execution continues after the instruction
+    Handled,
+    /// emulation stops with Exception::Stopped
+    Stop,
+}
+
+pub type CodeHook = Arc<dyn Fn(&mut Emulator, u64) -> HookAction + Send + Sync>;
+pub type MemoryHook = Arc<dyn Fn(&mut Emulator, &MemoryAccess) -> HookAction + Send + Sync>;
+pub type ExceptionHook = Arc<dyn Fn(&mut Emulator, Exception) -> HookAction + Send + Sync>;
+
+#[derive(Clone)]
+enum Hook {
+    /// before the instruction at an address in the range executes
+    Code(Range<u64>, CodeHook),
+    /// after an instruction read from the range
+    Read(Range<u64>, MemoryHook),
+    /// after an instruction wrote to the range
+    Write(Range<u64>, MemoryHook),
+    /// page faults (unmapped or permission-violating accesses)
+    Fault(ExceptionHook),
+    /// breakpoints, interrupts and system calls
+    Interrupt(ExceptionHook),
+}
+
+#[derive(Clone, Default)]
+pub(super) struct Hooks {
+    hooks: BTreeMap<HookId, Hook>,
+    next_id: HookId,
+    has_code: bool,
+    has_memory: bool,
+}
+
+impl Hooks {
+    fn add(&mut self, hook: Hook) -> HookId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.hooks.insert(id, hook);
+        self.update();
+        id
+    }
+
+    /// the kinds of hooks which are checked on every instruction
+    fn update(&mut self) {
+        self.has_code = self
+            .hooks
+            .values()
+            .any(|hook| matches!(hook, Hook::Code(..)));
+        self.has_memory = self
+            .hooks
+            .values()
+            .any(|hook| matches!(hook, Hook::Read(..) | Hook::Write(..)));
+    }
+}
+
+impl Emulator {
+    /// adds a hook which is called with rip before an instruction (or stub) in
+    /// the range executes
+    pub fn hook_code(
+        &mut self,
+        range: Range<u64>,
+        hook: impl Fn(&mut Emulator, u64) -> HookAction + Send + Sync + 'static,
+    ) -> HookId {
+        self.hooks.add(Hook::Code(range, Arc::new(hook)))
+    }
+
+    /// adds a hook which is called after an instruction read from the range,
+    /// it is called once per access
+    pub fn hook_read(
+        &mut self,
+        range: Range<u64>,
+        hook: impl Fn(&mut Emulator, &MemoryAccess) -> HookAction + Send + Sync + 'static,
+    ) -> HookId {
+        self.hooks.add(Hook::Read(range, Arc::new(hook)))
+    }
+
+    /// adds a hook which is called after an instruction wrote to the range
+    /// (watchpoint), it is called once per access
+    pub fn hook_write(
+        &mut self,
+        range: Range<u64>,
+        hook: impl Fn(&mut Emulator, &MemoryAccess) -> HookAction + Send + Sync + 'static,
+    ) -> HookId {
+        self.hooks.add(Hook::Write(range, Arc::new(hook)))
+    }
+
+    /// adds a hook which is called on page faults of instructions
+    pub fn hook_fault(
+        &mut self,
+        hook: impl Fn(&mut Emulator, Exception) -> HookAction + Send + Sync + 'static,
+    ) -> HookId {
+        self.hooks.add(Hook::Fault(Arc::new(hook)))
+    }
+
+    /// adds a hook which is called on breakpoints, interrupts and system
+    /// calls, rip is after the instruction
+    pub fn hook_interrupt(
+        &mut self,
+        hook: impl Fn(&mut Emulator, Exception) -> HookAction + Send + Sync + 'static,
+    ) -> HookId {
+        self.hooks.add(Hook::Interrupt(Arc::new(hook)))
+    }
+
+    /// removes a hook, returns false if there is none with the id
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let removed = self.hooks.hooks.remove(&id).is_some();
+        self.hooks.update();
+        removed
+    }
+
+    /// executes a single instruction and calls the hooks
+    pub(super) fn step_hooked(&mut self) -> Result<(), Exception> {
+        if self.hooks.has_code {
+            let rip = self.cpu.rip;
+            let hooks = self.matching_hooks(|hook| match hook {
+                Hook::Code(range, hook) if range.contains(&rip) => Some(hook.clone()),
+                _ => None,
+            });
+            for hook in hooks {
+                match hook(self, rip) {
+                    HookAction::Continue => {}
+                    HookAction::Handled => return Ok(()),
+                    HookAction::Stop => return Err(Exception::Stopped),
+                }
+            }
+        }
+
+        // accesses are collected unless they already are (e.g. while tracing)
+        let first_access = match &self.accesses {
+            Some(accesses) => Some(accesses.len()),
+            None if self.hooks.has_memory => {
+                self.accesses = Some(vec![]);
+                None
+            }
+            None => None,
+        };
+        let instruction_count = self.instruction_count;
+        let result = self.step_unhooked();
+        let accesses = match first_access {
+            Some(first_access) => self.accesses.as_ref().unwrap()[first_access..].to_vec(),
+            None => self.accesses.take().unwrap_or_default(),
+        };
+
+        // accesses of faulting instructions are repeated when retrying
+        if self.instruction_count != instruction_count {
+            for access in &accesses {
+                let access_range = access.range();
+                let overlaps = |range: &Range<u64>| {
+                    range.start < access_range.end && access_range.start < range.end
+                };
+                let hooks = self.matching_hooks(|hook| match hook {
+                    Hook::Read(range, hook) if !access.write && overlaps(range) => {
+                        Some(hook.clone())
+                    }
+                    Hook::Write(range, hook) if access.write && overlaps(range) => {
+                        Some(hook.clone())
+                    }
+                    _ => None,
+                });
+                for hook in hooks {
+                    match hook(self, access) {
+                        HookAction::Continue | HookAction::Handled => {}
+                        HookAction::Stop => return Err(Exception::Stopped),
+                    }
+                }
+            }
+        }
+
+        let Err(exception) = result else {
+            return Ok(());
+        };
+        let hooks = self.matching_hooks(|hook| match (hook, exception) {
+            (Hook::Fault(hook), Exception::PageFault { .. }) => Some(hook.clone()),
+            (
+                Hook::Interrupt(hook),
+                Exception::Breakpoint | Exception::Interrupt(_) | Exception::Syscall,
+            ) => Some(hook.clone()),
+            _ => None,
+        });
+        for hook in hooks {
+            match hook(self, exception) {
+                HookAction::Continue => {}
+                HookAction::Handled => return Ok(()),
+                HookAction::Stop => return Err(Exception::Stopped),
+            }
+        }
+        Err(exception)
+    }
+
+    fn matching_hooks<T>(&self, filter: impl Fn(&Hook) -> Option<T>) -> Vec<T> {
+        self.hooks.hooks.values().filter_map(filter).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+    use crate::emulator::tests::{emulator, LOOP};
+
+    #[test]
+    fn code() {
+        let mut emulator = emulator(LOOP);
+        let count = Arc::new(AtomicU64::new(0));
+        let hook_count = count.clone();
+        let id = emulator.hook_code(0x1005..0x1009, move |_, rip| {
+            assert_eq!(rip, 0x1005);
+            hook_count.fetch_add(1, Ordering::Relaxed);
+            HookAction::Continue
+        });
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+
+        // skip the loop
+        assert!(emulator.remove_hook(id));
+        assert!(!emulator.remove_hook(id));
+        emulator.cpu.rip = 0x1000;
+        emulator.hook_code(0x1005..0x1006, |emulator, _| {
+            emulator.cpu.rip = 0x100D;
+            HookAction::Handled
+        });
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+        assert_eq!(emulator.cpu.gpr[RCX], 3);
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn memory() {
+        let mut emulator = emulator(LOOP);
+        let rsp = emulator.cpu.gpr[RSP];
+        let writes = Arc::new(Mutex::new(vec![]));
+        let hook_writes = writes.clone();
+        emulator.hook_write(rsp + 7..rsp + 8, move |_, access| {
+            hook_writes.lock().unwrap().push(access.clone());
+            HookAction::Continue
+        });
+        emulator.hook_read(0..u64::MAX, |_, _| panic!("nothing is read"));
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+        let writes = writes.lock().unwrap();
+        assert_eq!(writes.len(), 3);
+        assert_eq!(writes[2].address, rsp);
+        assert_eq!(writes[2].data, 1u64.to_le_bytes());
+
+        // the watchpoint stops after the write
+        let mut emulator = self::emulator(LOOP);
+        emulator.hook_write(rsp..rsp + 1, |_, _| HookAction::Stop);
+        assert_eq!(emulator.run(None), Some(Exception::Stopped));
+        assert_eq!(emulator.cpu.rip, 0x1009);
+        assert_eq!(emulator.read_u64(rsp), Ok(3));
+    }
+
+    #[test]
+    fn fault() {
+        // mov rax, [0x5000] / int3
+        let mut emulator = emulator(&[0x48, 0x8B, 0x04, 0x25, 0x00, 0x50, 0x00, 0x00, 0xCC]);
+        emulator.hook_fault(|emulator, exception| {
+            assert_eq!(
+                exception,
+                Exception::PageFault {
+                    address: 0x5000,
+                    access: Access::Read
+                }
+            );
+            emulator.memory.map(0x5000, PAGE_SIZE, Protection::READ);
+            emulator.memory.write_raw(0x5000, &[0x42]).unwrap();
+            HookAction::Handled
+        });
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+        assert_eq!(emulator.cpu.gpr[RAX], 0x42);
+    }
+
+    #[test]
+    fn interrupt() {
+        // int3 / int3 / nop / int3
+        let mut emulator = emulator(&[0xCC, 0xCC, 0x90, 0xCC]);
+        let hits = Arc::new(AtomicU64::new(0));
+        let hook_hits = hits.clone();
+        emulator.hook_interrupt(move |_, exception| {
+            assert_eq!(exception, Exception::Breakpoint);
+            match hook_hits.fetch_add(1, Ordering::Relaxed) {
+                0 => HookAction::Handled,
+                1 => HookAction::Continue,
+                _ => HookAction::Stop,
+            }
+        });
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+        assert_eq!(emulator.cpu.rip, 0x1002);
+        assert_eq!(emulator.run(None), Some(Exception::Stopped));
+        assert_eq!(hits.load(Ordering::Relaxed), 3);
+    }
+}
@@ -0,0 +1,1029 @@
+use std::{collections::BTreeMap, ops::Range, sync::Arc};
+
+use crate::emulator::*;
+
+/// lowest address of anonymous and file mappings
+pub const MMAP_BASE: u64 = 0x7FFF_0000_0000;
+
+/// uid and gid of the guest
+pub const USER_ID: u64 = 1000;
+
+/// maximum size of a file in the sandboxed filesystem, larger writes fail with
+/// EFBIG
+pub const MAX_FILE_SIZE: usize = 1 << 30;
+
+// time of the first instruction, one instruction takes 1 ns
+const UNIX_TIME_BASE: u64 = 1_672_531_200;
+
+// system call numbers
+const SYS_READ: u64 = 0;
+const SYS_WRITE: u64 = 1;
+const SYS_OPEN: u64 = 2;
+const SYS_CLOSE: u64 = 3;
+const SYS_STAT: u64 = 4;
+const SYS_FSTAT: u64 = 5;
+const SYS_LSTAT: u64 = 6;
+const SYS_LSEEK: u64 = 8;
+const SYS_MMAP: u64 = 9;
+const SYS_MPROTECT: u64 = 10;
+const SYS_MUNMAP: u64 = 11;
+const SYS_BRK: u64 = 12;
+const SYS_RT_SIGACTION: u64 = 13;
+const SYS_RT_SIGPROCMASK: u64 = 14;
+const SYS_IOCTL: u64 = 16;
+const SYS_PREAD64: u64 = 17;
+const SYS_READV: u64 = 19;
+const SYS_WRITEV: u64 = 20;
+const SYS_ACCESS: u64 = 21;
+const SYS_MADVISE: u64 = 28;
+const SYS_GETPID: u64 = 39;
+const SYS_EXIT: u64 = 60;
+const SYS_KILL: u64 = 62;
+const SYS_UNAME: u64 = 63;
+const SYS_GETCWD: u64 = 79;
+const SYS_UNLINK: u64 = 87;
+const SYS_READLINK: u64 = 89;
+const SYS_GETTIMEOFDAY: u64 = 96;
+const SYS_GETUID: u64 = 102;
+const SYS_GETGID: u64 = 104;
+const SYS_GETEUID: u64 = 107;
+const SYS_GETEGID: u64 = 108;
+const SYS_GETPPID: u64 = 110;
+const SYS_SIGALTSTACK: u64 = 131;
+const SYS_ARCH_PRCTL: u64 = 158;
+const SYS_GETTID: u64 = 186;
+const SYS_TIME: u64 = 201;
+const SYS_SET_TID_ADDRESS: u64 = 218;
+const SYS_CLOCK_GETTIME: u64 = 228;
+const SYS_EXIT_GROUP: u64 = 231;
+const SYS_TGKILL: u64 = 234;
+const SYS_OPENAT: u64 = 257;
+const SYS_NEWFSTATAT: u64 = 262;
+const SYS_UNLINKAT: u64 = 263;
+const SYS_READLINKAT: u64 = 267;
+const SYS_FACCESSAT: u64 = 269;
+const SYS_SET_ROBUST_LIST: u64 = 273;
+const SYS_PRLIMIT64: u64 = 302;
+const SYS_GETRANDOM: u64 = 318;
+const SYS_RSEQ: u64 = 334;
+
+// errno
+const ENOENT: u64 = 2;
+const ESRCH: u64 = 3;
+const EBADF: u64 = 9;
+const ENOMEM: u64 = 12;
+const EFAULT: u64 = 14;
+const EEXIST: u64 = 17;
+const EISDIR: u64 = 21;
+const EINVAL: u64 = 22;
+const ENOTTY: u64 = 25;
+const EFBIG: u64 = 27;
+const ESPIPE: u64 = 29;
+const ERANGE: u64 = 34;
+const ENOSYS: u64 = 38;
+
+const AT_FDCWD: i32 = -100;
+const AT_EMPTY_PATH: u64 = 0x1000;
+
+const O_ACCMODE: u64 = 3;
+const O_RDONLY: u64 = 0;
+const O_WRONLY: u64 = 1;
+const O_CREAT: u64 = 0x40;
+const O_EXCL: u64 = 0x80;
+const O_TRUNC: u64 = 0x200;
+const O_APPEND: u64 = 0x400;
+
+const MAP_FIXED: u64 = 0x10;
+const MAP_ANONYMOUS: u64 = 0x20;
+
+const S_IFCHR: u32 = 0o020000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+/// open file description referenced by a file descriptor
+#[derive(Debug, Clone)]
+enum Descriptor {
+    Stdin,
+    Stdout,
+    Stderr,
+    File {
+        path: String,
+        position: u64,
+        readable: bool,
+        writable: bool,
+        append: bool,
+    },
+}
+
+/// emulated Linux environment: system call handlers and the state they work
+/// on
+#[derive(Clone)]
+pub struct Linux {
+    /// by system call number
+    handlers: BTreeMap<u64, Handler>,
+
+    /// sandboxed filesystem by absolute path
+    pub files: BTreeMap<String, Vec<u8>>,
+    /// absolute path without trailing slash, / for the root
+    pub current_directory: String,
+    /// path of the executable, it is also the target of /proc/self/exe
+    pub executable: String,
+    /// read by the guest from file descriptor 0
+    pub stdin: Vec<u8>,
+    /// everything written to file descriptor 1
+    pub stdout: Vec<u8>,
+    /// everything written to file descriptor 2
+    pub stderr: Vec<u8>,
+
+    descriptors: BTreeMap<u64, Descriptor>,
+    /// read position of stdin
+    stdin_position: usize,
+    /// start and current end of the program break
+    pub(super) brk: Range<u64>,
+    /// state of the deterministic random number generator
+    random: u64,
+}
+
+impl Default for Linux {
+    fn default() -> Self {
+        let mut linux = Self {
+            handlers: Default::default(),
+            files: Default::default(),
+            current_directory: "/".to_owned(),
+            executable: String::new(),
+            stdin: vec![],
+            stdout: vec![],
+            stderr: vec![],
+            descriptors: BTreeMap::from([
+                (0, Descriptor::Stdin),
+                (1, Descriptor::Stdout),
+                (2, Descriptor::Stderr),
+            ]),
+            stdin_position: 0,
+            brk: 0..0,
+            random: 0x2545_F491_4F6C_DD1D,
+        };
+        register(&mut linux);
+        linux
+    }
+}
+
+impl Linux {
+    /// registers a handler, it replaces any built-in handler of the system
+    /// call, the return value is passed in rax (-errno on failure)
+    pub fn register(
+        &mut self,
+        number: u64,
+        handler: impl Fn(&mut Emulator) -> Result<u64, Exception> + Send + Sync + 'static,
+    ) {
+        self.handlers.insert(number, Arc::new(handler));
+    }
+
+    /// handler of the system call
+    pub fn handler(&self, number: u64) -> Option<Handler> {
+        self.handlers.get(&number).cloned()
+    }
+
+    /// adds or replaces a file of the sandboxed filesystem
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) {
+        let path = self.resolve(path);
+        self.files.insert(path, data);
+    }
+
+    /// absolute path with . and .. resolved
+    pub fn resolve(&self, path: &str) -> String {
+        let path = if path.starts_with('/') {
+            path.to_owned()
+        } else {
+            format!("{}/{}", self.current_directory, path)
+        };
+        let mut components = vec![];
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    components.pop();
+                }
+                component => components.push(component),
+            }
+        }
+        format!("/{}", components.join("/"))
+    }
+
+    /// a directory exists if it contains any file
+    fn is_directory(&self, path: &str) -> bool {
+        path == "/"
+            || self
+                .files
+                .range(format!("{}/", path)..)
+                .next()
+                .is_some_and(|(file, _)| file.starts_with(&format!("{}/", path)))
+    }
+
+    fn add_descriptor(&mut self, descriptor: Descriptor) -> u64 {
+        // the lowest free number is used
+        let number = (0..)
+            .find(|number| !self.descriptors.contains_key(number))
+            .unwrap();
+        self.descriptors.insert(number, descriptor);
+        number
+    }
+
+    /// xorshift64*, seeded with a constant to stay deterministic
+    pub(super) fn random_bytes(&mut self, length: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(length);
+        while data.len() < length {
+            self.random ^= self.random >> 12;
+            self.random ^= self.random << 25;
+            self.random ^= self.random >> 27;
+            data.extend(
+                self.random
+                    .wrapping_mul(0x2545_F491_4F6C_DD1D)
+                    .to_le_bytes(),
+            );
+        }
+        data.truncate(length);
+        data
+    }
+}
+
+impl Emulator {
+    /// runs the guest and handles its system calls until another exception is
+    /// raised, the guest exits (Exception::Exit) or the limit of instructions
+    /// is reached, returns None in the latter case
+    pub fn run_linux(&mut self, limit: Option<u64>) -> Option<Exception> {
+        let start = self.instruction_count;
+        loop {
+            let remaining = limit.map(|limit| limit.saturating_sub(self.instruction_count - start));
+            match self.run(remaining) {
+                Some(Exception::Syscall) => {
+                    if let Err(exception) = self.syscall() {
+                        return Some(exception);
+                    }
+                }
+                exception => return exception,
+            }
+        }
+    }
+
+    /// handles the system call in rax, system calls without handler fail with
+    /// ENOSYS
+    pub fn syscall(&mut self) -> Result<(), Exception> {
+        let number = self.cpu.gpr[RAX];
+        let arguments: [u64; 6] = std::array::from_fn(|i| self.syscall_argument(i));
+        let result = match self.linux.handler(number) {
+            Some(handler) => handler(self)?,
+            None => error(ENOSYS),
+        };
+        self.cpu.gpr[RAX] = result;
+        self.taint_syscall(number, &arguments);
+        if let Some(symbolic) = &mut self.symbolic {
+            symbolic.set_register(RAX, None);
+        }
+        Ok(())
+    }
+
+    /// system call argument (rdi, rsi, rdx, r10, r8, r9)
+    pub fn syscall_argument(&self, index: usize) -> u64 {
+        self.cpu.gpr[[RDI, RSI, RDX, R10, R8, R9][index]]
+    }
+
+    /// reads a null-terminated utf-8 string
+    fn read_c_string(&self, address: u64) -> Result<String, Exception> {
+        let mut string = vec![];
+        loop {
+            // the string can't wrap around the address space
+            let address = address
+                .checked_add(string.len() as u64)
+                .ok_or(Exception::PageFault {
+                    address: 0,
+                    access: Access::Read,
+                })?;
+            let char = self.read_bytes(address, 1)?[0];
+            if char == 0 {
+                break;
+            }
+            string.push(char);
+        }
+        Ok(String::from_utf8_lossy(&string).into_owned())
+    }
+
+    /// resolved path of a *at system call, None if the directory is not the
+    /// current directory and the path is relative
+    fn path_at(&self, directory: u64, path: u64) -> Result<Option<String>, Exception> {
+        let path = self.read_c_string(path)?;
+        if directory as i32 != AT_FDCWD && !path.starts_with('/') {
+            return Ok(None);
+        }
+        Ok(Some(self.linux.resolve(&path)))
+    }
+
+    fn open(&mut self, path: String, flags: u64) -> u64 {
+        let exists = self.linux.files.contains_key(&path);
+        if self.linux.is_directory(&path) {
+            return error(EISDIR);
+        }
+        if exists && flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL {
+            return error(EEXIST);
+        }
+        if !exists {
+            if flags & O_CREAT == 0 {
+                return error(ENOENT);
+            }
+            self.linux.files.insert(path.clone(), vec![]);
+        }
+        let access_mode = flags & O_ACCMODE;
+        if flags & O_TRUNC != 0 && access_mode != O_RDONLY {
+            self.linux.files.get_mut(&path).unwrap().clear();
+        }
+        self.linux.add_descriptor(Descriptor::File {
+            path,
+            position: 0,
+            readable: access_mode != O_WRONLY,
+            writable: access_mode != O_RDONLY,
+            append: flags & O_APPEND != 0,
+        })
+    }
+
+    /// reads from a file descriptor at its position or the specified offset
+    fn read_descriptor(
+        &mut self,
+        number: u64,
+        length: usize,
+        offset: Option<u64>,
+    ) -> Result<Vec<u8>, u64> {
+        match self.linux.descriptors.get_mut(&number) {
+            Some(Descriptor::Stdin) if offset.is_none() => {
+                let stdin =
+                    &self.linux.stdin[self.linux.stdin_position.min(self.linux.stdin.len())..];
+                let data = stdin[..length.min(stdin.len())].to_vec();
+                self.linux.stdin_position += data.len();
+                Ok(data)
+            }
+            Some(Descriptor::Stdin) => Err(ESPIPE),
+            Some(Descriptor::File {
+                path,
+                position,
+                readable: true,
+                ..
+            }) => {
+                let file = &self.linux.files[path];
+                let start = offset.unwrap_or(*position).min(file.len() as u64) as usize;
+                let data = file[start..start.saturating_add(length).min(file.len())].to_vec();
+                if offset.is_none() {
+                    *position += data.len() as u64;
+                }
+                Ok(data)
+            }
+            _ => Err(EBADF),
+        }
+    }
+
+    fn write_descriptor(&mut self, number: u64, data: &[u8]) -> Result<(), u64> {
+        match self.linux.descriptors.get_mut(&number) {
+            Some(Descriptor::Stdout) => self.linux.stdout.extend(data),
+            Some(Descriptor::Stderr) => self.linux.stderr.extend(data),
+            Some(Descriptor::File {
+                path,
+                position,
+                writable: true,
+                append,
+                ..
+            }) => {
+                let file = self.linux.files.get_mut(path).unwrap();
+                if *append {
+                    *position = file.len() as u64;
+                }
+                let start = *position as usize;
+                let Some(end) = start
+                    .checked_add(data.len())
+                    .filter(|&end| end <= MAX_FILE_SIZE)
+                else {
+                    return Err(EFBIG);
+                };
+                if file.len() < end {
+                    file.resize(end, 0);
+                }
+                file[start..end].copy_from_slice(data);
+                *position += data.len() as u64;
+            }
+            _ => return Err(EBADF),
+        }
+        Ok(())
+    }
+
+    /// writes struct stat of a file, directory or standard stream
+    fn write_stat(&mut self, address: u64, mode: u32, size: u64) -> Result<(), Exception> {
+        let mut data = [0; 144];
+        // st_nlink, st_mode, st_uid, st_gid
+        data[16..24].copy_from_slice(&1u64.to_le_bytes());
+        data[24..28].copy_from_slice(&mode.to_le_bytes());
+        data[28..32].copy_from_slice(&(USER_ID as u32).to_le_bytes());
+        data[32..36].copy_from_slice(&(USER_ID as u32).to_le_bytes());
+        // st_size, st_blksize, st_blocks
+        data[48..56].copy_from_slice(&size.to_le_bytes());
+        data[56..64].copy_from_slice(&PAGE_SIZE.to_le_bytes());
+        data[64..72].copy_from_slice(&size.div_ceil(512).to_le_bytes());
+        self.memory.write(address, &data)
+    }
+
+    fn stat_path(&mut self, path: &str, address: u64) -> Result<u64, Exception> {
+        if let Some(file) = self.linux.files.get(path) {
+            let size = file.len() as u64;
+            self.write_stat(address, S_IFREG | 0o644, size)?;
+        } else if self.linux.is_directory(path) {
+            self.write_stat(address, S_IFDIR | 0o755, 0)?;
+        } else {
+            return Ok(error(ENOENT));
+        }
+        Ok(0)
+    }
+
+    fn stat_descriptor(&mut self, number: u64, address: u64) -> Result<u64, Exception> {
+        match self.linux.descriptors.get(&number) {
+            Some(Descriptor::File { path, .. }) => {
+                let path = path.clone();
+                self.stat_path(&path, address)
+            }
+            Some(_) => {
+                // standard streams are character devices
+                self.write_stat(address, S_IFCHR | 0o620, 0)?;
+                Ok(0)
+            }
+            None => Ok(error(EBADF)),
+        }
+    }
+
+    fn readlink(&mut self, path: &str, buffer: u64, size: u64) -> Result<u64, Exception> {
+        if path != "/proc/self/exe" {
+            return Ok(error(if self.linux.files.contains_key(path) {
+                EINVAL
+            } else {
+                ENOENT
+            }));
+        }
+        // the target is not null-terminated and silently truncated
+        let target = self.linux.executable.as_bytes();
+        let target = &target[..target.len().min(size as usize)];
+        self.memory.write(buffer, target)?;
+        Ok(target.len() as u64)
+    }
+
+    /// time since the epoch in nanoseconds
+    fn unix_time(&self) -> u64 {
+        UNIX_TIME_BASE * 1_000_000_000 + self.instruction_count
+    }
+}
+
+/// -errno as returned in rax
+fn error(errno: u64) -> u64 {
+    errno.wrapping_neg()
+}
+
+fn kill(process: u64, signal: u64) -> Result<u64, Exception> {
+    if process as i32 != PROCESS_ID as i32 && process as i32 != 0 {
+        return Ok(error(ESRCH));
+    }
+    if signal == 0 {
+        return Ok(0);
+    }
+    Err(Exception::Exit(128 + signal as u32))
+}
+
+fn protection(prot: u64) -> Protection {
+    // PROT_READ, PROT_WRITE, PROT_EXEC
+    Protection::new(prot & 1 != 0, prot & 2 != 0, prot & 4 != 0)
+}
+
+fn register(linux: &mut Linux) {
+    // files
+    linux.register(SYS_READ, |emulator| {
+        let buffer = emulator.syscall_argument(1);
+        let length = emulator.syscall_argument(2) as usize;
+        match emulator.read_descriptor(emulator.syscall_argument(0), length, None) {
+            Ok(data) => {
+                emulator.memory.write(buffer, &data)?;
+                Ok(data.len() as u64)
+            }
+            Err(errno) => Ok(error(errno)),
+        }
+    });
+    linux.register(SYS_PREAD64, |emulator| {
+        let buffer = emulator.syscall_argument(1);
+        let length = emulator.syscall_argument(2) as usize;
+        let offset = emulator.syscall_argument(3);
+        match emulator.read_descriptor(emulator.syscall_argument(0), length, Some(offset)) {
+            Ok(data) => {
+                emulator.memory.write(buffer, &data)?;
+                Ok(data.len() as u64)
+            }
+            Err(errno) => Ok(error(errno)),
+        }
+    });
+    linux.register(SYS_READV, |emulator| {
+        let descriptor = emulator.syscall_argument(0);
+        let vectors = emulator.syscall_argument(1);
+        let mut total = 0;
+        for i in 0..emulator.syscall_argument(2) {
+            let buffer = emulator.read_u64(vectors + i * 16)?;
+            let length = emulator.read_u64(vectors + i * 16 + 8)? as usize;
+            match emulator.read_descriptor(descriptor, length, None) {
+                Ok(data) => {
+                    emulator.memory.write(buffer, &data)?;
+                    total += data.len() as u64;
+                    if data.len() < length {
+                        break;
+                    }
+                }
+                Err(errno) => return Ok(error(errno)),
+            }
+        }
+        Ok(total)
+    });
+    linux.register(SYS_WRITE, |emulator| {
+        let data = emulator.read_bytes(
+            emulator.syscall_argument(1),
+            emulator.syscall_argument(2) as usize,
+        )?;
+        Ok(
+            match emulator.write_descriptor(emulator.syscall_argument(0), &data) {
+                Ok(()) => data.len() as u64,
+                Err(errno) => error(errno),
+            },
+        )
+    });
+    linux.register(SYS_WRITEV, |emulator| {
+        let descriptor = emulator.syscall_argument(0);
+        let vectors = emulator.syscall_argument(1);
+        let mut total = 0;
+        for i in 0..emulator.syscall_argument(2) {
+            let buffer = emulator.read_u64(vectors + i * 16)?;
+            let length = emulator.read_u64(vectors + i * 16 + 8)? as usize;
+            let data = emulator.read_bytes(buffer, length)?;
+            if let Err(errno) = emulator.write_descriptor(descriptor, &data) {
+                return Ok(error(errno));
+            }
+            total += length as u64;
+        }
+        Ok(total)
+    });
+    linux.register(SYS_OPEN, |emulator| {
+        let path = emulator.read_c_string(emulator.syscall_argument(0))?;
+        let path = emulator.linux.resolve(&path);
+        Ok(emulator.open(path, emulator.syscall_argument(1)))
+    });
+    linux.register(SYS_OPENAT, |emulator| {
+        let Some(path) =
+            emulator.path_at(emulator.syscall_argument(0), emulator.syscall_argument(1))?
+        else {
+            return Ok(error(EBADF));
+        };
+        Ok(emulator.open(path, emulator.syscall_argument(2)))
+    });
+    linux.register(SYS_CLOSE, |emulator| {
+        Ok(
+            match emulator
+                .linux
+                .descriptors
+                .remove(&emulator.syscall_argument(0))
+            {
+                Some(_) => 0,
+                None => error(EBADF),
+            },
+        )
+    });
+    linux.register(SYS_LSEEK, |emulator| {
+        let offset = emulator.syscall_argument(1) as i64;
+        let whence = emulator.syscall_argument(2);
+        let Some(Descriptor::File { path, position, .. }) = emulator
+            .linux
+            .descriptors
+            .get_mut(&emulator.syscall_argument(0))
+        else {
+            return Ok(error(ESPIPE));
+        };
+        let base = match whence {
+            // SEEK_SET, SEEK_CUR, SEEK_END
+            0 => 0,
+            1 => *position as i64,
+            2 => emulator.linux.files[path].len() as i64,
+            _ => return Ok(error(EINVAL)),
+        };
+        let Some(new_position) = base.checked_add(offset).filter(|&position| position >= 0) else {
+            return Ok(error(EINVAL));
+        };
+        *position = new_position as u64;
+        Ok(*position)
+    });
+    for number in [SYS_STAT, SYS_LSTAT] {
+        linux.register(number, |emulator| {
+            let path = emulator.read_c_string(emulator.syscall_argument(0))?;
+            let path = emulator.linux.resolve(&path);
+            emulator.stat_path(&path, emulator.syscall_argument(1))
+        });
+    }
+    linux.register(SYS_FSTAT, |emulator| {
+        emulator.stat_descriptor(emulator.syscall_argument(0), emulator.syscall_argument(1))
+    });
+    linux.register(SYS_NEWFSTATAT, |emulator| {
+        let directory = emulator.syscall_argument(0);
+        let path = emulator.syscall_argument(1);
+        let address = emulator.syscall_argument(2);
+        if emulator.syscall_argument(3) & AT_EMPTY_PATH != 0
+            && emulator.read_c_string(path)?.is_empty()
+        {
+            return emulator.stat_descriptor(directory, address);
+        }
+        match emulator.path_at(directory, path)? {
+            Some(path) => emulator.stat_path(&path, address),
+            None => Ok(error(EBADF)),
+        }
+    });
+    linux.register(SYS_ACCESS, |emulator| {
+        let path = emulator.read_c_string(emulator.syscall_argument(0))?;
+        let path = emulator.linux.resolve(&path);
+        Ok(
+            if emulator.linux.files.contains_key(&path) || emulator.linux.is_directory(&path) {
+                0
+            } else {
+                error(ENOENT)
+            },
+        )
+    });
+    linux.register(SYS_FACCESSAT, |emulator| {
+        let Some(path) =
+            emulator.path_at(emulator.syscall_argument(0), emulator.syscall_argument(1))?
+        else {
+            return Ok(error(EBADF));
+        };
+        Ok(
+            if emulator.linux.files.contains_key(&path) || emulator.linux.is_directory(&path) {
+                0
+            } else {
+                error(ENOENT)
+            },
+        )
+    });
+    linux.register(SYS_UNLINK, |emulator| {
+        let path = emulator.read_c_string(emulator.syscall_argument(0))?;
+        let path = emulator.linux.resolve(&path);
+        Ok(match emulator.linux.files.remove(&path) {
+            Some(_) => 0,
+            None => error(ENOENT),
+        })
+    });
+    linux.register(SYS_UNLINKAT, |emulator| {
+        let Some(path) =
+            emulator.path_at(emulator.syscall_argument(0), emulator.syscall_argument(1))?
+        else {
+            return Ok(error(EBADF));
+        };
+        Ok(match emulator.linux.files.remove(&path) {
+            Some(_) => 0,
+            None => error(ENOENT),
+        })
+    });
+    linux.register(SYS_READLINK, |emulator| {
+        let path = emulator.read_c_string(emulator.syscall_argument(0))?;
+        let path = emulator.linux.resolve(&path);
+        emulator.readlink(
+            &path,
+            emulator.syscall_argument(1),
+            emulator.syscall_argument(2),
+        )
+    });
+    linux.register(SYS_READLINKAT, |emulator| {
+        let Some(path) =
+            emulator.path_at(emulator.syscall_argument(0), emulator.syscall_argument(1))?
+        else {
+            return Ok(error(EBADF));
+        };
+        emulator.readlink(
+            &path,
+            emulator.syscall_argument(2),
+            emulator.syscall_argument(3),
+        )
+    });
+    linux.register(SYS_GETCWD, |emulator| {
+        let buffer = emulator.syscall_argument(0);
+        let mut data = emulator.linux.current_directory.as_bytes().to_vec();
+        data.push(0);
+        if (emulator.syscall_argument(1) as usize) < data.len() {
+            return Ok(error(ERANGE));
+        }
+        emulator.memory.write(buffer, &data)?;
+        Ok(data.len() as u64)
+    });
+    // no terminals
+    linux.register(SYS_IOCTL, |_| Ok(error(ENOTTY)));
+
+    // memory
+    linux.register(SYS_BRK, |emulator| {
+        let address = emulator.syscall_argument(0);
+        let brk = emulator.linux.brk.clone();
+        if address < brk.start {
+            return Ok(brk.end);
+        }
+        let current_end = brk.end.next_multiple_of(PAGE_SIZE);
+        let new_end = address.next_multiple_of(PAGE_SIZE);
+        if new_end > current_end {
+            if emulator
+                .memory
+                .find_free(current_end, new_end - current_end)
+                != Some(current_end)
+            {
+                return Ok(brk.end);
+            }
+            emulator
+                .memory
+                .map(current_end, new_end - current_end, Protection::READ_WRITE);
+        } else {
+            emulator.memory.unmap(new_end, current_end - new_end);
+        }
+        emulator.linux.brk.end = address;
+        Ok(address)
+    });
+    linux.register(SYS_MMAP, |emulator| {
+        let address = emulator.syscall_argument(0);
+        // like the kernel, a size which overflows when rounded up is too large
+        let Some(size) = emulator
+            .syscall_argument(1)
+            .checked_next_multiple_of(PAGE_SIZE)
+        else {
+            return Ok(error(ENOMEM));
+        };
+        let prot = emulator.syscall_argument(2);
+        let flags = emulator.syscall_argument(3);
+        let descriptor = emulator.syscall_argument(4);
+        let offset = emulator.syscall_argument(5);
+        if size == 0 || address % PAGE_SIZE != 0 || offset % PAGE_SIZE != 0 {
+            return Ok(error(EINVAL));
+        }
+        if address.checked_add(size).is_none() {
+            return Ok(error(ENOMEM));
+        }
+        let data = if flags & MAP_ANONYMOUS != 0 {
+            vec![]
+        } else {
+            match emulator.linux.descriptors.get(&descriptor) {
+                Some(Descriptor::File { path, .. }) => {
+                    let file = &emulator.linux.files[path];
+                    let start = (offset as usize).min(file.len());
+                    file[start..start.saturating_add(size as usize).min(file.len())].to_vec()
+                }
+                _ => return Ok(error(EBADF)),
+            }
+        };
+        let address = if flags & MAP_FIXED != 0 {
+            // replaces existing mappings
+            emulator.memory.unmap(address, size);
+            address
+        } else if address != 0 && emulator.memory.find_free(address, size) == Some(address) {
+            address
+        } else {
+            match emulator.memory.find_free(MMAP_BASE, size) {
+                Some(address) => address,
+                None => return Ok(error(ENOMEM)),
+            }
+        };
+        emulator.memory.map(address, size, protection(prot));
+        emulator.memory.write_raw(address, &data)?;
+        Ok(address)
+    });
+    linux.register(SYS_MUNMAP, |emulator| {
+        let address = emulator.syscall_argument(0);
+        if address % PAGE_SIZE != 0 {
+            return Ok(error(EINVAL));
+        }
+        emulator.memory.unmap(address, emulator.syscall_argument(1));
+        Ok(0)
+    });
+    linux.register(SYS_MPROTECT, |emulator| {
+        let address = emulator.syscall_argument(0);
+        if address % PAGE_SIZE != 0 {
+            return Ok(error(EINVAL));
+        }
+        Ok(
+            if emulator.memory.protect(
+                address,
+                emulator.syscall_argument(1),
+                protection(emulator.syscall_argument(2)),
+            ) {
+                0
+            } else {
+                error(ENOMEM)
+            },
+        )
+    });
+    linux.register(SYS_MADVISE, |_| Ok(0));
+
+    // process
+    linux.register(SYS_GETPID, |_| Ok(PROCESS_ID));
+    linux.register(SYS_GETTID, |_| Ok(PROCESS_ID));
+    linux.register(SYS_GETPPID, |_| Ok(1));
+    for number in [SYS_GETUID, SYS_GETEUID, SYS_GETGID, SYS_GETEGID] {
+        linux.register(number, |_| Ok(USER_ID));
+    }
+    linux.register(SYS_SET_TID_ADDRESS, |_| Ok(PROCESS_ID));
+    linux.register(SYS_SET_ROBUST_LIST, |_| Ok(0));
+    // glibc falls back if restartable sequences are not available
+    linux.register(SYS_RSEQ, |_| Ok(error(ENOSYS)));
+    linux.register(SYS_ARCH_PRCTL, |emulator| {
+        let address = emulator.syscall_argument(1);
+        match emulator.syscall_argument(0) {
+            // ARCH_SET_GS, ARCH_SET_FS, ARCH_GET_FS, ARCH_GET_GS
+            0x1001 => emulator.cpu.gs_base = address,
+            0x1002 => emulator.cpu.fs_base = address,
+            0x1003 => emulator.write_u64(address, emulator.cpu.fs_base)?,
+            0x1004 => emulator.write_u64(address, emulator.cpu.gs_base)?,
+            _ => return Ok(error(EINVAL)),
+        }
+        Ok(0)
+    });
+    linux.register(SYS_PRLIMIT64, |emulator| {
+        let old_limit = emulator.syscall_argument(3);
+        if old_limit != 0 {
+            let limit = match emulator.syscall_argument(1) {
+                // RLIMIT_STACK
+                3 => LINUX_STACK_SIZE,
+                // RLIM_INFINITY
+                _ => u64::MAX,
+            };
+            emulator.write_u64(old_limit, limit)?;
+            emulator.write_u64(old_limit + 8, u64::MAX)?;
+        }
+        Ok(0)
+    });
+    linux.register(SYS_UNAME, |emulator| {
+        let mut data = [0; 65 * 6];
+        for (i, field) in ["Linux", "localhost", "5.15.0", "#1 SMP", "x86_64", "(none)"]
+            .iter()
+            .enumerate()
+        {
+            data[i * 65..i * 65 + field.len()].copy_from_slice(field.as_bytes());
+        }
+        emulator.memory.write(emulator.syscall_argument(0), &data)?;
+        Ok(0)
+    });
+    for number in [SYS_EXIT, SYS_EXIT_GROUP] {
+        linux.register(number, |emulator| {
+            Err(Exception::Exit(emulator.syscall_argument(0) as u32))
+        });
+    }
+
+    // signals are never delivered
+    linux.register(SYS_RT_SIGACTION, |emulator| {
+        let old_action = emulator.syscall_argument(2);
+        if old_action != 0 {
+            emulator.memory.write(old_action, &[0; 32])?;
+        }
+        Ok(0)
+    });
+    linux.register(SYS_RT_SIGPROCMASK, |emulator| {
+        let old_set = emulator.syscall_argument(2);
+        if old_set != 0 {
+            emulator.write_u64(old_set, 0)?;
+        }
+        Ok(0)
+    });
+    linux.register(SYS_SIGALTSTACK, |_| Ok(0));
+    // signals sent to the process itself terminate it (e.g. abort), the exit
+    // code is the one reported by shells
+    linux.register(SYS_KILL, |emulator| {
+        kill(emulator.syscall_argument(0), emulator.syscall_argument(1))
+    });
+    linux.register(SYS_TGKILL, |emulator| {
+        kill(emulator.syscall_argument(0), emulator.syscall_argument(2))
+    });
+
+    // time and randomness
+    linux.register(SYS_GETTIMEOFDAY, |emulator| {
+        let time = emulator.unix_time();
+        let timeval = emulator.syscall_argument(0);
+        if timeval != 0 {
+            emulator.write_u64(timeval, time / 1_000_000_000)?;
+            emulator.write_u64(timeval + 8, time % 1_000_000_000 / 1000)?;
+        }
+        Ok(0)
+    });
+    linux.register(SYS_TIME, |emulator| {
+        let time = emulator.unix_time() / 1_000_000_000;
+        let address = emulator.syscall_argument(0);
+        if address != 0 {
+            emulator.write_u64(address, time)?;
+        }
+        Ok(time)
+    });
+    linux.register(SYS_CLOCK_GETTIME, |emulator| {
+        let time = match emulator.syscall_argument(0) {
+            // CLOCK_REALTIME, CLOCK_REALTIME_COARSE
+            0 | 5 => emulator.unix_time(),
+            _ => emulator.instruction_count(),
+        };
+        let timespec = emulator.syscall_argument(1);
+        if timespec == 0 {
+            return Ok(error(EFAULT));
+        }
+        emulator.write_u64(timespec, time / 1_000_000_000)?;
+        emulator.write_u64(timespec + 8, time % 1_000_000_000)?;
+        Ok(0)
+    });
+    linux.register(SYS_GETRANDOM, |emulator| {
+        let length = emulator.syscall_argument(1) as usize;
+        let data = emulator.linux.random_bytes(length);
+        emulator.memory.write(emulator.syscall_argument(0), &data)?;
+        Ok(length as u64)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // PROT_READ | PROT_WRITE, MAP_PRIVATE
+    const PROT_READ_WRITE: u64 = 3;
+    const MAP_PRIVATE: u64 = 2;
+
+    /// invokes the system call and returns rax
+    fn syscall(emulator: &mut Emulator, number: u64, arguments: &[u64]) -> Result<u64, Exception> {
+        emulator.cpu.gpr[RAX] = number;
+        for (&register, &argument) in [RDI, RSI, RDX, R10, R8, R9].iter().zip(arguments) {
+            emulator.cpu.gpr[register] = argument;
+        }
+        emulator.syscall()?;
+        Ok(emulator.cpu.gpr[RAX])
+    }
+
+    #[test]
+    fn mmap() {
+        let mut emulator = Emulator::new();
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+        let address = syscall(
+            &mut emulator,
+            SYS_MMAP,
+            &[0, 0x1800, PROT_READ_WRITE, flags, u64::MAX, 0],
+        );
+        assert_eq!(address, Ok(MMAP_BASE));
+        assert_eq!(
+            emulator.memory.protection(MMAP_BASE + 0x1000),
+            Some(Protection::READ_WRITE)
+        );
+
+        // the size overflows when rounded up to pages
+        let address = syscall(
+            &mut emulator,
+            SYS_MMAP,
+            &[0, u64::MAX - 0x10, PROT_READ_WRITE, flags, u64::MAX, 0],
+        );
+        assert_eq!(address, Ok(error(ENOMEM)));
+        // the mapping wraps around the address space
+        let address = syscall(
+            &mut emulator,
+            SYS_MMAP,
+            &[
+                u64::MAX - (PAGE_SIZE - 1),
+                2 * PAGE_SIZE,
+                PROT_READ_WRITE,
+                flags | MAP_FIXED,
+                u64::MAX,
+                0,
+            ],
+        );
+        assert_eq!(address, Ok(error(ENOMEM)));
+    }
+
+    #[test]
+    fn string_at_end_of_address_space() {
+        let mut emulator = Emulator::new();
+        let page = u64::MAX - (PAGE_SIZE - 1);
+        emulator.memory.map(page, PAGE_SIZE, Protection::READ);
+        emulator
+            .memory
+            .write_raw(page, &[b'a'; PAGE_SIZE as usize])
+            .unwrap();
+        assert_eq!(
+            emulator.read_c_string(page + 0xFF0),
+            Err(Exception::PageFault {
+                address: 0,
+                access: Access::Read,
+            })
+        );
+        emulator.memory.write_raw(u64::MAX, &[0]).unwrap();
+        assert_eq!(emulator.read_c_string(page + 0xFF0), Ok("a".repeat(15)));
+    }
+
+    #[test]
+    fn file_size_limit() {
+        let mut emulator = Emulator::new();
+        let descriptor = emulator.open("/file".to_owned(), O_WRONLY | O_CREAT);
+        assert_eq!(emulator.write_descriptor(descriptor, b"data"), Ok(()));
+        let position = syscall(
+            &mut emulator,
+            SYS_LSEEK,
+            &[descriptor, MAX_FILE_SIZE as u64 - 2, 0],
+        );
+        assert_eq!(position, Ok(MAX_FILE_SIZE as u64 - 2));
+        assert_eq!(emulator.write_descriptor(descriptor, b"data"), Err(EFBIG));
+        assert_eq!(emulator.linux.files["/file"], b"data");
+    }
+}
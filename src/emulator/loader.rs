@@ -0,0 +1,392 @@
+use object::{
+    pe::{
+        ImageNtHeaders64, IMAGE_FILE_DLL, IMAGE_REL_BASED_ABSOLUTE, IMAGE_REL_BASED_DIR64,
+        IMAGE_REL_BASED_HIGHLOW, IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_WRITE,
+    },
+    read::pe::{ImageNtHeaders, ImageOptionalHeader, Import, PeFile64},
+    LittleEndian as LE,
+};
+
+use crate::{call::CallingConvention, emulator::*, module::Module, Result};
+
+/// base used if the image can't be mapped at its preferred base
+pub const IMAGE_BASE: u64 = 0x1_4000_0000;
+
+/// lowest address of the stack mapped by [Emulator::start]
+pub const STACK_BASE: u64 = 0x10_0000;
+
+const DLL_PROCESS_ATTACH: u64 = 1;
+
+/// PE image mapped into the emulator
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub name: String,
+    pub base: u64,
+    pub size: u64,
+    pub entry_point: u64,
+    pub is_dll: bool,
+    /// reserved stack size of the main thread
+    pub stack_size: u64,
+    /// stubs bound to the import address table
+    pub imports: Vec<ImageImport>,
+}
+
+/// import bound to a stub
+#[derive(Debug, Clone)]
+pub struct ImageImport {
+    pub library: String,
+    /// function name or #ordinal
+    pub function: String,
+    /// address of the slot in the import address table
+    pub thunk: u64,
+    /// address of the stub
+    pub stub: u64,
+}
+
+impl Image {
+    /// module view of the mapped image (e.g. for symbols)
+    pub fn module(&self, emulator: &Emulator) -> Result<Module> {
+        Module::from_memory(&emulator.memory, self.name.clone(), self.base as usize)
+    }
+}
+
+impl Emulator {
+    /// maps a PE64 file, applies base relocations if it can't be mapped at its
+    /// preferred base and binds all imports to stubs
+    pub fn load(&mut self, name: &str, data: &[u8]) -> Result<Image> {
+        let file = PeFile64::parse(data)?;
+        let optional_header = file.nt_headers().optional_header();
+        let preferred_base = optional_header.image_base();
+        let size = (optional_header.size_of_image() as u64).next_multiple_of(PAGE_SIZE);
+        let base = if self.memory.find_free(preferred_base, size) == Some(preferred_base) {
+            preferred_base
+        } else {
+            self.memory
+                .find_free(IMAGE_BASE, size)
+                .ok_or(Exception::OutOfMemory)?
+        };
+
+        // map headers and sections
+        self.memory.map(base, size, Protection::READ_WRITE);
+        let headers_size = optional_header.size_of_headers() as usize;
+        self.memory
+            .write_raw(base, &data[..headers_size.min(data.len())])?;
+        let sections = file.section_table();
+        for section in sections.iter() {
+            let mut section_data = section.pe_data(data)?;
+            // the raw data is padded to the file alignment
+            let virtual_size = section.virtual_size.get(LE) as usize;
+            if virtual_size != 0 {
+                section_data = &section_data[..section_data.len().min(virtual_size)];
+            }
+            self.memory
+                .write_raw(base + section.virtual_address.get(LE) as u64, section_data)?;
+        }
+
+        // rebase, the image base in the headers is updated like the windows
+        // loader does, so that parsing the mapped image yields relocated
+        // addresses
+        let delta = base.wrapping_sub(preferred_base);
+        if delta != 0 {
+            if let Some(mut blocks) = file.data_directories().relocation_blocks(data, &sections)? {
+                while let Some(block) = blocks.next()? {
+                    for relocation in block {
+                        let address = base + relocation.virtual_address as u64;
+                        match relocation.typ {
+                            IMAGE_REL_BASED_ABSOLUTE => {}
+                            IMAGE_REL_BASED_DIR64 => {
+                                let mut value = [0; 8];
+                                self.memory.read_raw(address, &mut value)?;
+                                let value = u64::from_le_bytes(value).wrapping_add(delta);
+                                self.memory.write_raw(address, &value.to_le_bytes())?;
+                            }
+                            IMAGE_REL_BASED_HIGHLOW => {
+                                let mut value = [0; 4];
+                                self.memory.read_raw(address, &mut value)?;
+                                let value = u32::from_le_bytes(value).wrapping_add(delta as u32);
+                                self.memory.write_raw(address, &value.to_le_bytes())?;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            // e_lfanew + signature + file header + offset of ImageBase
+            let image_base_offset = file.dos_header().nt_headers_offset() as u64 + 4 + 20 + 24;
+            self.memory
+                .write_raw(base + image_base_offset, &base.to_le_bytes())?;
+        }
+
+        // bind imports to stubs
+        let mut imports = vec![];
+        if let Some(import_table) = file.import_table()? {
+            let mut descriptors = import_table.descriptors()?;
+            while let Some(descriptor) = descriptors.next()? {
+                let library = String::from_utf8_lossy(import_table.name(descriptor.name.get(LE))?)
+                    .into_owned();
+                let first_thunk = descriptor.first_thunk.get(LE);
+                let lookup_thunk = match descriptor.original_first_thunk.get(LE) {
+                    0 => first_thunk,
+                    original_first_thunk => original_first_thunk,
+                };
+                let mut thunks = import_table.thunks(lookup_thunk)?;
+                let mut thunk = base + first_thunk as u64;
+                while let Some(thunk_data) = thunks.next::<ImageNtHeaders64>()? {
+                    let function = match import_table.import::<ImageNtHeaders64>(thunk_data)? {
+                        Import::Ordinal(ordinal) => format!("#{}", ordinal),
+                        Import::Name(_, name) => String::from_utf8_lossy(name).into_owned(),
+                    };
+                    let stub = self.add_stub(&library, &function);
+                    self.memory.write_raw(thunk, &stub.to_le_bytes())?;
+                    imports.push(ImageImport {
+                        library: library.clone(),
+                        function,
+                        thunk,
+                        stub,
+                    });
+                    thunk += 8;
+                }
+            }
+        }
+
+        // apply section protections, headers are read-only
+        self.memory.protect(base, size, Protection::READ);
+        for section in sections.iter() {
+            let characteristics = section.characteristics.get(LE);
+            let section_size =
+                (section.virtual_size.get(LE)).max(section.size_of_raw_data.get(LE)) as u64;
+            self.memory.protect(
+                base + section.virtual_address.get(LE) as u64,
+                section_size,
+                Protection::new(
+                    true,
+                    characteristics & IMAGE_SCN_MEM_WRITE != 0,
+                    characteristics & IMAGE_SCN_MEM_EXECUTE != 0,
+                ),
+            );
+        }
+
+        Ok(Image {
+            name: name.to_owned(),
+            base,
+            size,
+            entry_point: match optional_header.address_of_entry_point() {
+                0 => 0,
+                entry_point => base + entry_point as u64,
+            },
+            is_dll: file.nt_headers().file_header().characteristics.get(LE) & IMAGE_FILE_DLL != 0,
+            stack_size: optional_header.size_of_stack_reserve(),
+            imports,
+        })
+    }
+
+    /// runs the tls callbacks and the entry point of the image, images without
+    /// entry point only run their tls callbacks, a stack is mapped if there is
+    /// none yet
+    pub fn start(&mut self, image: &Image) -> Result<u64> {
+        self.map_image_stack(image)?;
+        let module = image.module(self)?;
+        let symbols = module.symbols()?;
+        let tls_callbacks = symbols
+            .iter()
+            .filter(|(name, _)| name.starts_with("tls_callback_"))
+            .map(|&(_, address)| address as u64);
+        for tls_callback in tls_callbacks {
+            self.call(
+                tls_callback,
+                CallingConvention::Win64,
+                &[image.base, DLL_PROCESS_ATTACH, 0],
+            )?;
+        }
+        if image.entry_point == 0 {
+            return Ok(0);
+        }
+        Ok(self.call(
+            image.entry_point,
+            CallingConvention::Win64,
+            &[image.base, DLL_PROCESS_ATTACH, 0],
+        )?)
+    }
+
+    /// maps a stack of the reserved size of the image if there is none yet
+    pub(super) fn map_image_stack(&mut self, image: &Image) -> std::result::Result<(), Exception> {
+        if self.cpu.gpr[RSP] == 0 {
+            let stack_size = image.stack_size.next_multiple_of(PAGE_SIZE);
+            let stack = self
+                .memory
+                .find_free(STACK_BASE, stack_size)
+                .ok_or(Exception::OutOfMemory)?;
+            self.map_stack(stack, stack_size);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::unpack::{rebuild_imports, tests::image, Import};
+
+    const BASE: u64 = crate::unpack::tests::BASE as u64;
+
+    /// file with relocations, a tls callback at 0x1080 which stores its image
+    /// base at 0x2030 and an entry point which calls an import by name and one
+    /// by ordinal and adds the results to the stored base
+    fn file() -> Vec<u8> {
+        let mut data = image();
+        let mut put = |offset: usize, value: &[u8]| {
+            data[offset..offset + value.len()].copy_from_slice(value);
+        };
+        // reserved stack size, IMAGE_DIRECTORY_ENTRY_BASERELOC and
+        // IMAGE_DIRECTORY_ENTRY_TLS
+        put(0xE0, &0x10000u64.to_le_bytes());
+        put(0x130, &0x2080u32.to_le_bytes());
+        put(0x134, &16u32.to_le_bytes());
+        put(0x150, &0x2058u32.to_le_bytes());
+        put(0x154, &40u32.to_le_bytes());
+
+        // sub rsp, 0x28 / call [rip+0x2040] / mov rcx, rax / call [rip+0x2048] /
+        // add rsp, 0x28 / add rax, [rip+0x2030] / ret
+        put(
+            0x1000,
+            &[
+                0x48, 0x83, 0xEC, 0x28, 0xFF, 0x15, 0x36, 0x10, 0x00, 0x00, 0x48, 0x89, 0xC1, 0xFF,
+                0x15, 0x35, 0x10, 0x00, 0x00, 0x48, 0x83, 0xC4, 0x28, 0x48, 0x03, 0x05, 0x12, 0x10,
+                0x00, 0x00, 0xC3,
+            ],
+        );
+        // mov [rip+0x2030], rcx / ret
+        put(0x1080, &[0x48, 0x89, 0x0D, 0xA9, 0x0F, 0x00, 0x00, 0xC3]);
+
+        // tls callbacks, a 32-bit address and the address of the callbacks in
+        // the tls directory
+        put(0x2000, &(BASE + 0x1080).to_le_bytes());
+        put(0x2010, &((BASE + 0x1234) as u32).to_le_bytes());
+        put(0x2058 + 24, &(BASE + 0x2000).to_le_bytes());
+        // relocation block of the page at 0x2000
+        put(0x2080, &0x2000u32.to_le_bytes());
+        put(0x2084, &16u32.to_le_bytes());
+        for (i, entry) in [
+            IMAGE_REL_BASED_DIR64 << 12,
+            IMAGE_REL_BASED_DIR64 << 12 | 0x70,
+            IMAGE_REL_BASED_HIGHLOW << 12 | 0x10,
+            IMAGE_REL_BASED_ABSOLUTE << 12,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            put(0x2088 + i * 2, &entry.to_le_bytes());
+        }
+
+        let imports = [
+            Import {
+                library: "kernel32.dll".to_owned(),
+                function: "Sleep".to_owned(),
+                thunk: BASE as usize + 0x2040,
+            },
+            Import {
+                library: "user32.dll".to_owned(),
+                function: "#5".to_owned(),
+                thunk: BASE as usize + 0x2048,
+            },
+        ];
+        rebuild_imports(data, BASE as usize, &imports).unwrap()
+    }
+
+    #[test]
+    fn relocations() {
+        let data = file();
+        for occupied in [false, true] {
+            let mut emulator = Emulator::new();
+            if occupied {
+                emulator.memory.map(BASE, PAGE_SIZE, Protection::READ);
+            }
+            let image = emulator.load("test.exe", &data).unwrap();
+            assert_eq!(image.base == BASE, !occupied);
+            assert_eq!(image.entry_point, image.base + 0x1000);
+            assert_eq!(image.stack_size, 0x10000);
+
+            assert_eq!(
+                emulator.read_u64(image.base + 0x2000).unwrap(),
+                image.base + 0x1080
+            );
+            assert_eq!(
+                emulator.read_u64(image.base + 0x2070).unwrap(),
+                image.base + 0x2000
+            );
+            assert_eq!(
+                emulator.read_u32(image.base + 0x2010).unwrap(),
+                (image.base + 0x1234) as u32
+            );
+            // the image base in the headers is updated
+            assert_eq!(emulator.read_u64(image.base + 0xB0).unwrap(), image.base);
+            let module = image.module(&emulator).unwrap();
+            assert!(module
+                .symbols()
+                .unwrap()
+                .contains(&("tls_callback_0".to_owned(), image.base as usize + 0x1080)));
+        }
+    }
+
+    #[test]
+    fn imports() {
+        let mut emulator = Emulator::new();
+        emulator.memory.map(BASE, PAGE_SIZE, Protection::READ);
+        let image = emulator.load("test.exe", &file()).unwrap();
+        assert_eq!(image.imports.len(), 2);
+        for (library, function, thunk) in [
+            ("kernel32.dll", "Sleep", 0x2040),
+            ("user32.dll", "#5", 0x2048),
+        ] {
+            let import = image
+                .imports
+                .iter()
+                .find(|import| import.function == function)
+                .unwrap();
+            assert_eq!(import.library, library);
+            assert_eq!(import.thunk, image.base + thunk);
+            assert_eq!(emulator.read_u64(import.thunk).unwrap(), import.stub);
+            let stub = emulator.stub(import.stub).unwrap();
+            assert_eq!((&*stub.library, &*stub.function), (library, function));
+        }
+
+        // the guest calls through the import address table
+        let stub = |function: &str| {
+            image
+                .imports
+                .iter()
+                .find(|import| import.function == function)
+                .unwrap()
+                .stub
+        };
+        emulator.set_handler(stub("Sleep"), Arc::new(|_| Ok(0x10)));
+        emulator.set_handler(
+            stub("#5"),
+            Arc::new(|emulator| Ok(emulator.cpu.gpr[RCX] + 0x100)),
+        );
+        emulator.write_u64(image.base + 0x2030, 0).unwrap();
+        emulator.map_image_stack(&image).unwrap();
+        assert_eq!(
+            emulator
+                .call(image.entry_point, CallingConvention::Win64, &[])
+                .unwrap(),
+            0x110
+        );
+    }
+
+    #[test]
+    fn tls_callbacks() {
+        let mut emulator = Emulator::new();
+        emulator.memory.map(BASE, PAGE_SIZE, Protection::READ);
+        let image = emulator.load("test.exe", &file()).unwrap();
+        for import in &image.imports {
+            emulator.set_handler(import.stub, Arc::new(|_| Ok(0)));
+        }
+        // the entry point returns the base stored by the tls callback
+        assert_eq!(emulator.start(&image).unwrap(), image.base);
+        assert_eq!(emulator.read_u64(image.base + 0x2030).unwrap(), image.base);
+        assert_ne!(emulator.cpu.gpr[RSP], 0);
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+    sync::Arc,
+};
+
+use crate::{
+    emulator::{Access, Exception},
+    memory::Memory,
+};
+
+pub const PAGE_SIZE: u64 = 0x1000;
+
+/// protection of a page
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Protection {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Protection {
+    pub const NONE: Self = Self::new(false, false, false);
+    pub const READ: Self = Self::new(true, false, false);
+    pub const READ_WRITE: Self = Self::new(true, true, false);
+    pub const READ_EXECUTE: Self = Self::new(true, false, true);
+    pub const READ_WRITE_EXECUTE: Self = Self::new(true, true, true);
+
+    pub const fn new(read: bool, write: bool, execute: bool) -> Self {
+        Self {
+            read,
+            write,
+            execute,
+        }
+    }
+
+    pub fn allows(self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Page {
+    /// shared with snapshots and forks until written
+    data: Arc<[u8; PAGE_SIZE as usize]>,
+    protection: Protection,
+}
+
+/// sparse guest address space made of 4 KiB pages, clones share the page
+/// content copy-on-write
+#[derive(Clone, Default)]
+pub struct PagedMemory {
+    pages: BTreeMap<u64, Page>,
+    /// pages mapped, unmapped, protected or written since the snapshot
+    dirty: BTreeSet<u64>,
+    /// id of the snapshot the dirty pages are relative to, 0 if none
+    snapshot: u64,
+}
+
+impl PagedMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// maps zero-filled pages covering the range, pages which are already
+    /// mapped keep their content and only get the new protection
+    pub fn map(&mut self, address: u64, size: u64, protection: Protection) {
+        for page in pages(address, size) {
+            self.dirty.insert(page);
+            self.pages
+                .entry(page)
+                .and_modify(|page| page.protection = protection)
+                .or_insert_with(|| Page {
+                    data: Arc::new([0; PAGE_SIZE as usize]),
+                    protection,
+                });
+        }
+    }
+
+    /// unmaps all pages covering the range
+    pub fn unmap(&mut self, address: u64, size: u64) {
+        for page in pages(address, size) {
+            if self.pages.remove(&page).is_some() {
+                self.dirty.insert(page);
+            }
+        }
+    }
+
+    /// changes the protection of all pages covering the range, fails without
+    /// changing anything if a page is not mapped
+    pub fn protect(&mut self, address: u64, size: u64, protection: Protection) -> bool {
+        if !pages(address, size).all(|page| self.pages.contains_key(&page)) {
+            return false;
+        }
+        for page in pages(address, size) {
+            self.dirty.insert(page);
+            self.pages.get_mut(&page).unwrap().protection = protection;
+        }
+        true
+    }
+
+    /// protection of the page containing the address, None if not mapped
+    pub fn protection(&self, address: u64) -> Option<Protection> {
+        self.pages
+            .get(&(address & !(PAGE_SIZE - 1)))
+            .map(|page| page.protection)
+    }
+
+    /// lowest free range of the specified size at or above the address
+    pub fn find_free(&self, address: u64, size: u64) -> Option<u64> {
+        let size = size.next_multiple_of(PAGE_SIZE);
+        let mut start = address.next_multiple_of(PAGE_SIZE);
+        for (&page, _) in self.pages.range(start..) {
+            if page - start >= size {
+                break;
+            }
+            start = page + PAGE_SIZE;
+        }
+        start.checked_add(size).map(|_| start)
+    }
+
+    /// contiguous mapped ranges with the same protection
+    pub fn regions(&self) -> Vec<(Range<u64>, Protection)> {
+        let mut regions: Vec<(Range<u64>, Protection)> = vec![];
+        for (&address, page) in &self.pages {
+            match regions.last_mut() {
+                Some((range, protection))
+                    if range.end == address && *protection == page.protection =>
+                {
+                    range.end += PAGE_SIZE
+                }
+                _ => regions.push((address..address + PAGE_SIZE, page.protection)),
+            }
+        }
+        regions
+    }
+
+    /// addresses of the pages changed since the last snapshot was taken or
+    /// restored
+    pub fn dirty_pages(&self) -> &BTreeSet<u64> {
+        &self.dirty
+    }
+
+    /// starts tracking changes relative to a new snapshot, returns the
+    /// memory of the snapshot
+    pub(super) fn snapshot(&mut self, id: u64) -> Self {
+        self.dirty.clear();
+        self.snapshot = id;
+        self.clone()
+    }
+
+    /// returns to the memory of a snapshot, only dirty pages are restored if
+    /// the changes are tracked relative to it
+    pub(super) fn restore(&mut self, snapshot: &Self, id: u64) {
+        if self.snapshot == id {
+            for address in std::mem::take(&mut self.dirty) {
+                match snapshot.pages.get(&address) {
+                    Some(page) => self.pages.insert(address, page.clone()),
+                    None => self.pages.remove(&address),
+                };
+            }
+        } else {
+            self.pages = snapshot.pages.clone();
+            self.dirty.clear();
+            self.snapshot = id;
+        }
+    }
+
+    /// reads memory as the guest, access is either read or execute
+    pub fn read(&self, address: u64, data: &mut [u8], access: Access) -> Result<(), Exception> {
+        self.read_checked(address, data, Some(access))
+    }
+
+    /// writes memory as the guest
+    pub fn write(&mut self, address: u64, data: &[u8]) -> Result<(), Exception> {
+        self.write_checked(address, data, true)
+    }
+
+    /// reads memory ignoring the protection (e.g. for loaders)
+    pub fn read_raw(&self, address: u64, data: &mut [u8]) -> Result<(), Exception> {
+        self.read_checked(address, data, None)
+    }
+
+    /// writes memory ignoring the protection (e.g. for loaders)
+    pub fn write_raw(&mut self, address: u64, data: &[u8]) -> Result<(), Exception> {
+        self.write_checked(address, data, false)
+    }
+
+    /// reads as many executable bytes as possible, up to data.len()
+    pub fn fetch(&self, address: u64, data: &mut [u8]) -> usize {
+        let mut length = 0;
+        while length < data.len() {
+            let current = address.wrapping_add(length as u64);
+            let Some(page) = self.pages.get(&(current & !(PAGE_SIZE - 1))) else {
+                break;
+            };
+            if !page.protection.execute {
+                break;
+            }
+            let offset = (current % PAGE_SIZE) as usize;
+            let chunk = (data.len() - length).min(PAGE_SIZE as usize - offset);
+            data[length..length + chunk].copy_from_slice(&page.data[offset..offset + chunk]);
+            length += chunk;
+        }
+        length
+    }
+
+    fn read_checked(
+        &self,
+        address: u64,
+        data: &mut [u8],
+        access: Option<Access>,
+    ) -> Result<(), Exception> {
+        let mut done = 0;
+        while done < data.len() {
+            let current = address.wrapping_add(done as u64);
+            let page = self
+                .pages
+                .get(&(current & !(PAGE_SIZE - 1)))
+                .filter(|page| access.is_none_or(|access| page.protection.allows(access)))
+                .ok_or(Exception::PageFault {
+                    address: current,
+                    access: access.unwrap_or(Access::Read),
+                })?;
+            let offset = (current % PAGE_SIZE) as usize;
+            let chunk = (data.len() - done).min(PAGE_SIZE as usize - offset);
+            data[done..done + chunk].copy_from_slice(&page.data[offset..offset + chunk]);
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    fn write_checked(&mut self, address: u64, data: &[u8], check: bool) -> Result<(), Exception> {
+        // check all pages first, a faulting write must not be partially visible
+        for page in pages(address, data.len() as u64) {
+            if !self
+                .pages
+                .get(&page)
+                .is_some_and(|page| !check || page.protection.write)
+            {
+                return Err(Exception::PageFault {
+                    address: page.max(address),
+                    access: Access::Write,
+                });
+            }
+        }
+        let mut done = 0;
+        while done < data.len() {
+            let current = address.wrapping_add(done as u64);
+            let address = current & !(PAGE_SIZE - 1);
+            self.dirty.insert(address);
+            let page = self.pages.get_mut(&address).unwrap();
+            let offset = (current % PAGE_SIZE) as usize;
+            let chunk = (data.len() - done).min(PAGE_SIZE as usize - offset);
+            Arc::make_mut(&mut page.data)[offset..offset + chunk]
+                .copy_from_slice(&data[done..done + chunk]);
+            done += chunk;
+        }
+        Ok(())
+    }
+}
+
+impl Memory for PagedMemory {
+    fn read(&self, address: usize, data: &mut [u8]) -> crate::Result<()> {
+        Ok(self.read_raw(address as u64, data)?)
+    }
+
+    fn write(&mut self, address: usize, data: &[u8]) -> crate::Result<()> {
+        Ok(self.write_raw(address as u64, data)?)
+    }
+}
+
+/// addresses of all pages covering the range
+pub(super) fn pages(address: u64, size: u64) -> impl Iterator<Item = u64> {
+    let first = address & !(PAGE_SIZE - 1);
+    let count = if size == 0 {
+        0
+    } else {
+        (address.wrapping_add(size - 1) & !(PAGE_SIZE - 1)).wrapping_sub(first) / PAGE_SIZE + 1
+    };
+    (0..count).map(move |i| first.wrapping_add(i * PAGE_SIZE))
+}
@@ -0,0 +1,652 @@
+use std::mem::{offset_of, size_of};
+
+use crate::{
+    emulator::*,
+    process::{
+        LDR_DATA_TABLE_ENTRY, LIST_ENTRY, PEB, PEB_LDR_DATA, RTL_USER_PROCESS_PARAMETERS, TEB,
+        UNICODE_STRING,
+    },
+};
+
+/// lowest address of the TEB, PEB, loader data and process parameters
+pub const PROCESS_BASE: u64 = 0x7FFD_0000_0000;
+
+pub const PROCESS_ID: u64 = 0x1000;
+pub const THREAD_ID: u64 = 0x1004;
+
+// FLG_HEAP_ENABLE_TAIL_CHECK | FLG_HEAP_ENABLE_FREE_CHECK |
+// FLG_HEAP_VALIDATE_PARAMETERS, set when started under a debugger
+const DEBUG_GLOBAL_FLAGS: u32 = 0x70;
+
+/// process as seen by the guest
+#[derive(Debug, Clone)]
+pub struct ProcessParameters {
+    pub image_path: String,
+    pub command_line: String,
+    /// with trailing backslash
+    pub current_directory: String,
+    pub environment: Vec<(String, String)>,
+    pub being_debugged: bool,
+}
+
+impl ProcessParameters {
+    /// parameters of an image started without arguments from its directory
+    pub fn new(image_path: &str) -> Self {
+        let current_directory = match image_path.rfind('\\') {
+            Some(index) => image_path[..=index].to_owned(),
+            None => "C:\\".to_owned(),
+        };
+        Self {
+            image_path: image_path.to_owned(),
+            command_line: format!("\"{}\"", image_path),
+            current_directory,
+            environment: [
+                ("ComputerName", "DESKTOP"),
+                ("OS", "Windows_NT"),
+                ("Path", "C:\\Windows\\system32;C:\\Windows"),
+                ("SystemDrive", "C:"),
+                ("SystemRoot", "C:\\Windows"),
+                ("TEMP", "C:\\Users\\user\\AppData\\Local\\Temp"),
+                ("TMP", "C:\\Users\\user\\AppData\\Local\\Temp"),
+                ("USERNAME", "user"),
+                ("USERPROFILE", "C:\\Users\\user"),
+                ("windir", "C:\\Windows"),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect(),
+            being_debugged: false,
+        }
+    }
+}
+
+impl Emulator {
+    /// lays out TEB, PEB, loader data and process parameters in guest memory,
+    /// points gs to the TEB and adds the image as first module, returns the
+    /// address of the PEB
+    pub fn setup_process(
+        &mut self,
+        image: &Image,
+        parameters: &ProcessParameters,
+    ) -> Result<u64, Exception> {
+        self.map_image_stack(image)?;
+        let rsp = self.cpu.gpr[RSP];
+        let stack = self
+            .memory
+            .regions()
+            .into_iter()
+            .map(|(range, _)| range)
+            .find(|range| range.contains(&(rsp - 1)))
+            .unwrap_or(rsp..rsp);
+
+        // process parameters followed by their strings
+        let image_path = utf16(&parameters.image_path);
+        let command_line = utf16(&parameters.command_line);
+        let current_directory = utf16(&parameters.current_directory);
+        let mut environment = vec![];
+        for (name, value) in &parameters.environment {
+            environment.extend(utf16(&format!("{}={}", name, value)));
+        }
+        environment.extend([0, 0]);
+        let process_parameters = self.allocate_process_data(
+            (size_of::<RTL_USER_PROCESS_PARAMETERS>()
+                + image_path.len()
+                + command_line.len()
+                + current_directory.len()
+                + environment.len()) as u64
+                + 4 * 8,
+        )?;
+        let mut cursor = process_parameters + size_of::<RTL_USER_PROCESS_PARAMETERS>() as u64;
+        let size = size_of::<RTL_USER_PROCESS_PARAMETERS>() as u32;
+        self.write_field(
+            process_parameters,
+            offset_of!(RTL_USER_PROCESS_PARAMETERS, MaximumLength),
+            &size.to_le_bytes(),
+        )?;
+        self.write_field(
+            process_parameters,
+            offset_of!(RTL_USER_PROCESS_PARAMETERS, Length),
+            &size.to_le_bytes(),
+        )?;
+        // RTL_USER_PROC_PARAMS_NORMALIZED
+        self.write_field(
+            process_parameters,
+            offset_of!(RTL_USER_PROCESS_PARAMETERS, Flags),
+            &1u32.to_le_bytes(),
+        )?;
+        self.write_unicode_string(
+            process_parameters + offset_of!(RTL_USER_PROCESS_PARAMETERS, ImagePathName) as u64,
+            &mut cursor,
+            &image_path,
+        )?;
+        self.write_unicode_string(
+            process_parameters + offset_of!(RTL_USER_PROCESS_PARAMETERS, CommandLine) as u64,
+            &mut cursor,
+            &command_line,
+        )?;
+        self.write_unicode_string(
+            process_parameters
+                + offset_of!(RTL_USER_PROCESS_PARAMETERS, CurrentDirectory.DosPath) as u64,
+            &mut cursor,
+            &current_directory,
+        )?;
+        self.memory.write_raw(cursor, &environment)?;
+        self.write_field(
+            process_parameters,
+            offset_of!(RTL_USER_PROCESS_PARAMETERS, Environment),
+            &cursor.to_le_bytes(),
+        )?;
+        self.write_field(
+            process_parameters,
+            offset_of!(RTL_USER_PROCESS_PARAMETERS, EnvironmentSize),
+            &(environment.len() as u64).to_le_bytes(),
+        )?;
+
+        // PEB followed by the loader data
+        let peb =
+            self.allocate_process_data((size_of::<PEB>() + size_of::<PEB_LDR_DATA>()) as u64)?;
+        let ldr = peb + size_of::<PEB>() as u64;
+        self.write_field(
+            peb,
+            offset_of!(PEB, BeingDebugged),
+            &[parameters.being_debugged as u8],
+        )?;
+        self.write_field(
+            peb,
+            offset_of!(PEB, ImageBaseAddress),
+            &image.base.to_le_bytes(),
+        )?;
+        self.write_field(peb, offset_of!(PEB, Ldr), &ldr.to_le_bytes())?;
+        self.write_field(
+            peb,
+            offset_of!(PEB, ProcessParameters),
+            &process_parameters.to_le_bytes(),
+        )?;
+        self.write_field(
+            peb,
+            offset_of!(PEB, NumberOfProcessors),
+            &4u32.to_le_bytes(),
+        )?;
+        if parameters.being_debugged {
+            self.write_field(
+                peb,
+                offset_of!(PEB, NtGlobalFlag),
+                &DEBUG_GLOBAL_FLAGS.to_le_bytes(),
+            )?;
+        }
+        // Windows 10 22H2
+        self.write_field(peb, offset_of!(PEB, OSMajorVersion), &10u32.to_le_bytes())?;
+        self.write_field(peb, offset_of!(PEB, OSMinorVersion), &0u32.to_le_bytes())?;
+        self.write_field(peb, offset_of!(PEB, OSBuildNumber), &19045u16.to_le_bytes())?;
+        // VER_PLATFORM_WIN32_NT
+        self.write_field(peb, offset_of!(PEB, OSPlatformId), &2u32.to_le_bytes())?;
+        self.write_field(
+            ldr,
+            offset_of!(PEB_LDR_DATA, Length),
+            &(size_of::<PEB_LDR_DATA>() as u32).to_le_bytes(),
+        )?;
+        self.write_field(ldr, offset_of!(PEB_LDR_DATA, Initialized), &[1])?;
+        for list in [
+            offset_of!(PEB_LDR_DATA, InLoadOrderModuleList),
+            offset_of!(PEB_LDR_DATA, InMemoryOrderModuleList),
+            offset_of!(PEB_LDR_DATA, InInitializationOrderModuleList),
+        ] {
+            self.initialize_list(ldr + list as u64)?;
+        }
+
+        // TEB
+        let teb = self.allocate_process_data(size_of::<TEB>() as u64)?;
+        self.write_field(
+            teb,
+            offset_of!(TEB, NtTib.StackBase),
+            &stack.end.to_le_bytes(),
+        )?;
+        self.write_field(
+            teb,
+            offset_of!(TEB, NtTib.StackLimit),
+            &stack.start.to_le_bytes(),
+        )?;
+        self.write_field(teb, offset_of!(TEB, NtTib.Self_), &teb.to_le_bytes())?;
+        self.write_field(
+            teb,
+            offset_of!(TEB, ClientId.UniqueProcess),
+            &PROCESS_ID.to_le_bytes(),
+        )?;
+        self.write_field(
+            teb,
+            offset_of!(TEB, ClientId.UniqueThread),
+            &THREAD_ID.to_le_bytes(),
+        )?;
+        self.write_field(
+            teb,
+            offset_of!(TEB, ProcessEnvironmentBlock),
+            &peb.to_le_bytes(),
+        )?;
+        self.initialize_list(teb + offset_of!(TEB, TlsLinks) as u64)?;
+        self.cpu.gs_base = teb;
+        // selectors of a 64-bit windows thread
+        self.cpu.selectors = [0x2B, 0x33, 0x2B, 0x2B, 0x53, 0x2B];
+
+        // the image is not part of the initialization order
+        self.insert_module(
+            &parameters.image_path,
+            image.base,
+            image.size,
+            image.entry_point,
+            false,
+        )?;
+        Ok(peb)
+    }
+
+    /// address of the TEB of the current thread
+    pub fn teb(&self) -> u64 {
+        self.cpu.gs_base
+    }
+
+    /// address of the PEB
+    pub fn peb(&self) -> Result<u64, Exception> {
+        self.read_pointer(self.teb() + offset_of!(TEB, ProcessEnvironmentBlock) as u64)
+    }
+
+    /// appends a module to all loader lists of the PEB, returns the address of
+    /// its LDR_DATA_TABLE_ENTRY
+    pub fn add_module(
+        &mut self,
+        path: &str,
+        base: u64,
+        size: u64,
+        entry_point: u64,
+    ) -> Result<u64, Exception> {
+        self.insert_module(path, base, size, entry_point, true)
+    }
+
+    fn insert_module(
+        &mut self,
+        path: &str,
+        base: u64,
+        size: u64,
+        entry_point: u64,
+        initialization_order: bool,
+    ) -> Result<u64, Exception> {
+        let full_name = utf16(path);
+        let base_name = utf16(path.rsplit(['\\', '/']).next().unwrap());
+        let entry = self.allocate_process_data(
+            (size_of::<LDR_DATA_TABLE_ENTRY>() + full_name.len() + base_name.len()) as u64 + 2 * 8,
+        )?;
+        let mut cursor = entry + size_of::<LDR_DATA_TABLE_ENTRY>() as u64;
+        self.write_field(
+            entry,
+            offset_of!(LDR_DATA_TABLE_ENTRY, DllBase),
+            &base.to_le_bytes(),
+        )?;
+        self.write_field(
+            entry,
+            offset_of!(LDR_DATA_TABLE_ENTRY, EntryPoint),
+            &entry_point.to_le_bytes(),
+        )?;
+        self.write_field(
+            entry,
+            offset_of!(LDR_DATA_TABLE_ENTRY, SizeOfImage),
+            &(size as u32).to_le_bytes(),
+        )?;
+        self.write_unicode_string(
+            entry + offset_of!(LDR_DATA_TABLE_ENTRY, FullDllName) as u64,
+            &mut cursor,
+            &full_name,
+        )?;
+        self.write_unicode_string(
+            entry + offset_of!(LDR_DATA_TABLE_ENTRY, BaseDllName) as u64,
+            &mut cursor,
+            &base_name,
+        )?;
+        // static modules are never unloaded
+        self.write_field(
+            entry,
+            offset_of!(LDR_DATA_TABLE_ENTRY, ObsoleteLoadCount),
+            &u16::MAX.to_le_bytes(),
+        )?;
+        self.initialize_list(entry + offset_of!(LDR_DATA_TABLE_ENTRY, HashLinks) as u64)?;
+
+        let ldr = self.read_pointer(self.peb()? + offset_of!(PEB, Ldr) as u64)?;
+        let mut lists = vec![
+            (
+                offset_of!(PEB_LDR_DATA, InLoadOrderModuleList),
+                offset_of!(LDR_DATA_TABLE_ENTRY, InLoadOrderLinks),
+            ),
+            (
+                offset_of!(PEB_LDR_DATA, InMemoryOrderModuleList),
+                offset_of!(LDR_DATA_TABLE_ENTRY, InMemoryOrderLinks),
+            ),
+        ];
+        if initialization_order {
+            lists.push((
+                offset_of!(PEB_LDR_DATA, InInitializationOrderModuleList),
+                offset_of!(LDR_DATA_TABLE_ENTRY, InInitializationOrderLinks),
+            ));
+        } else {
+            self.initialize_list(
+                entry + offset_of!(LDR_DATA_TABLE_ENTRY, InInitializationOrderLinks) as u64,
+            )?;
+        }
+        for (list, links) in lists {
+            self.insert_tail(ldr + list as u64, entry + links as u64)?;
+        }
+        Ok(entry)
+    }
+
+    /// maps zeroed memory for process structures
+    fn allocate_process_data(&mut self, size: u64) -> Result<u64, Exception> {
+        let size = size.next_multiple_of(PAGE_SIZE);
+        let address = self
+            .memory
+            .find_free(PROCESS_BASE, size)
+            .ok_or(Exception::OutOfMemory)?;
+        self.memory.map(address, size, Protection::READ_WRITE);
+        Ok(address)
+    }
+
+    fn write_field(&mut self, address: u64, offset: usize, data: &[u8]) -> Result<(), Exception> {
+        self.memory.write_raw(address + offset as u64, data)
+    }
+
+    fn read_pointer(&self, address: u64) -> Result<u64, Exception> {
+        let mut data = [0; 8];
+        self.memory.read_raw(address, &mut data)?;
+        Ok(u64::from_le_bytes(data))
+    }
+
+    /// writes the null-terminated string at the cursor and the
+    /// UNICODE_STRING pointing to it
+    fn write_unicode_string(
+        &mut self,
+        address: u64,
+        cursor: &mut u64,
+        string: &[u8],
+    ) -> Result<(), Exception> {
+        self.memory.write_raw(*cursor, string)?;
+        self.write_field(
+            address,
+            offset_of!(UNICODE_STRING, Length),
+            &(string.len() as u16 - 2).to_le_bytes(),
+        )?;
+        self.write_field(
+            address,
+            offset_of!(UNICODE_STRING, MaximumLength),
+            &(string.len() as u16).to_le_bytes(),
+        )?;
+        self.write_field(
+            address,
+            offset_of!(UNICODE_STRING, Buffer),
+            &cursor.to_le_bytes(),
+        )?;
+        *cursor = (*cursor + string.len() as u64).next_multiple_of(8);
+        Ok(())
+    }
+
+    /// empty list, pointing to itself
+    fn initialize_list(&mut self, head: u64) -> Result<(), Exception> {
+        self.write_field(head, offset_of!(LIST_ENTRY, Flink), &head.to_le_bytes())?;
+        self.write_field(head, offset_of!(LIST_ENTRY, Blink), &head.to_le_bytes())
+    }
+
+    fn insert_tail(&mut self, head: u64, entry: u64) -> Result<(), Exception> {
+        let tail = self.read_pointer(head + offset_of!(LIST_ENTRY, Blink) as u64)?;
+        self.write_field(entry, offset_of!(LIST_ENTRY, Flink), &head.to_le_bytes())?;
+        self.write_field(entry, offset_of!(LIST_ENTRY, Blink), &tail.to_le_bytes())?;
+        self.write_field(tail, offset_of!(LIST_ENTRY, Flink), &entry.to_le_bytes())?;
+        self.write_field(head, offset_of!(LIST_ENTRY, Blink), &entry.to_le_bytes())
+    }
+}
+
+/// null-terminated utf-16
+pub(super) fn utf16(string: &str) -> Vec<u8> {
+    string
+        .encode_utf16()
+        .chain([0])
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unpack::{rebuild_imports, tests::image};
+
+    const IMAGE_PATH: &str = "C:\\test\\test.exe";
+    const KERNEL32_PATH: &str = "C:\\Windows\\System32\\kernel32.dll";
+    const KERNEL32: u64 = 0x7FF8_0000_0000;
+
+    /// emulator with a loaded image, its process and kernel32
+    fn process() -> (Emulator, Image, u64) {
+        let mut emulator = Emulator::new();
+        let mut data = image();
+        // reserved stack size
+        data[0xE0..0xE8].copy_from_slice(&0x10000u64.to_le_bytes());
+        let data = rebuild_imports(data, crate::unpack::tests::BASE, &[]).unwrap();
+        let image = emulator.load("test.exe", &data).unwrap();
+        let peb = emulator
+            .setup_process(&image, &ProcessParameters::new(IMAGE_PATH))
+            .unwrap();
+        emulator
+            .add_module(KERNEL32_PATH, KERNEL32, 0x10000, KERNEL32 + 0x1000)
+            .unwrap();
+        (emulator, image, peb)
+    }
+
+    fn read_unicode_string(emulator: &Emulator, address: u64) -> String {
+        let length = emulator
+            .read_u16(address + offset_of!(UNICODE_STRING, Length) as u64)
+            .unwrap();
+        let maximum_length = emulator
+            .read_u16(address + offset_of!(UNICODE_STRING, MaximumLength) as u64)
+            .unwrap();
+        assert_eq!(maximum_length, length + 2);
+        let buffer = emulator
+            .read_u64(address + offset_of!(UNICODE_STRING, Buffer) as u64)
+            .unwrap();
+        let string = emulator.read_string(buffer, true).unwrap();
+        assert_eq!(string.encode_utf16().count() * 2, length as usize);
+        string
+    }
+
+    /// entries of a list, checking the backward links
+    fn entries(emulator: &Emulator, head: u64, links: usize) -> Vec<u64> {
+        let mut entries = vec![];
+        let mut previous = head;
+        loop {
+            let next = emulator
+                .read_u64(previous + offset_of!(LIST_ENTRY, Flink) as u64)
+                .unwrap();
+            assert_eq!(
+                emulator
+                    .read_u64(next + offset_of!(LIST_ENTRY, Blink) as u64)
+                    .unwrap(),
+                previous
+            );
+            if next == head {
+                return entries;
+            }
+            entries.push(next - links as u64);
+            previous = next;
+        }
+    }
+
+    #[test]
+    fn thread() {
+        let (mut emulator, _, peb) = process();
+        // mov rax, gs:[0x30] / mov rcx, gs:[0x60] / int3
+        emulator
+            .memory
+            .map(0x1000, PAGE_SIZE, Protection::READ_EXECUTE);
+        emulator
+            .memory
+            .write_raw(
+                0x1000,
+                &[
+                    0x65, 0x48, 0x8B, 0x04, 0x25, 0x30, 0x00, 0x00, 0x00, 0x65, 0x48, 0x8B, 0x0C,
+                    0x25, 0x60, 0x00, 0x00, 0x00, 0xCC,
+                ],
+            )
+            .unwrap();
+        emulator.cpu.rip = 0x1000;
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+        let teb = emulator.cpu.gpr[RAX];
+        assert_eq!(teb, emulator.teb());
+        assert_eq!(emulator.cpu.gpr[RCX], peb);
+        assert_eq!(emulator.peb(), Ok(peb));
+
+        let field = |offset: usize| emulator.read_u64(teb + offset as u64).unwrap();
+        assert_eq!(field(offset_of!(TEB, ClientId.UniqueProcess)), PROCESS_ID);
+        assert_eq!(field(offset_of!(TEB, ClientId.UniqueThread)), THREAD_ID);
+        // the stack is the one mapped for the image
+        let rsp = emulator.cpu.gpr[RSP];
+        let stack_base = field(offset_of!(TEB, NtTib.StackBase));
+        let stack_limit = field(offset_of!(TEB, NtTib.StackLimit));
+        assert!(stack_limit < rsp && rsp <= stack_base);
+    }
+
+    #[test]
+    fn loader_data() {
+        let (emulator, image, peb) = process();
+        let field = |offset: usize| emulator.read_u64(peb + offset as u64).unwrap();
+        assert_eq!(field(offset_of!(PEB, ImageBaseAddress)), image.base);
+        let ldr = field(offset_of!(PEB, Ldr));
+        assert_eq!(ldr, peb + size_of::<PEB>() as u64);
+        assert_eq!(
+            emulator
+                .read_u32(ldr + offset_of!(PEB_LDR_DATA, Length) as u64)
+                .unwrap(),
+            size_of::<PEB_LDR_DATA>() as u32
+        );
+
+        // the image comes first in load and memory order, but it isn't
+        // initialized by the loader
+        let list = |list: usize, links: usize| entries(&emulator, ldr + list as u64, links);
+        let load_order = list(
+            offset_of!(PEB_LDR_DATA, InLoadOrderModuleList),
+            offset_of!(LDR_DATA_TABLE_ENTRY, InLoadOrderLinks),
+        );
+        assert_eq!(load_order.len(), 2);
+        assert_eq!(
+            list(
+                offset_of!(PEB_LDR_DATA, InMemoryOrderModuleList),
+                offset_of!(LDR_DATA_TABLE_ENTRY, InMemoryOrderLinks),
+            ),
+            load_order
+        );
+        assert_eq!(
+            list(
+                offset_of!(PEB_LDR_DATA, InInitializationOrderModuleList),
+                offset_of!(LDR_DATA_TABLE_ENTRY, InInitializationOrderLinks),
+            ),
+            load_order[1..]
+        );
+        assert!(entries(
+            &emulator,
+            load_order[0] + offset_of!(LDR_DATA_TABLE_ENTRY, InInitializationOrderLinks) as u64,
+            0
+        )
+        .is_empty());
+
+        for (&entry, (path, name, base, size, entry_point)) in load_order.iter().zip([
+            (
+                IMAGE_PATH,
+                "test.exe",
+                image.base,
+                image.size,
+                image.entry_point,
+            ),
+            (
+                KERNEL32_PATH,
+                "kernel32.dll",
+                KERNEL32,
+                0x10000,
+                KERNEL32 + 0x1000,
+            ),
+        ]) {
+            let field = |offset: usize| emulator.read_u64(entry + offset as u64).unwrap();
+            assert_eq!(field(offset_of!(LDR_DATA_TABLE_ENTRY, DllBase)), base);
+            assert_eq!(
+                field(offset_of!(LDR_DATA_TABLE_ENTRY, EntryPoint)),
+                entry_point
+            );
+            assert_eq!(
+                emulator
+                    .read_u32(entry + offset_of!(LDR_DATA_TABLE_ENTRY, SizeOfImage) as u64)
+                    .unwrap() as u64,
+                size
+            );
+            assert_eq!(
+                read_unicode_string(
+                    &emulator,
+                    entry + offset_of!(LDR_DATA_TABLE_ENTRY, FullDllName) as u64
+                ),
+                path
+            );
+            assert_eq!(
+                read_unicode_string(
+                    &emulator,
+                    entry + offset_of!(LDR_DATA_TABLE_ENTRY, BaseDllName) as u64
+                ),
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn process_parameters() {
+        let (emulator, _, peb) = process();
+        let parameters = ProcessParameters::new(IMAGE_PATH);
+        let process_parameters = emulator
+            .read_u64(peb + offset_of!(PEB, ProcessParameters) as u64)
+            .unwrap();
+        let string =
+            |offset: usize| read_unicode_string(&emulator, process_parameters + offset as u64);
+        assert_eq!(
+            string(offset_of!(RTL_USER_PROCESS_PARAMETERS, CommandLine)),
+            "\"C:\\test\\test.exe\""
+        );
+        assert_eq!(
+            string(offset_of!(RTL_USER_PROCESS_PARAMETERS, ImagePathName)),
+            IMAGE_PATH
+        );
+        assert_eq!(
+            string(offset_of!(
+                RTL_USER_PROCESS_PARAMETERS,
+                CurrentDirectory.DosPath
+            )),
+            "C:\\test\\"
+        );
+
+        // variables separated by null characters and terminated by an empty
+        // one
+        let field = |offset: usize| {
+            emulator
+                .read_u64(process_parameters + offset as u64)
+                .unwrap()
+        };
+        let environment = emulator
+            .read_bytes(
+                field(offset_of!(RTL_USER_PROCESS_PARAMETERS, Environment)),
+                field(offset_of!(RTL_USER_PROCESS_PARAMETERS, EnvironmentSize)) as usize,
+            )
+            .unwrap();
+        let environment = String::from_utf16(
+            &environment
+                .chunks(2)
+                .map(|char| u16::from_le_bytes([char[0], char[1]]))
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let variables = environment
+            .strip_suffix("\0\0")
+            .unwrap()
+            .split('\0')
+            .collect::<Vec<_>>();
+        assert_eq!(variables.len(), parameters.environment.len());
+        for (variable, (name, value)) in variables.iter().zip(&parameters.environment) {
+            assert_eq!(*variable, format!("{name}={value}"));
+        }
+        assert!(variables.contains(&"SystemRoot=C:\\Windows"));
+    }
+}
@@ -0,0 +1,746 @@
+use iced_x86::Mnemonic;
+
+use crate::{call::CallingConvention, emulator::*};
+
+pub const STATUS_BREAKPOINT: u32 = 0x8000_0003;
+pub const STATUS_SINGLE_STEP: u32 = 0x8000_0004;
+pub const STATUS_ACCESS_VIOLATION: u32 = 0xC000_0005;
+pub const STATUS_ILLEGAL_INSTRUCTION: u32 = 0xC000_001D;
+pub const STATUS_INTEGER_DIVIDE_BY_ZERO: u32 = 0xC000_0094;
+pub const STATUS_PRIVILEGED_INSTRUCTION: u32 = 0xC000_0096;
+
+// structures passed to handlers, placed below the stack of the faulting code
+const CONTEXT_SIZE: u64 = 0x4D0;
+const EXCEPTION_RECORD_SIZE: u64 = 0xA0;
+const EXCEPTION_POINTERS_SIZE: u64 = 0x10;
+const DISPATCHER_CONTEXT_SIZE: u64 = 0x50;
+
+const CONTEXT_ALL: u32 = 0x10_001F;
+
+// results of vectored handlers and exception filters
+const EXCEPTION_CONTINUE_EXECUTION: i32 = -1;
+
+// results of language-specific handlers
+const EXCEPTION_DISPOSITION_CONTINUE_EXECUTION: u32 = 0;
+const EXCEPTION_DISPOSITION_CONTINUE_SEARCH: u32 = 1;
+
+// UNWIND_INFO flags
+const UNW_FLAG_EHANDLER: u8 = 1;
+const UNW_FLAG_CHAININFO: u8 = 4;
+
+// unwind operations
+const UWOP_PUSH_NONVOL: u8 = 0;
+const UWOP_ALLOC_LARGE: u8 = 1;
+const UWOP_ALLOC_SMALL: u8 = 2;
+const UWOP_SET_FPREG: u8 = 3;
+const UWOP_SAVE_NONVOL: u8 = 4;
+const UWOP_SAVE_NONVOL_FAR: u8 = 5;
+const UWOP_EPILOG: u8 = 6;
+const UWOP_SPARE_CODE: u8 = 7;
+const UWOP_SAVE_XMM128: u8 = 8;
+const UWOP_SAVE_XMM128_FAR: u8 = 9;
+const UWOP_PUSH_MACHFRAME: u8 = 10;
+
+/// frames walked before giving up (e.g. on a corrupted stack)
+const MAX_FRAMES: usize = 256;
+
+/// exception raised in the guest (EXCEPTION_RECORD)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExceptionRecord {
+    pub code: u32,
+    /// address of the instruction, also rip of the context passed to handlers
+    pub address: u64,
+    pub parameters: Vec<u64>,
+}
+
+/// entry of the exception directory of a module (RUNTIME_FUNCTION)
+#[derive(Debug, Clone)]
+pub struct FunctionEntry {
+    pub image_base: u64,
+    /// address of the entry itself
+    pub address: u64,
+    pub begin: u64,
+    pub end: u64,
+    pub unwind_info: u64,
+}
+
+/// result of unwinding a single frame
+struct Unwind {
+    /// rsp after the prolog of the function (or based on the frame register)
+    establisher_frame: u64,
+    /// language-specific handler and its data, only if the frame has an
+    /// exception handler and rip is not in the prolog
+    handler: Option<(u64, u64)>,
+}
+
+impl Emulator {
+    /// registers a guest vectored exception handler, it is called before the
+    /// frame-based handlers, returns the handle to remove it, None if the
+    /// address space is exhausted
+    pub fn add_vectored_exception_handler(&mut self, first: bool, handler: u64) -> Option<u64> {
+        let handle = self.heap_allocate(0x20)?;
+        if first {
+            self.win32.vectored_handlers.insert(0, (handle, handler));
+        } else {
+            self.win32.vectored_handlers.push((handle, handler));
+        }
+        Some(handle)
+    }
+
+    /// removes a guest vectored exception handler, returns false if there is
+    /// none with the handle
+    pub fn remove_vectored_exception_handler(&mut self, handle: u64) -> bool {
+        let count = self.win32.vectored_handlers.len();
+        self.win32
+            .vectored_handlers
+            .retain(|&(vectored_handle, _)| vectored_handle != handle);
+        self.win32.vectored_handlers.len() != count
+    }
+
+    /// raises an exception with the current registers as context like
+    /// KiUserExceptionDispatcher: the vectored handlers are called first, then
+    /// the language-specific handlers of the frames found by unwinding with
+    /// the exception directory of the modules, returns true if a handler
+    /// continued execution (the registers are then those of the
+    /// continuation), the registers are unchanged if the exception is
+    /// unhandled or a handler raised another exception
+    pub fn dispatch_exception(&mut self, record: &ExceptionRecord) -> bool {
+        if self.win32.dispatching {
+            return false;
+        }
+        let cpu = self.cpu.clone();
+        self.win32.dispatching = true;
+        let handled = self.dispatch(record);
+        self.win32.dispatching = false;
+        if handled != Ok(true) {
+            self.cpu = cpu;
+        }
+        handled == Ok(true)
+    }
+
+    /// RUNTIME_FUNCTION of the loaded module containing the address, None for
+    /// leaf functions and code outside of modules
+    pub fn function_entry(&self, address: u64) -> Result<Option<FunctionEntry>, Exception> {
+        for (base, _) in self.modules()? {
+            let nt_headers = base + self.read_u32(base + 0x3C)? as u64;
+            let size = self.read_u32(nt_headers + 0x50)? as u64;
+            if !(base..base + size).contains(&address) {
+                continue;
+            }
+            // IMAGE_DIRECTORY_ENTRY_EXCEPTION of the optional header
+            let directory = self.read_u32(nt_headers + 0xA0)? as u64;
+            let count = self.read_u32(nt_headers + 0xA4)? as u64 / 12;
+            if directory == 0 {
+                return Ok(None);
+            }
+            // the entries are sorted by address
+            let rva = address - base;
+            let (mut low, mut high) = (0, count);
+            while low < high {
+                let middle = (low + high) / 2;
+                let mut entry = base + directory + middle * 12;
+                let begin = self.read_u32(entry)? as u64;
+                let end = self.read_u32(entry + 4)? as u64;
+                if rva < begin {
+                    high = middle;
+                } else if rva >= end {
+                    low = middle + 1;
+                } else {
+                    // the unwind data can point to another entry instead
+                    let mut unwind_info = self.read_u32(entry + 8)? as u64;
+                    if unwind_info & 1 != 0 {
+                        entry = base + (unwind_info & !1);
+                        unwind_info = self.read_u32(entry + 8)? as u64;
+                    }
+                    return Ok(Some(FunctionEntry {
+                        image_base: base,
+                        address: entry,
+                        begin: base + self.read_u32(entry)? as u64,
+                        end: base + self.read_u32(entry + 4)? as u64,
+                        unwind_info: base + unwind_info,
+                    }));
+                }
+            }
+            return Ok(None);
+        }
+        Ok(None)
+    }
+
+    /// raises a fault of the guest if exception dispatch is enabled, Ok if a
+    /// handler continued execution
+    pub(super) fn raise(&mut self, exception: Exception) -> Result<(), Exception> {
+        let Some(record) = self.exception_record(exception) else {
+            return Err(exception);
+        };
+        let rip = self.cpu.rip;
+        self.cpu.rip = record.address;
+        if self.dispatch_exception(&record) {
+            Ok(())
+        } else {
+            self.cpu.rip = rip;
+            Err(exception)
+        }
+    }
+
+    /// record windows raises for an exception of the emulator, None for
+    /// exceptions which aren't raised in the guest (e.g. system calls, exits
+    /// or returns of [Emulator::call])
+    fn exception_record(&self, exception: Exception) -> Option<ExceptionRecord> {
+        let rip = self.cpu.rip;
+        let (code, address, parameters) = match exception {
+            Exception::PageFault { address, .. } if address == RETURN_ADDRESS => return None,
+            Exception::PageFault { address, access } => (
+                STATUS_ACCESS_VIOLATION,
+                rip,
+                vec![
+                    match access {
+                        Access::Read => 0,
+                        Access::Write => 1,
+                        Access::Execute => 8,
+                    },
+                    address,
+                ],
+            ),
+            Exception::InvalidOpcode => (STATUS_ILLEGAL_INSTRUCTION, rip, vec![]),
+            Exception::DivideError => (STATUS_INTEGER_DIVIDE_BY_ZERO, rip, vec![]),
+            // also raised for misaligned sse accesses
+            Exception::GeneralProtection => match self.decode().map(|ins| ins.mnemonic()) {
+                Ok(
+                    Mnemonic::Hlt
+                    | Mnemonic::Cli
+                    | Mnemonic::Sti
+                    | Mnemonic::In
+                    | Mnemonic::Out
+                    | Mnemonic::Insb
+                    | Mnemonic::Insw
+                    | Mnemonic::Insd
+                    | Mnemonic::Outsb
+                    | Mnemonic::Outsw
+                    | Mnemonic::Outsd,
+                ) => (STATUS_PRIVILEGED_INSTRUCTION, rip, vec![]),
+                _ => (STATUS_ACCESS_VIOLATION, rip, vec![0, u64::MAX]),
+            },
+            // traps leave rip after the instruction, windows reports int3 at
+            // the instruction
+            Exception::Breakpoint => (STATUS_BREAKPOINT, rip - 1, vec![0]),
+            Exception::Interrupt(1) => (STATUS_SINGLE_STEP, rip, vec![]),
+            // __fastfail can't be handled
+            Exception::Interrupt(0x29) => return None,
+            // other interrupt gates aren't accessible from user-mode
+            Exception::Interrupt(_) => (STATUS_ACCESS_VIOLATION, rip - 2, vec![0, u64::MAX]),
+            _ => return None,
+        };
+        Some(ExceptionRecord {
+            code,
+            address,
+            parameters,
+        })
+    }
+
+    fn dispatch(&mut self, record: &ExceptionRecord) -> Result<bool, Exception> {
+        let context = self.cpu.clone();
+        let context_address = (context.gpr[RSP] - CONTEXT_SIZE) & !0xF;
+        let record_address = context_address - EXCEPTION_RECORD_SIZE;
+        let pointers_address = record_address - EXCEPTION_POINTERS_SIZE;
+        let dispatcher_address = pointers_address - DISPATCHER_CONTEXT_SIZE;
+        self.write_context(context_address, &context)?;
+        self.write_exception_record(record_address, record)?;
+        self.write_u64(pointers_address, record_address)?;
+        self.write_u64(pointers_address + 8, context_address)?;
+        let stack = dispatcher_address & !0xF;
+
+        for (_, handler) in self.win32.vectored_handlers.clone() {
+            let result = self.call_handler(stack, handler, &[pointers_address])?;
+            if result as i32 == EXCEPTION_CONTINUE_EXECUTION {
+                self.cpu = self.read_context(context_address)?;
+                return Ok(true);
+            }
+        }
+
+        let mut frame = context;
+        for _ in 0..MAX_FRAMES {
+            if frame.rip == RETURN_ADDRESS || frame.rip == 0 {
+                break;
+            }
+            let Some(entry) = self.function_entry(frame.rip)? else {
+                // leaf function or code without unwind info (e.g. a stub)
+                frame.rip = self.read_u64(frame.gpr[RSP])?;
+                frame.gpr[RSP] += 8;
+                continue;
+            };
+            let mut caller = frame.clone();
+            let unwind = self.virtual_unwind(&entry, &mut caller)?;
+            let Some((handler, handler_data)) = unwind.handler else {
+                frame = caller;
+                continue;
+            };
+
+            // handlers of the c runtime are usually called through an import
+            // thunk
+            if let Some(stub) = self.stub(self.thunk_target(handler)) {
+                if stub.function == "__C_specific_handler" {
+                    if self.c_specific_handler(
+                        stack,
+                        record,
+                        pointers_address,
+                        context_address,
+                        &entry,
+                        &frame,
+                        unwind.establisher_frame,
+                        handler_data,
+                    )? {
+                        return Ok(true);
+                    }
+                    frame = caller;
+                    continue;
+                }
+                // e.g. the c++ frame handler, which would ignore faults anyway
+                if stub.handler.is_none()
+                    && self.win32.handler(&stub.library, &stub.function).is_none()
+                {
+                    frame = caller;
+                    continue;
+                }
+            }
+
+            // DISPATCHER_CONTEXT
+            let mut data = vec![0; DISPATCHER_CONTEXT_SIZE as usize];
+            for (offset, value) in [
+                (0x00, frame.rip),
+                (0x08, entry.image_base),
+                (0x10, entry.address),
+                (0x18, unwind.establisher_frame),
+                (0x28, context_address),
+                (0x30, handler),
+                (0x38, handler_data),
+            ] {
+                data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            }
+            self.memory.write(dispatcher_address, &data)?;
+            let disposition = self.call_handler(
+                stack,
+                handler,
+                &[
+                    record_address,
+                    unwind.establisher_frame,
+                    context_address,
+                    dispatcher_address,
+                ],
+            )? as u32;
+            match disposition {
+                EXCEPTION_DISPOSITION_CONTINUE_EXECUTION => {
+                    self.cpu = self.read_context(context_address)?;
+                    return Ok(true);
+                }
+                EXCEPTION_DISPOSITION_CONTINUE_SEARCH => frame = caller,
+                // nested and collided unwinds
+                _ => return Ok(false),
+            }
+        }
+        Ok(false)
+    }
+
+    /// evaluates the scope table of __C_specific_handler (__try/__except of
+    /// msvc) without calling the stub, termination handlers (__finally) of
+    /// the unwound frames are not run
+    #[allow(clippy::too_many_arguments)]
+    fn c_specific_handler(
+        &mut self,
+        stack: u64,
+        record: &ExceptionRecord,
+        pointers_address: u64,
+        context_address: u64,
+        entry: &FunctionEntry,
+        frame: &Cpu,
+        establisher_frame: u64,
+        scope_table: u64,
+    ) -> Result<bool, Exception> {
+        let rva = frame.rip - entry.image_base;
+        for i in 0..self.read_u32(scope_table)? as u64 {
+            let scope = scope_table + 4 + i * 16;
+            let begin = self.read_u32(scope)? as u64;
+            let end = self.read_u32(scope + 4)? as u64;
+            let filter = self.read_u32(scope + 8)? as u64;
+            let target = self.read_u32(scope + 12)? as u64;
+            // scopes without target are __finally blocks
+            if !(begin..end).contains(&rva) || target == 0 {
+                continue;
+            }
+            // EXCEPTION_EXECUTE_HANDLER instead of a filter function
+            let result = if filter == 1 {
+                1
+            } else {
+                self.call_handler(
+                    stack,
+                    entry.image_base + filter,
+                    &[pointers_address, establisher_frame],
+                )? as i32
+            };
+            if result == EXCEPTION_CONTINUE_EXECUTION {
+                self.cpu = self.read_context(context_address)?;
+                return Ok(true);
+            }
+            if result > 0 {
+                // the __except block runs in the frame with the code in eax
+                self.cpu = frame.clone();
+                self.cpu.rip = entry.image_base + target;
+                self.cpu.gpr[RAX] = record.code as u64;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// applies the unwind codes of the function to the context, which then is
+    /// the one of the caller
+    fn virtual_unwind(
+        &self,
+        entry: &FunctionEntry,
+        context: &mut Cpu,
+    ) -> Result<Unwind, Exception> {
+        let mut entry = entry.clone();
+        let mut establisher_frame = context.gpr[RSP];
+        let mut handler = None;
+        let mut machine_frame = false;
+        loop {
+            let header = self.read_bytes(entry.unwind_info, 4)?;
+            let flags = header[0] >> 3;
+            let prolog_size = header[1] as u64;
+            let code_count = header[2] as u64;
+            let frame_register = (header[3] & 0xF) as usize;
+            let frame_offset = (header[3] >> 4) as u64 * 16;
+            let codes = self.read_bytes(entry.unwind_info + 4, code_count as usize * 2)?;
+            // codes of the prolog which didn't execute yet are skipped
+            let offset = context.rip.wrapping_sub(entry.begin);
+            let in_prolog = offset < prolog_size;
+            let executed = |index: usize| !in_prolog || codes[index * 2] as u64 <= offset;
+
+            if frame_register != 0 {
+                let frame_set = (0..code_count as usize)
+                    .any(|i| codes[i * 2 + 1] & 0xF == UWOP_SET_FPREG && executed(i));
+                if frame_set {
+                    establisher_frame = context.gpr[frame_register] - frame_offset;
+                }
+            }
+
+            let slot = |index: usize| u16::from_le_bytes([codes[index * 2], codes[index * 2 + 1]]);
+            let far = |index: usize| slot(index) as u64 | (slot(index + 1) as u64) << 16;
+            let mut i = 0;
+            while i < code_count as usize {
+                let operation = codes[i * 2 + 1] & 0xF;
+                let info = (codes[i * 2 + 1] >> 4) as usize;
+                let (slots, apply) = match operation {
+                    UWOP_ALLOC_LARGE if info == 0 => (2, executed(i)),
+                    UWOP_ALLOC_LARGE => (3, executed(i)),
+                    UWOP_SAVE_NONVOL | UWOP_SAVE_XMM128 => (2, executed(i)),
+                    UWOP_SAVE_NONVOL_FAR | UWOP_SAVE_XMM128_FAR => (3, executed(i)),
+                    // epilog descriptors (version 2) don't describe the prolog
+                    UWOP_EPILOG => (2, false),
+                    UWOP_SPARE_CODE => (3, false),
+                    _ => (1, executed(i)),
+                };
+                if apply {
+                    let rsp = context.gpr[RSP];
+                    match operation {
+                        UWOP_PUSH_NONVOL => {
+                            context.gpr[info] = self.read_u64(rsp)?;
+                            context.gpr[RSP] += 8;
+                        }
+                        UWOP_ALLOC_LARGE if info == 0 => {
+                            context.gpr[RSP] += slot(i + 1) as u64 * 8;
+                        }
+                        UWOP_ALLOC_LARGE => context.gpr[RSP] += far(i + 1),
+                        UWOP_ALLOC_SMALL => context.gpr[RSP] += info as u64 * 8 + 8,
+                        UWOP_SET_FPREG => context.gpr[RSP] = establisher_frame,
+                        UWOP_SAVE_NONVOL => {
+                            context.gpr[info] =
+                                self.read_u64(establisher_frame + slot(i + 1) as u64 * 8)?;
+                        }
+                        UWOP_SAVE_NONVOL_FAR => {
+                            context.gpr[info] = self.read_u64(establisher_frame + far(i + 1))?;
+                        }
+                        UWOP_SAVE_XMM128 | UWOP_SAVE_XMM128_FAR => {
+                            let address = if operation == UWOP_SAVE_XMM128 {
+                                establisher_frame + slot(i + 1) as u64 * 16
+                            } else {
+                                establisher_frame + far(i + 1)
+                            };
+                            let data = self.read_bytes(address, 16)?;
+                            context.xmm[info] = u128::from_le_bytes(data.try_into().unwrap());
+                        }
+                        UWOP_PUSH_MACHFRAME => {
+                            // with or without error code
+                            let frame = rsp + if info == 1 { 8 } else { 0 };
+                            context.rip = self.read_u64(frame)?;
+                            context.gpr[RSP] = self.read_u64(frame + 24)?;
+                            machine_frame = true;
+                        }
+                        _ => {}
+                    }
+                }
+                i += slots;
+            }
+
+            // the handler or chained entry follows the codes, aligned to 4
+            let trailer = entry.unwind_info + 4 + code_count.next_multiple_of(2) * 2;
+            if flags & UNW_FLAG_CHAININFO != 0 {
+                entry = FunctionEntry {
+                    image_base: entry.image_base,
+                    address: trailer,
+                    begin: entry.image_base + self.read_u32(trailer)? as u64,
+                    end: entry.image_base + self.read_u32(trailer + 4)? as u64,
+                    unwind_info: entry.image_base + self.read_u32(trailer + 8)? as u64,
+                };
+                continue;
+            }
+            if flags & UNW_FLAG_EHANDLER != 0 && !in_prolog {
+                handler = Some((
+                    entry.image_base + self.read_u32(trailer)? as u64,
+                    trailer + 4,
+                ));
+            }
+            break;
+        }
+
+        if !machine_frame {
+            context.rip = self.read_u64(context.gpr[RSP])?;
+            context.gpr[RSP] += 8;
+        }
+        Ok(Unwind {
+            establisher_frame,
+            handler,
+        })
+    }
+
+    /// target of an import thunk (jmp [rip+offset]), otherwise the address
+    fn thunk_target(&self, address: u64) -> u64 {
+        let mut data = [0; 7];
+        if self
+            .memory
+            .read(address, &mut data, Access::Execute)
+            .is_err()
+        {
+            return address;
+        }
+        // with or without rex.w
+        let (length, displacement) = match data {
+            [0xFF, 0x25, a, b, c, d, _] => (6, [a, b, c, d]),
+            [0x48, 0xFF, 0x25, a, b, c, d] => (7, [a, b, c, d]),
+            _ => return address,
+        };
+        let slot = (address + length).wrapping_add_signed(i32::from_le_bytes(displacement) as i64);
+        self.read_u64(slot).unwrap_or(address)
+    }
+
+    /// calls a guest handler on the stack below the exception structures
+    fn call_handler(
+        &mut self,
+        stack: u64,
+        handler: u64,
+        arguments: &[u64],
+    ) -> Result<u64, Exception> {
+        self.cpu.gpr[RSP] = stack;
+        self.call(handler, CallingConvention::Win64, arguments)
+    }
+
+    /// writes a CONTEXT with all registers of the cpu
+    fn write_context(&mut self, address: u64, cpu: &Cpu) -> Result<(), Exception> {
+        let mut data = vec![0; CONTEXT_SIZE as usize];
+        let mut put =
+            |offset: usize, value: &[u8]| data[offset..offset + value.len()].copy_from_slice(value);
+        put(0x30, &CONTEXT_ALL.to_le_bytes());
+        put(0x34, &cpu.mxcsr.to_le_bytes());
+        // cs, ds, es, fs, gs and ss selectors
+        for (offset, selector) in [
+            (0x38, 0x33u16),
+            (0x3A, 0x2B),
+            (0x3C, 0x2B),
+            (0x3E, 0x53),
+            (0x40, 0x2B),
+            (0x42, 0x2B),
+        ] {
+            put(offset, &selector.to_le_bytes());
+        }
+        put(0x44, &(cpu.rflags as u32).to_le_bytes());
+        // the debug registers at 0x48 stay 0
+        for (i, value) in cpu.gpr.iter().enumerate() {
+            put(0x78 + i * 8, &value.to_le_bytes());
+        }
+        put(0xF8, &cpu.rip.to_le_bytes());
+        // XMM_SAVE_AREA32 with control word, mxcsr and its mask
+        put(0x100, &0x27Fu16.to_le_bytes());
+        put(0x118, &cpu.mxcsr.to_le_bytes());
+        put(0x11C, &0xFFFFu32.to_le_bytes());
+        for (i, value) in cpu.xmm.iter().enumerate() {
+            put(0x1A0 + i * 16, &value.to_le_bytes());
+        }
+        self.memory.write(address, &data)
+    }
+
+    /// registers of a CONTEXT possibly modified by a handler, the segment
+    /// bases are kept
+    fn read_context(&self, address: u64) -> Result<Cpu, Exception> {
+        let data = self.read_bytes(address, CONTEXT_SIZE as usize)?;
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+        let mut cpu = self.cpu.clone();
+        for (i, value) in cpu.gpr.iter_mut().enumerate() {
+            *value = u64_at(0x78 + i * 8);
+        }
+        cpu.rip = u64_at(0xF8);
+        let eflags = u32::from_le_bytes(data[0x44..0x48].try_into().unwrap()) as u64;
+        cpu.rflags = (cpu.rflags & !USER_FLAGS) | (eflags & USER_FLAGS);
+        cpu.mxcsr = u32::from_le_bytes(data[0x34..0x38].try_into().unwrap());
+        for (i, value) in cpu.xmm.iter_mut().enumerate() {
+            *value = u128::from_le_bytes(data[0x1A0 + i * 16..0x1B0 + i * 16].try_into().unwrap());
+        }
+        Ok(cpu)
+    }
+
+    fn write_exception_record(
+        &mut self,
+        address: u64,
+        record: &ExceptionRecord,
+    ) -> Result<(), Exception> {
+        let mut data = vec![0; EXCEPTION_RECORD_SIZE as usize];
+        data[0x00..0x04].copy_from_slice(&record.code.to_le_bytes());
+        data[0x10..0x18].copy_from_slice(&record.address.to_le_bytes());
+        // at most EXCEPTION_MAXIMUM_PARAMETERS
+        let parameters = &record.parameters[..record.parameters.len().min(15)];
+        data[0x18..0x1C].copy_from_slice(&(parameters.len() as u32).to_le_bytes());
+        for (i, parameter) in parameters.iter().enumerate() {
+            data[0x20 + i * 8..0x28 + i * 8].copy_from_slice(&parameter.to_le_bytes());
+        }
+        self.memory.write(address, &data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::tests::emulator;
+
+    const IMAGE_BASE: u64 = 0x1_4000_0000;
+
+    /// process with an image whose function at 0x1000 executes ud2, the
+    /// language-specific handler at 0x1100 skips it if the flags of the
+    /// unwind info have UNW_FLAG_EHANDLER
+    fn process(flags: u8) -> Emulator {
+        let mut emulator = Emulator::new();
+        let image = Image {
+            name: "test.exe".to_owned(),
+            base: IMAGE_BASE,
+            size: 0x2000,
+            entry_point: 0x1000,
+            is_dll: false,
+            stack_size: 0x10000,
+            imports: vec![],
+        };
+        emulator
+            .setup_process(&image, &ProcessParameters::new("C:\\test.exe"))
+            .unwrap();
+        emulator
+            .memory
+            .map(IMAGE_BASE, 0x2000, Protection::READ_WRITE_EXECUTE);
+        for (rva, data) in [
+            // e_lfanew, SizeOfImage and the exception directory
+            (0x3C, &0x80u32.to_le_bytes()[..]),
+            (0xD0, &0x2000u32.to_le_bytes()),
+            (0x120, &[0x00, 0x02, 0x00, 0x00, 12, 0x00, 0x00, 0x00]),
+            // RUNTIME_FUNCTION
+            (
+                0x200,
+                &[
+                    0x00, 0x10, 0x00, 0x00, 0x10, 0x10, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00,
+                ],
+            ),
+            // UNWIND_INFO without codes followed by the handler
+            (0x300, &[flags << 3 | 1, 0, 0, 0, 0x00, 0x11, 0x00, 0x00]),
+            // ud2 / int3
+            (0x1000, &[0x0F, 0x0B, 0xCC]),
+            // mov rax, r8 / add qword [rax+0xF8], 2 / xor eax, eax / ret
+            (
+                0x1100,
+                &[
+                    0x4C, 0x89, 0xC0, 0x48, 0x83, 0x80, 0xF8, 0x00, 0x00, 0x00, 0x02, 0x31, 0xC0,
+                    0xC3,
+                ],
+            ),
+        ] {
+            emulator.memory.write(IMAGE_BASE + rva, data).unwrap();
+        }
+        // the caller ends the unwinding
+        emulator.cpu.gpr[RSP] -= 0x100;
+        emulator.write_u64(emulator.cpu.gpr[RSP], 0).unwrap();
+        emulator.cpu.rip = IMAGE_BASE + 0x1000;
+        emulator.win32.dispatch_exceptions = true;
+        emulator
+    }
+
+    #[test]
+    fn frame_handler() {
+        let mut emulator = process(UNW_FLAG_EHANDLER);
+        let entry = emulator
+            .function_entry(IMAGE_BASE + 0x1001)
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.begin, IMAGE_BASE + 0x1000);
+        assert_eq!(entry.end, IMAGE_BASE + 0x1010);
+        assert_eq!(entry.unwind_info, IMAGE_BASE + 0x300);
+        assert!(emulator
+            .function_entry(IMAGE_BASE + 0x1010)
+            .unwrap()
+            .is_none());
+
+        let rsp = emulator.cpu.gpr[RSP];
+        assert_eq!(emulator.step(), Ok(()));
+        assert_eq!(emulator.cpu.rip, IMAGE_BASE + 0x1002);
+        assert_eq!(emulator.cpu.gpr[RSP], rsp);
+    }
+
+    #[test]
+    fn no_handler() {
+        let mut emulator = process(0);
+        let rsp = emulator.cpu.gpr[RSP];
+        assert_eq!(emulator.step(), Err(Exception::InvalidOpcode));
+        assert_eq!(emulator.cpu.rip, IMAGE_BASE + 0x1000);
+        assert_eq!(emulator.cpu.gpr[RSP], rsp);
+
+        let record = ExceptionRecord {
+            code: STATUS_ILLEGAL_INSTRUCTION,
+            address: IMAGE_BASE + 0x1000,
+            parameters: vec![],
+        };
+        assert!(!emulator.dispatch_exception(&record));
+        assert_eq!(emulator.cpu.rip, IMAGE_BASE + 0x1000);
+        assert_eq!(emulator.cpu.gpr[RSP], rsp);
+    }
+
+    #[test]
+    fn vectored_handler() {
+        // ud2
+        let mut emulator = emulator(&[0x0F, 0x0B, 0xCC]);
+        // mov rax, [rcx+8] / mov rdx, [rcx] / mov edx, [rdx] / mov [rax+0x78], rdx /
+        // add qword [rax+0xF8], 2 / mov eax, -1 / ret
+        let handler = [
+            0x48, 0x8B, 0x41, 0x08, 0x48, 0x8B, 0x11, 0x8B, 0x12, 0x48, 0x89, 0x50, 0x78, 0x48,
+            0x83, 0x80, 0xF8, 0x00, 0x00, 0x00, 0x02, 0xB8, 0xFF, 0xFF, 0xFF, 0xFF, 0xC3,
+        ];
+        emulator.memory.write(0x1100, &handler).unwrap();
+        emulator.win32.dispatch_exceptions = true;
+        let handle = emulator
+            .add_vectored_exception_handler(true, 0x1100)
+            .unwrap();
+
+        assert_eq!(emulator.step(), Ok(()));
+        assert_eq!(emulator.cpu.rip, 0x1002);
+        assert_eq!(emulator.cpu.gpr[RAX], STATUS_ILLEGAL_INSTRUCTION as u64);
+        assert_eq!(emulator.cpu.gpr[RSP], 0x1FF00);
+
+        assert!(emulator.remove_vectored_exception_handler(handle));
+        assert!(!emulator.remove_vectored_exception_handler(handle));
+        emulator.cpu.rip = 0x1000;
+        assert_eq!(emulator.step(), Err(Exception::InvalidOpcode));
+        assert_eq!(emulator.cpu.rip, 0x1000);
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::emulator::*;
+
+static NEXT_SNAPSHOT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// emulator state to return to with [Emulator::restore], the memory pages are
+/// shared copy-on-write with the emulator
+#[derive(Clone)]
+pub struct Snapshot {
+    id: u64,
+    cpu: Cpu,
+    memory: PagedMemory,
+    win32: Win32,
+    linux: Linux,
+    taint: Option<Taint>,
+    symbolic: Option<Symbolic>,
+    stubs: BTreeMap<u64, Stub>,
+    instruction_count: u64,
+}
+
+impl Snapshot {
+    /// instructions retired when the snapshot was taken
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+}
+
+impl Emulator {
+    /// captures the registers, memory and process state, hooks are not part
+    /// of it, changed pages are tracked from now on so restoring this
+    /// snapshot only copies those
+    pub fn snapshot(&mut self) -> Snapshot {
+        let id = NEXT_SNAPSHOT_ID.fetch_add(1, Ordering::Relaxed);
+        Snapshot {
+            id,
+            cpu: self.cpu.clone(),
+            memory: self.memory.snapshot(id),
+            win32: self.win32.clone(),
+            linux: self.linux.clone(),
+            taint: self.taint.clone(),
+            symbolic: self.symbolic.clone(),
+            stubs: self.stubs.clone(),
+            instruction_count: self.instruction_count,
+        }
+    }
+
+    /// returns to a snapshot, the hooks are kept, restoring the most recent
+    /// snapshot (or the one a fork was made after) is cheap as only the dirty
+    /// pages are replaced
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.cpu = snapshot.cpu.clone();
+        self.memory.restore(&snapshot.memory, snapshot.id);
+        self.win32 = snapshot.win32.clone();
+        self.linux = snapshot.linux.clone();
+        self.taint = snapshot.taint.clone();
+        self.symbolic = snapshot.symbolic.clone();
+        self.stubs = snapshot.stubs.clone();
+        self.instruction_count = snapshot.instruction_count;
+        self.accesses = None;
+    }
+
+    /// independent copy including the hooks, the memory pages are shared
+    /// copy-on-write so this is cheap even for large address spaces
+    pub fn fork(&self) -> Self {
+        self.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::emulator::tests::{emulator, LOOP};
+
+    #[test]
+    fn restore() {
+        let mut emulator = emulator(LOOP);
+        let rsp = emulator.cpu.gpr[RSP];
+        emulator
+            .memory
+            .map(0x5000, PAGE_SIZE, Protection::READ_WRITE);
+        emulator.memory.write(0x5000, &[1]).unwrap();
+        let snapshot = emulator.snapshot();
+
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+        emulator.memory.write(0x5000, &[2]).unwrap();
+        emulator.memory.unmap(0x5000, PAGE_SIZE);
+        emulator.memory.map(0x8000, PAGE_SIZE, Protection::READ);
+        emulator.memory.protect(0x1000, PAGE_SIZE, Protection::READ);
+
+        emulator.restore(&snapshot);
+        assert_eq!(emulator.cpu.rip, 0x1000);
+        assert_eq!(emulator.cpu.gpr[RCX], 0);
+        assert_eq!(emulator.instruction_count(), snapshot.instruction_count());
+        assert_eq!(emulator.read_u64(rsp), Ok(0));
+        assert_eq!(emulator.read_bytes(0x5000, 1), Ok(vec![1]));
+        assert_eq!(emulator.memory.protection(0x8000), None);
+        assert_eq!(
+            emulator.memory.protection(0x1000),
+            Some(Protection::READ_WRITE_EXECUTE)
+        );
+
+        // the restored state runs the same way again
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+        assert_eq!(emulator.instruction_count(), 11);
+        assert_eq!(emulator.read_u64(rsp), Ok(1));
+    }
+
+    #[test]
+    fn multiple_snapshots() {
+        let mut emulator = emulator(LOOP);
+        let rsp = emulator.cpu.gpr[RSP];
+        let first = emulator.snapshot();
+        assert_eq!(emulator.run(Some(4)), None);
+        let second = emulator.snapshot();
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+
+        // the older snapshot doesn't track the changes since the newer one
+        emulator.restore(&first);
+        assert_eq!(emulator.cpu.rip, 0x1000);
+        assert_eq!(emulator.read_u64(rsp), Ok(0));
+        emulator.restore(&second);
+        assert_eq!(emulator.cpu.rip, 0x1005);
+        assert_eq!(emulator.cpu.gpr[RCX], 2);
+        assert_eq!(emulator.read_u64(rsp), Ok(3));
+        assert_eq!(emulator.instruction_count(), 4);
+        emulator.restore(&second);
+        assert_eq!(emulator.read_u64(rsp), Ok(3));
+    }
+
+    #[test]
+    fn fork() {
+        let mut emulator = emulator(LOOP);
+        let rsp = emulator.cpu.gpr[RSP];
+        let hits = Arc::new(AtomicU64::new(0));
+        let hook_hits = hits.clone();
+        emulator.hook_code(0x1005..0x1006, move |_, _| {
+            hook_hits.fetch_add(1, Ordering::Relaxed);
+            HookAction::Continue
+        });
+
+        let mut fork = emulator.fork();
+        assert_eq!(fork.run(None), Some(Exception::Breakpoint));
+        assert_eq!(fork.read_u64(rsp), Ok(1));
+        assert_eq!(hits.load(Ordering::Relaxed), 3);
+        // the pages are copied on write
+        assert_eq!(emulator.read_u64(rsp), Ok(0));
+        assert_eq!(emulator.cpu.rip, 0x1000);
+
+        // hooks are kept when restoring
+        let snapshot = emulator.snapshot();
+        emulator.restore(&snapshot);
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+        assert_eq!(hits.load(Ordering::Relaxed), 6);
+    }
+}
@@ -0,0 +1,770 @@
+use std::cmp::Ordering;
+
+use iced_x86::{Instruction, Mnemonic, OpKind};
+
+use crate::emulator::{
+    cpu::mask,
+    execute::{operand_size, sign_extend},
+    *,
+};
+
+impl Emulator {
+    /// sse and sse2 instructions, everything else is an invalid opcode
+    pub(super) fn execute_sse(&mut self, ins: &Instruction) -> Result<(), Exception> {
+        use Mnemonic::*;
+
+        match ins.mnemonic() {
+            // data transfer
+            Movdqa | Movaps | Movapd | Movntdq | Movntps | Movntpd => {
+                for operand in 0..2 {
+                    if ins.op_kind(operand) == OpKind::Memory
+                        && !self.address(ins, operand)?.is_multiple_of(16)
+                    {
+                        return Err(Exception::GeneralProtection);
+                    }
+                }
+                let value = self.read_xmm_operand(ins, 1)?;
+                self.write_xmm_operand(ins, 0, value)?;
+            }
+            Movdqu | Movups | Movupd | Lddqu => {
+                let value = self.read_xmm_operand(ins, 1)?;
+                self.write_xmm_operand(ins, 0, value)?;
+            }
+            Movd | Movq => {
+                let size = if ins.mnemonic() == Movd { 4 } else { 8 };
+                let value = self.read_xmm_operand(ins, 1)? & mask(size) as u128;
+                self.write_xmm_operand(ins, 0, value)?;
+            }
+            Movss | Movsd => {
+                let size = if ins.mnemonic() == Movss { 4 } else { 8 };
+                let value = self.read_xmm_operand(ins, 1)? & mask(size) as u128;
+                if ins.op0_kind() == OpKind::Register && ins.op1_kind() == OpKind::Register {
+                    // only register to register moves merge
+                    let merged = self.read_xmm_operand(ins, 0)? & !(mask(size) as u128) | value;
+                    self.write_xmm_operand(ins, 0, merged)?;
+                } else {
+                    self.write_xmm_operand(ins, 0, value)?;
+                }
+            }
+            Movlps | Movlpd | Movhps | Movhpd | Movhlps | Movlhps => {
+                let source = self.read_xmm_operand(ins, 1)?;
+                let (source, high) = match ins.mnemonic() {
+                    Movhlps => (source >> 64, false),
+                    Movlhps => (source, true),
+                    Movhps | Movhpd if ins.op0_kind() == OpKind::Memory => (source >> 64, false),
+                    mnemonic => (source, matches!(mnemonic, Movhps | Movhpd)),
+                };
+                if ins.op0_kind() == OpKind::Memory {
+                    self.write_xmm_operand(ins, 0, source as u64 as u128)?;
+                } else {
+                    let destination = self.read_xmm_operand(ins, 0)?;
+                    let value = if high {
+                        destination as u64 as u128 | (source as u64 as u128) << 64
+                    } else {
+                        destination & !(u64::MAX as u128) | source as u64 as u128
+                    };
+                    self.write_xmm_operand(ins, 0, value)?;
+                }
+            }
+            Pmovmskb | Movmskps | Movmskpd => {
+                let bits = match ins.mnemonic() {
+                    Pmovmskb => 8,
+                    Movmskps => 32,
+                    _ => 64,
+                };
+                let value = self.read_xmm_operand(ins, 1)?;
+                let result = (0..128 / bits).fold(0, |result, i| {
+                    result | ((value >> (i * bits + bits - 1)) as u64 & 1) << i
+                });
+                self.write_operand(ins, 0, result)?;
+            }
+            Pextrw => {
+                let value = self.read_xmm_operand(ins, 1)?;
+                let index = ins.immediate(2) & 7;
+                self.write_operand(ins, 0, (value >> (index * 16)) as u64 & 0xFFFF)?;
+            }
+            Pinsrw => {
+                let destination = self.read_xmm_operand(ins, 0)?;
+                let value = self.read_operand(ins, 1)? & 0xFFFF;
+                let shift = (ins.immediate(2) & 7) * 16;
+                let result = destination & !(0xFFFF << shift) | (value as u128) << shift;
+                self.write_xmm_operand(ins, 0, result)?;
+            }
+            Ldmxcsr => self.cpu.mxcsr = self.read_operand(ins, 0)? as u32,
+            Stmxcsr => self.write_operand(ins, 0, self.cpu.mxcsr as u64)?,
+
+            // integer
+            Pxor | Xorps | Xorpd | Por | Orps | Orpd | Pand | Andps | Andpd | Pandn | Andnps
+            | Andnpd | Paddb | Paddw | Paddd | Paddq | Psubb | Psubw | Psubd | Psubq | Paddsb
+            | Paddsw | Paddusb | Paddusw | Psubsb | Psubsw | Psubusb | Psubusw | Pcmpeqb
+            | Pcmpeqw | Pcmpeqd | Pcmpgtb | Pcmpgtw | Pcmpgtd | Pminub | Pmaxub | Pminsw
+            | Pmaxsw | Pavgb | Pavgw | Pmullw | Pmulhw | Pmulhuw | Pmuludq | Pmaddwd
+            | Punpcklbw | Punpcklwd | Punpckldq | Punpcklqdq | Punpckhbw | Punpckhwd
+            | Punpckhdq | Punpckhqdq | Unpcklps | Unpckhps | Unpcklpd | Unpckhpd | Packsswb
+            | Packssdw | Packuswb => {
+                let a = self.read_xmm_operand(ins, 0)?;
+                let b = self.read_xmm_operand(ins, 1)?;
+                let result = integer(ins.mnemonic(), a, b);
+                self.write_xmm_operand(ins, 0, result)?;
+            }
+            Psllw | Pslld | Psllq | Psrlw | Psrld | Psrlq | Psraw | Psrad => {
+                let count =
+                    if ins.op1_kind() == OpKind::Register || ins.op1_kind() == OpKind::Memory {
+                        self.read_xmm_operand(ins, 1)? as u64
+                    } else {
+                        ins.immediate(1)
+                    };
+                let bits = match ins.mnemonic() {
+                    Psllw | Psrlw | Psraw => 16,
+                    Pslld | Psrld | Psrad => 32,
+                    _ => 64,
+                };
+                let a = self.read_xmm_operand(ins, 0)?;
+                let result = lanes(a, 0, bits, |x, _| match ins.mnemonic() {
+                    Psraw | Psrad => {
+                        (sign_extend(x, bits as usize / 8) as i64 >> count.min(63)) as u64
+                    }
+                    _ if count >= bits as u64 => 0,
+                    Psllw | Pslld | Psllq => x << count,
+                    _ => x >> count,
+                });
+                self.write_xmm_operand(ins, 0, result)?;
+            }
+            Pslldq | Psrldq => {
+                let count = ins.immediate(1).min(16) as u32 * 8;
+                let a = self.read_xmm_operand(ins, 0)?;
+                let result = if count == 128 {
+                    0
+                } else if ins.mnemonic() == Pslldq {
+                    a << count
+                } else {
+                    a >> count
+                };
+                self.write_xmm_operand(ins, 0, result)?;
+            }
+            Pshufd | Pshuflw | Pshufhw => {
+                let source = self.read_xmm_operand(ins, 1)?;
+                let order = ins.immediate(2);
+                let (bits, base) = match ins.mnemonic() {
+                    Pshufd => (32, 0),
+                    Pshuflw => (16, 0),
+                    _ => (16, 4),
+                };
+                let mut result = source;
+                for i in 0..4 {
+                    let lane = base + (order >> (i * 2) & 3) as u32;
+                    let value = source >> (lane * bits) & mask(bits as usize / 8) as u128;
+                    let shift = (base + i) * bits;
+                    result =
+                        result & !((mask(bits as usize / 8) as u128) << shift) | value << shift;
+                }
+                self.write_xmm_operand(ins, 0, result)?;
+            }
+            Shufps | Shufpd => {
+                let a = self.read_xmm_operand(ins, 0)?;
+                let b = self.read_xmm_operand(ins, 1)?;
+                let order = ins.immediate(2) as u32;
+                let result = if ins.mnemonic() == Shufps {
+                    let lane = |value: u128, i: u32| {
+                        (value >> ((order >> (i * 2) & 3) * 32)) as u32 as u128
+                    };
+                    lane(a, 0) | lane(a, 1) << 32 | lane(b, 2) << 64 | lane(b, 3) << 96
+                } else {
+                    (a >> ((order & 1) * 64)) as u64 as u128
+                        | ((b >> ((order >> 1 & 1) * 64)) as u64 as u128) << 64
+                };
+                self.write_xmm_operand(ins, 0, result)?;
+            }
+
+            // floating point
+            Addss | Addsd | Addps | Addpd | Subss | Subsd | Subps | Subpd | Mulss | Mulsd
+            | Mulps | Mulpd | Divss | Divsd | Divps | Divpd | Minss | Minsd | Minps | Minpd
+            | Maxss | Maxsd | Maxps | Maxpd | Sqrtss | Sqrtsd | Sqrtps | Sqrtpd => {
+                let (bits, count) = match ins.mnemonic() {
+                    Addss | Subss | Mulss | Divss | Minss | Maxss | Sqrtss => (32, 1),
+                    Addsd | Subsd | Mulsd | Divsd | Minsd | Maxsd | Sqrtsd => (64, 1),
+                    Addps | Subps | Mulps | Divps | Minps | Maxps | Sqrtps => (32, 4),
+                    _ => (64, 2),
+                };
+                let operation = |a: f64, b: f64| match ins.mnemonic() {
+                    Addss | Addsd | Addps | Addpd => a + b,
+                    Subss | Subsd | Subps | Subpd => a - b,
+                    Mulss | Mulsd | Mulps | Mulpd => a * b,
+                    Divss | Divsd | Divps | Divpd => a / b,
+                    _ => b.sqrt(),
+                };
+                let a = self.read_xmm_operand(ins, 0)?;
+                let b = self.read_xmm_operand(ins, 1)?;
+                let result = float_lanes(a, b, bits, count, |x, y| match ins.mnemonic() {
+                    // the second operand is returned as is if any is nan or both
+                    // are zero
+                    Minss | Minsd | Minps | Minpd => {
+                        if to_f64(x, bits) < to_f64(y, bits) {
+                            x
+                        } else {
+                            y
+                        }
+                    }
+                    Maxss | Maxsd | Maxps | Maxpd => {
+                        if to_f64(x, bits) > to_f64(y, bits) {
+                            x
+                        } else {
+                            y
+                        }
+                    }
+                    _ if bits == 64 => operation(to_f64(x, 64), to_f64(y, 64)).to_bits(),
+                    // f32 operations are exact when done in f64 and rounded
+                    _ => (operation(to_f64(x, 32), to_f64(y, 32)) as f32).to_bits() as u64,
+                });
+                self.write_xmm_operand(ins, 0, result)?;
+            }
+            Cmpss | Cmpsd | Cmpps | Cmppd => {
+                let (bits, count) = match ins.mnemonic() {
+                    Cmpss => (32, 1),
+                    Cmpsd => (64, 1),
+                    Cmpps => (32, 4),
+                    _ => (64, 2),
+                };
+                let predicate = ins.immediate(2) & 7;
+                let a = self.read_xmm_operand(ins, 0)?;
+                let b = self.read_xmm_operand(ins, 1)?;
+                let result = float_lanes(a, b, bits, count, |a, b| {
+                    let (a, b) = (to_f64(a, bits), to_f64(b, bits));
+                    let unordered = a.is_nan() || b.is_nan();
+                    let value = match predicate {
+                        0 => a == b,
+                        1 => a < b,
+                        2 => a <= b,
+                        3 => unordered,
+                        4 => a != b,
+                        5 => a.partial_cmp(&b) != Some(Ordering::Less),
+                        6 => !matches!(a.partial_cmp(&b), Some(Ordering::Less | Ordering::Equal)),
+                        _ => !unordered,
+                    };
+                    if value {
+                        u64::MAX
+                    } else {
+                        0
+                    }
+                });
+                self.write_xmm_operand(ins, 0, result)?;
+            }
+            Ucomiss | Ucomisd | Comiss | Comisd => {
+                let bits = if matches!(ins.mnemonic(), Ucomiss | Comiss) {
+                    32
+                } else {
+                    64
+                };
+                let a = to_f64(self.read_xmm_operand(ins, 0)? as u64, bits);
+                let b = to_f64(self.read_xmm_operand(ins, 1)? as u64, bits);
+                let (zf, pf, cf) = if a.is_nan() || b.is_nan() {
+                    (true, true, true)
+                } else if a < b {
+                    (false, false, true)
+                } else if a == b {
+                    (true, false, false)
+                } else {
+                    (false, false, false)
+                };
+                self.cpu.rflags &= !(CF | PF | AF | ZF | SF | OF);
+                self.cpu.set_flag(ZF, zf);
+                self.cpu.set_flag(PF, pf);
+                self.cpu.set_flag(CF, cf);
+            }
+            Cvtsi2ss | Cvtsi2sd => {
+                let value = sign_extend(self.read_operand(ins, 1)?, operand_size(ins, 1)) as i64;
+                let destination = self.read_xmm_operand(ins, 0)?;
+                let result = if ins.mnemonic() == Cvtsi2ss {
+                    destination & !(u32::MAX as u128) | (value as f32).to_bits() as u128
+                } else {
+                    destination & !(u64::MAX as u128) | (value as f64).to_bits() as u128
+                };
+                self.write_xmm_operand(ins, 0, result)?;
+            }
+            Cvtss2si | Cvttss2si | Cvtsd2si | Cvttsd2si => {
+                let bits = if matches!(ins.mnemonic(), Cvtss2si | Cvttss2si) {
+                    32
+                } else {
+                    64
+                };
+                let value = to_f64(self.read_xmm_operand(ins, 1)? as u64, bits);
+                let truncate = matches!(ins.mnemonic(), Cvttss2si | Cvttsd2si);
+                let result = to_integer(value, operand_size(ins, 0), truncate);
+                self.write_operand(ins, 0, result)?;
+            }
+            Cvtss2sd | Cvtsd2ss => {
+                let value = self.read_xmm_operand(ins, 1)?;
+                let destination = self.read_xmm_operand(ins, 0)?;
+                let result = if ins.mnemonic() == Cvtss2sd {
+                    let value = f32::from_bits(value as u32) as f64;
+                    destination & !(u64::MAX as u128) | value.to_bits() as u128
+                } else {
+                    let value = f64::from_bits(value as u64) as f32;
+                    destination & !(u32::MAX as u128) | value.to_bits() as u128
+                };
+                self.write_xmm_operand(ins, 0, result)?;
+            }
+            Cvtdq2ps | Cvtps2dq | Cvttps2dq | Cvtdq2pd | Cvtps2pd | Cvtpd2ps => {
+                let value = self.read_xmm_operand(ins, 1)?;
+                let result = match ins.mnemonic() {
+                    Cvtdq2ps => lanes(value, 0, 32, |x, _| {
+                        (x as u32 as i32 as f32).to_bits() as u64
+                    }),
+                    Cvtps2dq | Cvttps2dq => lanes(value, 0, 32, |x, _| {
+                        to_integer(
+                            f32::from_bits(x as u32) as f64,
+                            4,
+                            ins.mnemonic() == Cvttps2dq,
+                        )
+                    }),
+                    Cvtdq2pd => (0..2).fold(0, |result, i| {
+                        let x = (value >> (i * 32)) as u32 as i32 as f64;
+                        result | (x.to_bits() as u128) << (i * 64)
+                    }),
+                    Cvtps2pd => (0..2).fold(0, |result, i| {
+                        let x = f32::from_bits((value >> (i * 32)) as u32) as f64;
+                        result | (x.to_bits() as u128) << (i * 64)
+                    }),
+                    _ => (0..2).fold(0, |result, i| {
+                        let x = f64::from_bits((value >> (i * 64)) as u64) as f32;
+                        result | (x.to_bits() as u128) << (i * 32)
+                    }),
+                };
+                self.write_xmm_operand(ins, 0, result)?;
+            }
+
+            _ => return Err(Exception::InvalidOpcode),
+        }
+        Ok(())
+    }
+
+    /// reads a xmm register, general purpose register or memory operand, memory
+    /// operands smaller than 16 bytes are zero-extended
+    fn read_xmm_operand(&mut self, ins: &Instruction, operand: u32) -> Result<u128, Exception> {
+        match ins.op_kind(operand) {
+            OpKind::Register => {
+                let register = ins.op_register(operand);
+                if register.is_xmm() {
+                    Ok(self.cpu.xmm(register))
+                } else if register.is_gpr() {
+                    Ok(self.cpu.gpr_value(register) as u128)
+                } else {
+                    Err(Exception::InvalidOpcode)
+                }
+            }
+            OpKind::Memory => {
+                let mut data = [0; 16];
+                let size = ins.memory_size().size().min(16);
+                self.read_data(self.address(ins, operand)?, &mut data[..size])?;
+                Ok(u128::from_le_bytes(data))
+            }
+            _ => Err(Exception::InvalidOpcode),
+        }
+    }
+
+    /// writes a whole xmm register, a general purpose register or memory
+    /// operand
+    fn write_xmm_operand(
+        &mut self,
+        ins: &Instruction,
+        operand: u32,
+        value: u128,
+    ) -> Result<(), Exception> {
+        match ins.op_kind(operand) {
+            OpKind::Register => {
+                let register = ins.op_register(operand);
+                if register.is_xmm() {
+                    self.cpu.set_xmm(register, value);
+                } else if register.is_gpr() {
+                    self.cpu.set_gpr_value(register, value as u64);
+                } else {
+                    return Err(Exception::InvalidOpcode);
+                }
+                Ok(())
+            }
+            OpKind::Memory => {
+                let size = ins.memory_size().size().min(16);
+                self.write_data(self.address(ins, operand)?, &value.to_le_bytes()[..size])
+            }
+            _ => Err(Exception::InvalidOpcode),
+        }
+    }
+}
+
+/// packed integer operations, bitwise logic, unpack and pack
+fn integer(mnemonic: Mnemonic, a: u128, b: u128) -> u128 {
+    use Mnemonic::*;
+
+    let signed = |x: u64, bits: u32| sign_extend(x, bits as usize / 8) as i64;
+    let saturate = |x: i64, min: i64, max: i64| x.clamp(min, max) as u64;
+    match mnemonic {
+        Pxor | Xorps | Xorpd => a ^ b,
+        Por | Orps | Orpd => a | b,
+        Pand | Andps | Andpd => a & b,
+        Pandn | Andnps | Andnpd => !a & b,
+        Paddb => lanes(a, b, 8, u64::wrapping_add),
+        Paddw => lanes(a, b, 16, u64::wrapping_add),
+        Paddd => lanes(a, b, 32, u64::wrapping_add),
+        Paddq => lanes(a, b, 64, u64::wrapping_add),
+        Psubb => lanes(a, b, 8, u64::wrapping_sub),
+        Psubw => lanes(a, b, 16, u64::wrapping_sub),
+        Psubd => lanes(a, b, 32, u64::wrapping_sub),
+        Psubq => lanes(a, b, 64, u64::wrapping_sub),
+        Paddsb => lanes(a, b, 8, |x, y| {
+            saturate(signed(x, 8) + signed(y, 8), -0x80, 0x7F)
+        }),
+        Paddsw => lanes(a, b, 16, |x, y| {
+            saturate(signed(x, 16) + signed(y, 16), -0x8000, 0x7FFF)
+        }),
+        Psubsb => lanes(a, b, 8, |x, y| {
+            saturate(signed(x, 8) - signed(y, 8), -0x80, 0x7F)
+        }),
+        Psubsw => lanes(a, b, 16, |x, y| {
+            saturate(signed(x, 16) - signed(y, 16), -0x8000, 0x7FFF)
+        }),
+        Paddusb => lanes(a, b, 8, |x, y| (x + y).min(0xFF)),
+        Paddusw => lanes(a, b, 16, |x, y| (x + y).min(0xFFFF)),
+        Psubusb | Psubusw => lanes(a, b, if mnemonic == Psubusb { 8 } else { 16 }, |x, y| {
+            x.saturating_sub(y)
+        }),
+        Pcmpeqb | Pcmpeqw | Pcmpeqd => {
+            let bits = match mnemonic {
+                Pcmpeqb => 8,
+                Pcmpeqw => 16,
+                _ => 32,
+            };
+            lanes(a, b, bits, |x, y| if x == y { u64::MAX } else { 0 })
+        }
+        Pcmpgtb | Pcmpgtw | Pcmpgtd => {
+            let bits = match mnemonic {
+                Pcmpgtb => 8,
+                Pcmpgtw => 16,
+                _ => 32,
+            };
+            lanes(a, b, bits, |x, y| {
+                if signed(x, bits) > signed(y, bits) {
+                    u64::MAX
+                } else {
+                    0
+                }
+            })
+        }
+        Pminub => lanes(a, b, 8, u64::min),
+        Pmaxub => lanes(a, b, 8, u64::max),
+        Pminsw => lanes(a, b, 16, |x, y| signed(x, 16).min(signed(y, 16)) as u64),
+        Pmaxsw => lanes(a, b, 16, |x, y| signed(x, 16).max(signed(y, 16)) as u64),
+        Pavgb => lanes(a, b, 8, |x, y| (x + y + 1) >> 1),
+        Pavgw => lanes(a, b, 16, |x, y| (x + y + 1) >> 1),
+        Pmullw => lanes(a, b, 16, |x, y| (signed(x, 16) * signed(y, 16)) as u64),
+        Pmulhw => lanes(a, b, 16, |x, y| {
+            ((signed(x, 16) * signed(y, 16)) >> 16) as u64
+        }),
+        Pmulhuw => lanes(a, b, 16, |x, y| (x * y) >> 16),
+        Pmuludq => lanes(a, b, 64, |x, y| (x & 0xFFFF_FFFF) * (y & 0xFFFF_FFFF)),
+        Pmaddwd => lanes(a, b, 32, |x, y| {
+            (signed(x & 0xFFFF, 16) * signed(y & 0xFFFF, 16)
+                + signed(x >> 16, 16) * signed(y >> 16, 16)) as u64
+        }),
+        Punpcklbw => unpack(a, b, 8, false),
+        Punpcklwd => unpack(a, b, 16, false),
+        Punpckldq | Unpcklps => unpack(a, b, 32, false),
+        Punpcklqdq | Unpcklpd => unpack(a, b, 64, false),
+        Punpckhbw => unpack(a, b, 8, true),
+        Punpckhwd => unpack(a, b, 16, true),
+        Punpckhdq | Unpckhps => unpack(a, b, 32, true),
+        Punpckhqdq | Unpckhpd => unpack(a, b, 64, true),
+        Packsswb => pack(a, b, 16, |x| saturate(signed(x, 16), -0x80, 0x7F)),
+        Packssdw => pack(a, b, 32, |x| saturate(signed(x, 32), -0x8000, 0x7FFF)),
+        _ => pack(a, b, 16, |x| saturate(signed(x, 16), 0, 0xFF)),
+    }
+}
+
+/// applies the operation to each pair of lanes
+fn lanes(a: u128, b: u128, bits: u32, operation: impl Fn(u64, u64) -> u64) -> u128 {
+    let lane_mask = mask(bits as usize / 8);
+    (0..128 / bits).fold(0, |result, i| {
+        let x = (a >> (i * bits)) as u64 & lane_mask;
+        let y = (b >> (i * bits)) as u64 & lane_mask;
+        result | ((operation(x, y) & lane_mask) as u128) << (i * bits)
+    })
+}
+
+/// applies the operation to the lowest count lanes, the remaining lanes are
+/// taken from a
+fn float_lanes(
+    a: u128,
+    b: u128,
+    bits: u32,
+    count: u32,
+    operation: impl Fn(u64, u64) -> u64,
+) -> u128 {
+    let lane_mask = mask(bits as usize / 8);
+    (0..count).fold(a, |result, i| {
+        let x = (a >> (i * bits)) as u64 & lane_mask;
+        let y = (b >> (i * bits)) as u64 & lane_mask;
+        result & !((lane_mask as u128) << (i * bits))
+            | ((operation(x, y) & lane_mask) as u128) << (i * bits)
+    })
+}
+
+/// interleaves the lower or upper halves
+fn unpack(a: u128, b: u128, bits: u32, high: bool) -> u128 {
+    let lane_mask = mask(bits as usize / 8) as u128;
+    let base = if high { 64 / bits } else { 0 };
+    (0..64 / bits).fold(0, |result, i| {
+        let x = a >> ((base + i) * bits) & lane_mask;
+        let y = b >> ((base + i) * bits) & lane_mask;
+        result | x << (i * 2 * bits) | y << ((i * 2 + 1) * bits)
+    })
+}
+
+/// narrows the lanes of a followed by the lanes of b to half their size
+fn pack(a: u128, b: u128, bits: u32, operation: impl Fn(u64) -> u64) -> u128 {
+    let lane_mask = mask(bits as usize / 8);
+    let narrow_mask = mask(bits as usize / 16);
+    let count = 128 / bits;
+    (0..count * 2).fold(0, |result, i| {
+        let source = if i < count { a } else { b };
+        let x = (source >> ((i % count) * bits)) as u64 & lane_mask;
+        result | ((operation(x) & narrow_mask) as u128) << (i * bits / 2)
+    })
+}
+
+fn to_f64(value: u64, bits: u32) -> f64 {
+    if bits == 32 {
+        f32::from_bits(value as u32) as f64
+    } else {
+        f64::from_bits(value)
+    }
+}
+
+/// converts to a signed integer of the specified size, nan and values out of
+/// range result in the integer indefinite value
+fn to_integer(value: f64, size: usize, truncate: bool) -> u64 {
+    let value = if truncate {
+        value.trunc()
+    } else {
+        value.round_ties_even()
+    };
+    let limit = 2f64.powi(size as i32 * 8 - 1);
+    if value >= -limit && value < limit {
+        value as i64 as u64 & mask(size)
+    } else {
+        1 << (size * 8 - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::tests::emulator;
+
+    const A: u128 = 0x0F0E0D0C_0B0A0908_07060504_03020100;
+    const B: u128 = 0x1F1E1D1C_1B1A1918_17161514_13121110;
+
+    /// executes a single instruction with xmm0 and xmm1 set
+    fn step(code: &[u8], xmm0: u128, xmm1: u128) -> Emulator {
+        let mut emulator = emulator(code);
+        emulator.cpu.xmm[0] = xmm0;
+        emulator.cpu.xmm[1] = xmm1;
+        emulator.step().unwrap();
+        emulator
+    }
+
+    #[test]
+    fn shuffles() {
+        for (code, xmm0, xmm1, result) in [
+            // pshufd xmm0, xmm1, 0x1B reverses the dwords
+            (
+                &[0x66, 0x0F, 0x70, 0xC1, 0x1B][..],
+                0,
+                0x33333333_22222222_11111111_00000000,
+                0x00000000_11111111_22222222_33333333,
+            ),
+            // punpcklbw xmm0, xmm1
+            (
+                &[0x66, 0x0F, 0x60, 0xC1],
+                A,
+                B,
+                0x17071606_15051404_13031202_11011000,
+            ),
+            // punpckldq xmm0, xmm1
+            (
+                &[0x66, 0x0F, 0x62, 0xC1],
+                A,
+                B,
+                0x17161514_07060504_13121110_03020100,
+            ),
+            // punpckhqdq xmm0, xmm1
+            (
+                &[0x66, 0x0F, 0x6D, 0xC1],
+                A,
+                B,
+                0x1F1E1D1C_1B1A1918_0F0E0D0C_0B0A0908,
+            ),
+        ] {
+            let emulator = step(code, xmm0, xmm1);
+            assert_eq!(emulator.cpu.xmm[0], result, "{code:02X?}");
+        }
+    }
+
+    #[test]
+    fn packs() {
+        let words = (
+            0x00420000_80007FFF_FFFF0001_FF7F0080,
+            0x00030002_00FF8001_0080007F_FFFF0100,
+        );
+        let dwords = (
+            0xFFFFFFFF_00001234_FFFEFFFF_00010000,
+            0xFFFF7FFF_FFFF8000_00008000_00007FFF,
+        );
+        for (code, (xmm0, xmm1), result) in [
+            // packsswb xmm0, xmm1
+            (
+                &[0x66, 0x0F, 0x63, 0xC1][..],
+                words,
+                0x03027F80_7F7FFF7F_4200807F_FF01807F,
+            ),
+            // packuswb xmm0, xmm1
+            (
+                &[0x66, 0x0F, 0x67, 0xC1],
+                words,
+                0x0302FF00_807F00FF_420000FF_00010080,
+            ),
+            // packssdw xmm0, xmm1
+            (
+                &[0x66, 0x0F, 0x6B, 0xC1],
+                dwords,
+                0x80008000_7FFF7FFF_FFFF1234_80007FFF,
+            ),
+        ] {
+            let emulator = step(code, xmm0, xmm1);
+            assert_eq!(emulator.cpu.xmm[0], result, "{code:02X?}");
+        }
+    }
+
+    #[test]
+    fn shifts() {
+        let words = 0x7FFF8000_00028001_7FFF8000_00028001;
+        for (code, xmm1, result) in [
+            // psllw xmm0, 16 / psllw xmm0, 15
+            (&[0x66, 0x0F, 0x71, 0xF0, 0x10][..], 0, 0),
+            (
+                &[0x66, 0x0F, 0x71, 0xF0, 0x0F],
+                0,
+                0x80000000_00008000_80000000_00008000,
+            ),
+            // psrld xmm0, 32
+            (&[0x66, 0x0F, 0x72, 0xD0, 0x20], 0, 0),
+            // psraw xmm0, 20 fills the lanes with the sign
+            (
+                &[0x66, 0x0F, 0x71, 0xE0, 0x14],
+                0,
+                0x0000FFFF_0000FFFF_0000FFFF_0000FFFF,
+            ),
+            // psrlq xmm0, xmm1 uses the whole lower quadword as count
+            (&[0x66, 0x0F, 0xD3, 0xC1], 64, 0),
+            (&[0x66, 0x0F, 0xD3, 0xC1], 1 << 64, words),
+            // pslld xmm0, xmm1
+            (&[0x66, 0x0F, 0xF2, 0xC1], 0xFFFF_FFFF_0000_0001, 0),
+        ] {
+            let emulator = step(code, words, xmm1);
+            assert_eq!(emulator.cpu.xmm[0], result, "{code:02X?} {xmm1:#x}");
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        let double = |value: f64| value.to_bits() as u128;
+        let single = |value: f32| value.to_bits() as u128;
+        for (code, xmm1, result) in [
+            // cvttsd2si eax, xmm1 truncates and zero-extends
+            (&[0xF2, 0x0F, 0x2C, 0xC1][..], double(2.9), 2),
+            (&[0xF2, 0x0F, 0x2C, 0xC1], double(-2.9), 0xFFFF_FFFE),
+            (&[0xF2, 0x0F, 0x2C, 0xC1], double(3e9), 0x8000_0000),
+            (&[0xF2, 0x0F, 0x2C, 0xC1], double(f64::NAN), 0x8000_0000),
+            // cvtsd2si rax, xmm1 rounds to even
+            (&[0xF2, 0x48, 0x0F, 0x2D, 0xC1], double(2.5), 2),
+            (&[0xF2, 0x48, 0x0F, 0x2D, 0xC1], double(3.5), 4),
+            (&[0xF2, 0x48, 0x0F, 0x2D, 0xC1], double(-2.5), -2i64 as u64),
+            (&[0xF2, 0x48, 0x0F, 0x2D, 0xC1], double(1e19), 1 << 63),
+            (&[0xF2, 0x48, 0x0F, 0x2D, 0xC1], double(-1e19), 1 << 63),
+            // cvttss2si eax, xmm1
+            (&[0xF3, 0x0F, 0x2C, 0xC1], single(-1.5), 0xFFFF_FFFF),
+            (&[0xF3, 0x0F, 0x2C, 0xC1], single(2147483648.0), 0x8000_0000),
+        ] {
+            let mut emulator = emulator(code);
+            emulator.cpu.gpr[RAX] = u64::MAX;
+            emulator.cpu.xmm[1] = xmm1;
+            emulator.step().unwrap();
+            assert_eq!(emulator.cpu.gpr[RAX], result, "{code:02X?} {xmm1:#x}");
+        }
+
+        // cvttps2dq xmm0, xmm1
+        let emulator = step(
+            &[0xF3, 0x0F, 0x5B, 0xC1],
+            0,
+            single(f32::NAN) << 96 | single(3e9) << 64 | single(-1.9) << 32 | single(1.9),
+        );
+        assert_eq!(emulator.cpu.xmm[0], 0x80000000_80000000_FFFFFFFF_00000001);
+    }
+
+    #[test]
+    fn comparisons() {
+        for (a, b, expected) in [
+            (f64::NAN, 1.0, ZF | PF | CF),
+            (1.0, f64::NAN, ZF | PF | CF),
+            (1.0, 2.0, CF),
+            (2.0, 2.0, ZF),
+            (3.0, 2.0, 0),
+        ] {
+            // ucomisd xmm0, xmm1
+            let mut emulator = emulator(&[0x66, 0x0F, 0x2E, 0xC1]);
+            emulator.cpu.xmm[0] = a.to_bits() as u128;
+            emulator.cpu.xmm[1] = b.to_bits() as u128;
+            emulator.cpu.rflags |= OF | SF | AF;
+            emulator.step().unwrap();
+            assert_eq!(
+                emulator.cpu.rflags & (CF | PF | AF | ZF | SF | OF),
+                expected,
+                "{a} {b}"
+            );
+        }
+    }
+
+    #[test]
+    fn scalar_moves() {
+        let xmm0 = 0x11111111_22222222_33333333_44444444;
+        let xmm1 = 0xAAAAAAAA_AAAAAAAA_AAAAAAAA_AAAAAAAA;
+        for (code, result) in [
+            // movss xmm0, xmm1 / movsd xmm0, xmm1 merge
+            (
+                &[0xF3, 0x0F, 0x10, 0xC1][..],
+                0x11111111_22222222_33333333_AAAAAAAA,
+            ),
+            (
+                &[0xF2, 0x0F, 0x10, 0xC1],
+                0x11111111_22222222_AAAAAAAA_AAAAAAAA,
+            ),
+            // movss xmm0, [rsp] / movsd xmm0, [rsp] zero-extend
+            (&[0xF3, 0x0F, 0x10, 0x04, 0x24], 0x66666666),
+            (&[0xF2, 0x0F, 0x10, 0x04, 0x24], 0x55555555_66666666),
+        ] {
+            let mut emulator = emulator(code);
+            emulator.write_u64(0x1FF00, 0x5555_5555_6666_6666).unwrap();
+            emulator.write_u64(0x1FF08, 0x7777_7777_7777_7777).unwrap();
+            emulator.cpu.xmm[0] = xmm0;
+            emulator.cpu.xmm[1] = xmm1;
+            emulator.step().unwrap();
+            assert_eq!(emulator.cpu.xmm[0], result, "{code:02X?}");
+        }
+
+        // movss [rsp], xmm0 only writes the lowest dword
+        let mut emulator = emulator(&[0xF3, 0x0F, 0x11, 0x04, 0x24]);
+        emulator.write_u64(0x1FF00, 0x5555_5555_6666_6666).unwrap();
+        emulator.cpu.xmm[0] = xmm0;
+        emulator.step().unwrap();
+        assert_eq!(emulator.read_u64(0x1FF00).unwrap(), 0x5555_5555_4444_4444);
+        assert_eq!(emulator.cpu.gpr[RSP], 0x1FF00);
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use crate::emulator::*;
+
+/// start of the region where stubs are allocated
+pub const STUB_BASE: u64 = 0x7FFE_0000_0000;
+
+/// space reserved for each stub
+pub const STUB_SIZE: u64 = 0x10;
+
+/// host function invoked in place of a guest function, it receives the
+/// arguments in the guest registers and returns the value for rax
+pub type Handler = Arc<dyn Fn(&mut Emulator) -> Result<u64, Exception> + Send + Sync>;
+
+/// guest address which calls into the host instead of executing code
+#[derive(Clone)]
+pub struct Stub {
+    pub library: String,
+    pub function: String,
+    pub handler: Option<Handler>,
+}
+
+impl Emulator {
+    /// returns the stub of the function, a new one is allocated if there is
+    /// none yet, stubs can be read by the guest (int3) but not executed
+    pub fn add_stub(&mut self, library: &str, function: &str) -> u64 {
+        if let Some((&address, _)) = self.stubs.iter().find(|(_, stub)| {
+            stub.library.eq_ignore_ascii_case(library) && stub.function == function
+        }) {
+            return address;
+        }
+        let address = self
+            .stubs
+            .range(STUB_BASE..)
+            .next_back()
+            .map_or(STUB_BASE, |(&address, _)| address + STUB_SIZE);
+        self.add_stub_at(address, library, function);
+        address
+    }
+
+    /// places a stub at the specified address (e.g. an export of a
+    /// synthesized module), replacing any stub at the same address
+    pub fn add_stub_at(&mut self, address: u64, library: &str, function: &str) {
+        self.memory.map(address, STUB_SIZE, Protection::READ);
+        self.memory
+            .write_raw(address, &[0xCC; STUB_SIZE as usize])
+            .unwrap();
+        self.stubs.insert(
+            address,
+            Stub {
+                library: library.to_owned(),
+                function: function.to_owned(),
+                handler: None,
+            },
+        );
+    }
+
+    /// stub at the specified address
+    pub fn stub(&self, address: u64) -> Option<&Stub> {
+        self.stubs.get(&address)
+    }
+
+    /// all stubs ordered by address
+    pub fn stubs(&self) -> impl Iterator<Item = (u64, &Stub)> {
+        self.stubs.iter().map(|(&address, stub)| (address, stub))
+    }
+
+    /// sets the handler of a stub, returns false if there is no stub at the
+    /// address
+    pub fn set_handler(&mut self, address: u64, handler: Handler) -> bool {
+        let Some(stub) = self.stubs.get_mut(&address) else {
+            return false;
+        };
+        stub.handler = Some(handler);
+        true
+    }
+
+    /// invokes the handler of the stub at rip and returns to the caller, stubs
+    /// without own handler fall back to the handlers registered in
+    /// [Win32], rip stays at the stub if there is no handler or it fails
+    pub(super) fn invoke_stub(&mut self, address: u64) -> Result<(), Exception> {
+        let stub = &self.stubs[&address];
+        let Some(handler) = stub
+            .handler
+            .clone()
+            .or_else(|| self.win32.handler(&stub.library, &stub.function))
+        else {
+            return Err(Exception::Stub(address));
+        };
+        let value = handler(self)?;
+        self.cpu.gpr[RAX] = value;
+        self.cpu.rip = self.pop(8)?;
+        Ok(())
+    }
+}
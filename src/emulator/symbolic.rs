@@ -0,0 +1,1005 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use iced_x86::{
+    ConditionCode, FlowControl, Instruction, InstructionInfoFactory, Mnemonic, OpAccess, OpKind,
+    Register,
+};
+
+use crate::{
+    emulator::*,
+    solver::{Expr, Model, Solver},
+    trace::MemoryAccess,
+    Error,
+};
+
+/// unknown input, its concrete value is the one the emulator runs with
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub location: Location,
+    pub width: u32,
+}
+
+/// where the value of a variable is stored
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// a byte
+    Memory(u64),
+    /// a general purpose register
+    Register(usize),
+}
+
+/// conditional branch depending on variables
+#[derive(Debug, Clone)]
+pub struct Branch {
+    pub address: u64,
+    pub taken: bool,
+    /// 1-bit condition which holds on the executed path
+    pub condition: Expr,
+}
+
+/// how the flags were last set
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum FlagKind {
+    Add,
+    Sub,
+    /// and, or, xor and test, carry and overflow are clear
+    Logic,
+    /// carry is unchanged
+    Increment,
+    Decrement,
+    /// only zero, sign and parity are known
+    Shift,
+}
+
+#[derive(Debug, Clone)]
+struct Flags {
+    kind: FlagKind,
+    left: Expr,
+    right: Expr,
+    result: Expr,
+}
+
+/// symbolic state of the emulator, enabled with [Emulator::enable_symbolic]
+///
+/// Execution is concolic: the emulator keeps running on concrete values and
+/// expressions over the variables are tracked next to the general purpose
+/// registers, memory and flags. Unsupported instructions, stub and system
+/// call results and values loaded through symbolic addresses are
+/// concretized, memory written by handlers keeps its expressions.
+#[derive(Debug, Clone, Default)]
+pub struct Symbolic {
+    /// symbolic branches of the current path in execution order
+    pub path: Vec<Branch>,
+
+    variables: Vec<Variable>,
+    /// None if the register is concrete
+    registers: [Option<Expr>; 16],
+    /// 8-bit expressions of the symbolic bytes
+    memory: BTreeMap<u64, Expr>,
+    flags: Option<Flags>,
+}
+
+/// options of [Emulator::explore]
+#[derive(Debug, Clone)]
+pub struct ExploreOptions {
+    pub solver: Solver,
+    /// maximum number of paths to execute
+    pub runs: usize,
+    /// maximum number of instructions per path
+    pub limit: u64,
+}
+
+impl Default for ExploreOptions {
+    fn default() -> Self {
+        Self {
+            solver: Solver::default(),
+            runs: 256,
+            limit: 1_000_000,
+        }
+    }
+}
+
+/// instruction being propagated
+struct Step<'a> {
+    ins: &'a Instruction,
+    /// registers before the instruction
+    gpr: &'a [u64; 16],
+    reads: Vec<&'a MemoryAccess>,
+    writes: Vec<&'a MemoryAccess>,
+}
+
+impl Symbolic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn variables(&self) -> &[Variable] {
+        &self.variables
+    }
+
+    /// makes every byte of the range a variable named name\[i\], returns the
+    /// variables
+    pub fn symbolic_memory(&mut self, address: u64, size: usize, name: &str) -> Vec<u32> {
+        (0..size as u64)
+            .map(|i| {
+                let address = address.wrapping_add(i);
+                let variable =
+                    self.add_variable(format!("{name}[{i}]"), Location::Memory(address), 8);
+                self.memory.insert(address, Expr::variable(variable, 8));
+                variable
+            })
+            .collect()
+    }
+
+    /// makes a general purpose register a variable
+    pub fn symbolic_register(&mut self, register: usize, name: &str) -> u32 {
+        let variable = self.add_variable(name.to_owned(), Location::Register(register), 64);
+        self.registers[register] = Some(Expr::variable(variable, 64));
+        variable
+    }
+
+    /// expression of a general purpose register, None if it is concrete
+    pub fn register(&self, register: usize) -> Option<&Expr> {
+        self.registers[register].as_ref()
+    }
+
+    /// sets the 64-bit expression of a general purpose register
+    pub fn set_register(&mut self, register: usize, value: Option<Expr>) {
+        self.registers[register] = value.filter(|value| !value.is_constant());
+    }
+
+    /// 8-bit expression of a byte, None if it is concrete
+    pub fn memory(&self, address: u64) -> Option<&Expr> {
+        self.memory.get(&address)
+    }
+
+    /// sets the 8-bit expression of a byte
+    pub fn set_memory(&mut self, address: u64, value: Option<Expr>) {
+        match value.filter(|value| !value.is_constant()) {
+            Some(value) => self.memory.insert(address, value),
+            None => self.memory.remove(&address),
+        };
+    }
+
+    /// conditions of the current path
+    pub fn constraints(&self) -> Vec<Expr> {
+        self.path
+            .iter()
+            .map(|branch| branch.condition.clone())
+            .collect()
+    }
+
+    fn add_variable(&mut self, name: String, location: Location, width: u32) -> u32 {
+        self.variables.push(Variable {
+            name,
+            location,
+            width,
+        });
+        self.variables.len() as u32 - 1
+    }
+
+    /// updates the expressions after an instruction retired, rip is the
+    /// address it continued at
+    fn propagate(
+        &mut self,
+        ins: &Instruction,
+        gpr: &[u64; 16],
+        accesses: &[MemoryAccess],
+        rip: u64,
+    ) {
+        if ins.flow_control() == FlowControl::ConditionalBranch {
+            if let Some(condition) = self.condition(ins.condition_code()) {
+                if !condition.is_constant() {
+                    let taken = rip != ins.next_ip();
+                    self.path.push(Branch {
+                        address: ins.ip(),
+                        taken,
+                        condition: if taken { condition } else { !condition },
+                    });
+                }
+            }
+        }
+
+        let step = Step {
+            ins,
+            gpr,
+            reads: accesses.iter().filter(|access| !access.write).collect(),
+            writes: accesses.iter().filter(|access| access.write).collect(),
+        };
+        if self.execute(&step).is_none() {
+            self.concretize(&step);
+        }
+    }
+
+    /// None if the instruction isn't supported
+    fn execute(&mut self, step: &Step) -> Option<()> {
+        let ins = step.ins;
+        let width = operand_width(ins, 0);
+        match ins.mnemonic() {
+            _ if ins.flow_control() != FlowControl::Next => return None,
+            Mnemonic::Mov | Mnemonic::Movzx | Mnemonic::Movsx | Mnemonic::Movsxd => {
+                let value = self.operand(step, 1, width)?;
+                let value = match ins.mnemonic() {
+                    Mnemonic::Movzx => value.zero_extend(width),
+                    Mnemonic::Movsx | Mnemonic::Movsxd => value.sign_extend(width),
+                    _ => value,
+                };
+                self.set_operand(step, 0, value)?;
+            }
+            Mnemonic::Lea => {
+                let address = self.address(step)?;
+                self.set_operand(step, 0, address.extract(0, width))?;
+            }
+            Mnemonic::Push => {
+                let write = step.writes.last()?;
+                let value = self.operand(step, 0, write.data.len() as u32 * 8)?;
+                self.set_memory_value(write.address, value);
+            }
+            Mnemonic::Pop => {
+                let value = self.memory_value(step.reads.first()?)?;
+                self.set_operand(step, 0, value)?;
+            }
+            Mnemonic::Xchg => {
+                let first = self.operand(step, 0, width)?;
+                let second = self.operand(step, 1, width)?;
+                self.set_operand(step, 0, second)?;
+                self.set_operand(step, 1, first)?;
+            }
+            Mnemonic::Add
+            | Mnemonic::Sub
+            | Mnemonic::Cmp
+            | Mnemonic::And
+            | Mnemonic::Or
+            | Mnemonic::Xor
+            | Mnemonic::Test => {
+                let left = self.operand(step, 0, width)?;
+                let right = self.operand(step, 1, width)?;
+                let same = ins.op0_kind() == OpKind::Register
+                    && ins.op1_kind() == OpKind::Register
+                    && ins.op0_register() == ins.op1_register();
+                let zero = Expr::constant(0, width);
+                let (kind, result) = match ins.mnemonic() {
+                    Mnemonic::Add => (FlagKind::Add, left.clone() + right.clone()),
+                    Mnemonic::Sub | Mnemonic::Cmp if same => (FlagKind::Sub, zero),
+                    Mnemonic::Sub | Mnemonic::Cmp => (FlagKind::Sub, left.clone() - right.clone()),
+                    Mnemonic::Xor if same => (FlagKind::Logic, zero),
+                    Mnemonic::Xor => (FlagKind::Logic, left.clone() ^ right.clone()),
+                    Mnemonic::Or => (FlagKind::Logic, left.clone() | right.clone()),
+                    _ => (FlagKind::Logic, left.clone() & right.clone()),
+                };
+                if !matches!(ins.mnemonic(), Mnemonic::Cmp | Mnemonic::Test) {
+                    self.set_operand(step, 0, result.clone())?;
+                }
+                self.flags = Some(Flags {
+                    kind,
+                    left,
+                    right,
+                    result,
+                });
+            }
+            Mnemonic::Inc | Mnemonic::Dec => {
+                let left = self.operand(step, 0, width)?;
+                let right = Expr::constant(1, width);
+                let (kind, result) = if ins.mnemonic() == Mnemonic::Inc {
+                    (FlagKind::Increment, left.clone() + right.clone())
+                } else {
+                    (FlagKind::Decrement, left.clone() - right.clone())
+                };
+                self.set_operand(step, 0, result.clone())?;
+                self.flags = Some(Flags {
+                    kind,
+                    left,
+                    right,
+                    result,
+                });
+            }
+            Mnemonic::Neg => {
+                let right = self.operand(step, 0, width)?;
+                let left = Expr::constant(0, width);
+                let result = -right.clone();
+                self.set_operand(step, 0, result.clone())?;
+                self.flags = Some(Flags {
+                    kind: FlagKind::Sub,
+                    left,
+                    right,
+                    result,
+                });
+            }
+            Mnemonic::Not => {
+                let value = self.operand(step, 0, width)?;
+                self.set_operand(step, 0, !value)?;
+            }
+            Mnemonic::Shl | Mnemonic::Sal | Mnemonic::Shr | Mnemonic::Sar => {
+                // the count is concretized
+                let count = shift_count(step, width)?;
+                if count == 0 {
+                    return Some(());
+                }
+                let value = self.operand(step, 0, width)?;
+                let amount = Expr::constant(count, width);
+                let result = match ins.mnemonic() {
+                    Mnemonic::Shr => value.clone().lshr(amount.clone()),
+                    Mnemonic::Sar => value.clone().ashr(amount.clone()),
+                    _ => value.clone() << amount.clone(),
+                };
+                self.set_operand(step, 0, result.clone())?;
+                self.flags = Some(Flags {
+                    kind: FlagKind::Shift,
+                    left: value,
+                    right: amount,
+                    result,
+                });
+            }
+            Mnemonic::Rol | Mnemonic::Ror => {
+                let count = shift_count(step, width)?;
+                if count == 0 {
+                    return Some(());
+                }
+                let value = self.operand(step, 0, width)?;
+                let count = match ins.mnemonic() {
+                    Mnemonic::Rol => count % width as u64,
+                    _ => (width as u64 - count % width as u64) % width as u64,
+                };
+                let result = if count == 0 {
+                    value
+                } else {
+                    (value.clone() << Expr::constant(count, width))
+                        | value.lshr(Expr::constant(width as u64 - count, width))
+                };
+                self.set_operand(step, 0, result)?;
+                self.flags = None;
+            }
+            Mnemonic::Imul if ins.op_count() >= 2 => {
+                let (left, right) = if ins.op_count() == 3 {
+                    (self.operand(step, 1, width)?, self.operand(step, 2, width)?)
+                } else {
+                    (self.operand(step, 0, width)?, self.operand(step, 1, width)?)
+                };
+                self.set_operand(step, 0, left * right)?;
+                self.flags = None;
+            }
+            // the high half of 64-bit products and dividends doesn't fit
+            Mnemonic::Mul | Mnemonic::Imul if width <= 32 => {
+                let signed = ins.mnemonic() == Mnemonic::Imul;
+                let extend = |value: Expr| {
+                    if signed {
+                        value.sign_extend(width * 2)
+                    } else {
+                        value.zero_extend(width * 2)
+                    }
+                };
+                let value = self.register_value(accumulator(width, RAX)?, step.gpr)?;
+                let source = self.operand(step, 0, width)?;
+                let product = extend(value) * extend(source);
+                if width == 8 {
+                    self.set_register_value(Register::AX, product, step.gpr)?;
+                } else {
+                    let high = product.clone().extract(width, width);
+                    self.set_register_value(
+                        accumulator(width, RAX)?,
+                        product.extract(0, width),
+                        step.gpr,
+                    )?;
+                    self.set_register_value(accumulator(width, RDX)?, high, step.gpr)?;
+                }
+                self.flags = None;
+            }
+            Mnemonic::Div if width <= 32 => {
+                let dividend = if width == 8 {
+                    self.register_value(Register::AX, step.gpr)?
+                } else {
+                    let high = self.register_value(accumulator(width, RDX)?, step.gpr)?;
+                    high.concat(self.register_value(accumulator(width, RAX)?, step.gpr)?)
+                };
+                let divisor = self.operand(step, 0, width)?.zero_extend(width * 2);
+                let quotient = dividend.clone().udiv(divisor.clone()).extract(0, width);
+                let remainder = dividend.urem(divisor).extract(0, width);
+                let (quotient_register, remainder_register) = if width == 8 {
+                    (Register::AL, Register::AH)
+                } else {
+                    (accumulator(width, RAX)?, accumulator(width, RDX)?)
+                };
+                self.set_register_value(quotient_register, quotient, step.gpr)?;
+                self.set_register_value(remainder_register, remainder, step.gpr)?;
+                self.flags = None;
+            }
+            Mnemonic::Cbw | Mnemonic::Cwde | Mnemonic::Cdqe => {
+                let width = match ins.mnemonic() {
+                    Mnemonic::Cbw => 16,
+                    Mnemonic::Cwde => 32,
+                    _ => 64,
+                };
+                let value = self.register_value(accumulator(width / 2, RAX)?, step.gpr)?;
+                self.set_register_value(
+                    accumulator(width, RAX)?,
+                    value.sign_extend(width),
+                    step.gpr,
+                )?;
+            }
+            Mnemonic::Cwd | Mnemonic::Cdq | Mnemonic::Cqo => {
+                let width = match ins.mnemonic() {
+                    Mnemonic::Cwd => 16,
+                    Mnemonic::Cdq => 32,
+                    _ => 64,
+                };
+                let value = self.register_value(accumulator(width, RAX)?, step.gpr)?;
+                let sign = value.ashr(Expr::constant(width as u64 - 1, width));
+                self.set_register_value(accumulator(width, RDX)?, sign, step.gpr)?;
+            }
+            Mnemonic::Bswap => {
+                let value = self.operand(step, 0, width)?;
+                let mut result = value.clone().extract(0, 8);
+                for byte in 1..width / 8 {
+                    result = result.concat(value.clone().extract(byte * 8, 8));
+                }
+                self.set_operand(step, 0, result)?;
+            }
+            // setcc
+            _ if ins.condition_code() != ConditionCode::None && ins.op_count() == 1 => {
+                let condition = self.condition(ins.condition_code())?;
+                self.set_operand(step, 0, condition.zero_extend(8))?;
+            }
+            // cmovcc
+            _ if ins.condition_code() != ConditionCode::None && ins.op_count() == 2 => {
+                let condition = self.condition(ins.condition_code())?;
+                let source = self.operand(step, 1, width)?;
+                let destination = self.operand(step, 0, width)?;
+                self.set_operand(step, 0, condition.ite(source, destination))?;
+            }
+            Mnemonic::Movsb | Mnemonic::Movsw | Mnemonic::Movsd | Mnemonic::Movsq
+                if is_string(ins) =>
+            {
+                for (read, write) in step.reads.iter().zip(&step.writes) {
+                    for i in 0..read.data.len() as u64 {
+                        let value = self.memory.get(&read.address.wrapping_add(i)).cloned();
+                        self.set_memory(write.address.wrapping_add(i), value);
+                    }
+                }
+                self.concretize_string();
+            }
+            Mnemonic::Stosb | Mnemonic::Stosw | Mnemonic::Stosd | Mnemonic::Stosq => {
+                for write in &step.writes {
+                    let width = write.data.len() as u32 * 8;
+                    let value = self.register_value(accumulator(width, RAX)?, step.gpr)?;
+                    self.set_memory_value(write.address, value);
+                }
+                self.concretize_string();
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// forgets the expressions of everything the instruction writes
+    fn concretize(&mut self, step: &Step) {
+        let mut factory = InstructionInfoFactory::new();
+        let info = factory.info(step.ins);
+        for used in info.used_registers() {
+            let write = matches!(
+                used.access(),
+                OpAccess::Write
+                    | OpAccess::CondWrite
+                    | OpAccess::ReadWrite
+                    | OpAccess::ReadCondWrite
+            );
+            if write && used.register().is_gpr() {
+                self.registers[gpr_index(used.register())] = None;
+            }
+        }
+        for write in &step.writes {
+            for i in 0..write.data.len() as u64 {
+                self.memory.remove(&write.address.wrapping_add(i));
+            }
+        }
+        if step.ins.rflags_modified() != 0 {
+            self.flags = None;
+        }
+    }
+
+    /// the pointers and count of string instructions are concretized
+    fn concretize_string(&mut self) {
+        for register in [RCX, RSI, RDI] {
+            self.registers[register] = None;
+        }
+    }
+
+    fn register_value(&self, register: Register, gpr: &[u64; 16]) -> Option<Expr> {
+        if !register.is_gpr() {
+            return None;
+        }
+        let index = gpr_index(register);
+        let full = self.registers[index]
+            .clone()
+            .unwrap_or_else(|| Expr::constant(gpr[index], 64));
+        Some(match register {
+            Register::AH | Register::CH | Register::DH | Register::BH => full.extract(8, 8),
+            _ => full.extract(0, register.size() as u32 * 8),
+        })
+    }
+
+    /// writes a register like the instruction would, 32-bit writes clear the
+    /// upper half
+    fn set_register_value(
+        &mut self,
+        register: Register,
+        value: Expr,
+        gpr: &[u64; 16],
+    ) -> Option<()> {
+        if !register.is_gpr() {
+            return None;
+        }
+        let index = gpr_index(register);
+        let full = match register.size() {
+            8 => value,
+            4 => value.zero_extend(64),
+            _ => {
+                let old = self.registers[index]
+                    .clone()
+                    .unwrap_or_else(|| Expr::constant(gpr[index], 64));
+                let low = match register {
+                    Register::AH | Register::CH | Register::DH | Register::BH => 8,
+                    _ => 0,
+                };
+                let width = value.width();
+                let mut full = value;
+                if low != 0 {
+                    full = full.concat(old.clone().extract(0, low));
+                }
+                old.extract(low + width, 64 - low - width).concat(full)
+            }
+        };
+        self.set_register(index, Some(full));
+        Some(())
+    }
+
+    /// value read by an access of at most 8 bytes
+    fn memory_value(&self, access: &MemoryAccess) -> Option<Expr> {
+        if access.data.is_empty() || access.data.len() > 8 {
+            return None;
+        }
+        let mut value: Option<Expr> = None;
+        for (i, &byte) in access.data.iter().enumerate() {
+            let byte = self
+                .memory
+                .get(&access.address.wrapping_add(i as u64))
+                .cloned()
+                .unwrap_or_else(|| Expr::constant(byte as u64, 8));
+            value = Some(match value {
+                Some(low) => byte.concat(low),
+                None => byte,
+            });
+        }
+        value
+    }
+
+    fn set_memory_value(&mut self, address: u64, value: Expr) {
+        for byte in 0..value.width() / 8 {
+            self.set_memory(
+                address.wrapping_add(byte as u64),
+                Some(value.clone().extract(byte * 8, 8)),
+            );
+        }
+    }
+
+    /// value of an operand, memory operands use the first read access and
+    /// immediates have the specified width
+    fn operand(&self, step: &Step, operand: u32, width: u32) -> Option<Expr> {
+        match step.ins.op_kind(operand) {
+            OpKind::Register => self.register_value(step.ins.op_register(operand), step.gpr),
+            OpKind::Memory => self.memory_value(step.reads.first()?),
+            OpKind::Immediate8
+            | OpKind::Immediate8_2nd
+            | OpKind::Immediate16
+            | OpKind::Immediate32
+            | OpKind::Immediate64
+            | OpKind::Immediate8to16
+            | OpKind::Immediate8to32
+            | OpKind::Immediate8to64
+            | OpKind::Immediate32to64 => Some(Expr::constant(step.ins.immediate(operand), width)),
+            _ => None,
+        }
+    }
+
+    /// writes an operand, memory operands use the first write access
+    fn set_operand(&mut self, step: &Step, operand: u32, value: Expr) -> Option<()> {
+        match step.ins.op_kind(operand) {
+            OpKind::Register => {
+                self.set_register_value(step.ins.op_register(operand), value, step.gpr)
+            }
+            OpKind::Memory => {
+                let write = step.writes.first()?;
+                if write.data.len() as u32 * 8 != value.width() {
+                    return None;
+                }
+                self.set_memory_value(write.address, value);
+                Some(())
+            }
+            _ => None,
+        }
+    }
+
+    /// address of the memory operand
+    fn address(&self, step: &Step) -> Option<Expr> {
+        let ins = step.ins;
+        let mut address = Expr::constant(ins.memory_displacement64(), 64);
+        if ins.memory_base() == Register::RIP {
+            return Some(address);
+        }
+        if ins.memory_base() != Register::None {
+            let base = self.register_value(ins.memory_base(), step.gpr)?;
+            address = base.zero_extend(64) + address;
+        }
+        if ins.memory_index() != Register::None {
+            let index = self.register_value(ins.memory_index(), step.gpr)?;
+            let scale = Expr::constant(ins.memory_index_scale() as u64, 64);
+            address = address + index.zero_extend(64) * scale;
+        }
+        Some(address)
+    }
+
+    /// 1-bit condition of a conditional instruction, None if the flags it
+    /// depends on aren't known
+    fn condition(&self, condition: ConditionCode) -> Option<Expr> {
+        let flags = self.flags.as_ref()?;
+        let width = flags.result.width();
+        let (left, right, result) = (
+            flags.left.clone(),
+            flags.right.clone(),
+            flags.result.clone(),
+        );
+        let sign_bit = |value: Expr| value.extract(width - 1, 1);
+        let zero = result.clone().equal(Expr::constant(0, width));
+        let sign = sign_bit(result.clone());
+        let (carry, overflow) = match flags.kind {
+            FlagKind::Add => (
+                Some(result.clone().ult(left.clone())),
+                Some(sign_bit(!(left.clone() ^ right) & (left ^ result.clone()))),
+            ),
+            FlagKind::Sub => (
+                Some(left.clone().ult(right.clone())),
+                Some(sign_bit((left.clone() ^ right) & (left ^ result.clone()))),
+            ),
+            FlagKind::Logic => (Some(Expr::bool(false)), Some(Expr::bool(false))),
+            FlagKind::Increment => (
+                None,
+                Some(
+                    result
+                        .clone()
+                        .equal(Expr::constant(1 << (width - 1), width)),
+                ),
+            ),
+            FlagKind::Decrement => (
+                None,
+                Some(
+                    result
+                        .clone()
+                        .equal(Expr::constant((1 << (width - 1)) - 1, width)),
+                ),
+            ),
+            FlagKind::Shift => (None, None),
+        };
+        // set if the low byte has an even number of bits set
+        let parity = (1..8).fold(result.clone().extract(0, 1), |parity, bit| {
+            parity ^ result.clone().extract(bit, 1)
+        });
+        let parity = !parity;
+        let less = || Some(sign.clone() ^ overflow.clone()?);
+        Some(match condition {
+            ConditionCode::o => overflow?,
+            ConditionCode::no => !overflow?,
+            ConditionCode::b => carry?,
+            ConditionCode::ae => !carry?,
+            ConditionCode::e => zero,
+            ConditionCode::ne => !zero,
+            ConditionCode::be => carry? | zero,
+            ConditionCode::a => !(carry? | zero),
+            ConditionCode::s => sign,
+            ConditionCode::ns => !sign,
+            ConditionCode::p => parity,
+            ConditionCode::np => !parity,
+            ConditionCode::l => less()?,
+            ConditionCode::ge => !less()?,
+            ConditionCode::le => less()? | zero,
+            ConditionCode::g => !(less()? | zero),
+            ConditionCode::None => return None,
+        })
+    }
+}
+
+impl Emulator {
+    /// enables symbolic execution if it isn't yet and returns its state
+    pub fn enable_symbolic(&mut self) -> &mut Symbolic {
+        self.symbolic.get_or_insert_with(Symbolic::new)
+    }
+
+    /// current concrete values of the variables
+    pub fn symbolic_model(&self) -> Result<Model, Exception> {
+        let mut model = Model::new();
+        let Some(symbolic) = &self.symbolic else {
+            return Ok(model);
+        };
+        for (variable, info) in symbolic.variables.iter().enumerate() {
+            let value = match info.location {
+                Location::Memory(address) => self.read_bytes(address, 1)?[0] as u64,
+                Location::Register(register) => self.cpu.gpr[register],
+            };
+            model.set(variable as u32, value);
+        }
+        Ok(model)
+    }
+
+    /// stores the values of the variables in the model where the variables
+    /// live
+    pub fn apply_model(&mut self, model: &Model) -> Result<(), Exception> {
+        let Some(symbolic) = &self.symbolic else {
+            return Ok(());
+        };
+        for (variable, value) in model.iter() {
+            match symbolic
+                .variables
+                .get(variable as usize)
+                .map(|info| info.location)
+            {
+                Some(Location::Memory(address)) => {
+                    self.memory.write_raw(address, &[value as u8])?
+                }
+                Some(Location::Register(register)) => self.cpu.gpr[register] = value,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// searches values of the variables for which execution starting at the
+    /// current state reaches the target address, paths are explored by
+    /// negating the conditions of the symbolic branches one at a time
+    ///
+    /// The emulator is left at the target if it was reached and restored to
+    /// the current state otherwise. System calls are handled along the way,
+    /// any other exception ends a path.
+    pub fn explore(
+        &mut self,
+        target: u64,
+        options: &ExploreOptions,
+    ) -> crate::Result<Option<Model>> {
+        let snapshot = self.snapshot();
+        let mut queue = VecDeque::from([(self.symbolic_model()?, 0)]);
+        // branch directions leading to each path already queued
+        let mut queued = HashSet::new();
+        let mut runs = 0;
+        while let Some((model, bound)) = queue.pop_front() {
+            if runs == options.runs {
+                break;
+            }
+            runs += 1;
+            self.restore(&snapshot);
+            self.apply_model(&model)?;
+            if self.run_to(target, options.limit) {
+                return Ok(Some(model));
+            }
+
+            let path = self
+                .symbolic
+                .as_ref()
+                .map(|symbolic| symbolic.path.clone())
+                .unwrap_or_default();
+            let variables: Vec<_> = path
+                .iter()
+                .map(|branch| branch.condition.variables())
+                .collect();
+            for index in bound..path.len() {
+                let mut directions: Vec<_> = path[..=index]
+                    .iter()
+                    .map(|branch| (branch.address, branch.taken))
+                    .collect();
+                directions[index].1 = !directions[index].1;
+                if !queued.insert(directions) {
+                    continue;
+                }
+
+                // only conditions sharing variables with the negated one matter
+                let mut relevant = variables[index].clone();
+                let mut selected = vec![false; index];
+                loop {
+                    let mut changed = false;
+                    for (i, branch_variables) in variables[..index].iter().enumerate() {
+                        if !selected[i] && !branch_variables.is_disjoint(&relevant) {
+                            selected[i] = true;
+                            relevant.extend(branch_variables);
+                            changed = true;
+                        }
+                    }
+                    if !changed {
+                        break;
+                    }
+                }
+                let mut constraints: Vec<_> = (0..index)
+                    .filter(|&i| selected[i])
+                    .map(|i| path[i].condition.clone())
+                    .collect();
+                constraints.push(!path[index].condition.clone());
+
+                match options.solver.solve(&constraints) {
+                    Ok(Some(solution)) => {
+                        let mut next = model.clone();
+                        next.extend(&solution);
+                        queue.push_back((next, index + 1));
+                    }
+                    Ok(None) | Err(Error::Solver(_)) => {}
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+        self.restore(&snapshot);
+        Ok(None)
+    }
+
+    /// steps until rip is the target, false if an exception or the limit
+    /// ended the path first
+    fn run_to(&mut self, target: u64, limit: u64) -> bool {
+        for _ in 0..limit {
+            if self.cpu.rip == target {
+                return true;
+            }
+            match self.step() {
+                Ok(()) => {}
+                Err(Exception::Syscall) => {
+                    if self.syscall().is_err() {
+                        return false;
+                    }
+                }
+                Err(_) => return false,
+            }
+        }
+        self.cpu.rip == target
+    }
+
+    /// steps while propagating the expressions, called instead of the other
+    /// step functions while symbolic execution is enabled
+    pub(super) fn step_symbolic(&mut self) -> Result<(), Exception> {
+        let rip = self.cpu.rip;
+        let instruction = if self.stubs.contains_key(&rip) {
+            None
+        } else {
+            self.decode().ok()
+        };
+        let gpr = self.cpu.gpr;
+
+        // accesses are collected unless they already are (e.g. while tracing)
+        let first_access = match &self.accesses {
+            Some(accesses) => Some(accesses.len()),
+            None => {
+                self.accesses = Some(vec![]);
+                None
+            }
+        };
+        let instruction_count = self.instruction_count;
+        let result = match self.taint {
+            Some(_) => self.step_tainted(),
+            None => self.step_hooked(),
+        };
+        let accesses = match first_access {
+            Some(first_access) => self
+                .accesses
+                .as_ref()
+                .map(|accesses| accesses[first_access.min(accesses.len())..].to_vec())
+                .unwrap_or_default(),
+            None => self.accesses.take().unwrap_or_default(),
+        };
+        if self.instruction_count == instruction_count {
+            return result;
+        }
+
+        let next_rip = self.cpu.rip;
+        let Some(symbolic) = &mut self.symbolic else {
+            return result;
+        };
+        match instruction {
+            Some(instruction) => symbolic.propagate(&instruction, &gpr, &accesses, next_rip),
+            // handlers return concrete values
+            None if self.stubs.contains_key(&rip) => symbolic.registers[RAX] = None,
+            None => {}
+        }
+        result
+    }
+}
+
+fn gpr_index(register: Register) -> usize {
+    register.full_register() as usize - Register::RAX as usize
+}
+
+/// part of a general purpose register with the specified width
+fn accumulator(width: u32, register: usize) -> Option<Register> {
+    let first = match width {
+        8 if register == RAX => return Some(Register::AL),
+        16 => Register::AX,
+        32 => Register::EAX,
+        64 => Register::RAX,
+        _ => return None,
+    };
+    Some(first + register as u32)
+}
+
+fn operand_width(ins: &Instruction, operand: u32) -> u32 {
+    if ins.op_count() <= operand {
+        return 0;
+    }
+    match ins.op_kind(operand) {
+        OpKind::Register => ins.op_register(operand).size() as u32 * 8,
+        OpKind::Memory => ins.memory_size().size() as u32 * 8,
+        _ => 0,
+    }
+}
+
+/// masked count of a shift or rotate
+fn shift_count(step: &Step, width: u32) -> Option<u64> {
+    let count = match step.ins.op1_kind() {
+        OpKind::Register => step.gpr[RCX],
+        OpKind::Immediate8 => step.ins.immediate8() as u64,
+        OpKind::Immediate8_2nd => step.ins.immediate8_2nd() as u64,
+        _ => return None,
+    };
+    Some(count & if width == 64 { 63 } else { 31 })
+}
+
+fn is_string(ins: &Instruction) -> bool {
+    (0..ins.op_count()).any(|operand| {
+        matches!(
+            ins.op_kind(operand),
+            OpKind::MemorySegRSI | OpKind::MemoryESRDI | OpKind::MemorySegESI | OpKind::MemoryESEDI
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::tests::emulator;
+
+    #[test]
+    fn explore() {
+        // movzx eax, byte [rsp] / cmp al, 0x42 / jne +1 / nop / int3
+        let mut emulator = emulator(&[0x0F, 0xB6, 0x04, 0x24, 0x3C, 0x42, 0x75, 0x01, 0x90, 0xCC]);
+        let variables = emulator
+            .enable_symbolic()
+            .symbolic_memory(0x1FF00, 1, "input");
+        assert_eq!(variables, [0]);
+        assert_eq!(emulator.symbolic_model().unwrap().value(0), Some(0));
+
+        let model = emulator
+            .explore(0x1008, &ExploreOptions::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(model.value(0), Some(0x42));
+        assert_eq!(emulator.cpu.rip, 0x1008);
+        assert_eq!(emulator.read_bytes(0x1FF00, 1).unwrap(), [0x42]);
+        let symbolic = emulator.symbolic.as_ref().unwrap();
+        assert_eq!(symbolic.variables()[0].name, "input[0]");
+        assert_eq!(symbolic.path.len(), 1);
+        assert_eq!(symbolic.path[0].address, 0x1006);
+        assert!(!symbolic.path[0].taken);
+        assert!(symbolic.register(RAX).is_some());
+    }
+
+    #[test]
+    fn unreachable() {
+        // movzx eax, byte [rsp] / cmp al, 0x42 / jne +5 / cmp al, 0x43 / jne +1 /
+        // nop / int3
+        let mut emulator = emulator(&[
+            0x0F, 0xB6, 0x04, 0x24, 0x3C, 0x42, 0x75, 0x05, 0x3C, 0x43, 0x75, 0x01, 0x90, 0xCC,
+        ]);
+        emulator
+            .enable_symbolic()
+            .symbolic_memory(0x1FF00, 1, "input");
+        assert_eq!(
+            emulator
+                .explore(0x100C, &ExploreOptions::default())
+                .unwrap(),
+            None
+        );
+        // restored to the initial state
+        assert_eq!(emulator.cpu.rip, 0x1000);
+        assert_eq!(emulator.read_bytes(0x1FF00, 1).unwrap(), [0]);
+    }
+}
@@ -0,0 +1,751 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+};
+
+use iced_x86::{
+    FlowControl, Instruction, InstructionInfoFactory, Mnemonic, OpAccess, OpKind, Register,
+};
+
+use crate::{emulator::*, trace::MemoryAccess};
+
+/// input byte a value depends on
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Label {
+    /// index of the source, see [Taint::source]
+    pub source: u32,
+    /// offset of the byte in the source
+    pub offset: u32,
+}
+
+/// input bytes a value depends on, empty if it doesn't depend on any
+pub type Labels = BTreeSet<Label>;
+
+/// tainted data reaching a sink
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaintEvent {
+    /// conditional branch on flags depending on tainted data
+    Branch {
+        address: u64,
+        /// instruction which set the flags (e.g. the cmp)
+        comparison: u64,
+        taken: bool,
+        labels: Labels,
+    },
+    /// indirect call, jump or return to a tainted target
+    Target {
+        address: u64,
+        target: u64,
+        labels: Labels,
+    },
+    /// tainted data written to one of the sink ranges
+    Write {
+        address: u64,
+        target: u64,
+        size: usize,
+        labels: Labels,
+    },
+}
+
+/// byte-level taint state of the emulator, enabled with
+/// [Emulator::enable_taint], memory written by stub handlers is not tracked
+/// (it keeps its previous taint unless it is a source)
+#[derive(Clone)]
+pub struct Taint {
+    /// values loaded through tainted pointers are tainted (e.g. table lookups)
+    pub propagate_addresses: bool,
+    /// sink events in the order they occurred
+    pub events: Vec<TaintEvent>,
+
+    sources: Vec<String>,
+    /// general purpose registers (low 8 bytes used) followed by xmm registers
+    registers: Vec<[Labels; 16]>,
+    flags: Labels,
+    /// instruction which last set tainted flags
+    flags_origin: u64,
+    memory: BTreeMap<u64, Labels>,
+    sinks: Vec<Range<u64>>,
+    /// (library, function) to (buffer, size) argument indexes
+    api_sources: BTreeMap<(String, String), Option<(usize, usize)>>,
+    /// system call number to (buffer, size) argument indexes
+    syscall_sources: BTreeMap<u64, Option<(usize, usize)>>,
+}
+
+impl Default for Taint {
+    fn default() -> Self {
+        Self {
+            propagate_addresses: false,
+            events: vec![],
+            sources: vec![],
+            registers: vec![Default::default(); 32],
+            flags: Labels::new(),
+            flags_origin: 0,
+            memory: BTreeMap::new(),
+            sinks: vec![],
+            api_sources: BTreeMap::new(),
+            syscall_sources: BTreeMap::new(),
+        }
+    }
+}
+
+impl Taint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// name of a source
+    pub fn source(&self, source: u32) -> Option<&str> {
+        self.sources.get(source as usize).map(String::as_str)
+    }
+
+    /// adds a source without tainting anything, returns its index
+    pub fn add_source(&mut self, name: &str) -> u32 {
+        self.sources.push(name.to_string());
+        self.sources.len() as u32 - 1
+    }
+
+    /// labels every byte of the range with its offset in a new source
+    pub fn taint_memory(&mut self, address: u64, size: usize, name: &str) -> u32 {
+        let source = self.add_source(name);
+        self.label_memory(address, size, source);
+        source
+    }
+
+    /// labels every byte of a general purpose register with its offset in a
+    /// new source
+    pub fn taint_register(&mut self, register: usize, name: &str) -> u32 {
+        let source = self.add_source(name);
+        self.label_register(register, source);
+        source
+    }
+
+    /// taints the return value (rax) of an API function each time it is
+    /// called, optionally also the buffer passed as an argument with its size
+    /// in another (e.g. (1, 2) for ReadFile)
+    pub fn add_api_source(
+        &mut self,
+        library: &str,
+        function: &str,
+        buffer: Option<(usize, usize)>,
+    ) {
+        self.api_sources
+            .insert((library.to_ascii_lowercase(), function.to_string()), buffer);
+    }
+
+    /// taints the result of a system call each time it is made, optionally
+    /// also the buffer passed as an argument with the size given by the
+    /// result (e.g. (1, 2) for read)
+    pub fn add_syscall_source(&mut self, number: u64, buffer: Option<(usize, usize)>) {
+        self.syscall_sources.insert(number, buffer);
+    }
+
+    /// records an event whenever tainted data is written to the range
+    pub fn add_sink(&mut self, range: Range<u64>) {
+        self.sinks.push(range);
+    }
+
+    /// labels of each byte of the memory range
+    pub fn memory(&self, address: u64, size: usize) -> Vec<Labels> {
+        (0..size as u64)
+            .map(|i| {
+                self.memory
+                    .get(&address.wrapping_add(i))
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// labels any byte of the memory range depends on
+    pub fn memory_labels(&self, address: u64, size: usize) -> Labels {
+        self.memory(address, size).into_iter().flatten().collect()
+    }
+
+    /// labels any byte of a general purpose register depends on
+    pub fn register(&self, register: usize) -> Labels {
+        self.registers[register][..8]
+            .iter()
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// labels any byte of an xmm register depends on
+    pub fn xmm(&self, register: usize) -> Labels {
+        self.registers[16 + register]
+            .iter()
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// labels the flags depend on
+    pub fn flags(&self) -> &Labels {
+        &self.flags
+    }
+
+    /// clears the taint of the memory range
+    pub fn clear_memory(&mut self, address: u64, size: usize) {
+        for i in 0..size as u64 {
+            self.memory.remove(&address.wrapping_add(i));
+        }
+    }
+
+    /// clears the taint of all registers, flags and memory, sources and
+    /// events are kept
+    pub fn clear(&mut self) {
+        self.registers = vec![Default::default(); 32];
+        self.flags.clear();
+        self.memory.clear();
+    }
+
+    fn label_memory(&mut self, address: u64, size: usize, source: u32) {
+        for offset in 0..size as u32 {
+            self.memory.insert(
+                address.wrapping_add(offset as u64),
+                Labels::from([Label { source, offset }]),
+            );
+        }
+    }
+
+    fn label_register(&mut self, register: usize, source: u32) {
+        for offset in 0..8 {
+            self.registers[register][offset] = Labels::from([Label {
+                source,
+                offset: offset as u32,
+            }]);
+        }
+    }
+
+    fn set_memory(&mut self, address: u64, bytes: impl IntoIterator<Item = Labels>) {
+        for (i, labels) in bytes.into_iter().enumerate() {
+            let address = address.wrapping_add(i as u64);
+            if labels.is_empty() {
+                self.memory.remove(&address);
+            } else {
+                self.memory.insert(address, labels);
+            }
+        }
+    }
+
+    fn register_bytes(&self, register: Register) -> Vec<Labels> {
+        match slot(register) {
+            Some((index, range)) => self.registers[index][range].to_vec(),
+            None => vec![],
+        }
+    }
+
+    /// writes the bytes of a register, missing bytes are cleared or copies
+    /// of the last one if sign extending, 32-bit writes clear the upper half
+    fn set_register(&mut self, register: Register, bytes: &[Labels], sign_extend: bool) {
+        let Some((index, range)) = slot(register) else {
+            return;
+        };
+        let extension = match bytes.last() {
+            Some(labels) if sign_extend => labels.clone(),
+            _ => Labels::new(),
+        };
+        for (i, byte) in range.clone().enumerate() {
+            self.registers[index][byte] = bytes.get(i).unwrap_or(&extension).clone();
+        }
+        if register.is_gpr32() {
+            for byte in 4..8 {
+                self.registers[index][byte].clear();
+            }
+        }
+    }
+
+    /// taint of an operand read by the instruction, memory operands use the
+    /// first read access
+    fn operand(&self, ins: &Instruction, operand: u32, reads: &[&MemoryAccess]) -> Vec<Labels> {
+        match ins.op_kind(operand) {
+            OpKind::Register => self.register_bytes(ins.op_register(operand)),
+            OpKind::Memory => reads
+                .first()
+                .map(|access| self.memory(access.address, access.data.len()))
+                .unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
+    /// writes the taint of an operand, memory operands use the first write
+    /// access
+    fn set_operand(
+        &mut self,
+        ins: &Instruction,
+        operand: u32,
+        bytes: &[Labels],
+        sign_extend: bool,
+        writes: &[&MemoryAccess],
+    ) {
+        match ins.op_kind(operand) {
+            OpKind::Register => self.set_register(ins.op_register(operand), bytes, sign_extend),
+            OpKind::Memory => {
+                if let Some(access) = writes.first() {
+                    let size = access.data.len();
+                    self.set_memory(
+                        access.address,
+                        (0..size).map(|i| bytes.get(i).cloned().unwrap_or_default()),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// updates the taint after an instruction retired, reads and writes are
+    /// its memory accesses and rip the address it continued at
+    fn propagate(&mut self, ins: &Instruction, accesses: &[MemoryAccess], rip: u64) {
+        let reads: Vec<_> = accesses.iter().filter(|access| !access.write).collect();
+        let writes: Vec<_> = accesses.iter().filter(|access| access.write).collect();
+        let mut factory = InstructionInfoFactory::new();
+        let info = factory.info(ins);
+
+        // registers which are only used to compute addresses
+        let address_registers: Vec<_> = info
+            .used_memory()
+            .iter()
+            .flat_map(|memory| [memory.base(), memory.index()])
+            .filter(|&register| register != Register::None)
+            .map(Register::full_register)
+            .collect();
+        let stack = ins.stack_pointer_increment() != 0;
+        let string = is_string(ins);
+        let implicit = |register: Register| {
+            let register = register.full_register();
+            (stack && register == Register::RSP)
+                || (string && matches!(register, Register::RSI | Register::RDI | Register::RCX))
+        };
+        let address: Labels = if self.propagate_addresses && !reads.is_empty() {
+            address_registers
+                .iter()
+                .flat_map(|&register| self.register_bytes(register))
+                .flatten()
+                .collect()
+        } else {
+            Labels::new()
+        };
+
+        // sinks, they see the state before the instruction
+        match ins.flow_control() {
+            FlowControl::ConditionalBranch if !self.flags.is_empty() => {
+                self.events.push(TaintEvent::Branch {
+                    address: ins.ip(),
+                    comparison: self.flags_origin,
+                    taken: rip != ins.next_ip(),
+                    labels: self.flags.clone(),
+                })
+            }
+            FlowControl::IndirectBranch | FlowControl::IndirectCall | FlowControl::Return => {
+                let labels: Labels = if ins.flow_control() == FlowControl::Return {
+                    reads
+                        .first()
+                        .map(|access| self.memory_labels(access.address, access.data.len()))
+                        .unwrap_or_default()
+                } else {
+                    self.operand(ins, 0, &reads).into_iter().flatten().collect()
+                };
+                if !labels.is_empty() {
+                    self.events.push(TaintEvent::Target {
+                        address: ins.ip(),
+                        target: rip,
+                        labels,
+                    });
+                }
+            }
+            _ => {}
+        }
+
+        match ins.mnemonic() {
+            // zeroing idioms
+            Mnemonic::Xor
+            | Mnemonic::Sub
+            | Mnemonic::Pxor
+            | Mnemonic::Xorps
+            | Mnemonic::Xorpd
+            | Mnemonic::Vpxor
+            | Mnemonic::Vxorps
+            | Mnemonic::Vxorpd
+                if same_registers(ins) =>
+            {
+                self.set_register(ins.op0_register(), &[], false);
+                self.flags.clear();
+            }
+            // data movement is tracked byte by byte
+            Mnemonic::Mov
+            | Mnemonic::Movzx
+            | Mnemonic::Movsx
+            | Mnemonic::Movsxd
+            | Mnemonic::Movnti
+            | Mnemonic::Movd
+            | Mnemonic::Movq
+            | Mnemonic::Movdqa
+            | Mnemonic::Movdqu
+            | Mnemonic::Movaps
+            | Mnemonic::Movups
+            | Mnemonic::Movapd
+            | Mnemonic::Movupd
+            | Mnemonic::Vmovd
+            | Mnemonic::Vmovq
+            | Mnemonic::Vmovdqa
+            | Mnemonic::Vmovdqu
+            | Mnemonic::Vmovaps
+            | Mnemonic::Vmovups
+                if !string =>
+            {
+                let mut bytes = self.operand(ins, 1, &reads);
+                for labels in &mut bytes {
+                    labels.extend(&address);
+                }
+                let sign_extend = matches!(ins.mnemonic(), Mnemonic::Movsx | Mnemonic::Movsxd);
+                self.set_operand(ins, 0, &bytes, sign_extend, &writes);
+            }
+            Mnemonic::Push => {
+                let bytes = self.operand(ins, 0, &reads);
+                if let Some(access) = writes.last() {
+                    let size = access.data.len();
+                    self.set_memory(
+                        access.address,
+                        (0..size).map(|i| bytes.get(i).cloned().unwrap_or_default()),
+                    );
+                }
+            }
+            Mnemonic::Pop => {
+                let bytes = reads
+                    .first()
+                    .map(|access| self.memory(access.address, access.data.len()))
+                    .unwrap_or_default();
+                self.set_operand(ins, 0, &bytes, false, &writes);
+            }
+            Mnemonic::Xchg => {
+                let first = self.operand(ins, 0, &reads);
+                let second = self.operand(ins, 1, &reads);
+                self.set_operand(ins, 0, &second, false, &writes);
+                self.set_operand(ins, 1, &first, false, &writes);
+            }
+            Mnemonic::Movsb | Mnemonic::Movsw | Mnemonic::Movsd | Mnemonic::Movsq if string => {
+                for (read, write) in reads.iter().zip(&writes) {
+                    let bytes = self.memory(read.address, read.data.len());
+                    self.set_memory(write.address, bytes);
+                }
+            }
+            Mnemonic::Stosb | Mnemonic::Stosw | Mnemonic::Stosd | Mnemonic::Stosq => {
+                let bytes = self.registers[RAX].clone();
+                for write in &writes {
+                    self.set_memory(write.address, bytes[..write.data.len()].to_vec());
+                }
+            }
+            Mnemonic::Lodsb | Mnemonic::Lodsw | Mnemonic::Lodsd | Mnemonic::Lodsq => {
+                if let Some(read) = reads.last() {
+                    let bytes = self.memory(read.address, read.data.len());
+                    self.set_register(ins.op0_register(), &bytes, false);
+                }
+            }
+            // the return address doesn't depend on the target
+            _ if matches!(
+                ins.flow_control(),
+                FlowControl::Call | FlowControl::IndirectCall
+            ) =>
+            {
+                for write in &writes {
+                    self.clear_memory(write.address, write.data.len());
+                }
+            }
+            // everything else depends on everything it reads
+            _ => {
+                let mut labels = address;
+                for used in info.used_registers() {
+                    let register = used.register();
+                    let read = matches!(
+                        used.access(),
+                        OpAccess::Read
+                            | OpAccess::CondRead
+                            | OpAccess::ReadWrite
+                            | OpAccess::ReadCondWrite
+                    );
+                    // cmov keeps the old value if the condition is false
+                    let conditional = matches!(used.access(), OpAccess::CondWrite);
+                    let address_only = used.access() == OpAccess::Read
+                        && address_registers.contains(&register.full_register());
+                    if (read || conditional) && !implicit(register) && !address_only {
+                        labels.extend(self.register_bytes(register).into_iter().flatten());
+                    }
+                }
+                for read in &reads {
+                    labels.extend(self.memory_labels(read.address, read.data.len()));
+                }
+                if ins.rflags_read() != 0 {
+                    labels.extend(&self.flags);
+                }
+
+                for used in info.used_registers() {
+                    let register = used.register();
+                    let write = matches!(
+                        used.access(),
+                        OpAccess::Write
+                            | OpAccess::CondWrite
+                            | OpAccess::ReadWrite
+                            | OpAccess::ReadCondWrite
+                    );
+                    if write && !implicit(register) {
+                        let size = slot(register).map_or(0, |(_, range)| range.len());
+                        self.set_register(register, &vec![labels.clone(); size], false);
+                    }
+                }
+                for write in &writes {
+                    self.set_memory(write.address, vec![labels.clone(); write.data.len()]);
+                }
+                if ins.rflags_modified() != 0 {
+                    if !labels.is_empty() {
+                        self.flags_origin = ins.ip();
+                    }
+                    self.flags = labels;
+                }
+            }
+        }
+
+        for write in &writes {
+            let range = write.range();
+            if self
+                .sinks
+                .iter()
+                .any(|sink| sink.start < range.end && range.start < sink.end)
+            {
+                let labels = self.memory_labels(write.address, write.data.len());
+                if !labels.is_empty() {
+                    self.events.push(TaintEvent::Write {
+                        address: ins.ip(),
+                        target: write.address,
+                        size: write.data.len(),
+                        labels,
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Emulator {
+    /// enables taint tracking if it isn't yet and returns its state
+    pub fn enable_taint(&mut self) -> &mut Taint {
+        self.taint.get_or_insert_with(Taint::new)
+    }
+
+    /// steps while propagating the taint, called instead of
+    /// [Emulator::step_hooked] while taint tracking is enabled
+    pub(super) fn step_tainted(&mut self) -> Result<(), Exception> {
+        let rip = self.cpu.rip;
+        let stub = self.stubs.get(&rip).map(|stub| {
+            let key = (stub.library.to_ascii_lowercase(), stub.function.clone());
+            (format!("{}!{}", stub.library, stub.function), key)
+        });
+        let instruction = match stub {
+            Some(_) => None,
+            None => self.decode().ok(),
+        };
+        // the arguments are gone once the handler returned
+        let api_buffer = stub.as_ref().and_then(|(_, key)| {
+            let taint = self.taint.as_ref().unwrap();
+            let (buffer, size) = (*taint.api_sources.get(key)?)?;
+            Some((self.argument(buffer).ok()?, self.argument(size).ok()?))
+        });
+
+        // accesses are collected unless they already are (e.g. while tracing)
+        let first_access = match &self.accesses {
+            Some(accesses) => Some(accesses.len()),
+            None => {
+                self.accesses = Some(vec![]);
+                None
+            }
+        };
+        let instruction_count = self.instruction_count;
+        let result = self.step_hooked();
+        let accesses = match first_access {
+            Some(first_access) => self
+                .accesses
+                .as_ref()
+                .map(|accesses| accesses[first_access.min(accesses.len())..].to_vec())
+                .unwrap_or_default(),
+            None => self.accesses.take().unwrap_or_default(),
+        };
+        if self.instruction_count == instruction_count {
+            return result;
+        }
+
+        let next_rip = self.cpu.rip;
+        let Some(taint) = &mut self.taint else {
+            return result;
+        };
+        match (stub, instruction) {
+            (Some((name, key)), _) => {
+                if taint.api_sources.contains_key(&key) {
+                    let source = taint.taint_register(RAX, &name);
+                    if let Some((buffer, size)) = api_buffer {
+                        taint.label_memory(buffer, size as usize, source);
+                    }
+                } else {
+                    taint.registers[RAX] = Default::default();
+                }
+            }
+            (None, Some(instruction)) => taint.propagate(&instruction, &accesses, next_rip),
+            (None, None) => {}
+        }
+        result
+    }
+
+    /// applies the system call sources after the result was set
+    pub(super) fn taint_syscall(&mut self, number: u64, arguments: &[u64; 6]) {
+        let result = self.cpu.gpr[RAX];
+        let Some(taint) = &mut self.taint else {
+            return;
+        };
+        let Some(&buffer) = taint.syscall_sources.get(&number) else {
+            taint.registers[RAX] = Default::default();
+            return;
+        };
+        let source = taint.taint_register(RAX, &format!("syscall {number}"));
+        // the result is the number of bytes transferred
+        if let Some((buffer, size)) = buffer {
+            if (result as i64) > 0 {
+                let size = result.min(arguments[size]);
+                taint.label_memory(arguments[buffer], size as usize, source);
+            }
+        }
+    }
+}
+
+/// index in [Taint::registers] and bytes of a register, None if it isn't
+/// tracked
+fn slot(register: Register) -> Option<(usize, Range<usize>)> {
+    if register.is_gpr() {
+        let full = register.full_register();
+        let index = full as usize - Register::RAX as usize;
+        let range = match register {
+            Register::AH | Register::CH | Register::DH | Register::BH => 1..2,
+            _ => 0..register.size(),
+        };
+        Some((index, range))
+    } else if register.is_xmm() || register.is_ymm() || register.is_zmm() {
+        Some((16 + register.number(), 0..16))
+    } else {
+        None
+    }
+}
+
+fn same_registers(ins: &Instruction) -> bool {
+    let operands = match ins.op_count() {
+        2 => [0, 1],
+        3 => [1, 2],
+        _ => return false,
+    };
+    operands
+        .iter()
+        .all(|&operand| ins.op_kind(operand) == OpKind::Register)
+        && ins.op_register(operands[0]) == ins.op_register(operands[1])
+}
+
+fn is_string(ins: &Instruction) -> bool {
+    (0..ins.op_count()).any(|operand| {
+        matches!(
+            ins.op_kind(operand),
+            OpKind::MemorySegRSI | OpKind::MemoryESRDI | OpKind::MemorySegESI | OpKind::MemoryESEDI
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::tests::emulator;
+
+    fn labels(source: u32, offsets: Range<u32>) -> Labels {
+        offsets.map(|offset| Label { source, offset }).collect()
+    }
+
+    #[test]
+    fn propagation() {
+        // mov eax, [rsp] / add ecx, eax / xor eax, eax / cmp ecx, 5 / jz +0 /
+        // mov [rsp+8], ecx / int3
+        let mut emulator = emulator(&[
+            0x8B, 0x04, 0x24, 0x01, 0xC1, 0x31, 0xC0, 0x83, 0xF9, 0x05, 0x74, 0x00, 0x89, 0x4C,
+            0x24, 0x08, 0xCC,
+        ]);
+        emulator.write_u64(0x1FF00, 7).unwrap();
+        let taint = emulator.enable_taint();
+        assert_eq!(taint.taint_memory(0x1FF00, 4, "input"), 0);
+        taint.add_sink(0x1FF08..0x1FF10);
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+
+        let taint = emulator.taint.as_ref().unwrap();
+        let input = labels(0, 0..4);
+        assert_eq!(taint.source(0), Some("input"));
+        assert_eq!(taint.register(RCX), input);
+        assert!(taint.register(RAX).is_empty());
+        assert_eq!(taint.flags(), &input);
+        assert_eq!(
+            taint.memory(0x1FF07, 6),
+            [
+                Labels::new(),
+                input.clone(),
+                input.clone(),
+                input.clone(),
+                input.clone(),
+                Labels::new()
+            ]
+        );
+        assert_eq!(
+            taint.events,
+            [
+                TaintEvent::Branch {
+                    address: 0x100A,
+                    comparison: 0x1007,
+                    taken: false,
+                    labels: input.clone(),
+                },
+                TaintEvent::Write {
+                    address: 0x100C,
+                    target: 0x1FF08,
+                    size: 4,
+                    labels: input.clone(),
+                },
+            ]
+        );
+
+        let taint = emulator.taint.as_mut().unwrap();
+        taint.clear_memory(0x1FF08, 2);
+        assert!(taint.memory_labels(0x1FF08, 2).is_empty());
+        assert_eq!(taint.memory_labels(0x1FF0A, 2), input);
+        taint.clear();
+        assert!(taint.register(RCX).is_empty());
+        assert!(taint.memory_labels(0x1FF00, 0x10).is_empty());
+        assert_eq!(taint.events.len(), 2);
+    }
+
+    #[test]
+    fn byte_movement() {
+        // mov ax, [rsp+2] / mov [rsp+0x10], ax / int3
+        let mut emulator = emulator(&[
+            0x66, 0x8B, 0x44, 0x24, 0x02, 0x66, 0x89, 0x44, 0x24, 0x10, 0xCC,
+        ]);
+        emulator.enable_taint().taint_memory(0x1FF00, 4, "input");
+        assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+        let taint = emulator.taint.as_ref().unwrap();
+        assert_eq!(taint.memory(0x1FF10, 2), [labels(0, 2..3), labels(0, 3..4)]);
+        assert_eq!(taint.register(RAX), labels(0, 2..4));
+    }
+
+    #[test]
+    fn addresses() {
+        for propagate_addresses in [false, true] {
+            // mov al, [rsp+rbx] / int3
+            let mut emulator = emulator(&[0x8A, 0x04, 0x1C, 0xCC]);
+            let taint = emulator.enable_taint();
+            taint.propagate_addresses = propagate_addresses;
+            taint.taint_register(RBX, "index");
+            assert_eq!(emulator.run(None), Some(Exception::Breakpoint));
+            let taint = emulator.taint.as_ref().unwrap();
+            assert_eq!(taint.register(RAX).is_empty(), !propagate_addresses);
+            assert_eq!(taint.register(RBX), labels(0, 0..8));
+        }
+    }
+}
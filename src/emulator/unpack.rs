@@ -0,0 +1,114 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    emulator::*,
+    module::Module,
+    unpack::{self, Exports, Import},
+    Result,
+};
+
+/// bytes of the image written since unpacking started, one bit per byte
+struct Written {
+    base: u64,
+    bits: Vec<u64>,
+}
+
+impl Written {
+    fn insert(&mut self, address: u64) {
+        if let Some(offset) = address.checked_sub(self.base) {
+            if let Some(bits) = self.bits.get_mut(offset as usize / 64) {
+                *bits |= 1 << (offset % 64);
+            }
+        }
+    }
+
+    fn contains(&self, address: u64) -> bool {
+        address
+            .checked_sub(self.base)
+            .and_then(|offset| {
+                self.bits
+                    .get(offset as usize / 64)
+                    .map(|bits| (offset, bits))
+            })
+            .is_some_and(|(offset, bits)| bits & 1 << (offset % 64) != 0)
+    }
+}
+
+impl Emulator {
+    /// runs the tls callbacks and the entry point of a packed image until it
+    /// executes code inside the image which was written since, rip is left at
+    /// that address (the original entry point), returns None if the entry
+    /// point returned before, code written outside the image (e.g. a stub in
+    /// allocated memory) doesn't count
+    pub fn run_to_original_entry_point(&mut self, image: &Image) -> Result<Option<u64>> {
+        let range = image.base..image.base + image.size;
+        let written = Arc::new(Mutex::new(Written {
+            base: image.base,
+            bits: vec![0; image.size.div_ceil(64) as usize],
+        }));
+        let original_entry_point = Arc::new(Mutex::new(None));
+
+        let write_hook = self.hook_write(range.clone(), {
+            let written = written.clone();
+            move |_, access| {
+                let mut written = written.lock().unwrap();
+                for address in access.range() {
+                    written.insert(address);
+                }
+                HookAction::Continue
+            }
+        });
+        let code_hook = self.hook_code(range, {
+            let original_entry_point = original_entry_point.clone();
+            move |_, rip| {
+                if !written.lock().unwrap().contains(rip) {
+                    return HookAction::Continue;
+                }
+                *original_entry_point.lock().unwrap() = Some(rip);
+                HookAction::Stop
+            }
+        });
+        let result = self.start(image);
+        self.remove_hook(write_hook);
+        self.remove_hook(code_hook);
+
+        let original_entry_point = original_entry_point.lock().unwrap().take();
+        match result {
+            Err(crate::Error::Emulator(Exception::Stopped)) if original_entry_point.is_some() => {
+                self.cpu.rip = original_entry_point.unwrap();
+                Ok(original_entry_point)
+            }
+            Err(error) => Err(error),
+            Ok(_) => Ok(None),
+        }
+    }
+
+    /// dumps the image as a PE file with the specified entry point (see
+    /// [unpack::rebuild]), slots pointing to stubs or exports of the loaded
+    /// modules are rebuilt as imports
+    pub fn dump(&self, image: &Image, entry_point: u64) -> Result<Vec<u8>> {
+        let data = unpack::read_image(&self.memory, image.base as usize)?;
+        let imports = self.find_imports(&data, image.base)?;
+        unpack::rebuild(data, image.base as usize, entry_point as usize, &imports)
+    }
+
+    /// slots of the image pointing to stubs or exports of the loaded modules
+    fn find_imports(&self, data: &[u8], base: u64) -> Result<Vec<Import>> {
+        // images loaded without process environment have no module list
+        let mut modules = vec![];
+        for (module_base, path) in self.modules().unwrap_or_default() {
+            if module_base == base {
+                continue;
+            }
+            let library = super::win32::file_name(&path);
+            if let Ok(module) = Module::from_memory(&self.memory, library, module_base as usize) {
+                modules.push(module);
+            }
+        }
+        let mut exports = Exports::from_modules(&modules)?;
+        for (address, stub) in self.stubs() {
+            exports.insert(address, &stub.library, &stub.function);
+        }
+        Ok(exports.find_imports(data, base as usize))
+    }
+}
@@ -0,0 +1,732 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    mem::offset_of,
+    ops::Range,
+    sync::Arc,
+};
+
+use crate::{
+    emulator::{peb::utf16, *},
+    process::{LDR_DATA_TABLE_ENTRY, PEB, PEB_LDR_DATA, RTL_USER_PROCESS_PARAMETERS, TEB},
+};
+
+mod advapi32;
+mod kernel32;
+mod ntdll;
+mod user32;
+
+/// lowest address of synthesized system modules
+pub const SYSTEM_MODULE_BASE: u64 = 0x7FF8_0000_0000;
+
+/// lowest address of the heap
+pub const HEAP_BASE: u64 = 0x2000_0000;
+
+/// lowest address of virtual allocations without preferred address
+pub const VIRTUAL_BASE: u64 = 0x1000_0000;
+
+const HEAP_CHUNK_SIZE: u64 = 0x10_0000;
+
+pub const CURRENT_PROCESS: u64 = u64::MAX;
+pub const CURRENT_THREAD: u64 = u64::MAX - 1;
+pub const INVALID_HANDLE_VALUE: u64 = u64::MAX;
+
+pub const STD_INPUT_HANDLE: u64 = 0x10;
+pub const STD_OUTPUT_HANDLE: u64 = 0x14;
+pub const STD_ERROR_HANDLE: u64 = 0x18;
+
+pub const ERROR_SUCCESS: u32 = 0;
+pub const ERROR_FILE_NOT_FOUND: u32 = 2;
+pub const ERROR_INVALID_HANDLE: u32 = 6;
+pub const ERROR_NOT_ENOUGH_MEMORY: u32 = 8;
+pub const ERROR_FILE_EXISTS: u32 = 80;
+pub const ERROR_INVALID_PARAMETER: u32 = 87;
+pub const ERROR_INSUFFICIENT_BUFFER: u32 = 122;
+pub const ERROR_MOD_NOT_FOUND: u32 = 126;
+pub const ERROR_PROC_NOT_FOUND: u32 = 127;
+pub const ERROR_ALREADY_EXISTS: u32 = 183;
+pub const ERROR_BAD_EXE_FORMAT: u32 = 193;
+pub const ERROR_ENVVAR_NOT_FOUND: u32 = 203;
+pub const ERROR_MORE_DATA: u32 = 234;
+pub const ERROR_INVALID_ADDRESS: u32 = 487;
+
+/// value of the registry stand-in, strings are stored as utf-16 like on
+/// Windows
+#[derive(Debug, Clone)]
+pub struct RegistryValue {
+    /// REG_SZ, REG_DWORD, ...
+    pub kind: u32,
+    pub data: Vec<u8>,
+}
+
+/// kernel object referenced by a handle
+#[derive(Debug, Clone)]
+enum Object {
+    Console,
+    File { path: String, position: u64 },
+    Key(String),
+    Heap,
+}
+
+/// emulated Windows environment: handlers for system functions and the state
+/// they work on
+#[derive(Clone)]
+pub struct Win32 {
+    /// by lowercase library name without extension and function name
+    handlers: BTreeMap<(String, String), Handler>,
+
+    /// sandboxed filesystem by normalized path (see [normalize_path])
+    pub files: BTreeMap<String, Vec<u8>>,
+    /// registry stand-in, keys by lowercase path starting with the name of the
+    /// root key (e.g. hkey_local_machine\software), values by lowercase name
+    pub registry: BTreeMap<String, BTreeMap<String, RegistryValue>>,
+    /// everything written to the console
+    pub console: Vec<u8>,
+    /// caption and text of all message boxes
+    pub message_boxes: Vec<(String, String)>,
+    /// faults of the guest are dispatched to its exception handlers, enabled
+    /// by [Emulator::setup_windows]
+    pub dispatch_exceptions: bool,
+
+    handles: BTreeMap<u64, Object>,
+    /// heap allocations and their size, freed memory is never reused
+    heap: BTreeMap<u64, u64>,
+    /// unused part of the current heap chunk
+    heap_free: Range<u64>,
+    /// virtual allocations and their size
+    allocations: BTreeMap<u64, u64>,
+    /// allocated tls indices
+    tls: u64,
+    /// ansi copy of the command line
+    command_line: Option<u64>,
+    /// file names of synthesized modules by base
+    synthesized_modules: BTreeMap<u64, String>,
+    /// handles and functions of the guest vectored exception handlers in
+    /// call order
+    pub(super) vectored_handlers: Vec<(u64, u64)>,
+    /// an exception is being dispatched, faults of handlers aren't
+    pub(super) dispatching: bool,
+}
+
+impl Default for Win32 {
+    fn default() -> Self {
+        let mut win32 = Self {
+            handlers: Default::default(),
+            files: Default::default(),
+            registry: Default::default(),
+            console: vec![],
+            message_boxes: vec![],
+            dispatch_exceptions: false,
+            handles: BTreeMap::from([
+                (STD_INPUT_HANDLE, Object::Console),
+                (STD_OUTPUT_HANDLE, Object::Console),
+                (STD_ERROR_HANDLE, Object::Console),
+            ]),
+            heap: Default::default(),
+            heap_free: 0..0,
+            allocations: Default::default(),
+            tls: 0,
+            command_line: None,
+            synthesized_modules: Default::default(),
+            vectored_handlers: vec![],
+            dispatching: false,
+        };
+        advapi32::register(&mut win32);
+        kernel32::register(&mut win32);
+        ntdll::register(&mut win32);
+        user32::register(&mut win32);
+        win32
+    }
+}
+
+impl Win32 {
+    /// registers a handler, it replaces any built-in handler and is used by all
+    /// stubs of the function which have no own handler
+    pub fn register(
+        &mut self,
+        library: &str,
+        function: &str,
+        handler: impl Fn(&mut Emulator) -> Result<u64, Exception> + Send + Sync + 'static,
+    ) {
+        self.handlers.insert(
+            (library_name(library), function.to_owned()),
+            Arc::new(handler),
+        );
+    }
+
+    /// handler of the function, api sets and kernelbase fall back to the
+    /// handler of any library
+    pub fn handler(&self, library: &str, function: &str) -> Option<Handler> {
+        let library = library_name(library);
+        if let Some(handler) = self.handlers.get(&(library.clone(), function.to_owned())) {
+            return Some(handler.clone());
+        }
+        if library.starts_with("api-ms-win-")
+            || library.starts_with("ext-ms-win-")
+            || library == "kernelbase"
+        {
+            return self
+                .handlers
+                .iter()
+                .find(|((_, handler_function), _)| handler_function == function)
+                .map(|(_, handler)| handler.clone());
+        }
+        None
+    }
+
+    /// functions with a handler in the library, ordered by name
+    pub fn functions(&self, library: &str) -> Vec<String> {
+        let library = library_name(library);
+        self.handlers
+            .keys()
+            .filter(|(handler_library, _)| *handler_library == library)
+            .map(|(_, function)| function.clone())
+            .collect()
+    }
+
+    /// adds or replaces a file of the sandboxed filesystem
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) {
+        self.files.insert(normalize_path(path), data);
+    }
+
+    /// adds or replaces a value of the registry stand-in, the key is created if
+    /// it doesn't exist
+    pub fn set_registry_value(&mut self, key: &str, name: &str, value: RegistryValue) {
+        self.registry
+            .entry(key.to_lowercase())
+            .or_default()
+            .insert(name.to_lowercase(), value);
+    }
+
+    fn add_handle(&mut self, object: Object) -> u64 {
+        let handle = self
+            .handles
+            .keys()
+            .next_back()
+            .map_or(0, |handle| handle + 4)
+            .max(0x100);
+        self.handles.insert(handle, object);
+        handle
+    }
+}
+
+impl Emulator {
+    /// sets up the process (see [Emulator::setup_process]) with a process
+    /// heap, and ntdll, kernel32 and kernelbase loaded in that order, and
+    /// enables exception dispatch
+    pub fn setup_windows(
+        &mut self,
+        image: &Image,
+        parameters: &ProcessParameters,
+    ) -> Result<u64, Exception> {
+        let peb = self.setup_process(image, parameters)?;
+
+        // checked for debug flags (Flags at 0x70, ForceFlags at 0x74)
+        let process_heap = self
+            .memory
+            .find_free(HEAP_BASE, PAGE_SIZE)
+            .ok_or(Exception::OutOfMemory)?;
+        self.memory
+            .map(process_heap, PAGE_SIZE, Protection::READ_WRITE);
+        // HEAP_GROWABLE
+        self.memory
+            .write_raw(process_heap + 0x70, &2u32.to_le_bytes())?;
+        self.memory.write_raw(
+            peb + offset_of!(PEB, ProcessHeap) as u64,
+            &process_heap.to_le_bytes(),
+        )?;
+
+        for library in ["ntdll.dll", "kernel32.dll", "kernelbase.dll"] {
+            self.load_library(library)?;
+        }
+        self.win32.dispatch_exceptions = true;
+        Ok(peb)
+    }
+
+    /// argument of a Win64 function, only valid on entry (e.g. in a handler)
+    pub fn argument(&self, index: usize) -> Result<u64, Exception> {
+        match index {
+            0 => Ok(self.cpu.gpr[RCX]),
+            1 => Ok(self.cpu.gpr[RDX]),
+            2 => Ok(self.cpu.gpr[R8]),
+            3 => Ok(self.cpu.gpr[R9]),
+            // return address and shadow space
+            index => self.read_u64(self.cpu.gpr[RSP] + 8 + index as u64 * 8),
+        }
+    }
+
+    pub fn read_bytes(&self, address: u64, length: usize) -> Result<Vec<u8>, Exception> {
+        let mut data = vec![0; length];
+        self.memory.read(address, &mut data, Access::Read)?;
+        Ok(data)
+    }
+
+    pub fn read_u16(&self, address: u64) -> Result<u16, Exception> {
+        let mut data = [0; 2];
+        self.memory.read(address, &mut data, Access::Read)?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    pub fn read_u32(&self, address: u64) -> Result<u32, Exception> {
+        let mut data = [0; 4];
+        self.memory.read(address, &mut data, Access::Read)?;
+        Ok(u32::from_le_bytes(data))
+    }
+
+    pub fn read_u64(&self, address: u64) -> Result<u64, Exception> {
+        let mut data = [0; 8];
+        self.memory.read(address, &mut data, Access::Read)?;
+        Ok(u64::from_le_bytes(data))
+    }
+
+    pub fn write_u32(&mut self, address: u64, value: u32) -> Result<(), Exception> {
+        self.memory.write(address, &value.to_le_bytes())
+    }
+
+    pub fn write_u64(&mut self, address: u64, value: u64) -> Result<(), Exception> {
+        self.memory.write(address, &value.to_le_bytes())
+    }
+
+    /// reads a null-terminated string, narrow strings are read as latin-1
+    pub fn read_string(&self, address: u64, wide: bool) -> Result<String, Exception> {
+        let mut string = vec![];
+        let mut address = address;
+        loop {
+            let char = if wide {
+                self.read_u16(address)?
+            } else {
+                self.read_bytes(address, 1)?[0] as u16
+            };
+            if char == 0 {
+                break;
+            }
+            string.push(char);
+            address += if wide { 2 } else { 1 };
+        }
+        Ok(String::from_utf16_lossy(&string))
+    }
+
+    /// writes a null-terminated string
+    pub fn write_string(
+        &mut self,
+        address: u64,
+        string: &str,
+        wide: bool,
+    ) -> Result<(), Exception> {
+        self.memory.write(address, &encode(string, wide))
+    }
+
+    /// last error of the current thread
+    pub fn last_error(&self) -> Result<u32, Exception> {
+        self.read_u32(self.teb() + offset_of!(TEB, LastErrorValue) as u64)
+    }
+
+    pub fn set_last_error(&mut self, error: u32) -> Result<(), Exception> {
+        self.write_u32(self.teb() + offset_of!(TEB, LastErrorValue) as u64, error)
+    }
+
+    /// value of an environment variable of the process parameters
+    pub fn environment_variable(&self, name: &str) -> Result<Option<String>, Exception> {
+        let process_parameters =
+            self.read_u64(self.peb()? + offset_of!(PEB, ProcessParameters) as u64)?;
+        let mut address = self.read_u64(
+            process_parameters + offset_of!(RTL_USER_PROCESS_PARAMETERS, Environment) as u64,
+        )?;
+        loop {
+            let variable = self.read_string(address, true)?;
+            if variable.is_empty() {
+                return Ok(None);
+            }
+            address += (variable.encode_utf16().count() as u64 + 1) * 2;
+            if let Some((variable_name, value)) = variable.split_once('=') {
+                if variable_name.eq_ignore_ascii_case(name) {
+                    return Ok(Some(value.to_owned()));
+                }
+            }
+        }
+    }
+
+    /// allocates zeroed memory on the heap, None if the address space is
+    /// exhausted
+    pub fn heap_allocate(&mut self, size: u64) -> Option<u64> {
+        let allocation_size = size.max(1).checked_next_multiple_of(16)?;
+        if self.win32.heap_free.end - self.win32.heap_free.start < allocation_size {
+            let chunk_size = allocation_size
+                .max(HEAP_CHUNK_SIZE)
+                .checked_next_multiple_of(PAGE_SIZE)?;
+            let chunk = self.memory.find_free(HEAP_BASE, chunk_size)?;
+            self.memory.map(chunk, chunk_size, Protection::READ_WRITE);
+            self.win32.heap_free = chunk..chunk + chunk_size;
+        }
+        let address = self.win32.heap_free.start;
+        self.win32.heap_free.start += allocation_size;
+        self.win32.heap.insert(address, size);
+        Some(address)
+    }
+
+    /// frees a heap allocation, returns false if there is none at the address
+    pub fn heap_free(&mut self, address: u64) -> bool {
+        self.win32.heap.remove(&address).is_some()
+    }
+
+    /// size of a heap allocation
+    pub fn heap_size(&self, address: u64) -> Option<u64> {
+        self.win32.heap.get(&address).copied()
+    }
+
+    /// base addresses and full paths of all modules in load order, fails if
+    /// the guest corrupted the list (e.g. an entry links back to another one
+    /// instead of the head)
+    pub fn modules(&self) -> Result<Vec<(u64, String)>, Exception> {
+        let ldr = self.read_u64(self.peb()? + offset_of!(PEB, Ldr) as u64)?;
+        let head = ldr + offset_of!(PEB_LDR_DATA, InLoadOrderModuleList) as u64;
+        let mut modules = vec![];
+        let mut visited = BTreeSet::new();
+        let mut link = self.read_u64(head)?;
+        while link != head {
+            if !visited.insert(link) {
+                return Err(Exception::Corrupted("loader list"));
+            }
+            let entry = link - offset_of!(LDR_DATA_TABLE_ENTRY, InLoadOrderLinks) as u64;
+            let full_name = entry + offset_of!(LDR_DATA_TABLE_ENTRY, FullDllName) as u64;
+            modules.push((
+                self.read_u64(entry + offset_of!(LDR_DATA_TABLE_ENTRY, DllBase) as u64)?,
+                self.read_string(self.read_u64(full_name + 8)?, true)?,
+            ));
+            link = self.read_u64(link)?;
+        }
+        Ok(modules)
+    }
+
+    /// base of a loaded module, names without extension get .dll appended
+    pub fn module_handle(&self, name: &str) -> Result<Option<u64>, Exception> {
+        let file_name = file_name(name);
+        Ok(self
+            .modules()?
+            .into_iter()
+            .find(|(_, path)| self::file_name(path) == file_name)
+            .map(|(base, _)| base))
+    }
+
+    /// loads a module if it isn't loaded yet, images in the sandboxed
+    /// filesystem are mapped and initialized, any other module is synthesized
+    /// with the functions of the registered handlers as exports, returns None
+    /// if the image is invalid
+    pub fn load_library(&mut self, name: &str) -> Result<Option<u64>, Exception> {
+        if let Some(base) = self.module_handle(name)? {
+            return Ok(Some(base));
+        }
+
+        let file_name = file_name(name);
+        let path = normalize_path(name);
+        let file = self
+            .win32
+            .files
+            .iter()
+            .find(|(file_path, _)| **file_path == path || self::file_name(file_path) == file_name)
+            .map(|(path, data)| (path.clone(), data.clone()));
+        if let Some((path, data)) = file {
+            let image = match self.load(&file_name, &data) {
+                Ok(image) => image,
+                Err(crate::Error::Emulator(exception)) => return Err(exception),
+                Err(_) => return Ok(None),
+            };
+            self.add_module(&path, image.base, image.size, image.entry_point)?;
+            if image.is_dll {
+                if let Err(crate::Error::Emulator(exception)) = self.start(&image) {
+                    return Err(exception);
+                }
+            }
+            return Ok(Some(image.base));
+        }
+
+        let (base, size) = self.synthesize_module(&file_name)?;
+        self.add_module(
+            &format!("C:\\Windows\\System32\\{}", file_name),
+            base,
+            size,
+            0,
+        )?;
+        Ok(Some(base))
+    }
+
+    /// address of an exported function by name or #ordinal, forwarded exports
+    /// are loaded and resolved, synthesized modules export a stub for any
+    /// function
+    pub fn proc_address(&mut self, module: u64, function: &str) -> Result<Option<u64>, Exception> {
+        if let Some(file_name) = self.win32.synthesized_modules.get(&module).cloned() {
+            return Ok(Some(match self.find_export(module, function)? {
+                Some(address) => address,
+                None => self.add_stub(&file_name, function),
+            }));
+        }
+        self.find_export(module, function)
+    }
+
+    fn find_export(&mut self, module: u64, function: &str) -> Result<Option<u64>, Exception> {
+        // IMAGE_DIRECTORY_ENTRY_EXPORT of the optional header
+        let nt_headers = module + self.read_u32(module + 0x3C)? as u64;
+        let directory_address = self.read_u32(nt_headers + 0x88)? as u64;
+        let directory_size = self.read_u32(nt_headers + 0x8C)? as u64;
+        if directory_address == 0 {
+            return Ok(None);
+        }
+        let directory = module + directory_address;
+        let ordinal_base = self.read_u32(directory + 0x10)? as u64;
+        let number_of_functions = self.read_u32(directory + 0x14)? as u64;
+        let number_of_names = self.read_u32(directory + 0x18)? as u64;
+        let functions = module + self.read_u32(directory + 0x1C)? as u64;
+        let names = module + self.read_u32(directory + 0x20)? as u64;
+        let name_ordinals = module + self.read_u32(directory + 0x24)? as u64;
+
+        let index = if let Some(ordinal) = function.strip_prefix('#') {
+            let Ok(ordinal) = ordinal.parse::<u64>() else {
+                return Ok(None);
+            };
+            ordinal.wrapping_sub(ordinal_base)
+        } else {
+            let mut index = None;
+            for i in 0..number_of_names {
+                let name = module + self.read_u32(names + i * 4)? as u64;
+                if self.read_string(name, false)? == function {
+                    index = Some(self.read_u16(name_ordinals + i * 2)? as u64);
+                    break;
+                }
+            }
+            let Some(index) = index else {
+                return Ok(None);
+            };
+            index
+        };
+        if index >= number_of_functions {
+            return Ok(None);
+        }
+        let address = self.read_u32(functions + index * 4)? as u64;
+        if address == 0 {
+            return Ok(None);
+        }
+        if !(directory_address..directory_address + directory_size).contains(&address) {
+            return Ok(Some(module + address));
+        }
+
+        // forwarded as library.function
+        let forwarder = self.read_string(module + address, false)?;
+        let Some((library, function)) = forwarder.split_once('.') else {
+            return Ok(None);
+        };
+        let Some(library) = self.load_library(library)? else {
+            return Ok(None);
+        };
+        self.proc_address(library, function)
+    }
+
+    /// maps a minimal image exporting the functions of the registered handlers,
+    /// each export is a stub, returns base and size
+    fn synthesize_module(&mut self, file_name: &str) -> Result<(u64, u64), Exception> {
+        let functions = self.win32.functions(file_name);
+        let count = functions.len() as u32;
+
+        // export directory and its tables, followed by the names
+        let directory_address = PAGE_SIZE as u32;
+        let functions_address = directory_address + 0x28;
+        let names_address = functions_address + count * 4;
+        let name_ordinals_address = names_address + count * 4;
+        let mut strings = file_name.as_bytes().to_vec();
+        strings.push(0);
+        let mut name_addresses = vec![];
+        for function in &functions {
+            name_addresses.push(name_ordinals_address + count * 2 + strings.len() as u32);
+            strings.extend(function.as_bytes());
+            strings.push(0);
+        }
+        let directory_size =
+            name_ordinals_address + count * 2 + strings.len() as u32 - directory_address;
+        let stubs_address = (directory_address + directory_size).next_multiple_of(PAGE_SIZE as u32);
+        let size = (stubs_address as u64 + count as u64 * STUB_SIZE).next_multiple_of(PAGE_SIZE);
+        let base = self
+            .memory
+            .find_free(SYSTEM_MODULE_BASE, size)
+            .ok_or(Exception::OutOfMemory)?;
+
+        let mut data = vec![0; stubs_address as usize];
+        let mut put = |offset: u32, value: &[u8]| {
+            data[offset as usize..offset as usize + value.len()].copy_from_slice(value)
+        };
+        // DOS header
+        put(0x00, b"MZ");
+        put(0x3C, &0x40u32.to_le_bytes());
+        // NT headers
+        put(0x40, b"PE\0\0");
+        put(0x44, &0x8664u16.to_le_bytes());
+        put(0x46, &1u16.to_le_bytes());
+        put(0x54, &0xF0u16.to_le_bytes());
+        // IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE |
+        // IMAGE_FILE_DLL
+        put(0x56, &0x2022u16.to_le_bytes());
+        // optional header
+        put(0x58, &0x20Bu16.to_le_bytes());
+        put(0x70, &base.to_le_bytes());
+        put(0x78, &(PAGE_SIZE as u32).to_le_bytes());
+        put(0x7C, &(PAGE_SIZE as u32).to_le_bytes());
+        put(0x80, &6u16.to_le_bytes());
+        put(0x88, &6u16.to_le_bytes());
+        put(0x90, &(size as u32).to_le_bytes());
+        put(0x94, &(PAGE_SIZE as u32).to_le_bytes());
+        // IMAGE_SUBSYSTEM_WINDOWS_CUI
+        put(0x9C, &3u16.to_le_bytes());
+        put(0xC4, &16u32.to_le_bytes());
+        put(0xC8, &directory_address.to_le_bytes());
+        put(0xCC, &directory_size.to_le_bytes());
+        // single section which spans the export directory and the stubs,
+        // file and memory layout are the same
+        put(0x148, b".text\0\0\0");
+        put(0x150, &(size as u32 - directory_address).to_le_bytes());
+        put(0x154, &directory_address.to_le_bytes());
+        put(0x158, &(size as u32 - directory_address).to_le_bytes());
+        put(0x15C, &directory_address.to_le_bytes());
+        // IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ
+        put(0x16C, &0x6000_0020u32.to_le_bytes());
+        // export directory
+        put(
+            directory_address + 0x0C,
+            &(name_ordinals_address + count * 2).to_le_bytes(),
+        );
+        put(directory_address + 0x10, &1u32.to_le_bytes());
+        put(directory_address + 0x14, &count.to_le_bytes());
+        put(directory_address + 0x18, &count.to_le_bytes());
+        put(directory_address + 0x1C, &functions_address.to_le_bytes());
+        put(directory_address + 0x20, &names_address.to_le_bytes());
+        put(
+            directory_address + 0x24,
+            &name_ordinals_address.to_le_bytes(),
+        );
+        for (i, &name_address) in name_addresses.iter().enumerate() {
+            let i = i as u32;
+            put(
+                functions_address + i * 4,
+                &(stubs_address + i * STUB_SIZE as u32).to_le_bytes(),
+            );
+            put(names_address + i * 4, &name_address.to_le_bytes());
+            put(name_ordinals_address + i * 2, &(i as u16).to_le_bytes());
+        }
+        put(name_ordinals_address + count * 2, &strings);
+
+        self.memory.map(base, size, Protection::READ);
+        self.memory.write_raw(base, &data)?;
+        for (i, function) in functions.iter().enumerate() {
+            self.add_stub_at(
+                base + stubs_address as u64 + i as u64 * STUB_SIZE,
+                file_name,
+                function,
+            );
+        }
+        self.win32
+            .synthesized_modules
+            .insert(base, file_name.to_owned());
+        Ok((base, size))
+    }
+}
+
+/// lowercase path with backslashes as separator
+pub fn normalize_path(path: &str) -> String {
+    path.replace('/', "\\").to_lowercase()
+}
+
+/// lowercase file name of a module, .dll is appended if there is no extension
+pub(super) fn file_name(path: &str) -> String {
+    let mut file_name = normalize_path(path.rsplit(['\\', '/']).next().unwrap());
+    if !file_name.contains('.') {
+        file_name.push_str(".dll");
+    }
+    file_name
+}
+
+/// lowercase name of a library without extension
+fn library_name(library: &str) -> String {
+    let library = library.to_lowercase();
+    match library.strip_suffix(".dll") {
+        Some(library) => library.to_owned(),
+        None => library,
+    }
+}
+
+/// null-terminated string, narrow strings are encoded as latin-1
+fn encode(string: &str, wide: bool) -> Vec<u8> {
+    if wide {
+        utf16(string)
+    } else {
+        string
+            .chars()
+            .map(|char| u8::try_from(char).unwrap_or(b'?'))
+            .chain([0])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_BASE: u64 = 0x1_4000_0000;
+    const NTDLL_BASE: u64 = 0x7FF0_0000_0000;
+
+    /// emulator with the process structures of an image and ntdll as second
+    /// module, returns the loader data table entry of ntdll
+    fn process() -> (Emulator, u64) {
+        let mut emulator = Emulator::new();
+        let image = Image {
+            name: "test.exe".to_owned(),
+            base: IMAGE_BASE,
+            size: PAGE_SIZE,
+            entry_point: 0,
+            is_dll: false,
+            stack_size: 0x10000,
+            imports: vec![],
+        };
+        emulator
+            .setup_process(&image, &ProcessParameters::new("C:\\test.exe"))
+            .unwrap();
+        let entry = emulator
+            .add_module("C:\\Windows\\System32\\ntdll.dll", NTDLL_BASE, PAGE_SIZE, 0)
+            .unwrap();
+        (emulator, entry)
+    }
+
+    #[test]
+    fn modules() {
+        let (emulator, _) = process();
+        let modules = emulator.modules().unwrap();
+        assert_eq!(modules.len(), 2);
+        assert_eq!(modules[0].0, IMAGE_BASE);
+        assert_eq!(
+            modules[1],
+            (NTDLL_BASE, "C:\\Windows\\System32\\ntdll.dll".to_owned())
+        );
+        assert_eq!(emulator.module_handle("NTDLL"), Ok(Some(NTDLL_BASE)));
+    }
+
+    #[test]
+    fn circular_modules() {
+        let (mut emulator, entry) = process();
+        // the last entry links to itself instead of the head
+        emulator
+            .memory
+            .write_raw(
+                entry + offset_of!(LDR_DATA_TABLE_ENTRY, InLoadOrderLinks) as u64,
+                &entry.to_le_bytes(),
+            )
+            .unwrap();
+        assert_eq!(emulator.modules(), Err(Exception::Corrupted("loader list")));
+        assert_eq!(
+            emulator.module_handle("kernel32"),
+            Err(Exception::Corrupted("loader list"))
+        );
+    }
+
+    #[test]
+    fn heap_exhausted() {
+        let mut emulator = Emulator::new();
+        assert_eq!(emulator.heap_allocate(u64::MAX - 0x10), None);
+        let address = emulator.heap_allocate(0x10).unwrap();
+        assert_eq!(emulator.heap_size(address), Some(0x10));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::emulator::{win32::Object, *};
+
+const REG_SZ: u32 = 1;
+const REG_EXPAND_SZ: u32 = 2;
+const REG_MULTI_SZ: u32 = 7;
+
+// REG_CREATED_NEW_KEY, REG_OPENED_EXISTING_KEY
+const CREATED_NEW_KEY: u32 = 1;
+const OPENED_EXISTING_KEY: u32 = 2;
+
+pub(super) fn register(win32: &mut Win32) {
+    for (suffix, wide) in [("A", false), ("W", true)] {
+        win32.register(
+            "advapi32",
+            &format!("RegOpenKeyEx{}", suffix),
+            move |emulator| {
+                let Some(path) = key_path(emulator, wide)? else {
+                    return Ok(ERROR_INVALID_HANDLE as u64);
+                };
+                if !emulator.win32.registry.contains_key(&path) {
+                    return Ok(ERROR_FILE_NOT_FOUND as u64);
+                }
+                let key = emulator.win32.add_handle(Object::Key(path));
+                emulator.write_u64(emulator.argument(4)?, key)?;
+                Ok(ERROR_SUCCESS as u64)
+            },
+        );
+        win32.register(
+            "advapi32",
+            &format!("RegCreateKeyEx{}", suffix),
+            move |emulator| {
+                let Some(path) = key_path(emulator, wide)? else {
+                    return Ok(ERROR_INVALID_HANDLE as u64);
+                };
+                let disposition = if emulator.win32.registry.contains_key(&path) {
+                    OPENED_EXISTING_KEY
+                } else {
+                    emulator
+                        .win32
+                        .registry
+                        .insert(path.clone(), Default::default());
+                    CREATED_NEW_KEY
+                };
+                let key = emulator.win32.add_handle(Object::Key(path));
+                emulator.write_u64(emulator.argument(7)?, key)?;
+                let disposition_address = emulator.argument(8)?;
+                if disposition_address != 0 {
+                    emulator.write_u32(disposition_address, disposition)?;
+                }
+                Ok(ERROR_SUCCESS as u64)
+            },
+        );
+        win32.register(
+            "advapi32",
+            &format!("RegQueryValueEx{}", suffix),
+            move |emulator| query_value(emulator, wide),
+        );
+        win32.register(
+            "advapi32",
+            &format!("RegSetValueEx{}", suffix),
+            move |emulator| set_value(emulator, wide),
+        );
+        win32.register(
+            "advapi32",
+            &format!("RegDeleteValue{}", suffix),
+            move |emulator| {
+                let Some(Object::Key(path)) = emulator.win32.handles.get(&emulator.argument(0)?)
+                else {
+                    return Ok(ERROR_INVALID_HANDLE as u64);
+                };
+                let path = path.clone();
+                let name = value_name(emulator, emulator.argument(1)?, wide)?;
+                Ok(match emulator
+                    .win32
+                    .registry
+                    .get_mut(&path)
+                    .and_then(|values| values.remove(&name))
+                {
+                    Some(_) => ERROR_SUCCESS,
+                    None => ERROR_FILE_NOT_FOUND,
+                } as u64)
+            },
+        );
+        win32.register(
+            "advapi32",
+            &format!("GetUserName{}", suffix),
+            move |emulator| {
+                let buffer = emulator.argument(0)?;
+                let size_address = emulator.argument(1)?;
+                let user_name = emulator
+                    .environment_variable("USERNAME")?
+                    .unwrap_or_default();
+                // in characters including the terminator
+                let length = user_name.chars().count() as u32 + 1;
+                let size = emulator.read_u32(size_address)?;
+                emulator.write_u32(size_address, length)?;
+                if size < length {
+                    emulator.set_last_error(ERROR_INSUFFICIENT_BUFFER)?;
+                    return Ok(0);
+                }
+                emulator.write_string(buffer, &user_name, wide)?;
+                Ok(1)
+            },
+        );
+    }
+    win32.register("advapi32", "RegCloseKey", |emulator| {
+        let key = emulator.argument(0)?;
+        if is_root_key(key) {
+            return Ok(ERROR_SUCCESS as u64);
+        }
+        Ok(match emulator.win32.handles.remove(&key) {
+            Some(Object::Key(_)) => ERROR_SUCCESS,
+            _ => ERROR_INVALID_HANDLE,
+        } as u64)
+    });
+}
+
+/// path of the key (first argument) joined with the sub key (second argument)
+fn key_path(emulator: &Emulator, wide: bool) -> Result<Option<String>, Exception> {
+    let key = emulator.argument(0)?;
+    let path = match key as u32 {
+        0x8000_0000 if is_root_key(key) => "hkey_classes_root".to_owned(),
+        0x8000_0001 if is_root_key(key) => "hkey_current_user".to_owned(),
+        0x8000_0002 if is_root_key(key) => "hkey_local_machine".to_owned(),
+        0x8000_0003 if is_root_key(key) => "hkey_users".to_owned(),
+        _ => match emulator.win32.handles.get(&key) {
+            Some(Object::Key(path)) => path.clone(),
+            _ => return Ok(None),
+        },
+    };
+    let sub_key = emulator.argument(1)?;
+    if sub_key == 0 {
+        return Ok(Some(path));
+    }
+    let sub_key = emulator.read_string(sub_key, wide)?.to_lowercase();
+    let sub_key = sub_key.trim_matches('\\');
+    Ok(Some(if sub_key.is_empty() {
+        path
+    } else {
+        format!("{}\\{}", path, sub_key)
+    }))
+}
+
+fn is_root_key(key: u64) -> bool {
+    // predefined keys are sign-extended
+    (0xFFFF_FFFF_8000_0000..=0xFFFF_FFFF_8000_0003).contains(&key)
+        || (0x8000_0000..=0x8000_0003).contains(&key)
+}
+
+/// lowercase name of a value, the default value has an empty name
+fn value_name(emulator: &Emulator, name: u64, wide: bool) -> Result<String, Exception> {
+    Ok(if name == 0 {
+        String::new()
+    } else {
+        emulator.read_string(name, wide)?.to_lowercase()
+    })
+}
+
+fn query_value(emulator: &mut Emulator, wide: bool) -> Result<u64, Exception> {
+    let Some(Object::Key(path)) = emulator.win32.handles.get(&emulator.argument(0)?) else {
+        return Ok(ERROR_INVALID_HANDLE as u64);
+    };
+    let path = path.clone();
+    let name = value_name(emulator, emulator.argument(1)?, wide)?;
+    let kind_address = emulator.argument(3)?;
+    let buffer = emulator.argument(4)?;
+    let size_address = emulator.argument(5)?;
+    let Some(value) = emulator
+        .win32
+        .registry
+        .get(&path)
+        .and_then(|values| values.get(&name))
+        .cloned()
+    else {
+        return Ok(ERROR_FILE_NOT_FOUND as u64);
+    };
+
+    let data = if !wide && is_string(value.kind) {
+        narrow(&value.data)
+    } else {
+        value.data
+    };
+    if kind_address != 0 {
+        emulator.write_u32(kind_address, value.kind)?;
+    }
+    if size_address == 0 {
+        return Ok(if buffer == 0 {
+            ERROR_SUCCESS
+        } else {
+            ERROR_INVALID_PARAMETER
+        } as u64);
+    }
+    let size = emulator.read_u32(size_address)?;
+    emulator.write_u32(size_address, data.len() as u32)?;
+    if buffer == 0 {
+        return Ok(ERROR_SUCCESS as u64);
+    }
+    if (size as usize) < data.len() {
+        return Ok(ERROR_MORE_DATA as u64);
+    }
+    emulator.memory.write(buffer, &data)?;
+    Ok(ERROR_SUCCESS as u64)
+}
+
+fn set_value(emulator: &mut Emulator, wide: bool) -> Result<u64, Exception> {
+    let Some(Object::Key(path)) = emulator.win32.handles.get(&emulator.argument(0)?) else {
+        return Ok(ERROR_INVALID_HANDLE as u64);
+    };
+    let path = path.clone();
+    let name = value_name(emulator, emulator.argument(1)?, wide)?;
+    let kind = emulator.argument(3)? as u32;
+    let data = emulator.read_bytes(emulator.argument(4)?, emulator.argument(5)? as u32 as usize)?;
+    let data = if !wide && is_string(kind) {
+        data.iter()
+            .flat_map(|&char| (char as u16).to_le_bytes())
+            .collect()
+    } else {
+        data
+    };
+    emulator
+        .win32
+        .registry
+        .entry(path)
+        .or_default()
+        .insert(name, RegistryValue { kind, data });
+    Ok(ERROR_SUCCESS as u64)
+}
+
+fn is_string(kind: u32) -> bool {
+    matches!(kind, REG_SZ | REG_EXPAND_SZ | REG_MULTI_SZ)
+}
+
+/// utf-16 to latin-1
+fn narrow(data: &[u8]) -> Vec<u8> {
+    data.chunks(2)
+        .map(
+            |char| match u16::from_le_bytes([char[0], *char.get(1).unwrap_or(&0)]) {
+                char @ 0..=0xFF => char as u8,
+                _ => b'?',
+            },
+        )
+        .collect()
+}
@@ -0,0 +1,867 @@
+use std::mem::offset_of;
+
+use crate::{
+    emulator::{win32::Object, *},
+    process::{PEB, RTL_USER_PROCESS_PARAMETERS, TEB},
+};
+
+// time of the first instruction, one instruction takes 1 ns
+const FILETIME_BASE: u64 = 133_170_048_000_000_000;
+const PERFORMANCE_FREQUENCY: u64 = 1_000_000_000;
+
+const TLS_OUT_OF_INDEXES: u64 = 0xFFFF_FFFF;
+const INVALID_FILE_ATTRIBUTES: u64 = 0xFFFF_FFFF;
+const FILE_ATTRIBUTE_DIRECTORY: u64 = 0x10;
+const FILE_ATTRIBUTE_NORMAL: u64 = 0x80;
+
+const CREATE_NEW: u64 = 1;
+const CREATE_ALWAYS: u64 = 2;
+const OPEN_EXISTING: u64 = 3;
+const OPEN_ALWAYS: u64 = 4;
+const TRUNCATE_EXISTING: u64 = 5;
+
+const MEM_COMMIT: u64 = 0x1000;
+const MEM_RESERVE: u64 = 0x2000;
+const MEM_DECOMMIT: u64 = 0x4000;
+const MEM_RELEASE: u64 = 0x8000;
+const MEM_FREE: u32 = 0x10000;
+const MEM_PRIVATE: u32 = 0x20000;
+
+const CP_UTF8: u64 = 65001;
+
+pub(super) fn register(win32: &mut Win32) {
+    // process and thread
+    win32.register("kernel32", "GetCurrentProcess", |_| Ok(CURRENT_PROCESS));
+    win32.register("kernel32", "GetCurrentThread", |_| Ok(CURRENT_THREAD));
+    win32.register("kernel32", "GetCurrentProcessId", |_| Ok(PROCESS_ID));
+    win32.register("kernel32", "GetCurrentThreadId", |_| Ok(THREAD_ID));
+    win32.register("kernel32", "ExitProcess", exit);
+    win32.register("kernel32", "ExitThread", exit);
+    win32.register("kernel32", "TerminateProcess", terminate_process);
+    win32.register("kernel32", "AddVectoredExceptionHandler", |emulator| {
+        let first = emulator.argument(0)? as u32 != 0;
+        Ok(emulator
+            .add_vectored_exception_handler(first, emulator.argument(1)?)
+            .unwrap_or(0))
+    });
+    win32.register("kernel32", "RemoveVectoredExceptionHandler", |emulator| {
+        Ok(emulator.remove_vectored_exception_handler(emulator.argument(0)?) as u64)
+    });
+    win32.register("kernel32", "IsDebuggerPresent", is_debugger_present);
+    win32.register(
+        "kernel32",
+        "CheckRemoteDebuggerPresent",
+        check_remote_debugger_present,
+    );
+    win32.register("kernel32", "Sleep", |_| Ok(0));
+    win32.register("kernel32", "GetLastError", |emulator| {
+        Ok(emulator.last_error()? as u64)
+    });
+    win32.register("kernel32", "SetLastError", |emulator| {
+        emulator.set_last_error(emulator.argument(0)? as u32)?;
+        Ok(0)
+    });
+
+    // time
+    win32.register("kernel32", "GetTickCount", |emulator| {
+        Ok((emulator.instruction_count() / 1_000_000) & 0xFFFF_FFFF)
+    });
+    win32.register("kernel32", "GetTickCount64", |emulator| {
+        Ok(emulator.instruction_count() / 1_000_000)
+    });
+    win32.register("kernel32", "QueryPerformanceCounter", |emulator| {
+        emulator.write_u64(emulator.argument(0)?, emulator.instruction_count())?;
+        Ok(1)
+    });
+    win32.register("kernel32", "QueryPerformanceFrequency", |emulator| {
+        emulator.write_u64(emulator.argument(0)?, PERFORMANCE_FREQUENCY)?;
+        Ok(1)
+    });
+    win32.register("kernel32", "GetSystemTimeAsFileTime", |emulator| {
+        emulator.write_u64(
+            emulator.argument(0)?,
+            FILETIME_BASE + emulator.instruction_count() / 100,
+        )?;
+        Ok(0)
+    });
+
+    // modules
+    win32.register("kernel32", "GetProcAddress", get_proc_address);
+    win32.register("kernel32", "FreeLibrary", |_| Ok(1));
+    for (suffix, wide) in [("A", false), ("W", true)] {
+        win32.register(
+            "kernel32",
+            &format!("GetModuleHandle{}", suffix),
+            move |emulator| get_module_handle(emulator, wide),
+        );
+        win32.register(
+            "kernel32",
+            &format!("LoadLibrary{}", suffix),
+            move |emulator| load_library(emulator, wide),
+        );
+        win32.register(
+            "kernel32",
+            &format!("LoadLibraryEx{}", suffix),
+            move |emulator| load_library(emulator, wide),
+        );
+        win32.register(
+            "kernel32",
+            &format!("GetModuleFileName{}", suffix),
+            move |emulator| get_module_file_name(emulator, wide),
+        );
+    }
+
+    // heap
+    win32.register("kernel32", "GetProcessHeap", |emulator| {
+        emulator.read_u64(emulator.peb()? + offset_of!(PEB, ProcessHeap) as u64)
+    });
+    win32.register("kernel32", "HeapCreate", |emulator| {
+        Ok(emulator.win32.add_handle(Object::Heap))
+    });
+    win32.register("kernel32", "HeapDestroy", |emulator| {
+        let heap = emulator.argument(0)?;
+        Ok(emulator.win32.handles.remove(&heap).is_some() as u64)
+    });
+    win32.register("kernel32", "HeapAlloc", |emulator| {
+        Ok(emulator.heap_allocate(emulator.argument(2)?).unwrap_or(0))
+    });
+    win32.register("kernel32", "HeapReAlloc", |emulator| {
+        heap_reallocate(emulator, emulator.argument(2)?, emulator.argument(3)?)
+    });
+    win32.register("kernel32", "HeapFree", |emulator| {
+        Ok(emulator.heap_free(emulator.argument(2)?) as u64)
+    });
+    win32.register("kernel32", "HeapSize", |emulator| {
+        Ok(emulator
+            .heap_size(emulator.argument(2)?)
+            .unwrap_or(u64::MAX))
+    });
+    for prefix in ["Local", "Global"] {
+        win32.register("kernel32", &format!("{}Alloc", prefix), |emulator| {
+            Ok(emulator.heap_allocate(emulator.argument(1)?).unwrap_or(0))
+        });
+        win32.register("kernel32", &format!("{}ReAlloc", prefix), |emulator| {
+            heap_reallocate(emulator, emulator.argument(0)?, emulator.argument(1)?)
+        });
+        win32.register("kernel32", &format!("{}Free", prefix), |emulator| {
+            let address = emulator.argument(0)?;
+            Ok(if emulator.heap_free(address) {
+                0
+            } else {
+                address
+            })
+        });
+    }
+
+    // virtual memory
+    win32.register("kernel32", "VirtualAlloc", virtual_alloc);
+    win32.register("kernel32", "VirtualFree", virtual_free);
+    win32.register("kernel32", "VirtualProtect", virtual_protect);
+    win32.register("kernel32", "VirtualQuery", virtual_query);
+
+    // strings
+    for (suffix, wide) in [("A", false), ("W", true)] {
+        win32.register("kernel32", &format!("lstrlen{}", suffix), move |emulator| {
+            let string = emulator.argument(0)?;
+            if string == 0 {
+                return Ok(0);
+            }
+            Ok(emulator.read_string(string, wide)?.chars().count() as u64)
+        });
+        win32.register("kernel32", &format!("lstrcpy{}", suffix), move |emulator| {
+            let destination = emulator.argument(0)?;
+            let string = emulator.read_string(emulator.argument(1)?, wide)?;
+            emulator.write_string(destination, &string, wide)?;
+            Ok(destination)
+        });
+        win32.register("kernel32", &format!("lstrcat{}", suffix), move |emulator| {
+            let destination = emulator.argument(0)?;
+            let string = emulator.read_string(destination, wide)?
+                + &emulator.read_string(emulator.argument(1)?, wide)?;
+            emulator.write_string(destination, &string, wide)?;
+            Ok(destination)
+        });
+        win32.register("kernel32", &format!("lstrcmp{}", suffix), move |emulator| {
+            let string = emulator.read_string(emulator.argument(0)?, wide)?;
+            let other = emulator.read_string(emulator.argument(1)?, wide)?;
+            Ok(string.cmp(&other) as i64 as u64)
+        });
+        win32.register(
+            "kernel32",
+            &format!("lstrcmpi{}", suffix),
+            move |emulator| {
+                let string = emulator.read_string(emulator.argument(0)?, wide)?;
+                let other = emulator.read_string(emulator.argument(1)?, wide)?;
+                Ok(string.to_lowercase().cmp(&other.to_lowercase()) as i64 as u64)
+            },
+        );
+    }
+    win32.register("kernel32", "MultiByteToWideChar", multi_byte_to_wide_char);
+    win32.register("kernel32", "WideCharToMultiByte", wide_char_to_multi_byte);
+
+    // environment
+    win32.register("kernel32", "GetCommandLineW", |emulator| {
+        let process_parameters =
+            emulator.read_u64(emulator.peb()? + offset_of!(PEB, ProcessParameters) as u64)?;
+        emulator.read_u64(
+            process_parameters + offset_of!(RTL_USER_PROCESS_PARAMETERS, CommandLine.Buffer) as u64,
+        )
+    });
+    win32.register("kernel32", "GetCommandLineA", get_command_line_a);
+    for (suffix, wide) in [("A", false), ("W", true)] {
+        win32.register(
+            "kernel32",
+            &format!("GetEnvironmentVariable{}", suffix),
+            move |emulator| get_environment_variable(emulator, wide),
+        );
+    }
+
+    // files
+    win32.register("kernel32", "GetStdHandle", |emulator| {
+        Ok(match emulator.argument(0)? as u32 as i32 {
+            -10 => STD_INPUT_HANDLE,
+            -11 => STD_OUTPUT_HANDLE,
+            -12 => STD_ERROR_HANDLE,
+            _ => INVALID_HANDLE_VALUE,
+        })
+    });
+    win32.register("kernel32", "ReadFile", read_file);
+    win32.register("kernel32", "WriteFile", write_file);
+    win32.register("kernel32", "GetFileSize", get_file_size);
+    win32.register("kernel32", "SetFilePointer", set_file_pointer);
+    win32.register("kernel32", "CloseHandle", close_handle);
+    for (suffix, wide) in [("A", false), ("W", true)] {
+        win32.register(
+            "kernel32",
+            &format!("CreateFile{}", suffix),
+            move |emulator| create_file(emulator, wide),
+        );
+        win32.register(
+            "kernel32",
+            &format!("DeleteFile{}", suffix),
+            move |emulator| {
+                let path = normalize_path(&emulator.read_string(emulator.argument(0)?, wide)?);
+                if emulator.win32.files.remove(&path).is_none() {
+                    emulator.set_last_error(ERROR_FILE_NOT_FOUND)?;
+                    return Ok(0);
+                }
+                Ok(1)
+            },
+        );
+        win32.register(
+            "kernel32",
+            &format!("GetFileAttributes{}", suffix),
+            move |emulator| get_file_attributes(emulator, wide),
+        );
+    }
+
+    // synchronization, there is only a single thread
+    for function in [
+        "InitializeCriticalSection",
+        "InitializeCriticalSectionAndSpinCount",
+        "InitializeCriticalSectionEx",
+        "EnterCriticalSection",
+        "LeaveCriticalSection",
+        "DeleteCriticalSection",
+    ] {
+        win32.register("kernel32", function, |_| Ok(1));
+    }
+
+    // thread local storage, fiber local storage shares the slots
+    for prefix in ["Tls", "Fls"] {
+        win32.register("kernel32", &format!("{}Alloc", prefix), |emulator| {
+            let index = emulator.win32.tls.trailing_ones() as u64;
+            if index >= 64 {
+                return Ok(TLS_OUT_OF_INDEXES);
+            }
+            emulator.win32.tls |= 1 << index;
+            emulator.write_u64(tls_slot(emulator, index), 0)?;
+            Ok(index)
+        });
+        win32.register("kernel32", &format!("{}Free", prefix), |emulator| {
+            let index = emulator.argument(0)?;
+            if index >= 64 || emulator.win32.tls & (1 << index) == 0 {
+                emulator.set_last_error(ERROR_INVALID_PARAMETER)?;
+                return Ok(0);
+            }
+            emulator.win32.tls &= !(1 << index);
+            Ok(1)
+        });
+        win32.register("kernel32", &format!("{}GetValue", prefix), |emulator| {
+            let index = emulator.argument(0)?;
+            if index >= 64 {
+                emulator.set_last_error(ERROR_INVALID_PARAMETER)?;
+                return Ok(0);
+            }
+            emulator.set_last_error(ERROR_SUCCESS)?;
+            emulator.read_u64(tls_slot(emulator, index))
+        });
+        win32.register("kernel32", &format!("{}SetValue", prefix), |emulator| {
+            let index = emulator.argument(0)?;
+            if index >= 64 {
+                emulator.set_last_error(ERROR_INVALID_PARAMETER)?;
+                return Ok(0);
+            }
+            emulator.write_u64(tls_slot(emulator, index), emulator.argument(1)?)?;
+            Ok(1)
+        });
+    }
+}
+
+fn exit(emulator: &mut Emulator) -> Result<u64, Exception> {
+    Err(Exception::Exit(emulator.argument(0)? as u32))
+}
+
+fn terminate_process(emulator: &mut Emulator) -> Result<u64, Exception> {
+    if emulator.argument(0)? != CURRENT_PROCESS {
+        emulator.set_last_error(ERROR_INVALID_HANDLE)?;
+        return Ok(0);
+    }
+    Err(Exception::Exit(emulator.argument(1)? as u32))
+}
+
+fn is_debugger_present(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let being_debugged =
+        emulator.read_bytes(emulator.peb()? + offset_of!(PEB, BeingDebugged) as u64, 1)?;
+    Ok(being_debugged[0] as u64)
+}
+
+fn check_remote_debugger_present(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let being_debugged = is_debugger_present(emulator)? as u32;
+    emulator.write_u32(emulator.argument(1)?, being_debugged)?;
+    Ok(1)
+}
+
+fn get_proc_address(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let module = emulator.argument(0)?;
+    let name = emulator.argument(1)?;
+    // ordinals are passed in the low word
+    let function = if name >> 16 == 0 {
+        format!("#{}", name)
+    } else {
+        emulator.read_string(name, false)?
+    };
+    match emulator.proc_address(module, &function)? {
+        Some(address) => Ok(address),
+        None => {
+            emulator.set_last_error(ERROR_PROC_NOT_FOUND)?;
+            Ok(0)
+        }
+    }
+}
+
+fn get_module_handle(emulator: &mut Emulator, wide: bool) -> Result<u64, Exception> {
+    let name = emulator.argument(0)?;
+    if name == 0 {
+        return emulator.read_u64(emulator.peb()? + offset_of!(PEB, ImageBaseAddress) as u64);
+    }
+    let name = emulator.read_string(name, wide)?;
+    match emulator.module_handle(&name)? {
+        Some(base) => Ok(base),
+        None => {
+            emulator.set_last_error(ERROR_MOD_NOT_FOUND)?;
+            Ok(0)
+        }
+    }
+}
+
+fn load_library(emulator: &mut Emulator, wide: bool) -> Result<u64, Exception> {
+    let name = emulator.read_string(emulator.argument(0)?, wide)?;
+    match emulator.load_library(&name)? {
+        Some(base) => Ok(base),
+        None => {
+            emulator.set_last_error(ERROR_BAD_EXE_FORMAT)?;
+            Ok(0)
+        }
+    }
+}
+
+fn get_module_file_name(emulator: &mut Emulator, wide: bool) -> Result<u64, Exception> {
+    let mut module = emulator.argument(0)?;
+    let buffer = emulator.argument(1)?;
+    let size = emulator.argument(2)? as u32 as usize;
+    if module == 0 {
+        module = emulator.read_u64(emulator.peb()? + offset_of!(PEB, ImageBaseAddress) as u64)?;
+    }
+    let Some((_, path)) = emulator
+        .modules()?
+        .into_iter()
+        .find(|&(base, _)| base == module)
+    else {
+        emulator.set_last_error(ERROR_MOD_NOT_FOUND)?;
+        return Ok(0);
+    };
+    if size == 0 {
+        emulator.set_last_error(ERROR_INSUFFICIENT_BUFFER)?;
+        return Ok(0);
+    }
+    // truncated and null-terminated if the buffer is too small
+    let path: String = path.chars().take(size - 1).collect();
+    emulator.write_string(buffer, &path, wide)?;
+    let length = path.chars().count();
+    if length == size - 1 {
+        emulator.set_last_error(ERROR_INSUFFICIENT_BUFFER)?;
+    }
+    Ok(length as u64)
+}
+
+fn heap_reallocate(emulator: &mut Emulator, address: u64, size: u64) -> Result<u64, Exception> {
+    let Some(previous_size) = emulator.heap_size(address) else {
+        emulator.set_last_error(ERROR_INVALID_PARAMETER)?;
+        return Ok(0);
+    };
+    let Some(new_address) = emulator.heap_allocate(size) else {
+        emulator.set_last_error(ERROR_NOT_ENOUGH_MEMORY)?;
+        return Ok(0);
+    };
+    let data = emulator.read_bytes(address, previous_size.min(size) as usize)?;
+    emulator.memory.write(new_address, &data)?;
+    emulator.heap_free(address);
+    Ok(new_address)
+}
+
+fn virtual_alloc(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let address = emulator.argument(0)? & !(PAGE_SIZE - 1);
+    let size =
+        (emulator.argument(1)? + (emulator.argument(0)? - address)).next_multiple_of(PAGE_SIZE);
+    let allocation_type = emulator.argument(2)?;
+    let protection = emulator.argument(3)? as u32;
+    if size == 0 || allocation_type & (MEM_COMMIT | MEM_RESERVE) == 0 {
+        emulator.set_last_error(ERROR_INVALID_PARAMETER)?;
+        return Ok(0);
+    }
+
+    let address = if address == 0 {
+        let Some(address) = emulator.memory.find_free(VIRTUAL_BASE, size) else {
+            emulator.set_last_error(ERROR_NOT_ENOUGH_MEMORY)?;
+            return Ok(0);
+        };
+        emulator.win32.allocations.insert(address, size);
+        address
+    } else if allocation_type & MEM_RESERVE != 0 {
+        if emulator.memory.find_free(address, size) != Some(address) {
+            emulator.set_last_error(ERROR_INVALID_ADDRESS)?;
+            return Ok(0);
+        }
+        emulator.win32.allocations.insert(address, size);
+        address
+    } else {
+        // commit of reserved memory
+        if (address..address + size)
+            .step_by(PAGE_SIZE as usize)
+            .any(|page| emulator.memory.protection(page).is_none())
+        {
+            emulator.set_last_error(ERROR_INVALID_ADDRESS)?;
+            return Ok(0);
+        }
+        address
+    };
+    emulator.memory.map(
+        address,
+        size,
+        if allocation_type & MEM_COMMIT != 0 {
+            from_page_protection(protection)
+        } else {
+            Protection::NONE
+        },
+    );
+    Ok(address)
+}
+
+fn virtual_free(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let address = emulator.argument(0)?;
+    let size = emulator.argument(1)?;
+    let free_type = emulator.argument(2)?;
+    if free_type & MEM_RELEASE != 0 {
+        let Some(size) = emulator.win32.allocations.remove(&address) else {
+            emulator.set_last_error(ERROR_INVALID_ADDRESS)?;
+            return Ok(0);
+        };
+        emulator.memory.unmap(address, size);
+        Ok(1)
+    } else if free_type & MEM_DECOMMIT != 0 {
+        Ok(emulator.memory.protect(address, size, Protection::NONE) as u64)
+    } else {
+        emulator.set_last_error(ERROR_INVALID_PARAMETER)?;
+        Ok(0)
+    }
+}
+
+fn virtual_protect(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let address = emulator.argument(0)?;
+    let size = emulator.argument(1)?;
+    let protection = emulator.argument(2)? as u32;
+    let previous_protection = emulator.argument(3)?;
+    let Some(previous) = emulator.memory.protection(address) else {
+        emulator.set_last_error(ERROR_INVALID_ADDRESS)?;
+        return Ok(0);
+    };
+    if !emulator
+        .memory
+        .protect(address, size, from_page_protection(protection))
+    {
+        emulator.set_last_error(ERROR_INVALID_ADDRESS)?;
+        return Ok(0);
+    }
+    emulator.write_u32(previous_protection, to_page_protection(previous))?;
+    Ok(1)
+}
+
+fn virtual_query(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let address = emulator.argument(0)? & !(PAGE_SIZE - 1);
+    let information = emulator.argument(1)?;
+    if emulator.argument(2)? < 0x30 {
+        emulator.set_last_error(ERROR_INSUFFICIENT_BUFFER)?;
+        return Ok(0);
+    }
+
+    let regions = emulator.memory.regions();
+    let (allocation_base, size, protection, state) =
+        match regions.iter().find(|(range, _)| range.contains(&address)) {
+            Some((range, protection)) => {
+                let allocation_base = emulator
+                    .win32
+                    .allocations
+                    .range(..=address)
+                    .next_back()
+                    .filter(|&(&base, &size)| address < base + size)
+                    .map_or(range.start, |(&base, _)| base);
+                (
+                    allocation_base,
+                    range.end - address,
+                    to_page_protection(*protection),
+                    if *protection == Protection::NONE {
+                        MEM_RESERVE as u32
+                    } else {
+                        MEM_COMMIT as u32
+                    },
+                )
+            }
+            None => {
+                let end = regions
+                    .iter()
+                    .map(|(range, _)| range.start)
+                    .find(|&start| start > address)
+                    .unwrap_or(0x7FFF_FFFF_0000);
+                (0, end.saturating_sub(address), 0, MEM_FREE)
+            }
+        };
+    // MEMORY_BASIC_INFORMATION
+    let mut data = [0; 0x30];
+    data[0x00..0x08].copy_from_slice(&address.to_le_bytes());
+    data[0x08..0x10].copy_from_slice(&allocation_base.to_le_bytes());
+    data[0x10..0x14].copy_from_slice(&protection.to_le_bytes());
+    data[0x18..0x20].copy_from_slice(&size.to_le_bytes());
+    data[0x20..0x24].copy_from_slice(&state.to_le_bytes());
+    data[0x24..0x28].copy_from_slice(&protection.to_le_bytes());
+    if state != MEM_FREE {
+        data[0x28..0x2C].copy_from_slice(&MEM_PRIVATE.to_le_bytes());
+    }
+    emulator.memory.write(information, &data)?;
+    Ok(data.len() as u64)
+}
+
+fn multi_byte_to_wide_char(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let code_page = emulator.argument(0)?;
+    let string = emulator.argument(2)?;
+    let length = emulator.argument(3)? as u32 as i32;
+    let buffer = emulator.argument(4)?;
+    let size = emulator.argument(5)? as u32 as usize;
+    let data = if length < 0 {
+        let mut data = emulator
+            .read_string(string, false)?
+            .chars()
+            .map(|char| char as u8)
+            .collect::<Vec<_>>();
+        data.push(0);
+        data
+    } else {
+        emulator.read_bytes(string, length as usize)?
+    };
+    let string = if code_page == CP_UTF8 {
+        String::from_utf8_lossy(&data).into_owned()
+    } else {
+        data.iter().map(|&char| char as char).collect()
+    };
+    let string = string.encode_utf16().collect::<Vec<_>>();
+    if size == 0 {
+        return Ok(string.len() as u64);
+    }
+    if size < string.len() {
+        emulator.set_last_error(ERROR_INSUFFICIENT_BUFFER)?;
+        return Ok(0);
+    }
+    let data = string
+        .iter()
+        .flat_map(|char| char.to_le_bytes())
+        .collect::<Vec<_>>();
+    emulator.memory.write(buffer, &data)?;
+    Ok(string.len() as u64)
+}
+
+fn wide_char_to_multi_byte(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let code_page = emulator.argument(0)?;
+    let string = emulator.argument(2)?;
+    let length = emulator.argument(3)? as u32 as i32;
+    let buffer = emulator.argument(4)?;
+    let size = emulator.argument(5)? as u32 as usize;
+    let string = if length < 0 {
+        emulator.read_string(string, true)? + "\0"
+    } else {
+        let data = emulator.read_bytes(string, length as usize * 2)?;
+        String::from_utf16_lossy(
+            &data
+                .chunks(2)
+                .map(|char| u16::from_le_bytes([char[0], char[1]]))
+                .collect::<Vec<_>>(),
+        )
+    };
+    let data = if code_page == CP_UTF8 {
+        string.into_bytes()
+    } else {
+        string
+            .chars()
+            .map(|char| u8::try_from(char).unwrap_or(b'?'))
+            .collect()
+    };
+    if size == 0 {
+        return Ok(data.len() as u64);
+    }
+    if size < data.len() {
+        emulator.set_last_error(ERROR_INSUFFICIENT_BUFFER)?;
+        return Ok(0);
+    }
+    emulator.memory.write(buffer, &data)?;
+    Ok(data.len() as u64)
+}
+
+fn get_command_line_a(emulator: &mut Emulator) -> Result<u64, Exception> {
+    if let Some(command_line) = emulator.win32.command_line {
+        return Ok(command_line);
+    }
+    let process_parameters =
+        emulator.read_u64(emulator.peb()? + offset_of!(PEB, ProcessParameters) as u64)?;
+    let command_line = emulator.read_string(
+        emulator.read_u64(
+            process_parameters + offset_of!(RTL_USER_PROCESS_PARAMETERS, CommandLine.Buffer) as u64,
+        )?,
+        true,
+    )?;
+    let address = emulator
+        .heap_allocate(command_line.len() as u64 + 1)
+        .ok_or(Exception::OutOfMemory)?;
+    emulator.write_string(address, &command_line, false)?;
+    emulator.win32.command_line = Some(address);
+    Ok(address)
+}
+
+fn get_environment_variable(emulator: &mut Emulator, wide: bool) -> Result<u64, Exception> {
+    let name = emulator.read_string(emulator.argument(0)?, wide)?;
+    let buffer = emulator.argument(1)?;
+    let size = emulator.argument(2)? as u32 as u64;
+    let Some(value) = emulator.environment_variable(&name)? else {
+        emulator.set_last_error(ERROR_ENVVAR_NOT_FOUND)?;
+        return Ok(0);
+    };
+    // the size including the terminator if the buffer is too small
+    let length = value.chars().count() as u64;
+    if size <= length {
+        return Ok(length + 1);
+    }
+    emulator.write_string(buffer, &value, wide)?;
+    Ok(length)
+}
+
+fn create_file(emulator: &mut Emulator, wide: bool) -> Result<u64, Exception> {
+    let path = normalize_path(&emulator.read_string(emulator.argument(0)?, wide)?);
+    let disposition = emulator.argument(4)?;
+    if path == "conout$" || path == "conin$" {
+        return Ok(emulator.win32.add_handle(Object::Console));
+    }
+
+    let exists = emulator.win32.files.contains_key(&path);
+    let error = match disposition {
+        CREATE_NEW if exists => Some(ERROR_FILE_EXISTS),
+        OPEN_EXISTING | TRUNCATE_EXISTING if !exists => Some(ERROR_FILE_NOT_FOUND),
+        CREATE_NEW | CREATE_ALWAYS | OPEN_EXISTING | OPEN_ALWAYS | TRUNCATE_EXISTING => None,
+        _ => Some(ERROR_INVALID_PARAMETER),
+    };
+    if let Some(error) = error {
+        emulator.set_last_error(error)?;
+        return Ok(INVALID_HANDLE_VALUE);
+    }
+    if matches!(disposition, CREATE_NEW | CREATE_ALWAYS | TRUNCATE_EXISTING)
+        || (disposition == OPEN_ALWAYS && !exists)
+    {
+        emulator.win32.files.insert(path.clone(), vec![]);
+    }
+    emulator.set_last_error(
+        if exists && matches!(disposition, CREATE_ALWAYS | OPEN_ALWAYS) {
+            ERROR_ALREADY_EXISTS
+        } else {
+            ERROR_SUCCESS
+        },
+    )?;
+    Ok(emulator
+        .win32
+        .add_handle(Object::File { path, position: 0 }))
+}
+
+fn read_file(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let handle = emulator.argument(0)?;
+    let buffer = emulator.argument(1)?;
+    let size = emulator.argument(2)? as u32 as u64;
+    let read = emulator.argument(3)?;
+    let data = match emulator.win32.handles.get_mut(&handle) {
+        Some(Object::Console) => vec![],
+        Some(Object::File { path, position }) => {
+            let file = &emulator.win32.files[path.as_str()];
+            let start = (*position).min(file.len() as u64);
+            let end = (start + size).min(file.len() as u64);
+            *position = end;
+            file[start as usize..end as usize].to_vec()
+        }
+        _ => {
+            emulator.set_last_error(ERROR_INVALID_HANDLE)?;
+            return Ok(0);
+        }
+    };
+    emulator.memory.write(buffer, &data)?;
+    if read != 0 {
+        emulator.write_u32(read, data.len() as u32)?;
+    }
+    Ok(1)
+}
+
+fn write_file(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let handle = emulator.argument(0)?;
+    let data = emulator.read_bytes(emulator.argument(1)?, emulator.argument(2)? as u32 as usize)?;
+    let written = emulator.argument(3)?;
+    match emulator.win32.handles.get_mut(&handle) {
+        Some(Object::Console) => emulator.win32.console.extend(&data),
+        Some(Object::File { path, position }) => {
+            let file = emulator.win32.files.entry(path.clone()).or_default();
+            let start = *position as usize;
+            if file.len() < start + data.len() {
+                file.resize(start + data.len(), 0);
+            }
+            file[start..start + data.len()].copy_from_slice(&data);
+            *position += data.len() as u64;
+        }
+        _ => {
+            emulator.set_last_error(ERROR_INVALID_HANDLE)?;
+            return Ok(0);
+        }
+    }
+    if written != 0 {
+        emulator.write_u32(written, data.len() as u32)?;
+    }
+    Ok(1)
+}
+
+fn get_file_size(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let handle = emulator.argument(0)?;
+    let size_high = emulator.argument(1)?;
+    let Some(Object::File { path, .. }) = emulator.win32.handles.get(&handle) else {
+        emulator.set_last_error(ERROR_INVALID_HANDLE)?;
+        return Ok(0xFFFF_FFFF);
+    };
+    let size = emulator.win32.files[path.as_str()].len() as u64;
+    if size_high != 0 {
+        emulator.write_u32(size_high, (size >> 32) as u32)?;
+    }
+    Ok(size & 0xFFFF_FFFF)
+}
+
+fn set_file_pointer(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let handle = emulator.argument(0)?;
+    let distance_low = emulator.argument(1)? as u32;
+    let distance_high = emulator.argument(2)?;
+    let method = emulator.argument(3)?;
+    let distance = if distance_high != 0 {
+        ((emulator.read_u32(distance_high)? as u64) << 32 | distance_low as u64) as i64
+    } else {
+        distance_low as i32 as i64
+    };
+    let Some(Object::File { path, position }) = emulator.win32.handles.get_mut(&handle) else {
+        emulator.set_last_error(ERROR_INVALID_HANDLE)?;
+        return Ok(0xFFFF_FFFF);
+    };
+    let origin = match method {
+        // FILE_BEGIN, FILE_CURRENT, FILE_END
+        0 => 0,
+        1 => *position as i64,
+        2 => emulator.win32.files[path.as_str()].len() as i64,
+        _ => -1,
+    };
+    let Ok(new_position) = u64::try_from(origin + distance) else {
+        emulator.set_last_error(ERROR_INVALID_PARAMETER)?;
+        return Ok(0xFFFF_FFFF);
+    };
+    *position = new_position;
+    if distance_high != 0 {
+        emulator.write_u32(distance_high, (new_position >> 32) as u32)?;
+    }
+    Ok(new_position & 0xFFFF_FFFF)
+}
+
+fn close_handle(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let handle = emulator.argument(0)?;
+    if handle == CURRENT_PROCESS || handle == CURRENT_THREAD {
+        return Ok(1);
+    }
+    if emulator.win32.handles.remove(&handle).is_none() {
+        emulator.set_last_error(ERROR_INVALID_HANDLE)?;
+        return Ok(0);
+    }
+    Ok(1)
+}
+
+fn get_file_attributes(emulator: &mut Emulator, wide: bool) -> Result<u64, Exception> {
+    let path = normalize_path(&emulator.read_string(emulator.argument(0)?, wide)?);
+    let directory = format!("{}\\", path.trim_end_matches('\\'));
+    if emulator.win32.files.contains_key(&path) {
+        Ok(FILE_ATTRIBUTE_NORMAL)
+    } else if emulator
+        .win32
+        .files
+        .keys()
+        .any(|file| file.starts_with(&directory))
+    {
+        Ok(FILE_ATTRIBUTE_DIRECTORY)
+    } else {
+        emulator.set_last_error(ERROR_FILE_NOT_FOUND)?;
+        Ok(INVALID_FILE_ATTRIBUTES)
+    }
+}
+
+fn tls_slot(emulator: &Emulator, index: u64) -> u64 {
+    emulator.teb() + offset_of!(TEB, TlsSlots) as u64 + index * 8
+}
+
+fn from_page_protection(protection: u32) -> Protection {
+    match protection & 0xFF {
+        // PAGE_READONLY
+        0x02 => Protection::READ,
+        // PAGE_READWRITE, PAGE_WRITECOPY
+        0x04 | 0x08 => Protection::READ_WRITE,
+        // PAGE_EXECUTE
+        0x10 => Protection::new(false, false, true),
+        // PAGE_EXECUTE_READ
+        0x20 => Protection::READ_EXECUTE,
+        // PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY
+        0x40 | 0x80 => Protection::READ_WRITE_EXECUTE,
+        _ => Protection::NONE,
+    }
+}
+
+fn to_page_protection(protection: Protection) -> u32 {
+    match (protection.read, protection.write, protection.execute) {
+        (false, false, false) => 0x01,
+        (_, false, false) => 0x02,
+        (_, true, false) => 0x04,
+        (false, false, true) => 0x10,
+        (_, false, true) => 0x20,
+        (_, true, true) => 0x40,
+    }
+}
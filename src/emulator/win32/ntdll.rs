@@ -0,0 +1,146 @@
+use std::mem::offset_of;
+
+use crate::{emulator::*, process::PEB};
+
+const STATUS_SUCCESS: u64 = 0;
+const STATUS_INFO_LENGTH_MISMATCH: u64 = 0xC000_0004;
+const STATUS_INVALID_INFO_CLASS: u64 = 0xC000_0003;
+const STATUS_INVALID_HANDLE: u64 = 0xC000_0008;
+const STATUS_PORT_NOT_SET: u64 = 0xC000_0353;
+
+pub(super) fn register(win32: &mut Win32) {
+    win32.register("ntdll", "RtlAllocateHeap", |emulator| {
+        Ok(emulator.heap_allocate(emulator.argument(2)?).unwrap_or(0))
+    });
+    win32.register("ntdll", "RtlFreeHeap", |emulator| {
+        Ok(emulator.heap_free(emulator.argument(2)?) as u64)
+    });
+    win32.register("ntdll", "RtlGetVersion", rtl_get_version);
+    win32.register("ntdll", "RtlInitUnicodeString", |emulator| {
+        let destination = emulator.argument(0)?;
+        let string = emulator.argument(1)?;
+        let length = if string == 0 {
+            0
+        } else {
+            emulator.read_string(string, true)?.encode_utf16().count() as u64 * 2
+        };
+        let mut data = [0; 16];
+        data[0..2].copy_from_slice(&(length as u16).to_le_bytes());
+        data[2..4]
+            .copy_from_slice(&(if string == 0 { 0 } else { length as u16 + 2 }).to_le_bytes());
+        data[8..16].copy_from_slice(&string.to_le_bytes());
+        emulator.memory.write(destination, &data)?;
+        Ok(0)
+    });
+    win32.register(
+        "ntdll",
+        "NtQueryInformationProcess",
+        nt_query_information_process,
+    );
+    win32.register("ntdll", "NtTerminateProcess", |emulator| {
+        let process = emulator.argument(0)?;
+        if process != 0 && process != CURRENT_PROCESS {
+            return Ok(STATUS_INVALID_HANDLE);
+        }
+        Err(Exception::Exit(emulator.argument(1)? as u32))
+    });
+    win32.register("ntdll", "RtlExitUserProcess", |emulator| {
+        Err(Exception::Exit(emulator.argument(0)? as u32))
+    });
+    win32.register("ntdll", "RtlAddVectoredExceptionHandler", |emulator| {
+        let first = emulator.argument(0)? as u32 != 0;
+        Ok(emulator
+            .add_vectored_exception_handler(first, emulator.argument(1)?)
+            .unwrap_or(0))
+    });
+    win32.register("ntdll", "RtlRemoveVectoredExceptionHandler", |emulator| {
+        Ok(emulator.remove_vectored_exception_handler(emulator.argument(0)?) as u64)
+    });
+
+    // c runtime
+    for function in ["memcpy", "memmove"] {
+        win32.register("ntdll", function, |emulator| {
+            let destination = emulator.argument(0)?;
+            let data =
+                emulator.read_bytes(emulator.argument(1)?, emulator.argument(2)? as usize)?;
+            emulator.memory.write(destination, &data)?;
+            Ok(destination)
+        });
+    }
+    win32.register("ntdll", "memset", |emulator| {
+        let destination = emulator.argument(0)?;
+        let data = vec![emulator.argument(1)? as u8; emulator.argument(2)? as usize];
+        emulator.memory.write(destination, &data)?;
+        Ok(destination)
+    });
+    win32.register("ntdll", "strlen", |emulator| {
+        Ok(emulator
+            .read_string(emulator.argument(0)?, false)?
+            .chars()
+            .count() as u64)
+    });
+    win32.register("ntdll", "wcslen", |emulator| {
+        Ok(emulator
+            .read_string(emulator.argument(0)?, true)?
+            .encode_utf16()
+            .count() as u64)
+    });
+}
+
+fn rtl_get_version(emulator: &mut Emulator) -> Result<u64, Exception> {
+    // OSVERSIONINFOW, the size is set by the caller
+    let information = emulator.argument(0)?;
+    let peb = emulator.peb()?;
+    let major_version = emulator.read_u32(peb + offset_of!(PEB, OSMajorVersion) as u64)?;
+    let minor_version = emulator.read_u32(peb + offset_of!(PEB, OSMinorVersion) as u64)?;
+    let build_number = emulator.read_u16(peb + offset_of!(PEB, OSBuildNumber) as u64)?;
+    let platform_id = emulator.read_u32(peb + offset_of!(PEB, OSPlatformId) as u64)?;
+    emulator.write_u32(information + 0x04, major_version)?;
+    emulator.write_u32(information + 0x08, minor_version)?;
+    emulator.write_u32(information + 0x0C, build_number as u32)?;
+    emulator.write_u32(information + 0x10, platform_id)?;
+    Ok(STATUS_SUCCESS)
+}
+
+fn nt_query_information_process(emulator: &mut Emulator) -> Result<u64, Exception> {
+    let process = emulator.argument(0)?;
+    let class = emulator.argument(1)? as u32;
+    let information = emulator.argument(2)?;
+    let length = emulator.argument(3)? as u32 as u64;
+    let return_length = emulator.argument(4)?;
+    if process != CURRENT_PROCESS {
+        return Ok(STATUS_INVALID_HANDLE);
+    }
+
+    let data = match class {
+        // ProcessBasicInformation
+        0x00 => {
+            let mut data = vec![0; 0x30];
+            data[0x08..0x10].copy_from_slice(&emulator.peb()?.to_le_bytes());
+            data[0x10..0x18].copy_from_slice(&1u64.to_le_bytes());
+            data[0x20..0x28].copy_from_slice(&PROCESS_ID.to_le_bytes());
+            data
+        }
+        // ProcessDebugPort
+        0x07 => vec![0; 8],
+        // ProcessDebugObjectHandle
+        0x1E => {
+            if length < 8 {
+                return Ok(STATUS_INFO_LENGTH_MISMATCH);
+            }
+            emulator.write_u64(information, 0)?;
+            return Ok(STATUS_PORT_NOT_SET);
+        }
+        // ProcessDebugFlags, NoDebugInherit
+        0x1F => vec![1, 0, 0, 0],
+        _ => return Ok(STATUS_INVALID_INFO_CLASS),
+    };
+    if return_length != 0 {
+        emulator.write_u32(return_length, data.len() as u32)?;
+    }
+    if length < data.len() as u64 {
+        return Ok(STATUS_INFO_LENGTH_MISMATCH);
+    }
+    emulator.memory.write(information, &data)?;
+    Ok(STATUS_SUCCESS)
+}
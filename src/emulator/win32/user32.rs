@@ -0,0 +1,49 @@
+use crate::emulator::*;
+
+/// handle of the desktop and foreground window
+const WINDOW: u64 = 0x10010;
+
+const SCREEN_WIDTH: u64 = 1920;
+const SCREEN_HEIGHT: u64 = 1080;
+
+pub(super) fn register(win32: &mut Win32) {
+    for (suffix, wide) in [("A", false), ("W", true)] {
+        win32.register(
+            "user32",
+            &format!("MessageBox{}", suffix),
+            move |emulator| {
+                let text = emulator.argument(1)?;
+                let caption = emulator.argument(2)?;
+                let text = if text == 0 {
+                    String::new()
+                } else {
+                    emulator.read_string(text, wide)?
+                };
+                let caption = if caption == 0 {
+                    "Error".to_owned()
+                } else {
+                    emulator.read_string(caption, wide)?
+                };
+                emulator.win32.message_boxes.push((caption, text));
+                // IDOK
+                Ok(1)
+            },
+        );
+    }
+    win32.register("user32", "GetDesktopWindow", |_| Ok(WINDOW));
+    win32.register("user32", "GetForegroundWindow", |_| Ok(WINDOW));
+    win32.register("user32", "GetSystemMetrics", |emulator| {
+        Ok(match emulator.argument(0)? as u32 {
+            // SM_CXSCREEN, SM_CYSCREEN
+            0 => SCREEN_WIDTH,
+            1 => SCREEN_HEIGHT,
+            _ => 0,
+        })
+    });
+    win32.register("user32", "GetCursorPos", |emulator| {
+        let point = emulator.argument(0)?;
+        emulator.write_u32(point, SCREEN_WIDTH as u32 / 2)?;
+        emulator.write_u32(point + 4, SCREEN_HEIGHT as u32 / 2)?;
+        Ok(1)
+    });
+}
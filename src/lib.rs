@@ -5,15 +5,36 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
+    #[cfg(windows)]
     #[error("Windows error")]
     Windows(#[from] windows::core::Error),
     #[error("IO error")]
     Io(#[from] std::io::Error),
     #[error("PE error")]
     Pe(#[from] object::read::Error),
+    #[error("Assembler error")]
+    Asm(#[from] iced_x86::IcedError),
+    #[error("Emulator exception")]
+    Emulator(#[from] emulator::Exception),
+    #[error("Unsupported: {0}")]
+    Unsupported(&'static str),
+    #[error("Trace error: {0}")]
+    Trace(&'static str),
+    #[error("Solver error: {0}")]
+    Solver(&'static str),
+    #[error("Scan error: {0}")]
+    Scan(&'static str),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+pub mod call;
+pub mod coverage;
+pub mod emulator;
+pub mod memory;
 pub mod module;
 pub mod process;
+pub mod scan;
+pub mod solver;
+pub mod trace;
+pub mod unpack;
@@ -0,0 +1,102 @@
+pub use mbg_hook_shared::protocol::{RegionEntry, RegionKind};
+
+use crate::{module::Module, Result};
+
+/// address space which can be read and written, implemented by live
+/// processes and the emulator
+pub trait Memory {
+    /// reads data.len() bytes at the specified address
+    fn read(&self, address: usize, data: &mut [u8]) -> Result<()>;
+
+    /// writes data at the specified address
+    fn write(&mut self, address: usize, data: &[u8]) -> Result<()>;
+
+    /// reads size bytes at the specified address
+    fn read_vec(&self, address: usize, size: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; size];
+        self.read(address, &mut data)?;
+        Ok(data)
+    }
+}
+
+/// splits the regions at the headers and sections of the modules, the parts
+/// inside a module become images owned by the module and section
+pub fn label_regions(regions: Vec<RegionEntry>, modules: &[Module]) -> Result<Vec<RegionEntry>> {
+    let mut labels = vec![];
+    for module in modules {
+        labels.extend(module.regions()?);
+    }
+    labels.sort_by_key(|label| label.base);
+
+    let mut result = vec![];
+    for region in regions {
+        let end = region.base + region.size;
+        let mut start = region.base;
+        for label in &labels {
+            let label_start = label.base.max(start);
+            let label_end = (label.base + label.size).min(end);
+            if label_start >= label_end {
+                continue;
+            }
+            if label_start > start {
+                result.push(RegionEntry {
+                    base: start,
+                    size: label_start - start,
+                    ..region.clone()
+                });
+            }
+            result.push(RegionEntry {
+                base: label_start,
+                size: label_end - label_start,
+                kind: RegionKind::Image,
+                owner: label.owner.clone(),
+                ..region.clone()
+            });
+            start = label_end;
+        }
+        if start < end {
+            result.push(RegionEntry {
+                base: start,
+                size: end - start,
+                ..region
+            });
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unpack::tests::{image, BASE};
+
+    #[test]
+    fn label() {
+        let base = BASE as u64;
+        let module = Module::from_image("test.exe".to_owned(), BASE, image()).unwrap();
+        let region = |base, size, kind, owner: &str| RegionEntry {
+            base,
+            size,
+            read: true,
+            write: true,
+            execute: false,
+            kind,
+            owner: owner.to_owned(),
+        };
+        let regions = vec![
+            region(0x10000, 0x1000, RegionKind::Private, ""),
+            region(base - 0x1000, 0x5000, RegionKind::Private, ""),
+        ];
+        assert_eq!(
+            label_regions(regions, &[module]).unwrap(),
+            [
+                region(0x10000, 0x1000, RegionKind::Private, ""),
+                region(base - 0x1000, 0x1000, RegionKind::Private, ""),
+                region(base, 0x1000, RegionKind::Image, "test.exe"),
+                region(base + 0x1000, 0x1000, RegionKind::Image, "test.exe .text"),
+                region(base + 0x2000, 0x1000, RegionKind::Image, "test.exe .data"),
+                region(base + 0x3000, 0x1000, RegionKind::Private, ""),
+            ]
+        );
+    }
+}
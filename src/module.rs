@@ -1,11 +1,18 @@
-use std::{ffi::OsString, ops::Range, os::windows::ffi::OsStringExt};
+use std::{borrow::Cow, collections::BTreeMap, mem::size_of, ops::Range};
+#[cfg(windows)]
+use std::{ffi::OsString, os::windows::ffi::OsStringExt};
 
 use byteorder::{ReadBytesExt, LE};
 use object::{
-    pe::{ImageTlsDirectory64, IMAGE_DIRECTORY_ENTRY_TLS},
-    read::pe::{ImageNtHeaders, ImageOptionalHeader, PeFile64},
+    pe::{
+        ImageRuntimeFunctionEntry, ImageTlsDirectory64, IMAGE_DIRECTORY_ENTRY_EXCEPTION,
+        IMAGE_DIRECTORY_ENTRY_EXPORT, IMAGE_DIRECTORY_ENTRY_TLS, IMAGE_SCN_MEM_EXECUTE,
+        IMAGE_SCN_MEM_READ, IMAGE_SCN_MEM_WRITE,
+    },
+    read::pe::{Export, ExportTable, ExportTarget, ImageNtHeaders, ImageOptionalHeader, PeFile64},
     LittleEndian, Object, ReadRef,
 };
+#[cfg(windows)]
 use windows::{
     core::HSTRING,
     Win32::{
@@ -23,18 +30,32 @@ use windows::{
     },
 };
 
-use crate::{process::PEB, Result};
+#[cfg(windows)]
+use crate::process::PEB;
+use crate::{
+    memory::{Memory, RegionEntry, RegionKind},
+    Result,
+};
 
 pub struct Module {
-    process: HANDLE,
+    source: Source,
 
     name: String,
     base: usize,
     size: usize,
 }
 
+enum Source {
+    /// read from the process whenever needed
+    #[cfg(windows)]
+    Process(HANDLE),
+    /// copied once (e.g. out of the emulator)
+    Image(Vec<u8>),
+}
+
 impl Module {
     /// all known modules
+    #[cfg(windows)]
     pub fn all(process: HANDLE) -> Result<Vec<Self>> {
         let mut result = vec![];
         unsafe {
@@ -56,11 +77,13 @@ impl Module {
 
     /// searches for a module with the specified name, if the name is None the
     /// image module will be returned
+    #[cfg(windows)]
     pub fn by_name(process: HANDLE, name: String) -> Result<Self> {
         unsafe { Self::from_handle(process, GetModuleHandleW(&HSTRING::from(name))?) }
     }
 
     /// module from PEB
+    #[cfg(windows)]
     pub fn from_peb(process: HANDLE) -> Result<Self> {
         unsafe {
             let mut pbi = PROCESS_BASIC_INFORMATION::default();
@@ -81,7 +104,7 @@ impl Module {
             )
             .ok()?;
             Ok(Self {
-                process,
+                source: Source::Process(process),
                 name: "".to_string(),
                 base: peb.ImageBaseAddress as usize,
                 size: PeFile64::parse(ProcessMemoryReadRef {
@@ -96,6 +119,7 @@ impl Module {
     }
 
     /// module from handle
+    #[cfg(windows)]
     pub fn from_handle(process: HANDLE, module: HMODULE) -> Result<Self> {
         unsafe {
             let mut module_name = [0; MAX_PATH as usize];
@@ -114,7 +138,7 @@ impl Module {
             )
             .ok()?;
             Ok(Self {
-                process,
+                source: Source::Process(process),
                 name: module_name,
                 base: module_info.lpBaseOfDll as usize,
                 size: module_info.SizeOfImage as usize,
@@ -122,6 +146,36 @@ impl Module {
         }
     }
 
+    /// module mapped in any memory, the image is copied once
+    pub fn from_memory(memory: &impl Memory, name: String, base: usize) -> Result<Self> {
+        let headers = memory.read_vec(base, 0x1000)?;
+        let size = PeFile64::parse(headers.as_slice())?
+            .nt_headers()
+            .optional_header()
+            .size_of_image() as usize;
+        Ok(Self {
+            source: Source::Image(memory.read_vec(base, size)?),
+            name,
+            base,
+            size,
+        })
+    }
+
+    /// module of a mapped image which isn't loaded anywhere (e.g. a dump whose
+    /// file offsets equal the relative addresses)
+    pub fn from_image(name: String, base: usize, data: Vec<u8>) -> Result<Self> {
+        let size = PeFile64::parse(data.as_slice())?
+            .nt_headers()
+            .optional_header()
+            .size_of_image() as usize;
+        Ok(Self {
+            source: Source::Image(data),
+            name,
+            base,
+            size,
+        })
+    }
+
     /// name of the module
     pub fn name(&self) -> &str {
         self.name.as_str()
@@ -129,7 +183,7 @@ impl Module {
 
     /// base address of the module
     pub fn base(&self) -> usize {
-        self.base as usize
+        self.base
     }
 
     /// size of the module
@@ -139,18 +193,8 @@ impl Module {
 
     /// all known addresses of the module
     pub fn symbols(&self) -> Result<Vec<(String, usize)>> {
-        let mut data = vec![0; self.size];
-        unsafe {
-            ReadProcessMemory(
-                self.process,
-                self.base as *const std::ffi::c_void,
-                data.as_mut_ptr() as *mut _,
-                data.len(),
-                None,
-            )
-            .ok()?;
-        }
-        let image = PeFile64::parse(data.as_slice())?;
+        let data = self.image()?;
+        let image = PeFile64::parse(&*data)?;
 
         let mut symbols = vec![("entry_point".to_owned(), image.entry() as usize)];
         if let Some(directory) = image.data_directory(IMAGE_DIRECTORY_ENTRY_TLS) {
@@ -177,20 +221,128 @@ impl Module {
         Ok(symbols)
     }
 
+    /// functions of the exception directory, named after their export or
+    /// sub_<address>, ordered by address
+    pub fn functions(&self) -> Result<Vec<(String, Range<usize>)>> {
+        let data = self.image()?;
+        let image = PeFile64::parse(&*data)?;
+
+        let mut names = BTreeMap::new();
+        for export in export_entries(&image, &data)? {
+            if let (Some(name), ExportTarget::Address(address)) = (export.name, export.target) {
+                names.insert(address, String::from_utf8_lossy(name).into_owned());
+            }
+        }
+
+        let mut functions = vec![];
+        if let Some(directory) = image.data_directory(IMAGE_DIRECTORY_ENTRY_EXCEPTION) {
+            let (address, size) = directory.address_range();
+            let entries = (&*data)
+                .read_slice_at::<ImageRuntimeFunctionEntry>(
+                    address as u64,
+                    size as usize / size_of::<ImageRuntimeFunctionEntry>(),
+                )
+                .unwrap_or_default();
+            for entry in entries {
+                let begin = entry.begin_address.get(LittleEndian);
+                let end = entry.end_address.get(LittleEndian);
+                let name = names
+                    .get(&begin)
+                    .cloned()
+                    .unwrap_or_else(|| format!("sub_{:X}", self.base + begin as usize));
+                functions.push((name, self.base + begin as usize..self.base + end as usize));
+            }
+        }
+        functions.sort_by_key(|(_, range)| range.start);
+        Ok(functions)
+    }
+
+    /// exported addresses by name, or #ordinal if unnamed, forwarded exports
+    /// are left out
+    pub fn exports(&self) -> Result<Vec<(String, usize)>> {
+        let data = self.image()?;
+        let image = PeFile64::parse(&*data)?;
+
+        let mut exports = vec![];
+        for export in export_entries(&image, &data)? {
+            if let ExportTarget::Address(address) = export.target {
+                exports.push((export_name(&export), self.base + address as usize));
+            }
+        }
+        Ok(exports)
+    }
+
+    /// forwarded exports by name, or #ordinal if unnamed, with the library and
+    /// function (name or #ordinal) they are forwarded to
+    pub fn forwards(&self) -> Result<Vec<(String, String, String)>> {
+        let data = self.image()?;
+        let image = PeFile64::parse(&*data)?;
+
+        let mut forwards = vec![];
+        for export in export_entries(&image, &data)? {
+            let (library, function) = match export.target {
+                ExportTarget::Address(_) => continue,
+                ExportTarget::ForwardByName(library, name) => {
+                    (library, String::from_utf8_lossy(name).into_owned())
+                }
+                ExportTarget::ForwardByOrdinal(library, ordinal) => {
+                    (library, format!("#{}", ordinal))
+                }
+            };
+            forwards.push((
+                export_name(&export),
+                String::from_utf8_lossy(library).into_owned(),
+                function,
+            ));
+        }
+        Ok(forwards)
+    }
+
+    /// headers and sections as regions with the protection of their
+    /// characteristics, as the module is mapped by the loader
+    pub fn regions(&self) -> Result<Vec<RegionEntry>> {
+        let data = self.image()?;
+        let image = PeFile64::parse(&*data)?;
+        let section_alignment = image.nt_headers().optional_header().section_alignment() as u64;
+
+        let mut regions = vec![];
+        let headers_size = image.nt_headers().optional_header().size_of_headers() as u64;
+        regions.push(RegionEntry {
+            base: self.base as u64,
+            size: headers_size.next_multiple_of(section_alignment),
+            read: true,
+            write: false,
+            execute: false,
+            kind: RegionKind::Image,
+            owner: self.name.clone(),
+        });
+        for section in image.section_table().iter() {
+            let size = match section.virtual_size.get(LittleEndian) {
+                0 => section.size_of_raw_data.get(LittleEndian),
+                size => size,
+            };
+            let characteristics = section.characteristics.get(LittleEndian);
+            regions.push(RegionEntry {
+                base: self.base as u64 + section.virtual_address.get(LittleEndian) as u64,
+                size: (size as u64).next_multiple_of(section_alignment),
+                read: characteristics & IMAGE_SCN_MEM_READ != 0,
+                write: characteristics & IMAGE_SCN_MEM_WRITE != 0,
+                execute: characteristics & IMAGE_SCN_MEM_EXECUTE != 0,
+                kind: RegionKind::Image,
+                owner: format!(
+                    "{} {}",
+                    self.name,
+                    String::from_utf8_lossy(section.raw_name())
+                ),
+            });
+        }
+        Ok(regions)
+    }
+
     /// searches for an address with the specified name
     pub fn symbol(&self, name: &str) -> Result<Option<usize>> {
-        let mut data = vec![0; self.size];
-        unsafe {
-            ReadProcessMemory(
-                self.process,
-                self.base as *const std::ffi::c_void,
-                data.as_mut_ptr() as *mut _,
-                data.len(),
-                None,
-            )
-            .ok()?;
-        }
-        let image = PeFile64::parse(data.as_slice())?;
+        let data = self.image()?;
+        let image = PeFile64::parse(&*data)?;
 
         match name {
             "entry_point" => Ok(Some(image.entry() as usize)),
@@ -231,14 +383,62 @@ impl Module {
             }
         }
     }
+
+    /// mapped image of the module
+    fn image(&self) -> Result<Cow<'_, [u8]>> {
+        match &self.source {
+            #[cfg(windows)]
+            Source::Process(process) => {
+                let mut data = vec![0; self.size];
+                unsafe {
+                    ReadProcessMemory(
+                        *process,
+                        self.base as *const std::ffi::c_void,
+                        data.as_mut_ptr() as *mut _,
+                        data.len(),
+                        None,
+                    )
+                    .ok()?;
+                }
+                Ok(Cow::Owned(data))
+            }
+            Source::Image(data) => Ok(Cow::Borrowed(data)),
+        }
+    }
+}
+
+/// entries of the export directory of a mapped image, the directory is read at
+/// its relative address
+fn export_entries<'data>(
+    image: &PeFile64<'_>,
+    data: &'data [u8],
+) -> Result<impl Iterator<Item = Export<'data>>> {
+    let mut exports = vec![];
+    if let Some(directory) = image.data_directory(IMAGE_DIRECTORY_ENTRY_EXPORT) {
+        let (address, size) = directory.address_range();
+        if let Some(directory_data) = data.get(address as usize..(address + size) as usize) {
+            exports = ExportTable::parse(directory_data, address)?.exports()?;
+        }
+    }
+    Ok(exports.into_iter())
+}
+
+/// name of an export, or #ordinal if unnamed
+fn export_name(export: &Export) -> String {
+    match export.name {
+        Some(name) => String::from_utf8_lossy(name).into_owned(),
+        None => format!("#{}", export.ordinal),
+    }
 }
 
+#[cfg(windows)]
 #[derive(Copy, Clone)]
 struct ProcessMemoryReadRef {
     process: HANDLE,
     base: *mut std::ffi::c_void,
 }
 
+#[cfg(windows)]
 impl<'a> ReadRef<'a> for ProcessMemoryReadRef {
     fn len(self) -> std::result::Result<u64, ()> {
         todo!()
@@ -1,132 +1,15 @@
-use std::path::Path;
-
-use windows::{
-    core::{HSTRING, PCWSTR, PWSTR},
-    s,
-    Win32::{
-        Foundation::{CloseHandle, FALSE, HANDLE, UNICODE_STRING},
-        System::{
-            Diagnostics::Debug::{ReadProcessMemory, WriteProcessMemory},
-            Kernel::STRING,
-            LibraryLoader::{GetModuleHandleA, GetProcAddress},
-            Memory::{VirtualAllocEx, MEM_COMMIT, MEM_RESERVE, PAGE_READWRITE},
-            Threading::{
-                CreateProcessW, CreateRemoteThread, ResumeThread, TerminateProcess,
-                WaitForSingleObject, CREATE_SUSPENDED, INFINITE, PEB_LDR_DATA,
-                PPS_POST_PROCESS_INIT_ROUTINE, PROCESS_INFORMATION, STARTUPINFOW,
-                THREAD_CREATE_RUN_IMMEDIATELY,
-            },
-        },
-    },
-};
-
-use crate::{module::Module, Result};
-
-pub struct Process {
-    process: HANDLE,
-    thread: HANDLE,
-
-    name: String,
-}
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+pub use self::windows::*;
 
-impl Process {
-    /// spawns a new process
-    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
-        unsafe {
-            let startup_info = STARTUPINFOW::default();
-            let mut process_info = PROCESS_INFORMATION::default();
-            CreateProcessW(
-                &HSTRING::from(path.as_ref()),
-                PWSTR::null(),
-                None,
-                None,
-                FALSE,
-                CREATE_SUSPENDED,
-                None,
-                PCWSTR::null(),
-                &startup_info,
-                &mut process_info,
-            )
-            .ok()?;
-            Ok(Self {
-                process: process_info.hProcess,
-                thread: process_info.hThread,
-                name: path
-                    .as_ref()
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string(),
-            })
-        }
-    }
-
-    /// all known modules
-    pub fn modules(&self) -> Result<Vec<Module>> {
-        Module::all(self.process)
-    }
-
-    /// searches for a module with the specified name, if the name is None the
-    /// image module will be returned
-    pub fn module(&self, name: Option<String>) -> Result<Module> {
-        if let Some(name) = name {
-            return Module::by_name(self.process, name);
-        }
-        return Module::from_peb(self.process);
-    }
-
-    /// loads a library into the process
-    pub fn load_library(&self, path: impl AsRef<Path>) -> Result<()> {
-        let path = HSTRING::from(path.as_ref());
-        let path = path.as_wide();
-        unsafe {
-            let path_address = VirtualAllocEx(
-                self.process,
-                None,
-                std::mem::size_of_val(path),
-                MEM_COMMIT | MEM_RESERVE,
-                PAGE_READWRITE,
-            );
-            WriteProcessMemory(
-                self.process,
-                path_address as *const std::ffi::c_void,
-                path.as_ptr() as *const _,
-                std::mem::size_of_val(path),
-                None,
-            )
-            .ok()?;
-            let load_library_w =
-                GetProcAddress(GetModuleHandleA(s!("kernel32.dll"))?, s!("LoadLibraryW"));
-            let load_library_thread = CreateRemoteThread(
-                self.process,
-                None,
-                0,
-                Some(std::mem::transmute(load_library_w)),
-                Some(path_address),
-                THREAD_CREATE_RUN_IMMEDIATELY.0,
-                None,
-            )?;
-            WaitForSingleObject(load_library_thread, INFINITE).ok()?;
-            Ok(())
-        }
-    }
-
-    pub fn resume(&self) {
-        unsafe {
-            ResumeThread(self.thread);
-        }
-    }
-}
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+pub use self::linux::*;
 
-impl Drop for Process {
-    fn drop(&mut self) {
-        unsafe {
-            TerminateProcess(self.process, 0).ok().unwrap();
-            CloseHandle(self.process);
-        }
-    }
-}
+// layouts of the process structures, defined here instead of taken from the
+// windows crate so that they can be laid out in the emulator on any host
 
 #[repr(C)]
 #[derive(Debug)]
@@ -173,7 +56,7 @@ pub struct PEB {
     pub ProcessStarterHelper: *mut std::ffi::c_void,
     pub GdiDCAttributeList: u32,
     pub LoaderLock: *mut std::ffi::c_void,
-    pub OSSMajorVersion: u32,
+    pub OSMajorVersion: u32,
     pub OSMinorVersion: u32,
     pub OSBuildNumber: u16,
     pub OSCSDVersion: u16,
@@ -183,7 +66,7 @@ pub struct PEB {
     pub ImageSubsystemMinorVersion: u32,
     pub ActiveProcessAffinityMask: u64,
     pub GdiHandleBuffer: [u32; 0x3C],
-    pub PostProcessInitRoutine: PPS_POST_PROCESS_INIT_ROUTINE,
+    pub PostProcessInitRoutine: *mut std::ffi::c_void,
     pub TlsExpansionBitmap: *mut std::ffi::c_void,
     pub TlsExpansionBitmapBits: [u32; 0x20],
     pub SessionId: u32,
@@ -228,7 +111,7 @@ pub struct RTL_USER_PROCESS_PARAMETERS {
     pub LoaderThreads: u32,
     pub RedirectionDllName: UNICODE_STRING,
     pub HeapPartitionName: UNICODE_STRING,
-    pub DefaultThreadpoolCpuSetMasks: *mut core::ffi::c_void,
+    pub DefaultThreadpoolCpuSetMasks: *mut std::ffi::c_void,
     pub DefaultThreadpoolCpuSetMaskCount: u32,
     pub DefaultThreadpoolThreadMaximum: u32,
 }
@@ -250,3 +133,181 @@ pub struct RTL_DRIVE_LETTER_CURDIR {
     pub TimeStamp: u32,
     pub DosPath: STRING,
 }
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_snake_case)]
+pub struct UNICODE_STRING {
+    pub Length: u16,
+    pub MaximumLength: u16,
+    pub Buffer: *mut u16,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_snake_case)]
+pub struct STRING {
+    pub Length: u16,
+    pub MaximumLength: u16,
+    pub Buffer: *mut u8,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_snake_case)]
+pub struct LIST_ENTRY {
+    pub Flink: *mut LIST_ENTRY,
+    pub Blink: *mut LIST_ENTRY,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_snake_case)]
+pub struct PEB_LDR_DATA {
+    pub Length: u32,
+    pub Initialized: u8,
+    pub SsHandle: *mut std::ffi::c_void,
+    pub InLoadOrderModuleList: LIST_ENTRY,
+    pub InMemoryOrderModuleList: LIST_ENTRY,
+    pub InInitializationOrderModuleList: LIST_ENTRY,
+    pub EntryInProgress: *mut std::ffi::c_void,
+    pub ShutdownInProgress: u8,
+    pub ShutdownThreadId: *mut std::ffi::c_void,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_snake_case)]
+pub struct LDR_DATA_TABLE_ENTRY {
+    pub InLoadOrderLinks: LIST_ENTRY,
+    pub InMemoryOrderLinks: LIST_ENTRY,
+    pub InInitializationOrderLinks: LIST_ENTRY,
+    pub DllBase: *mut std::ffi::c_void,
+    pub EntryPoint: *mut std::ffi::c_void,
+    pub SizeOfImage: u32,
+    pub FullDllName: UNICODE_STRING,
+    pub BaseDllName: UNICODE_STRING,
+    pub Flags: u32,
+    pub ObsoleteLoadCount: u16,
+    pub TlsIndex: u16,
+    pub HashLinks: LIST_ENTRY,
+    pub TimeDateStamp: u32,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_snake_case)]
+pub struct NT_TIB {
+    pub ExceptionList: *mut std::ffi::c_void,
+    pub StackBase: *mut std::ffi::c_void,
+    pub StackLimit: *mut std::ffi::c_void,
+    pub SubSystemTib: *mut std::ffi::c_void,
+    pub FiberData: *mut std::ffi::c_void,
+    pub ArbitraryUserPointer: *mut std::ffi::c_void,
+    pub Self_: *mut NT_TIB,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_snake_case)]
+pub struct CLIENT_ID {
+    pub UniqueProcess: *mut std::ffi::c_void,
+    pub UniqueThread: *mut std::ffi::c_void,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_snake_case)]
+pub struct TEB {
+    pub NtTib: NT_TIB,
+    pub EnvironmentPointer: *mut std::ffi::c_void,
+    pub ClientId: CLIENT_ID,
+    pub ActiveRpcHandle: *mut std::ffi::c_void,
+    pub ThreadLocalStoragePointer: *mut std::ffi::c_void,
+    pub ProcessEnvironmentBlock: *mut PEB,
+    pub LastErrorValue: u32,
+    pub Reserved: [u8; 0x1414],
+    pub TlsSlots: [*mut std::ffi::c_void; 0x40],
+    pub TlsLinks: LIST_ENTRY,
+}
+
+// the offsets of the 64-bit windows structures, a field added or resized by
+// mistake has to fail at compile time, the structures end after the last field
+// used
+#[cfg(target_pointer_width = "64")]
+const _: () = {
+    use std::mem::{offset_of, size_of};
+
+    assert!(size_of::<LIST_ENTRY>() == 0x10);
+    assert!(size_of::<UNICODE_STRING>() == 0x10);
+    assert!(offset_of!(UNICODE_STRING, MaximumLength) == 0x2);
+    assert!(offset_of!(UNICODE_STRING, Buffer) == 0x8);
+    assert!(size_of::<STRING>() == 0x10);
+    assert!(size_of::<CURDIR>() == 0x18);
+    assert!(size_of::<RTL_DRIVE_LETTER_CURDIR>() == 0x18);
+
+    assert!(offset_of!(PEB, BeingDebugged) == 0x2);
+    assert!(offset_of!(PEB, ImageBaseAddress) == 0x10);
+    assert!(offset_of!(PEB, Ldr) == 0x18);
+    assert!(offset_of!(PEB, ProcessParameters) == 0x20);
+    assert!(offset_of!(PEB, ProcessHeap) == 0x30);
+    assert!(offset_of!(PEB, KernelCallbackTable) == 0x58);
+    assert!(offset_of!(PEB, ApiSetMap) == 0x68);
+    assert!(offset_of!(PEB, NumberOfProcessors) == 0xB8);
+    assert!(offset_of!(PEB, NtGlobalFlag) == 0xBC);
+    assert!(offset_of!(PEB, ProcessHeaps) == 0xF0);
+    assert!(offset_of!(PEB, LoaderLock) == 0x110);
+    assert!(offset_of!(PEB, OSMajorVersion) == 0x118);
+    assert!(offset_of!(PEB, OSMinorVersion) == 0x11C);
+    assert!(offset_of!(PEB, OSBuildNumber) == 0x120);
+    assert!(offset_of!(PEB, OSPlatformId) == 0x124);
+    assert!(offset_of!(PEB, ImageSubsystem) == 0x128);
+    assert!(offset_of!(PEB, GdiHandleBuffer) == 0x140);
+    assert!(offset_of!(PEB, TlsExpansionBitmapBits) == 0x240);
+    assert!(offset_of!(PEB, SessionId) == 0x2C0);
+    assert!(size_of::<PEB>() == 0x2C8);
+
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, Flags) == 0x8);
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, ConsoleHandle) == 0x10);
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, StandardInput) == 0x20);
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, CurrentDirectory) == 0x38);
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, DllPath) == 0x50);
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, ImagePathName) == 0x60);
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, CommandLine) == 0x70);
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, Environment) == 0x80);
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, WindowTitle) == 0xB0);
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, CurrentDirectories) == 0xF0);
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, EnvironmentSize) == 0x3F0);
+    assert!(offset_of!(RTL_USER_PROCESS_PARAMETERS, RedirectionDllName) == 0x410);
+    assert!(size_of::<RTL_USER_PROCESS_PARAMETERS>() == 0x440);
+
+    assert!(offset_of!(PEB_LDR_DATA, Initialized) == 0x4);
+    assert!(offset_of!(PEB_LDR_DATA, InLoadOrderModuleList) == 0x10);
+    assert!(offset_of!(PEB_LDR_DATA, InMemoryOrderModuleList) == 0x20);
+    assert!(offset_of!(PEB_LDR_DATA, InInitializationOrderModuleList) == 0x30);
+    assert!(offset_of!(PEB_LDR_DATA, ShutdownThreadId) == 0x50);
+    assert!(size_of::<PEB_LDR_DATA>() == 0x58);
+
+    assert!(offset_of!(LDR_DATA_TABLE_ENTRY, InMemoryOrderLinks) == 0x10);
+    assert!(offset_of!(LDR_DATA_TABLE_ENTRY, InInitializationOrderLinks) == 0x20);
+    assert!(offset_of!(LDR_DATA_TABLE_ENTRY, DllBase) == 0x30);
+    assert!(offset_of!(LDR_DATA_TABLE_ENTRY, EntryPoint) == 0x38);
+    assert!(offset_of!(LDR_DATA_TABLE_ENTRY, SizeOfImage) == 0x40);
+    assert!(offset_of!(LDR_DATA_TABLE_ENTRY, FullDllName) == 0x48);
+    assert!(offset_of!(LDR_DATA_TABLE_ENTRY, BaseDllName) == 0x58);
+    assert!(offset_of!(LDR_DATA_TABLE_ENTRY, Flags) == 0x68);
+    assert!(offset_of!(LDR_DATA_TABLE_ENTRY, ObsoleteLoadCount) == 0x6C);
+    assert!(offset_of!(LDR_DATA_TABLE_ENTRY, HashLinks) == 0x70);
+    assert!(offset_of!(LDR_DATA_TABLE_ENTRY, TimeDateStamp) == 0x80);
+
+    assert!(offset_of!(NT_TIB, StackBase) == 0x8);
+    assert!(offset_of!(NT_TIB, StackLimit) == 0x10);
+    assert!(offset_of!(NT_TIB, Self_) == 0x30);
+    assert!(offset_of!(TEB, ClientId) == 0x40);
+    assert!(offset_of!(TEB, ThreadLocalStoragePointer) == 0x58);
+    assert!(offset_of!(TEB, ProcessEnvironmentBlock) == 0x60);
+    assert!(offset_of!(TEB, LastErrorValue) == 0x68);
+    assert!(offset_of!(TEB, TlsSlots) == 0x1480);
+    assert!(offset_of!(TEB, TlsLinks) == 0x1680);
+    assert!(size_of::<TEB>() == 0x1690);
+};
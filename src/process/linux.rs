@@ -0,0 +1,90 @@
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    os::unix::fs::FileExt,
+    path::Path,
+    process::{Child, Command},
+};
+
+use crate::{
+    memory::{Memory, RegionEntry},
+    Result,
+};
+
+pub struct Process {
+    child: Child,
+
+    name: String,
+}
+
+impl Process {
+    /// spawns a new process
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_preload(path, &[] as &[&Path])
+    }
+
+    /// spawns a new process with the libraries loaded before any other library
+    /// using LD_PRELOAD (e.g. mbg_hook)
+    pub fn with_preload(path: impl AsRef<Path>, libraries: &[impl AsRef<Path>]) -> Result<Self> {
+        let mut command = Command::new(path.as_ref());
+        if !libraries.is_empty() {
+            let mut preload = OsString::new();
+            for (i, library) in libraries.iter().enumerate() {
+                if i != 0 {
+                    preload.push(":");
+                }
+                preload.push(library.as_ref());
+            }
+            command.env("LD_PRELOAD", preload);
+        }
+        Ok(Self {
+            child: command.spawn()?,
+            name: path
+                .as_ref()
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+        })
+    }
+
+    /// id of the process
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// name of the executable
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// mapped regions as listed in /proc/<pid>/maps
+    pub fn regions(&self) -> Result<Vec<RegionEntry>> {
+        let maps = std::fs::read_to_string(format!("/proc/{}/maps", self.id()))?;
+        Ok(RegionEntry::parse_maps(&maps))
+    }
+}
+
+impl Memory for Process {
+    fn read(&self, address: usize, data: &mut [u8]) -> Result<()> {
+        let memory = File::open(format!("/proc/{}/mem", self.id()))?;
+        memory.read_exact_at(data, address as u64)?;
+        Ok(())
+    }
+
+    fn write(&mut self, address: usize, data: &[u8]) -> Result<()> {
+        let memory = OpenOptions::new()
+            .write(true)
+            .open(format!("/proc/{}/mem", self.id()))?;
+        memory.write_all_at(data, address as u64)?;
+        Ok(())
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,731 @@
+use std::{
+    io::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use iced_x86::{Decoder, DecoderOptions, InstructionInfoFactory, OpAccess, Register};
+use windows::{
+    core::{HSTRING, PCWSTR, PWSTR},
+    s,
+    Win32::{
+        Foundation::{
+            CloseHandle, DBG_CONTINUE, DBG_EXCEPTION_NOT_HANDLED, EXCEPTION_ACCESS_VIOLATION,
+            EXCEPTION_BREAKPOINT, EXCEPTION_SINGLE_STEP, FALSE, HANDLE, MAX_PATH, NTSTATUS,
+        },
+        System::{
+            Diagnostics::Debug::{
+                ContinueDebugEvent, DebugActiveProcess, DebugActiveProcessStop,
+                DebugSetProcessKillOnExit, GetThreadContext, ReadProcessMemory, SetThreadContext,
+                WaitForDebugEvent, WriteProcessMemory, CONTEXT, CONTEXT_CONTROL_AMD64,
+                CONTEXT_FULL_AMD64, CREATE_PROCESS_DEBUG_EVENT, DEBUG_EVENT, EXCEPTION_DEBUG_EVENT,
+                EXIT_PROCESS_DEBUG_EVENT, EXIT_THREAD_DEBUG_EVENT, LOAD_DLL_DEBUG_EVENT,
+            },
+            LibraryLoader::{GetModuleHandleA, GetProcAddress},
+            Memory::{
+                VirtualAllocEx, VirtualFreeEx, VirtualProtectEx, VirtualQueryEx,
+                MEMORY_BASIC_INFORMATION, MEM_COMMIT, MEM_MAPPED, MEM_RELEASE, MEM_RESERVE,
+                PAGE_EXECUTE_READWRITE, PAGE_PROTECTION_FLAGS, PAGE_READWRITE,
+            },
+            ProcessStatus::GetMappedFileNameW,
+            Threading::{
+                CreateProcessW, CreateRemoteThread, GetProcessId, GetThreadId, IsWow64Process,
+                ResumeThread, SuspendThread, TerminateProcess, WaitForSingleObject,
+                CREATE_SUSPENDED, INFINITE, PROCESS_INFORMATION, STARTUPINFOW,
+                THREAD_CREATE_RUN_IMMEDIATELY,
+            },
+        },
+    },
+};
+
+use crate::{
+    call,
+    call::{Argument, CallingConvention},
+    memory::{self, Memory, RegionEntry, RegionKind},
+    module::Module,
+    trace::{self, context_registers, MemoryAccess, Registers, TraceWriter},
+    unpack, Error, Result,
+};
+
+/// single-step flag of rflags
+const TRAP_FLAG: u32 = 0x100;
+
+/// access violation information of a data execution prevention fault
+const EXECUTE_FAULT: usize = 8;
+
+/// protection of a page without execute access, reads and writes stay allowed
+fn non_executable(protection: PAGE_PROTECTION_FLAGS) -> PAGE_PROTECTION_FLAGS {
+    let access = match protection.0 & 0xFF {
+        0x10 | 0x20 => 0x02,
+        0x40 => 0x04,
+        0x80 => 0x08,
+        access => access,
+    };
+    PAGE_PROTECTION_FLAGS(protection.0 & !0xFF | access)
+}
+
+/// continue status of a debug event the debugger doesn't handle, exceptions
+/// are passed to the process
+fn passed(event: &DEBUG_EVENT) -> NTSTATUS {
+    if event.dwDebugEventCode == EXCEPTION_DEBUG_EVENT {
+        DBG_EXCEPTION_NOT_HANDLED
+    } else {
+        DBG_CONTINUE
+    }
+}
+
+pub struct Process {
+    process: HANDLE,
+    thread: HANDLE,
+
+    name: String,
+}
+
+impl Process {
+    /// spawns a new process
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        unsafe {
+            let startup_info = STARTUPINFOW::default();
+            let mut process_info = PROCESS_INFORMATION::default();
+            CreateProcessW(
+                &HSTRING::from(path.as_ref()),
+                PWSTR::null(),
+                None,
+                None,
+                FALSE,
+                CREATE_SUSPENDED,
+                None,
+                PCWSTR::null(),
+                &startup_info,
+                &mut process_info,
+            )
+            .ok()?;
+            Ok(Self {
+                process: process_info.hProcess,
+                thread: process_info.hThread,
+                name: path
+                    .as_ref()
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            })
+        }
+    }
+
+    /// all known modules
+    pub fn modules(&self) -> Result<Vec<Module>> {
+        Module::all(self.process)
+    }
+
+    /// searches for a module with the specified name, if the name is None the
+    /// image module will be returned
+    pub fn module(&self, name: Option<String>) -> Result<Module> {
+        if let Some(name) = name {
+            return Module::by_name(self.process, name);
+        }
+        return Module::from_peb(self.process);
+    }
+
+    /// committed regions, the ones of modules are split at their sections
+    pub fn regions(&self) -> Result<Vec<RegionEntry>> {
+        let mut regions = vec![];
+        let mut address = 0;
+        let mut info = MEMORY_BASIC_INFORMATION::default();
+        unsafe {
+            while VirtualQueryEx(
+                self.process,
+                Some(address as *const std::ffi::c_void),
+                &mut info,
+                std::mem::size_of::<MEMORY_BASIC_INFORMATION>(),
+            ) != 0
+            {
+                let base = info.BaseAddress as u64;
+                let size = info.RegionSize as u64;
+                address = base + size;
+                if info.State != MEM_COMMIT {
+                    continue;
+                }
+                let (read, write, execute) = match info.Protect.0 & 0xFF {
+                    0x02 => (true, false, false),
+                    0x04 | 0x08 => (true, true, false),
+                    0x10 => (false, false, true),
+                    0x20 => (true, false, true),
+                    0x40 | 0x80 => (true, true, true),
+                    _ => (false, false, false),
+                };
+                let (kind, owner) = if info.Type == MEM_MAPPED {
+                    let mut name = [0; MAX_PATH as usize];
+                    let length = GetMappedFileNameW(self.process, info.BaseAddress, &mut name);
+                    (
+                        RegionKind::Mapped,
+                        String::from_utf16_lossy(&name[..length as usize]),
+                    )
+                } else {
+                    (RegionKind::Private, String::new())
+                };
+                regions.push(RegionEntry {
+                    base,
+                    size,
+                    read,
+                    write,
+                    execute,
+                    kind,
+                    owner,
+                });
+            }
+        }
+        memory::label_regions(regions, &self.modules()?)
+    }
+
+    /// loads a library into the process
+    pub fn load_library(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = HSTRING::from(path.as_ref());
+        unsafe {
+            let load_library_w =
+                GetProcAddress(GetModuleHandleA(s!("kernel32.dll"))?, s!("LoadLibraryW"))
+                    .ok_or_else(windows::core::Error::from_win32)?;
+            self.call(
+                load_library_w as usize,
+                CallingConvention::Win64,
+                &mut [Argument::WideString(path.as_wide().to_vec())],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// bitness of the process, 32 for WOW64 processes
+    pub fn bitness(&self) -> Result<u32> {
+        let mut wow64 = FALSE;
+        unsafe {
+            IsWow64Process(self.process, &mut wow64).ok()?;
+        }
+        Ok(if wow64.as_bool() { 32 } else { 64 })
+    }
+
+    /// calls a function in the process on a new thread and returns its return
+    /// value, buffer arguments are updated with their content after the call,
+    /// the convention has to match the bitness of the process
+    pub fn call(
+        &self,
+        function: usize,
+        convention: CallingConvention,
+        arguments: &mut [Argument],
+    ) -> Result<u64> {
+        if convention.bitness() != self.bitness()? {
+            return Err(Error::Unsupported("calling convention of another bitness"));
+        }
+        // lay out the return value followed by the argument data
+        let mut data = vec![0u8; 8];
+        let mut offsets = vec![];
+        for argument in arguments.iter() {
+            offsets.push(argument.data().map(|argument_data| {
+                let offset = data.len();
+                data.extend_from_slice(&argument_data);
+                data.resize(data.len().next_multiple_of(8), 0);
+                offset
+            }));
+        }
+        unsafe {
+            let data_allocation = self.allocate(data.len(), PAGE_READWRITE)?;
+            let data_address = data_allocation.address;
+            WriteProcessMemory(
+                self.process,
+                data_address as *const std::ffi::c_void,
+                data.as_ptr() as *const _,
+                data.len(),
+                None,
+            )
+            .ok()?;
+            let resolved_arguments = arguments
+                .iter()
+                .zip(&offsets)
+                .map(|(argument, offset)| match (argument, offset) {
+                    (_, Some(offset)) => (data_address + offset) as u64,
+                    (Argument::Integer(value), None) => *value,
+                    (Argument::Pointer(value), None) => *value as u64,
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>();
+
+            // the code doesn't reference its own address, therefore it is
+            // assembled with the final operands before being allocated
+            let code = call::assemble(
+                convention,
+                function as u64,
+                &resolved_arguments,
+                data_address as u64,
+                0,
+            )?;
+            let code_allocation = self.allocate(code.len(), PAGE_EXECUTE_READWRITE)?;
+            WriteProcessMemory(
+                self.process,
+                code_allocation.address as *const std::ffi::c_void,
+                code.as_ptr() as *const _,
+                code.len(),
+                None,
+            )
+            .ok()?;
+
+            let thread = CreateRemoteThread(
+                self.process,
+                None,
+                0,
+                Some(std::mem::transmute(code_allocation.address)),
+                None,
+                THREAD_CREATE_RUN_IMMEDIATELY.0,
+                None,
+            )?;
+            let wait = WaitForSingleObject(thread, INFINITE).ok();
+            CloseHandle(thread);
+            if let Err(error) = wait {
+                // the thread might still use the memory
+                std::mem::forget(data_allocation);
+                std::mem::forget(code_allocation);
+                return Err(error.into());
+            }
+
+            // read back the return value and buffers
+            ReadProcessMemory(
+                self.process,
+                data_address as *const std::ffi::c_void,
+                data.as_mut_ptr() as *mut _,
+                data.len(),
+                None,
+            )
+            .ok()?;
+            for (argument, offset) in arguments.iter_mut().zip(offsets) {
+                if let (Argument::Buffer(buffer), Some(offset)) = (argument, offset) {
+                    let buffer_length = buffer.len();
+                    buffer.copy_from_slice(&data[offset..offset + buffer_length]);
+                }
+            }
+        }
+        Ok(u64::from_le_bytes(data[..8].try_into().unwrap()))
+    }
+
+    unsafe fn allocate(
+        &self,
+        size: usize,
+        protection: PAGE_PROTECTION_FLAGS,
+    ) -> Result<Allocation> {
+        let address = VirtualAllocEx(
+            self.process,
+            None,
+            size,
+            MEM_COMMIT | MEM_RESERVE,
+            protection,
+        );
+        if address.is_null() {
+            return Err(windows::core::Error::from_win32().into());
+        }
+        Ok(Allocation {
+            process: self.process,
+            address: address as usize,
+        })
+    }
+
+    pub fn resume(&self) {
+        unsafe {
+            ResumeThread(self.thread);
+        }
+    }
+
+    /// resumes the process as a debugger and stops the main thread at the
+    /// first execution of a page of the image which changed since (the
+    /// unpacked code), the executable pages are made non-executable to catch
+    /// the execute faults and the page executed last stays executable while
+    /// it is unchanged, pages the process protects itself aren't watched, the
+    /// thread is left suspended at the original entry point, returns None on
+    /// timeout or exit with the process running
+    pub fn run_to_original_entry_point(&self, timeout: Duration) -> Result<Option<usize>> {
+        let module = self.module(None)?;
+        let base = module.base();
+        let original = unpack::read_image(self, base)?;
+        let pages = self.executable_pages(base, original.len());
+        let thread_id = unsafe { GetThreadId(self.thread) };
+        let restore = || {
+            for &(page, protection) in &pages {
+                let _ = self.protect(page, protection);
+            }
+        };
+        // the watched page executed last
+        let mut executed = None;
+        let mut entry_point = None;
+        let result = unsafe {
+            self.debug(Some(timeout), |event| match event.dwDebugEventCode {
+                CREATE_PROCESS_DEBUG_EVENT => {
+                    for &(page, protection) in &pages {
+                        self.protect(page, non_executable(protection))?;
+                    }
+                    self.resume();
+                    Ok((DBG_CONTINUE, false))
+                }
+                EXCEPTION_DEBUG_EVENT => {
+                    let record = &event.u.Exception.ExceptionRecord;
+                    let address = record.ExceptionInformation[1];
+                    let watched = pages
+                        .iter()
+                        .find(|(page, _)| (*page..page + 0x1000).contains(&address))
+                        .filter(|_| {
+                            record.ExceptionCode == EXCEPTION_ACCESS_VIOLATION
+                                && record.ExceptionInformation[0] == EXECUTE_FAULT
+                        });
+                    let Some(&(page, protection)) = watched else {
+                        return Ok((passed(event), false));
+                    };
+                    let offset = page - base;
+                    let original_page = &original[offset..original.len().min(offset + 0x1000)];
+                    let mut data = vec![0; original_page.len()];
+                    self.read(page, &mut data)?;
+                    if event.dwThreadId == thread_id && data != original_page {
+                        // the faulting instruction is executed again once resumed
+                        SuspendThread(self.thread);
+                        restore();
+                        entry_point = Some(address);
+                        return Ok((DBG_CONTINUE, true));
+                    }
+                    if let Some((page, protection)) = executed.replace((page, protection)) {
+                        self.protect(page, non_executable(protection))?;
+                    }
+                    self.protect(page, protection)?;
+                    Ok((DBG_CONTINUE, false))
+                }
+                EXIT_PROCESS_DEBUG_EVENT => Ok((DBG_CONTINUE, true)),
+                _ => Ok((DBG_CONTINUE, false)),
+            })
+        };
+        if entry_point.is_none() {
+            restore();
+        }
+        result.map(|_| entry_point)
+    }
+
+    /// committed executable pages of a range with their protection
+    fn executable_pages(&self, address: usize, size: usize) -> Vec<(usize, PAGE_PROTECTION_FLAGS)> {
+        let mut pages = vec![];
+        let mut page = address;
+        let mut info = MEMORY_BASIC_INFORMATION::default();
+        unsafe {
+            while page < address + size
+                && VirtualQueryEx(
+                    self.process,
+                    Some(page as *const std::ffi::c_void),
+                    &mut info,
+                    std::mem::size_of::<MEMORY_BASIC_INFORMATION>(),
+                ) != 0
+            {
+                let end = (info.BaseAddress as usize + info.RegionSize).min(address + size);
+                if info.State == MEM_COMMIT && info.Protect.0 & 0xF0 != 0 {
+                    pages.extend((page..end).step_by(0x1000).map(|page| (page, info.Protect)));
+                }
+                page = end;
+            }
+        }
+        pages
+    }
+
+    fn protect(&self, page: usize, protection: PAGE_PROTECTION_FLAGS) -> Result<()> {
+        let mut old = PAGE_PROTECTION_FLAGS::default();
+        unsafe {
+            VirtualProtectEx(
+                self.process,
+                page as *const std::ffi::c_void,
+                0x1000,
+                protection,
+                &mut old,
+            )
+            .ok()?;
+        }
+        Ok(())
+    }
+
+    /// single-steps the suspended main thread (e.g. after [Process::new] or
+    /// [Process::run_to_original_entry_point]) as a debugger and records each
+    /// instruction, memory operands with fs or gs segment aren't recorded as
+    /// the segment bases are unknown, stops after the limit of steps, at the
+    /// first exception of the thread (which is raised once it is resumed) or
+    /// when it exits, the thread is left suspended
+    pub fn run_traced(
+        &self,
+        limit: Option<u64>,
+        trace: &mut TraceWriter<impl Write>,
+    ) -> Result<()> {
+        let thread_id = unsafe { GetThreadId(self.thread) };
+        let mut count = 0;
+        let mut resumed = false;
+        // registers before and accesses of the stepped instruction
+        let mut step: Option<(Registers, Vec<MemoryAccess>)> = None;
+        unsafe {
+            self.debug(None, |event| {
+                let result = (|| -> Result<(NTSTATUS, bool)> {
+                    match event.dwDebugEventCode {
+                        // the first event of the attach, the thread starts
+                        // stepping once the event is continued
+                        CREATE_PROCESS_DEBUG_EVENT => {
+                            step = Some(self.prepare_step()?);
+                            ResumeThread(self.thread);
+                            resumed = true;
+                            Ok((DBG_CONTINUE, false))
+                        }
+                        _ if event.dwThreadId != thread_id => Ok((passed(event), false)),
+                        EXCEPTION_DEBUG_EVENT
+                            if event.u.Exception.ExceptionRecord.ExceptionCode
+                                == EXCEPTION_SINGLE_STEP =>
+                        {
+                            if let Some((registers, mut accesses)) = step.take() {
+                                // writes are read once the instruction executed
+                                accesses.retain_mut(|access| {
+                                    !access.write
+                                        || self
+                                            .read(access.address as usize, &mut access.data)
+                                            .is_ok()
+                                });
+                                trace.record(thread_id, &registers, &accesses)?;
+                                count += 1;
+                            }
+                            if limit.is_some_and(|limit| count >= limit) {
+                                SuspendThread(self.thread);
+                                return Ok((DBG_CONTINUE, true));
+                            }
+                            step = Some(self.prepare_step()?);
+                            Ok((DBG_CONTINUE, false))
+                        }
+                        EXCEPTION_DEBUG_EVENT => {
+                            SuspendThread(self.thread);
+                            Ok((DBG_EXCEPTION_NOT_HANDLED, true))
+                        }
+                        EXIT_THREAD_DEBUG_EVENT | EXIT_PROCESS_DEBUG_EVENT => {
+                            Ok((DBG_CONTINUE, true))
+                        }
+                        _ => Ok((passed(event), false)),
+                    }
+                })();
+                // the thread must not run on with the trap flag set
+                if result.is_err() && resumed {
+                    SuspendThread(self.thread);
+                    let _ = self.set_trap_flag(false);
+                }
+                result
+            })?;
+        }
+        Ok(())
+    }
+
+    /// sets the trap flag of the main thread and captures the registers and
+    /// memory reads of its next instruction, written memory is captured with
+    /// zeroed data
+    unsafe fn prepare_step(&self) -> Result<(Registers, Vec<MemoryAccess>)> {
+        let mut context = CONTEXT {
+            ContextFlags: CONTEXT_FULL_AMD64,
+            ..Default::default()
+        };
+        GetThreadContext(self.thread, &mut context).ok()?;
+        let registers = context_registers(&context);
+        context.EFlags |= TRAP_FLAG;
+        SetThreadContext(self.thread, &context).ok()?;
+        Ok((registers, self.accesses(&registers)))
+    }
+
+    unsafe fn set_trap_flag(&self, value: bool) -> Result<()> {
+        let mut context = CONTEXT {
+            ContextFlags: CONTEXT_CONTROL_AMD64,
+            ..Default::default()
+        };
+        GetThreadContext(self.thread, &mut context).ok()?;
+        if value {
+            context.EFlags |= TRAP_FLAG;
+        } else {
+            context.EFlags &= !TRAP_FLAG;
+        }
+        SetThreadContext(self.thread, &context).ok()?;
+        Ok(())
+    }
+
+    /// memory operands of the instruction at rip, reads come first
+    fn accesses(&self, registers: &Registers) -> Vec<MemoryAccess> {
+        let rip = registers[trace::RIP];
+        let mut code = [0; 15];
+        // the instruction can end right before an unreadable page
+        let length = code.len().min(0x1000 - (rip as usize & 0xFFF));
+        if self.read(rip as usize, &mut code).is_err()
+            && self.read(rip as usize, &mut code[..length]).is_err()
+        {
+            return vec![];
+        }
+        let instruction = Decoder::with_ip(64, &code, rip, DecoderOptions::NONE).decode();
+        let mut info_factory = InstructionInfoFactory::new();
+        let mut reads = vec![];
+        let mut writes = vec![];
+        for memory in info_factory.info(&instruction).used_memory() {
+            let size = memory.memory_size().size();
+            let address = match memory.segment() {
+                Register::FS | Register::GS => None,
+                _ => memory.virtual_address(0, |register, _, _| match register {
+                    Register::ES | Register::CS | Register::SS | Register::DS => Some(0),
+                    Register::RIP => Some(rip),
+                    register if register.is_gpr64() => Some(registers[register.number()]),
+                    register if register.is_gpr32() => {
+                        Some(registers[register.number()] as u32 as u64)
+                    }
+                    _ => None,
+                }),
+            };
+            let Some(address) = address.filter(|_| size != 0) else {
+                continue;
+            };
+            let (read, write) = match memory.access() {
+                OpAccess::Read | OpAccess::CondRead => (true, false),
+                OpAccess::Write | OpAccess::CondWrite => (false, true),
+                OpAccess::ReadWrite | OpAccess::ReadCondWrite => (true, true),
+                _ => (false, false),
+            };
+            let mut data = vec![0; size];
+            if read && self.read(address as usize, &mut data).is_ok() {
+                reads.push(MemoryAccess {
+                    address,
+                    data: data.clone(),
+                    write: false,
+                });
+            }
+            if write {
+                writes.push(MemoryAccess {
+                    address,
+                    data,
+                    write: true,
+                });
+            }
+        }
+        reads.extend(writes);
+        reads
+    }
+
+    /// attaches as debugger and passes the events but the breakpoint of the
+    /// attach to the handler, which returns how to continue the event and
+    /// whether to detach afterwards, returns false if it timed out before, the
+    /// process keeps running when detached
+    unsafe fn debug(
+        &self,
+        timeout: Option<Duration>,
+        mut handler: impl FnMut(&DEBUG_EVENT) -> Result<(NTSTATUS, bool)>,
+    ) -> Result<bool> {
+        let process_id = GetProcessId(self.process);
+        DebugActiveProcess(process_id).ok()?;
+        DebugSetProcessKillOnExit(FALSE);
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut attached = false;
+        let result = (|| -> Result<bool> {
+            loop {
+                let mut event = DEBUG_EVENT::default();
+                let wait = deadline.map_or(INFINITE, |deadline| {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    u32::try_from(remaining.as_millis()).unwrap_or(INFINITE - 1)
+                });
+                if let Err(error) = WaitForDebugEvent(&mut event, wait).ok() {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Ok(false);
+                    }
+                    return Err(error.into());
+                }
+                match event.dwDebugEventCode {
+                    CREATE_PROCESS_DEBUG_EVENT => {
+                        CloseHandle(event.u.CreateProcessInfo.hFile);
+                    }
+                    LOAD_DLL_DEBUG_EVENT => {
+                        CloseHandle(event.u.LoadDll.hFile);
+                    }
+                    EXCEPTION_DEBUG_EVENT
+                        if !attached
+                            && event.u.Exception.ExceptionRecord.ExceptionCode
+                                == EXCEPTION_BREAKPOINT =>
+                    {
+                        attached = true;
+                        ContinueDebugEvent(event.dwProcessId, event.dwThreadId, DBG_CONTINUE)
+                            .ok()?;
+                        continue;
+                    }
+                    _ => {}
+                }
+                let (status, detach) = match handler(&event) {
+                    Ok(result) => result,
+                    Err(error) => {
+                        ContinueDebugEvent(event.dwProcessId, event.dwThreadId, DBG_CONTINUE);
+                        return Err(error);
+                    }
+                };
+                ContinueDebugEvent(event.dwProcessId, event.dwThreadId, status).ok()?;
+                if detach {
+                    return Ok(true);
+                }
+            }
+        })();
+        DebugActiveProcessStop(process_id);
+        result
+    }
+
+    /// dumps the image as a PE file with the specified entry point (see
+    /// [unpack::rebuild]), slots pointing to exports of the loaded modules are
+    /// rebuilt as imports
+    pub fn dump(&self, entry_point: usize) -> Result<Vec<u8>> {
+        let base = self.module(None)?.base();
+        let data = unpack::read_image(self, base)?;
+        let modules = self.modules()?;
+        let others = modules.iter().filter(|module| module.base() != base);
+        let imports = unpack::Exports::from_modules(others)?.find_imports(&data, base);
+        unpack::rebuild(data, base, entry_point, &imports)
+    }
+}
+
+/// memory allocated in the process, released when dropped
+struct Allocation {
+    process: HANDLE,
+    address: usize,
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        unsafe {
+            VirtualFreeEx(self.process, self.address as *mut _, 0, MEM_RELEASE);
+        }
+    }
+}
+
+impl Memory for Process {
+    fn read(&self, address: usize, data: &mut [u8]) -> Result<()> {
+        unsafe {
+            ReadProcessMemory(
+                self.process,
+                address as *const std::ffi::c_void,
+                data.as_mut_ptr() as *mut _,
+                data.len(),
+                None,
+            )
+            .ok()?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, address: usize, data: &[u8]) -> Result<()> {
+        unsafe {
+            WriteProcessMemory(
+                self.process,
+                address as *const std::ffi::c_void,
+                data.as_ptr() as *const _,
+                data.len(),
+                None,
+            )
+            .ok()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        unsafe {
+            TerminateProcess(self.process, 0).ok().unwrap();
+            CloseHandle(self.process);
+        }
+    }
+}
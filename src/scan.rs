@@ -0,0 +1,531 @@
+use std::cmp::Ordering;
+
+use crate::{
+    memory::{Memory, RegionEntry},
+    Error, Result,
+};
+
+mod pointer;
+mod signature;
+
+pub use self::{pointer::*, signature::*};
+
+/// bytes read at once while scanning regions
+const CHUNK_SIZE: usize = 0x10_0000;
+
+/// bytes read at once while rescanning hits, hits closer than this share a
+/// read
+const WINDOW_SIZE: usize = 0x1_0000;
+
+/// type of the scanned values, integers and floats are little-endian
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ValueType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    /// byte array (or string) of the specified length
+    Bytes(usize),
+}
+
+impl ValueType {
+    /// size of a value in bytes
+    pub fn size(self) -> usize {
+        match self {
+            ValueType::I8 | ValueType::U8 => 1,
+            ValueType::I16 | ValueType::U16 => 2,
+            ValueType::I32 | ValueType::U32 | ValueType::F32 => 4,
+            ValueType::I64 | ValueType::U64 | ValueType::F64 => 8,
+            ValueType::Bytes(size) => size,
+        }
+    }
+}
+
+/// scanned value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    /// string as utf-8 or utf-16 bytes without terminator
+    pub fn string(value: &str, wide: bool) -> Self {
+        if wide {
+            Value::Bytes(value.encode_utf16().flat_map(u16::to_le_bytes).collect())
+        } else {
+            Value::Bytes(value.as_bytes().to_vec())
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::I8(_) => ValueType::I8,
+            Value::I16(_) => ValueType::I16,
+            Value::I32(_) => ValueType::I32,
+            Value::I64(_) => ValueType::I64,
+            Value::U8(_) => ValueType::U8,
+            Value::U16(_) => ValueType::U16,
+            Value::U32(_) => ValueType::U32,
+            Value::U64(_) => ValueType::U64,
+            Value::F32(_) => ValueType::F32,
+            Value::F64(_) => ValueType::F64,
+            Value::Bytes(value) => ValueType::Bytes(value.len()),
+        }
+    }
+
+    /// value of the type stored in data, which has to be of its size
+    pub fn decode(value_type: ValueType, data: &[u8]) -> Self {
+        match value_type {
+            ValueType::I8 => Value::I8(data[0] as i8),
+            ValueType::I16 => Value::I16(i16::from_le_bytes(data.try_into().unwrap())),
+            ValueType::I32 => Value::I32(i32::from_le_bytes(data.try_into().unwrap())),
+            ValueType::I64 => Value::I64(i64::from_le_bytes(data.try_into().unwrap())),
+            ValueType::U8 => Value::U8(data[0]),
+            ValueType::U16 => Value::U16(u16::from_le_bytes(data.try_into().unwrap())),
+            ValueType::U32 => Value::U32(u32::from_le_bytes(data.try_into().unwrap())),
+            ValueType::U64 => Value::U64(u64::from_le_bytes(data.try_into().unwrap())),
+            ValueType::F32 => Value::F32(f32::from_le_bytes(data.try_into().unwrap())),
+            ValueType::F64 => Value::F64(f64::from_le_bytes(data.try_into().unwrap())),
+            ValueType::Bytes(_) => Value::Bytes(data.to_vec()),
+        }
+    }
+
+    /// bytes as stored in memory
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Value::I8(value) => value.to_le_bytes().to_vec(),
+            Value::I16(value) => value.to_le_bytes().to_vec(),
+            Value::I32(value) => value.to_le_bytes().to_vec(),
+            Value::I64(value) => value.to_le_bytes().to_vec(),
+            Value::U8(value) => value.to_le_bytes().to_vec(),
+            Value::U16(value) => value.to_le_bytes().to_vec(),
+            Value::U32(value) => value.to_le_bytes().to_vec(),
+            Value::U64(value) => value.to_le_bytes().to_vec(),
+            Value::F32(value) => value.to_le_bytes().to_vec(),
+            Value::F64(value) => value.to_le_bytes().to_vec(),
+            Value::Bytes(value) => value.clone(),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    /// numeric order of values of the same type, byte arrays are unordered
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::I8(a), Value::I8(b)) => a.partial_cmp(b),
+            (Value::I16(a), Value::I16(b)) => a.partial_cmp(b),
+            (Value::I32(a), Value::I32(b)) => a.partial_cmp(b),
+            (Value::I64(a), Value::I64(b)) => a.partial_cmp(b),
+            (Value::U8(a), Value::U8(b)) => a.partial_cmp(b),
+            (Value::U16(a), Value::U16(b)) => a.partial_cmp(b),
+            (Value::U32(a), Value::U32(b)) => a.partial_cmp(b),
+            (Value::U64(a), Value::U64(b)) => a.partial_cmp(b),
+            (Value::F32(a), Value::F32(b)) => a.partial_cmp(b),
+            (Value::F64(a), Value::F64(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+/// condition a value has to meet to be kept
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// any value, the first scan takes a snapshot to compare later scans with
+    /// (unknown initial value)
+    Any,
+    /// same bytes as the value
+    Exact(Value),
+    /// within the inclusive range
+    Range(Value, Value),
+    /// differs from the previous scan
+    Changed,
+    /// same as in the previous scan
+    Unchanged,
+    /// greater than in the previous scan
+    Increased,
+    /// less than in the previous scan
+    Decreased,
+}
+
+impl Filter {
+    fn check(&self, value_type: ValueType) -> Result<()> {
+        match self {
+            Filter::Exact(value) if value.value_type() != value_type => {
+                Err(Error::Unsupported("value of another type"))
+            }
+            Filter::Range(low, high)
+                if low.value_type() != value_type
+                    || high.value_type() != value_type
+                    || matches!(value_type, ValueType::Bytes(_)) =>
+            {
+                Err(Error::Unsupported("range of another type"))
+            }
+            Filter::Increased | Filter::Decreased if matches!(value_type, ValueType::Bytes(_)) => {
+                Err(Error::Unsupported("byte arrays are unordered"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// whether the current value is kept, previous is None for the first scan
+    fn matches(&self, value_type: ValueType, current: &[u8], previous: Option<&[u8]>) -> bool {
+        let decode = |data| Value::decode(value_type, data);
+        match (self, previous) {
+            (Filter::Any, _) => true,
+            (Filter::Exact(value), _) => match value {
+                Value::Bytes(value) => value == current,
+                // floats are compared by value (e.g. 0.0 and -0.0)
+                Value::F32(_) | Value::F64(_) => decode(current) == *value,
+                value => value.encode() == current,
+            },
+            (Filter::Range(low, high), _) => {
+                let current = decode(current);
+                *low <= current && current <= *high
+            }
+            (_, None) => false,
+            (Filter::Changed, Some(previous)) => current != previous,
+            (Filter::Unchanged, Some(previous)) => current == previous,
+            (Filter::Increased, Some(previous)) => decode(current) > decode(previous),
+            (Filter::Decreased, Some(previous)) => decode(current) < decode(previous),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+enum State {
+    /// no scan yet
+    #[default]
+    Initial,
+    /// copy of the regions taken by a first scan without value
+    Snapshot(Vec<(u64, Vec<u8>)>),
+    /// addresses in ascending order and their values, value after value
+    Hits {
+        addresses: Vec<u64>,
+        values: Vec<u8>,
+    },
+}
+
+/// finds values in memory and narrows them down over successive scans (e.g.
+/// scan for 100, let it change to 90, scan for decreased values)
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    value_type: ValueType,
+    /// addresses are multiples of it
+    alignment: u64,
+    state: State,
+}
+
+impl Scanner {
+    pub fn new(value_type: ValueType, alignment: u64) -> Self {
+        Self {
+            value_type,
+            alignment: alignment.max(1),
+            state: State::Initial,
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
+
+    /// scans the readable regions, previous results are discarded, filters
+    /// comparing with the previous scan match nothing, returns the number of
+    /// hits, which is 0 for [Filter::Any] as a snapshot is taken instead
+    pub fn first_scan(
+        &mut self,
+        memory: &impl Memory,
+        regions: &[RegionEntry],
+        filter: &Filter,
+    ) -> Result<usize> {
+        filter.check(self.value_type)?;
+        let size = self.value_type.size();
+        if size == 0 {
+            return Err(Error::Unsupported("empty value"));
+        }
+
+        if *filter == Filter::Any {
+            let mut snapshot = vec![];
+            for region in regions.iter().filter(|region| region.read) {
+                for (address, data) in read_region(memory, region, size - 1) {
+                    snapshot.push((address, data));
+                }
+            }
+            self.state = State::Snapshot(snapshot);
+            return Ok(0);
+        }
+
+        let mut addresses = vec![];
+        let mut values = vec![];
+        for region in regions.iter().filter(|region| region.read) {
+            for (address, data) in read_region(memory, region, size - 1) {
+                self.scan_data(address, &data, None, filter, &mut addresses, &mut values);
+            }
+        }
+        self.state = State::Hits { addresses, values };
+        Ok(self.count())
+    }
+
+    /// rescans the hits (or the snapshot) of the previous scan, returns the
+    /// number of remaining hits
+    pub fn next_scan(&mut self, memory: &impl Memory, filter: &Filter) -> Result<usize> {
+        filter.check(self.value_type)?;
+        let size = self.value_type.size();
+        let mut next_addresses = vec![];
+        let mut next_values = vec![];
+        match std::mem::take(&mut self.state) {
+            State::Initial => return Err(Error::Unsupported("no previous scan")),
+            State::Snapshot(snapshot) => {
+                for (address, previous) in snapshot {
+                    let mut data = vec![0; previous.len()];
+                    if memory.read(address as usize, &mut data).is_err() {
+                        continue;
+                    }
+                    self.scan_data(
+                        address,
+                        &data,
+                        Some(&previous),
+                        filter,
+                        &mut next_addresses,
+                        &mut next_values,
+                    );
+                }
+            }
+            State::Hits { addresses, values } => {
+                // hits close to each other are read at once
+                let mut i = 0;
+                while i < addresses.len() {
+                    let start = addresses[i];
+                    let count = addresses[i..]
+                        .iter()
+                        .take_while(|&&address| address + size as u64 - start <= WINDOW_SIZE as u64)
+                        .count();
+                    let end = addresses[i + count - 1] + size as u64;
+                    let mut window = vec![0; (end - start) as usize];
+                    let window_read = memory.read(start as usize, &mut window).is_ok();
+                    for j in i..i + count {
+                        let address = addresses[j];
+                        let previous = &values[j * size..(j + 1) * size];
+                        let mut single = vec![0; size];
+                        let current = if window_read {
+                            let offset = (address - start) as usize;
+                            &window[offset..offset + size]
+                        } else if memory.read(address as usize, &mut single).is_ok() {
+                            &single
+                        } else {
+                            continue;
+                        };
+                        if filter.matches(self.value_type, current, Some(previous)) {
+                            next_addresses.push(address);
+                            next_values.extend_from_slice(current);
+                        }
+                    }
+                    i += count;
+                }
+            }
+        }
+        self.state = State::Hits {
+            addresses: next_addresses,
+            values: next_values,
+        };
+        Ok(self.count())
+    }
+
+    /// number of hits of the last scan
+    pub fn count(&self) -> usize {
+        match &self.state {
+            State::Hits { addresses, .. } => addresses.len(),
+            _ => 0,
+        }
+    }
+
+    /// hits with their value at the last scan in ascending order
+    pub fn hits(&self) -> impl Iterator<Item = (u64, Value)> + '_ {
+        let (addresses, values): (&[u64], &[u8]) = match &self.state {
+            State::Hits { addresses, values } => (addresses, values),
+            _ => (&[], &[]),
+        };
+        let size = self.value_type.size();
+        addresses.iter().enumerate().map(move |(i, &address)| {
+            let value = Value::decode(self.value_type, &values[i * size..(i + 1) * size]);
+            (address, value)
+        })
+    }
+
+    /// discards all results
+    pub fn reset(&mut self) {
+        self.state = State::Initial;
+    }
+
+    /// appends the matching values of data read at address
+    fn scan_data(
+        &self,
+        address: u64,
+        data: &[u8],
+        previous: Option<&[u8]>,
+        filter: &Filter,
+        addresses: &mut Vec<u64>,
+        values: &mut Vec<u8>,
+    ) {
+        let size = self.value_type.size();
+        if data.len() < size {
+            return;
+        }
+        let first = (address.next_multiple_of(self.alignment) - address) as usize;
+        for offset in (first..=data.len() - size).step_by(self.alignment as usize) {
+            let current = &data[offset..offset + size];
+            let previous = previous.map(|previous| &previous[offset..offset + size]);
+            if filter.matches(self.value_type, current, previous) {
+                addresses.push(address + offset as u64);
+                values.extend_from_slice(current);
+            }
+        }
+    }
+}
+
+/// reads a region in chunks which extend overlap bytes into the next one so
+/// values crossing a chunk boundary are found, unreadable chunks are skipped
+fn read_region(memory: &impl Memory, region: &RegionEntry, overlap: usize) -> Vec<(u64, Vec<u8>)> {
+    let mut chunks = vec![];
+    let end = region.base + region.size;
+    let mut address = region.base;
+    while address < end {
+        let size = (end - address).min(CHUNK_SIZE as u64) as usize;
+        let extended = (end - address).min((size + overlap) as u64) as usize;
+        if let Ok(data) = memory.read_vec(address as usize, extended) {
+            chunks.push((address, data));
+        } else if let Ok(data) = memory.read_vec(address as usize, size) {
+            chunks.push((address, data));
+        }
+        address += size as u64;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        emulator::{PagedMemory, Protection},
+        memory::RegionKind,
+    };
+
+    pub(super) fn region(base: u64, size: u64, read: bool) -> RegionEntry {
+        RegionEntry {
+            base,
+            size,
+            read,
+            write: true,
+            execute: false,
+            kind: RegionKind::Private,
+            owner: String::new(),
+        }
+    }
+
+    /// readable pages at 0x1000 and 0x3000 and an unreadable region at 0x5000
+    /// with 100 at 0x1010, 0x1022 (unaligned), 0x3020 and 0x5000
+    fn memory() -> (PagedMemory, Vec<RegionEntry>) {
+        let mut memory = PagedMemory::new();
+        for address in [0x1000, 0x3000, 0x5000] {
+            memory.map(address, 0x1000, Protection::READ_WRITE);
+        }
+        for address in [0x1010, 0x1022, 0x3020, 0x5000] {
+            memory.write(address, &100u32.to_le_bytes()).unwrap();
+        }
+        let regions = vec![
+            region(0x1000, 0x1000, true),
+            region(0x3000, 0x1000, true),
+            region(0x5000, 0x1000, false),
+        ];
+        (memory, regions)
+    }
+
+    fn hits(scanner: &Scanner) -> Vec<(u64, Value)> {
+        scanner.hits().collect()
+    }
+
+    #[test]
+    fn narrowing() {
+        let (mut memory, regions) = memory();
+        let mut scanner = Scanner::new(ValueType::U32, 4);
+        let filter = Filter::Exact(Value::U32(100));
+        assert_eq!(scanner.first_scan(&memory, &regions, &filter).unwrap(), 2);
+        assert_eq!(
+            hits(&scanner),
+            [(0x1010, Value::U32(100)), (0x3020, Value::U32(100))]
+        );
+
+        memory.write(0x1010, &90u32.to_le_bytes()).unwrap();
+        assert_eq!(scanner.next_scan(&memory, &Filter::Decreased).unwrap(), 1);
+        assert_eq!(hits(&scanner), [(0x1010, Value::U32(90))]);
+        assert_eq!(scanner.next_scan(&memory, &Filter::Unchanged).unwrap(), 1);
+        memory.write(0x1010, &95u32.to_le_bytes()).unwrap();
+        assert_eq!(scanner.next_scan(&memory, &Filter::Increased).unwrap(), 1);
+        let range = Filter::Range(Value::U32(96), Value::U32(200));
+        assert_eq!(scanner.next_scan(&memory, &range).unwrap(), 0);
+        assert_eq!(hits(&scanner), []);
+
+        // unaligned values are found with an alignment of 1
+        let mut scanner = Scanner::new(ValueType::U32, 1);
+        assert_eq!(scanner.first_scan(&memory, &regions, &filter).unwrap(), 2);
+        assert_eq!(scanner.hits().next().unwrap().0, 0x1022);
+    }
+
+    #[test]
+    fn unknown_value() {
+        let (mut memory, regions) = memory();
+        let mut scanner = Scanner::new(ValueType::U32, 4);
+        assert_eq!(
+            scanner.first_scan(&memory, &regions, &Filter::Any).unwrap(),
+            0
+        );
+        memory.write(0x3020, &101u32.to_le_bytes()).unwrap();
+        assert_eq!(scanner.next_scan(&memory, &Filter::Changed).unwrap(), 1);
+        assert_eq!(hits(&scanner), [(0x3020, Value::U32(101))]);
+
+        scanner.reset();
+        assert_eq!(scanner.count(), 0);
+        assert!(scanner.next_scan(&memory, &Filter::Changed).is_err());
+    }
+
+    #[test]
+    fn values() {
+        let (mut memory, regions) = memory();
+        memory.write(0x3100, "text".as_bytes()).unwrap();
+        memory.write(0x3200, &(-0.0f32).to_le_bytes()).unwrap();
+
+        let mut scanner = Scanner::new(ValueType::Bytes(4), 1);
+        let filter = Filter::Exact(Value::string("text", false));
+        assert_eq!(scanner.first_scan(&memory, &regions, &filter).unwrap(), 1);
+        assert_eq!(hits(&scanner), [(0x3100, Value::string("text", false))]);
+        assert!(scanner.next_scan(&memory, &Filter::Increased).is_err());
+
+        // floats are compared by value
+        let mut scanner = Scanner::new(ValueType::F32, 4);
+        let filter = Filter::Exact(Value::F32(0.0));
+        let count = scanner.first_scan(&memory, &regions, &filter).unwrap();
+        assert!(scanner.hits().any(|(address, _)| address == 0x3200));
+        assert_eq!(count, scanner.count());
+
+        assert!(Scanner::new(ValueType::U32, 4)
+            .first_scan(&memory, &regions, &Filter::Exact(Value::U8(100)))
+            .is_err());
+        assert_eq!(Value::string("ab", true).encode(), [b'a', 0, b'b', 0]);
+        assert_eq!(Value::decode(ValueType::I16, &[0xFF, 0xFF]), Value::I16(-1));
+    }
+}
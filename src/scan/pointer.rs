@@ -0,0 +1,344 @@
+use std::{
+    fmt,
+    io::{BufRead, Write},
+    ops::Range,
+    str::FromStr,
+};
+
+use super::read_region;
+use crate::{
+    memory::{Memory, RegionEntry},
+    module::Module,
+    Error, Result,
+};
+
+/// chain from a static address inside a module to a target, the address is
+/// read and the next offset added for each offset
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PointerPath {
+    pub module: String,
+    /// offset of the first pointer from the module base
+    pub offset: u64,
+    pub offsets: Vec<u64>,
+}
+
+impl PointerPath {
+    /// address the path leads to, None if the module isn't loaded or a
+    /// pointer is unreadable
+    pub fn resolve(&self, memory: &impl Memory, modules: &[Module]) -> Option<u64> {
+        let module = modules
+            .iter()
+            .find(|module| module.name().eq_ignore_ascii_case(&self.module))?;
+        let mut address = module.base() as u64 + self.offset;
+        for offset in &self.offsets {
+            let mut pointer = [0; 8];
+            memory.read(address as usize, &mut pointer).ok()?;
+            address = u64::from_le_bytes(pointer).wrapping_add(*offset);
+        }
+        Some(address)
+    }
+}
+
+impl fmt::Display for PointerPath {
+    /// module+0x1234 -> 0x10 -> 0x8
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.module, self.offset)?;
+        for offset in &self.offsets {
+            write!(f, " -> {offset:#x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PointerPath {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let parse = |value: &str| {
+            u64::from_str_radix(value.trim().trim_start_matches("0x"), 16)
+                .map_err(|_| Error::Scan("invalid offset"))
+        };
+        let mut parts = value.split("->");
+        let (module, offset) = parts
+            .next()
+            .and_then(|root| root.trim().rsplit_once('+'))
+            .ok_or(Error::Scan("missing module"))?;
+        Ok(Self {
+            module: module.to_owned(),
+            offset: parse(offset)?,
+            offsets: parts.map(parse).collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// writes the paths one per line
+pub fn save_paths(writer: &mut impl Write, paths: &[PointerPath]) -> Result<()> {
+    for path in paths {
+        writeln!(writer, "{path}")?;
+    }
+    Ok(())
+}
+
+/// reads paths written by [save_paths], empty lines are skipped
+pub fn load_paths(reader: impl BufRead) -> Result<Vec<PointerPath>> {
+    let mut paths = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            paths.push(line.parse()?);
+        }
+    }
+    Ok(paths)
+}
+
+/// keeps the paths which still lead to the target (e.g. after a restart the
+/// target was found again by a value scan)
+pub fn revalidate(
+    paths: Vec<PointerPath>,
+    memory: &impl Memory,
+    modules: &[Module],
+    target: u64,
+) -> Vec<PointerPath> {
+    paths
+        .into_iter()
+        .filter(|path| path.resolve(memory, modules) == Some(target))
+        .collect()
+}
+
+/// limits of a pointer scan
+#[derive(Debug, Copy, Clone)]
+pub struct PointerScanOptions {
+    /// maximum number of pointers in a path
+    pub max_depth: usize,
+    /// maximum offset added to a pointer
+    pub max_offset: u64,
+    /// the scan stops after finding this many paths
+    pub max_results: usize,
+}
+
+impl Default for PointerScanOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 5,
+            max_offset: 0x1000,
+            max_results: 100_000,
+        }
+    }
+}
+
+/// map of all pointers of a target for finding paths to addresses
+pub struct PointerScanner {
+    /// values and addresses of the aligned pointers into readable regions
+    /// sorted by value
+    pointers: Vec<(u64, u64)>,
+    /// image ranges of the modules and their names
+    roots: Vec<(Range<u64>, String)>,
+}
+
+impl PointerScanner {
+    /// reads the readable regions and collects all pointers into them, the
+    /// modules are the roots of the paths
+    pub fn new(memory: &impl Memory, regions: &[RegionEntry], modules: &[Module]) -> Self {
+        let mut readable = regions
+            .iter()
+            .filter(|region| region.read)
+            .map(|region| region.base..region.base + region.size)
+            .collect::<Vec<_>>();
+        readable.sort_by_key(|range| range.start);
+        let is_readable = |value: u64| {
+            let index = readable.partition_point(|range| range.start <= value);
+            index > 0 && readable[index - 1].contains(&value)
+        };
+
+        let mut pointers = vec![];
+        for region in regions.iter().filter(|region| region.read) {
+            for (address, data) in read_region(memory, region, 0) {
+                let first = (address.next_multiple_of(8) - address) as usize;
+                for offset in (first..data.len().saturating_sub(7)).step_by(8) {
+                    let value = u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+                    if is_readable(value) {
+                        pointers.push((value, address + offset as u64));
+                    }
+                }
+            }
+        }
+        pointers.sort_unstable();
+
+        let roots = modules
+            .iter()
+            .map(|module| {
+                let base = module.base() as u64;
+                (base..base + module.size() as u64, module.name().to_owned())
+            })
+            .collect();
+        Self { pointers, roots }
+    }
+
+    /// number of pointers found
+    pub fn len(&self) -> usize {
+        self.pointers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pointers.is_empty()
+    }
+
+    /// paths from the modules to the target, ordered by length, a pointer
+    /// inside a module ends a path
+    pub fn scan(&self, target: u64, options: &PointerScanOptions) -> Vec<PointerPath> {
+        let mut paths = vec![];
+        let mut offsets = vec![];
+        self.scan_level(target, options, &mut offsets, &mut paths);
+        paths.sort_by_key(|path| path.offsets.len());
+        paths
+    }
+
+    /// finds the pointers up to max offset below the target, offsets are the
+    /// ones following the target in reverse order
+    fn scan_level(
+        &self,
+        target: u64,
+        options: &PointerScanOptions,
+        offsets: &mut Vec<u64>,
+        paths: &mut Vec<PointerPath>,
+    ) {
+        let start = self
+            .pointers
+            .partition_point(|&(value, _)| value < target.saturating_sub(options.max_offset));
+        let end = self.pointers.partition_point(|&(value, _)| value <= target);
+        for &(value, address) in &self.pointers[start..end] {
+            if paths.len() >= options.max_results {
+                return;
+            }
+            offsets.push(target - value);
+            if let Some((range, module)) = self
+                .roots
+                .iter()
+                .find(|(range, _)| range.contains(&address))
+            {
+                paths.push(PointerPath {
+                    module: module.clone(),
+                    offset: address - range.start,
+                    offsets: offsets.iter().rev().copied().collect(),
+                });
+            } else if offsets.len() < options.max_depth {
+                self.scan_level(address, options, offsets, paths);
+            }
+            offsets.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        emulator::{PagedMemory, Protection},
+        scan::tests::region,
+        unpack::tests::{image, BASE},
+    };
+
+    /// test.exe+0x2008 -> 0x18 -> 0x40 leads to 0x20040
+    fn memory() -> (PagedMemory, Vec<RegionEntry>, Vec<Module>) {
+        let module = Module::from_image("test.exe".to_owned(), BASE, image()).unwrap();
+        let mut memory = PagedMemory::new();
+        memory.map(BASE as u64, 0x3000, Protection::READ_WRITE);
+        memory.write(BASE as u64, &image()).unwrap();
+        memory.map(0x10000, 0x1000, Protection::READ_WRITE);
+        memory.map(0x20000, 0x1000, Protection::READ_WRITE);
+        memory
+            .write(BASE as u64 + 0x2008, &0x10000u64.to_le_bytes())
+            .unwrap();
+        memory.write(0x10018, &0x20000u64.to_le_bytes()).unwrap();
+        let regions = vec![
+            region(BASE as u64, 0x3000, true),
+            region(0x10000, 0x1000, true),
+            region(0x20000, 0x1000, true),
+        ];
+        (memory, regions, vec![module])
+    }
+
+    fn path() -> PointerPath {
+        PointerPath {
+            module: "test.exe".to_owned(),
+            offset: 0x2008,
+            offsets: vec![0x18, 0x40],
+        }
+    }
+
+    #[test]
+    fn scan() {
+        let (memory, regions, modules) = memory();
+        let scanner = PointerScanner::new(&memory, &regions, &modules);
+        assert!(!scanner.is_empty());
+        let options = PointerScanOptions::default();
+        assert_eq!(scanner.scan(0x20040, &options), [path()]);
+
+        let options = PointerScanOptions {
+            max_depth: 1,
+            ..Default::default()
+        };
+        assert!(scanner.scan(0x20040, &options).is_empty());
+        let options = PointerScanOptions {
+            max_offset: 0x20,
+            ..Default::default()
+        };
+        assert!(scanner.scan(0x20040, &options).is_empty());
+    }
+
+    #[test]
+    fn resolve() {
+        let (mut memory, _, modules) = memory();
+        assert_eq!(path().resolve(&memory, &modules), Some(0x20040));
+        let other = PointerPath {
+            module: "other.dll".to_owned(),
+            ..path()
+        };
+        assert_eq!(other.resolve(&memory, &modules), None);
+
+        let paths = vec![path(), other];
+        assert_eq!(
+            revalidate(paths.clone(), &memory, &modules, 0x20040),
+            [path()]
+        );
+        memory.write(0x10018, &0x20100u64.to_le_bytes()).unwrap();
+        assert!(revalidate(paths, &memory, &modules, 0x20040).is_empty());
+        // unreadable pointer
+        memory.write(0x10018, &0x30000u64.to_le_bytes()).unwrap();
+        let path = PointerPath {
+            offsets: vec![0x18, 0x40, 0],
+            ..path()
+        };
+        assert_eq!(path.resolve(&memory, &modules), None);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(path().to_string(), "test.exe+0x2008 -> 0x18 -> 0x40");
+        assert_eq!(
+            "test.exe+0x2008 -> 0x18 -> 0x40"
+                .parse::<PointerPath>()
+                .unwrap(),
+            path()
+        );
+        assert_eq!(
+            "test.exe+2008->18->40".parse::<PointerPath>().unwrap(),
+            path()
+        );
+        assert!("test.exe".parse::<PointerPath>().is_err());
+        assert!("test.exe+0x2008 -> zz".parse::<PointerPath>().is_err());
+
+        let paths = vec![
+            path(),
+            PointerPath {
+                module: "a+b.dll".to_owned(),
+                offset: 0,
+                offsets: vec![],
+            },
+        ];
+        let mut data = vec![];
+        save_paths(&mut data, &paths).unwrap();
+        data.extend_from_slice(b"\n  \n");
+        assert_eq!(load_paths(data.as_slice()).unwrap(), paths);
+    }
+}
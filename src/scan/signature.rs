@@ -0,0 +1,587 @@
+use std::{fmt, str::FromStr};
+
+use iced_x86::{Decoder, DecoderError, DecoderOptions, Instruction, OpKind};
+
+use super::{read_region, CHUNK_SIZE};
+use crate::{
+    memory::{Memory, RegionEntry},
+    module::Module,
+    Error, Result,
+};
+
+/// bytes after a match needed to decode the followed instruction
+const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+/// bytes frequent in x86-64 code, bad candidates for the byte a match is
+/// looked up by
+const COMMON_BYTES: &[u8] = &[
+    0x00, 0xFF, 0xCC, 0x90, 0x48, 0x49, 0x4C, 0x89, 0x8B, 0x0F, 0x24, 0x44, 0x83, 0xE8, 0xC3,
+];
+
+/// byte pattern with wildcards, written like 48 8B 05 ?? ?? ?? ?? or with
+/// wildcard nibbles like 4? 8B
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    bytes: Vec<u8>,
+    /// bits which have to match
+    mask: Vec<u8>,
+    /// bytes and mask in little-endian words, padded with wildcards
+    words: Vec<(u64, u64)>,
+}
+
+impl Pattern {
+    /// pattern from bytes and mask of the same length, bits cleared in the
+    /// mask are wildcards
+    pub fn new(bytes: Vec<u8>, mask: Vec<u8>) -> Result<Self> {
+        if bytes.len() != mask.len() || mask.iter().all(|&mask| mask == 0) {
+            return Err(Error::Scan("invalid pattern"));
+        }
+        let bytes = bytes
+            .iter()
+            .zip(&mask)
+            .map(|(byte, mask)| byte & mask)
+            .collect::<Vec<_>>();
+        let words = bytes
+            .chunks(8)
+            .zip(mask.chunks(8))
+            .map(|(bytes, mask)| {
+                let mut word = [0; 8];
+                let mut word_mask = [0; 8];
+                word[..bytes.len()].copy_from_slice(bytes);
+                word_mask[..mask.len()].copy_from_slice(mask);
+                (u64::from_le_bytes(word), u64::from_le_bytes(word_mask))
+            })
+            .collect();
+        Ok(Self { bytes, mask, words })
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// whether data starts with the pattern
+    pub fn matches(&self, data: &[u8]) -> bool {
+        if data.len() >= self.words.len() * 8 {
+            // compares a word at once
+            self.words.iter().enumerate().all(|(index, &(word, mask))| {
+                let data = u64::from_le_bytes(data[index * 8..index * 8 + 8].try_into().unwrap());
+                (data ^ word) & mask == 0
+            })
+        } else {
+            data.len() >= self.bytes.len()
+                && self
+                    .bytes
+                    .iter()
+                    .zip(&self.mask)
+                    .zip(data)
+                    .all(|((byte, mask), data)| data & mask == *byte)
+        }
+    }
+
+    /// offset of the byte a match is looked up by, preferring fully known
+    /// rare bytes
+    fn anchor(&self) -> usize {
+        let mut known = (0..self.len()).filter(|&index| self.mask[index] == 0xFF);
+        let first = known.clone().next();
+        known
+            .find(|&index| !COMMON_BYTES.contains(&self.bytes[index]))
+            .or(first)
+            .unwrap_or_else(|| {
+                // only wildcard nibbles
+                (0..self.len())
+                    .max_by_key(|&index| self.mask[index].count_ones())
+                    .unwrap()
+            })
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let mut bytes = vec![];
+        let mut mask = vec![];
+        for token in value.split_whitespace() {
+            let (byte, byte_mask) = match token.as_bytes() {
+                b"?" => (0, 0),
+                &[high, low] => {
+                    let (high, high_mask) = parse_nibble(high)?;
+                    let (low, low_mask) = parse_nibble(low)?;
+                    (high << 4 | low, high_mask << 4 | low_mask)
+                }
+                _ => return Err(Error::Scan("invalid pattern")),
+            };
+            bytes.push(byte);
+            mask.push(byte_mask);
+        }
+        Self::new(bytes, mask)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (byte, mask)) in self.bytes.iter().zip(&self.mask).enumerate() {
+            if index != 0 {
+                write!(f, " ")?;
+            }
+            for shift in [4, 0] {
+                if mask >> shift & 0xF == 0 {
+                    write!(f, "?")?;
+                } else {
+                    write!(f, "{:X}", byte >> shift & 0xF)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// value and mask of a hex digit or ?
+fn parse_nibble(digit: u8) -> Result<(u8, u8)> {
+    if digit == b'?' {
+        return Ok((0, 0));
+    }
+    (digit as char)
+        .to_digit(16)
+        .map(|digit| (digit as u8, 0xF))
+        .ok_or(Error::Scan("invalid pattern"))
+}
+
+/// named pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub pattern: Pattern,
+    /// offset of an instruction in the match whose rip-relative memory
+    /// operand or branch target is the result instead of the match (e.g. 0
+    /// for 48 8B 05 ?? ?? ?? ??)
+    pub follow: Option<usize>,
+}
+
+impl Signature {
+    pub fn new(name: impl Into<String>, pattern: &str) -> Result<Self> {
+        Ok(Self {
+            name: name.into(),
+            pattern: pattern.parse()?,
+            follow: None,
+        })
+    }
+
+    pub fn follow(mut self, offset: usize) -> Self {
+        self.follow = Some(offset);
+        self
+    }
+}
+
+/// signature found
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SignatureMatch {
+    /// index of the signature
+    pub signature: usize,
+    /// start of the match
+    pub address: u64,
+    /// followed address or the start of the match
+    pub target: u64,
+}
+
+/// scans for many signatures in a single pass
+pub struct SignatureScanner {
+    signatures: Vec<Signature>,
+    /// signatures and their anchor offsets by the anchor byte
+    anchors: Vec<Vec<(usize, usize)>>,
+    /// bytes needed after the start of a match
+    overlap: usize,
+}
+
+impl SignatureScanner {
+    pub fn new(signatures: Vec<Signature>) -> Self {
+        let mut anchors = vec![vec![]; 256];
+        let mut overlap = 0;
+        for (index, signature) in signatures.iter().enumerate() {
+            let pattern = &signature.pattern;
+            let anchor = pattern.anchor();
+            for byte in 0..=255u8 {
+                if byte & pattern.mask[anchor] == pattern.bytes[anchor] {
+                    anchors[byte as usize].push((index, anchor));
+                }
+            }
+            let length = match signature.follow {
+                Some(offset) => pattern.len().max(offset + MAX_INSTRUCTION_LENGTH),
+                None => pattern.len(),
+            };
+            overlap = overlap.max(length);
+        }
+        Self {
+            signatures,
+            anchors,
+            overlap,
+        }
+    }
+
+    pub fn signatures(&self) -> &[Signature] {
+        &self.signatures
+    }
+
+    /// matches in data at the address starting before limit, in ascending
+    /// order
+    pub fn scan_data(&self, data: &[u8], address: u64, limit: usize) -> Vec<SignatureMatch> {
+        let mut matches = vec![];
+        for (index, byte) in data.iter().enumerate() {
+            for &(signature, anchor) in &self.anchors[*byte as usize] {
+                let Some(start) = index.checked_sub(anchor) else {
+                    continue;
+                };
+                if start >= limit || !self.signatures[signature].pattern.matches(&data[start..]) {
+                    continue;
+                }
+                if let Some(target) = self.target(signature, &data[start..], address + start as u64)
+                {
+                    matches.push(SignatureMatch {
+                        signature,
+                        address: address + start as u64,
+                        target,
+                    });
+                }
+            }
+        }
+        matches.sort_by_key(|found| (found.address, found.signature));
+        matches
+    }
+
+    /// matches in the readable regions (e.g. the whole address space)
+    pub fn scan(&self, memory: &impl Memory, regions: &[RegionEntry]) -> Vec<SignatureMatch> {
+        let mut matches = vec![];
+        for region in regions.iter().filter(|region| region.read) {
+            for (address, data) in read_region(memory, region, self.overlap.saturating_sub(1)) {
+                matches.extend(self.scan_data(&data, address, CHUNK_SIZE));
+            }
+        }
+        matches
+    }
+
+    /// matches in the executable sections of the module
+    pub fn scan_module(
+        &self,
+        memory: &impl Memory,
+        module: &Module,
+    ) -> Result<Vec<SignatureMatch>> {
+        let regions = module
+            .regions()?
+            .into_iter()
+            .filter(|region| region.execute)
+            .collect::<Vec<_>>();
+        Ok(self.scan(memory, &regions))
+    }
+
+    /// address the match leads to, None if the followed instruction has no
+    /// rip-relative operand
+    fn target(&self, signature: usize, data: &[u8], address: u64) -> Option<u64> {
+        let Some(offset) = self.signatures[signature].follow else {
+            return Some(address);
+        };
+        let mut decoder = Decoder::with_ip(
+            64,
+            data.get(offset..)?,
+            address + offset as u64,
+            DecoderOptions::NONE,
+        );
+        let instruction = decoder.decode();
+        if instruction.is_invalid() {
+            return None;
+        }
+        if instruction.is_ip_rel_memory_operand() {
+            return Some(instruction.ip_rel_memory_address());
+        }
+        is_near_branch(&instruction).then(|| instruction.near_branch_target())
+    }
+}
+
+/// maximum length of a generated pattern
+const MAX_PATTERN_LENGTH: usize = 128;
+
+/// shortest pattern which matches only at the address within the executable
+/// sections of the module, built from the instructions starting there with
+/// rip-relative displacements, branch targets and constants pointing into the
+/// module (relocated or changing with a recompile) as wildcards
+pub fn unique_pattern(memory: &impl Memory, module: &Module, address: u64) -> Result<Pattern> {
+    let mut chunks = vec![];
+    for region in module.regions()?.iter().filter(|region| region.execute) {
+        chunks.extend(read_region(memory, region, MAX_PATTERN_LENGTH - 1));
+    }
+    let (chunk, offset) = chunks
+        .iter()
+        .enumerate()
+        .find_map(|(index, (start, data))| {
+            let offset = address.checked_sub(*start)? as usize;
+            (offset < CHUNK_SIZE.min(data.len())).then_some((index, offset))
+        })
+        .ok_or(Error::Scan("address outside of the executable sections"))?;
+    let image = module.base() as u64..(module.base() + module.size()) as u64;
+    let data = &chunks[chunk].1[offset..];
+
+    let mut bytes = vec![];
+    let mut mask = vec![];
+    // matches of the pattern up to the previous instruction as chunk and
+    // offset, None if not scanned yet
+    let mut candidates: Option<Vec<(usize, usize)>> = None;
+    let mut decoder = Decoder::with_ip(64, data, address, DecoderOptions::NONE);
+    while bytes.len() < MAX_PATTERN_LENGTH {
+        let instruction = decoder.decode();
+        if decoder.last_error() == DecoderError::NoMoreBytes {
+            break;
+        }
+        if instruction.is_invalid() {
+            return Err(Error::Scan("invalid instruction"));
+        }
+        let start = bytes.len();
+        bytes.extend_from_slice(&data[start..start + instruction.len()]);
+        mask.resize(bytes.len(), 0xFF);
+
+        let offsets = decoder.get_constant_offsets(&instruction);
+        let mut wildcard = |offset: usize, size: usize| {
+            mask[start + offset..start + offset + size].fill(0);
+        };
+        let in_image = |offset: usize, size: usize| {
+            let mut value = [0; 8];
+            value[..size].copy_from_slice(&bytes[start + offset..start + offset + size]);
+            size >= 4 && image.contains(&u64::from_le_bytes(value))
+        };
+        let branch = is_near_branch(&instruction);
+        if offsets.has_displacement()
+            && (branch
+                || instruction.is_ip_rel_memory_operand()
+                || in_image(offsets.displacement_offset(), offsets.displacement_size()))
+        {
+            wildcard(offsets.displacement_offset(), offsets.displacement_size());
+        }
+        if offsets.has_immediate()
+            && (branch || in_image(offsets.immediate_offset(), offsets.immediate_size()))
+        {
+            wildcard(offsets.immediate_offset(), offsets.immediate_size());
+        }
+        if mask.iter().all(|&mask| mask == 0) {
+            continue;
+        }
+
+        // the shortest unique prefix ends within this instruction
+        let end = bytes.len().min(MAX_PATTERN_LENGTH);
+        for length in start + 1..=end {
+            if mask[..length].iter().all(|&mask| mask == 0) {
+                continue;
+            }
+            let pattern = Pattern::new(bytes[..length].to_vec(), mask[..length].to_vec())?;
+            let matches = find_candidates(&pattern, &chunks, candidates.as_deref());
+            if matches.len() == 1 {
+                return Ok(pattern);
+            }
+            if length == end {
+                candidates = Some(matches);
+            }
+        }
+    }
+    Err(Error::Scan("no unique pattern"))
+}
+
+/// matches of the pattern among the candidates or in all chunks
+fn find_candidates(
+    pattern: &Pattern,
+    chunks: &[(u64, Vec<u8>)],
+    candidates: Option<&[(usize, usize)]>,
+) -> Vec<(usize, usize)> {
+    match candidates {
+        Some(candidates) => candidates
+            .iter()
+            .copied()
+            .filter(|&(chunk, offset)| pattern.matches(&chunks[chunk].1[offset..]))
+            .collect(),
+        None => {
+            let scanner = SignatureScanner::new(vec![Signature {
+                name: String::new(),
+                pattern: pattern.clone(),
+                follow: None,
+            }]);
+            chunks
+                .iter()
+                .enumerate()
+                .flat_map(|(chunk, (address, data))| {
+                    scanner
+                        .scan_data(data, *address, CHUNK_SIZE)
+                        .into_iter()
+                        .map(move |found| (chunk, (found.address - address) as usize))
+                })
+                .collect()
+        }
+    }
+}
+
+/// whether the instruction has a relative branch target
+fn is_near_branch(instruction: &Instruction) -> bool {
+    (0..instruction.op_count()).any(|operand| {
+        matches!(
+            instruction.op_kind(operand),
+            OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        emulator::{PagedMemory, Protection},
+        scan::tests::region,
+        unpack::tests::{image, BASE},
+    };
+
+    #[test]
+    fn parse() {
+        let pattern: Pattern = "48 8B 05 ?? ?? ? ??".parse().unwrap();
+        assert_eq!(pattern.len(), 7);
+        assert_eq!(pattern.bytes, [0x48, 0x8B, 0x05, 0, 0, 0, 0]);
+        assert_eq!(pattern.mask, [0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+        assert_eq!(pattern.to_string(), "48 8B 05 ?? ?? ?? ??");
+
+        // wildcard nibbles
+        let pattern: Pattern = "4? ?b".parse().unwrap();
+        assert_eq!(pattern.bytes, [0x40, 0x0B]);
+        assert_eq!(pattern.mask, [0xF0, 0x0F]);
+        assert_eq!(pattern.to_string(), "4? ?B");
+        assert!(pattern.matches(&[0x48, 0x8B]));
+        assert!(pattern.matches(&[0x4C, 0x0B, 0xFF]));
+        assert!(!pattern.matches(&[0x58, 0x8B]));
+        assert!(!pattern.matches(&[0x48]));
+
+        for invalid in ["", "?? ??", "4", "GG", "123", "48 8B 0x05"] {
+            assert!(invalid.parse::<Pattern>().is_err(), "{invalid}");
+        }
+        assert!(Pattern::new(vec![1, 2], vec![0xFF]).is_err());
+    }
+
+    #[test]
+    fn words() {
+        // longer than a word with data long enough for word compares
+        let pattern: Pattern = "01 02 03 04 05 06 07 08 ?? 0A".parse().unwrap();
+        let mut data: Vec<u8> = (1..=16).collect();
+        assert!(pattern.matches(&data));
+        data[9] = 0;
+        assert!(!pattern.matches(&data));
+        data[9] = 10;
+        data[8] = 0xFF;
+        assert!(pattern.matches(&data[..10]));
+    }
+
+    #[test]
+    fn follow() {
+        let mut data = vec![0xCC; 0x40];
+        // mov rax, [rip+0x10] / call +0x100 / mov rax, rcx
+        data[0x10..0x17].copy_from_slice(&[0x48, 0x8B, 0x05, 0x10, 0x00, 0x00, 0x00]);
+        data[0x20..0x25].copy_from_slice(&[0xE8, 0x00, 0x01, 0x00, 0x00]);
+        data[0x30..0x33].copy_from_slice(&[0x48, 0x89, 0xC8]);
+        let scanner = SignatureScanner::new(vec![
+            Signature::new("global", "48 8B 05 ?? ?? ?? ??")
+                .unwrap()
+                .follow(0),
+            Signature::new("function", "CC E8").unwrap().follow(1),
+            Signature::new("move", "48 89 C8").unwrap(),
+            // no rip-relative operand
+            Signature::new("invalid", "48 89 C8").unwrap().follow(0),
+        ]);
+        assert_eq!(
+            scanner.scan_data(&data, 0x1000, data.len()),
+            [
+                SignatureMatch {
+                    signature: 0,
+                    address: 0x1010,
+                    target: 0x1027,
+                },
+                SignatureMatch {
+                    signature: 1,
+                    address: 0x101F,
+                    target: 0x1125,
+                },
+                SignatureMatch {
+                    signature: 2,
+                    address: 0x1030,
+                    target: 0x1030,
+                },
+            ]
+        );
+        // matches have to start before the limit
+        assert_eq!(scanner.scan_data(&data, 0x1000, 0x1F).len(), 1);
+
+        let mut memory = PagedMemory::new();
+        memory.map(0x1000, 0x2000, Protection::READ_EXECUTE);
+        memory.write_raw(0x1FF0, &data[..0x17]).unwrap();
+        let regions = [region(0x1000, 0x2000, true), region(0x3000, 0x1000, false)];
+        let matches = scanner.scan(&memory, &regions);
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].address, matches[0].target), (0x2000, 0x2017));
+    }
+
+    #[test]
+    fn unique() {
+        let base = BASE as u64;
+        let module = Module::from_image("test.exe".to_owned(), BASE, image()).unwrap();
+        let mut memory = PagedMemory::new();
+        memory.map(base, 0x3000, Protection::READ_EXECUTE);
+        memory.write_raw(base, &image()).unwrap();
+        for (offset, code) in [
+            // mov rax, [rip+0x100] / ret
+            (
+                0x1000,
+                &[0x48, 0x8B, 0x05, 0x00, 0x01, 0x00, 0x00, 0xC3][..],
+            ),
+            // mov rax, [rip+0x200] / test rax, rax / ret
+            (
+                0x1008,
+                &[
+                    0x48, 0x8B, 0x05, 0x00, 0x02, 0x00, 0x00, 0x48, 0x85, 0xC0, 0xC3,
+                ],
+            ),
+            // mov rax, image+0x2000 / ret
+            (
+                0x1020,
+                &[
+                    0x48, 0xB8, 0x00, 0x20, 0x00, 0x40, 0x01, 0x00, 0x00, 0x00, 0xC3,
+                ],
+            ),
+            // mov rax, image+0x2010 / nop / ret
+            (
+                0x1030,
+                &[
+                    0x48, 0xB8, 0x10, 0x20, 0x00, 0x40, 0x01, 0x00, 0x00, 0x00, 0x90, 0xC3,
+                ],
+            ),
+        ] {
+            memory.write_raw(base + offset, code).unwrap();
+        }
+
+        for (offset, expected) in [
+            (0x1008, "48 8B 05 ?? ?? ?? ?? 48"),
+            (0x1000, "48 8B 05 ?? ?? ?? ?? C3"),
+            (0x1030, "48 B8 ?? ?? ?? ?? ?? ?? ?? ?? 90"),
+            (0x100A, "05 00 02"),
+        ] {
+            let pattern = unique_pattern(&memory, &module, base + offset).unwrap();
+            assert_eq!(pattern.to_string(), expected);
+            // the pattern matches exactly once within the module
+            let scanner = SignatureScanner::new(vec![Signature {
+                name: String::new(),
+                pattern,
+                follow: None,
+            }]);
+            let matches = scanner.scan_module(&memory, &module).unwrap();
+            assert_eq!(matches.len(), 1);
+            assert_eq!(matches[0].address, base + offset);
+        }
+
+        // outside of the code section
+        assert!(unique_pattern(&memory, &module, base + 0x2000).is_err());
+        // only zeros follow
+        assert!(unique_pattern(&memory, &module, base + 0x1100).is_err());
+    }
+}
@@ -0,0 +1,626 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    io::Write,
+    ops::{Add, BitAnd, BitOr, BitXor, Mul, Neg, Not, Shl, Sub},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+use crate::{Error, Result};
+
+mod blast;
+mod sat;
+mod smtlib;
+
+pub use self::smtlib::script;
+
+/// binary operation of an expression
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    /// unsigned division, the quotient is all ones if dividing by zero
+    UDiv,
+    /// unsigned remainder, the dividend if dividing by zero
+    URem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    LShr,
+    AShr,
+    /// 1-bit result
+    Eq,
+    /// unsigned less than, 1-bit result
+    Ult,
+    /// signed less than, 1-bit result
+    Slt,
+}
+
+/// operation of an expression node
+#[derive(Debug, Clone)]
+pub enum Op {
+    Constant(u64),
+    Variable(u32),
+    Not(Expr),
+    Binary(BinaryOp, Expr, Expr),
+    /// bits starting at the specified one
+    Extract(u32, Expr),
+    ZeroExtend(Expr),
+    SignExtend(Expr),
+    /// high and low part
+    Concat(Expr, Expr),
+    /// 1-bit condition, then and else
+    Ite(Expr, Expr, Expr),
+}
+
+#[derive(Debug)]
+struct Node {
+    op: Op,
+    width: u32,
+}
+
+/// immutable bit-vector expression of 1 to 64 bits, sub-expressions are
+/// shared, constructors fold constants and simplify where cheap
+#[derive(Clone)]
+pub struct Expr(Arc<Node>);
+
+impl Expr {
+    fn new(op: Op, width: u32) -> Self {
+        assert!((1..=64).contains(&width));
+        Self(Arc::new(Node { op, width }))
+    }
+
+    pub fn constant(value: u64, width: u32) -> Self {
+        Self::new(Op::Constant(value & mask(width)), width)
+    }
+
+    pub fn variable(variable: u32, width: u32) -> Self {
+        Self::new(Op::Variable(variable), width)
+    }
+
+    pub fn bool(value: bool) -> Self {
+        Self::constant(value as u64, 1)
+    }
+
+    pub fn op(&self) -> &Op {
+        &self.0.op
+    }
+
+    pub fn width(&self) -> u32 {
+        self.0.width
+    }
+
+    pub fn as_constant(&self) -> Option<u64> {
+        match self.0.op {
+            Op::Constant(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.as_constant().is_some()
+    }
+
+    /// true if both are the same node or equal constants
+    fn same(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+            || (self.width() == other.width()
+                && self.as_constant().is_some()
+                && self.as_constant() == other.as_constant())
+    }
+
+    fn binary(op: BinaryOp, left: Self, right: Self) -> Self {
+        assert_eq!(left.width(), right.width());
+        let width = left.width();
+        let result_width = match op {
+            BinaryOp::Eq | BinaryOp::Ult | BinaryOp::Slt => 1,
+            _ => width,
+        };
+        if let (Some(a), Some(b)) = (left.as_constant(), right.as_constant()) {
+            return Self::constant(evaluate_binary(op, a, b, width), result_width);
+        }
+
+        // constants on the right for commutative operations
+        let (left, right) = match op {
+            BinaryOp::Add
+            | BinaryOp::Mul
+            | BinaryOp::And
+            | BinaryOp::Or
+            | BinaryOp::Xor
+            | BinaryOp::Eq
+                if left.is_constant() =>
+            {
+                (right, left)
+            }
+            _ => (left, right),
+        };
+        let ones = mask(width);
+        match (op, right.as_constant()) {
+            (
+                BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Or
+                | BinaryOp::Xor
+                | BinaryOp::Shl
+                | BinaryOp::LShr
+                | BinaryOp::AShr,
+                Some(0),
+            ) => return left,
+            (BinaryOp::Mul | BinaryOp::UDiv, Some(1)) => return left,
+            (BinaryOp::Mul | BinaryOp::And, Some(0)) => return right,
+            (BinaryOp::And, Some(value)) if value == ones => return left,
+            (BinaryOp::Or, Some(value)) if value == ones => return right,
+            (BinaryOp::Ult, Some(0)) => return Self::bool(false),
+            _ => {}
+        }
+        if left.same(&right) {
+            match op {
+                BinaryOp::And | BinaryOp::Or => return left,
+                BinaryOp::Sub | BinaryOp::Xor => return Self::constant(0, width),
+                BinaryOp::Eq => return Self::bool(true),
+                BinaryOp::Ult | BinaryOp::Slt => return Self::bool(false),
+                _ => {}
+            }
+        }
+        Self::new(Op::Binary(op, left, right), result_width)
+    }
+
+    pub fn udiv(self, divisor: Self) -> Self {
+        Self::binary(BinaryOp::UDiv, self, divisor)
+    }
+
+    pub fn urem(self, divisor: Self) -> Self {
+        Self::binary(BinaryOp::URem, self, divisor)
+    }
+
+    pub fn lshr(self, amount: Self) -> Self {
+        Self::binary(BinaryOp::LShr, self, amount)
+    }
+
+    pub fn ashr(self, amount: Self) -> Self {
+        Self::binary(BinaryOp::AShr, self, amount)
+    }
+
+    pub fn equal(self, other: Self) -> Self {
+        Self::binary(BinaryOp::Eq, self, other)
+    }
+
+    pub fn ult(self, other: Self) -> Self {
+        Self::binary(BinaryOp::Ult, self, other)
+    }
+
+    pub fn slt(self, other: Self) -> Self {
+        Self::binary(BinaryOp::Slt, self, other)
+    }
+
+    pub fn ule(self, other: Self) -> Self {
+        !other.ult(self)
+    }
+
+    pub fn sle(self, other: Self) -> Self {
+        !other.slt(self)
+    }
+
+    /// bits low..low + width
+    pub fn extract(self, low: u32, width: u32) -> Self {
+        assert!(low + width <= self.width());
+        if width == self.width() {
+            return self;
+        }
+        match &self.0.op {
+            Op::Constant(value) => Self::constant(value >> low, width),
+            Op::Extract(inner_low, inner) => inner.clone().extract(inner_low + low, width),
+            Op::Concat(high, low_part) => {
+                let split = low_part.width();
+                if low + width <= split {
+                    low_part.clone().extract(low, width)
+                } else if low >= split {
+                    high.clone().extract(low - split, width)
+                } else {
+                    Self::new(Op::Extract(low, self), width)
+                }
+            }
+            Op::ZeroExtend(inner) if low + width <= inner.width() => {
+                inner.clone().extract(low, width)
+            }
+            Op::ZeroExtend(inner) if low >= inner.width() => Self::constant(0, width),
+            Op::SignExtend(inner) if low + width <= inner.width() => {
+                inner.clone().extract(low, width)
+            }
+            _ => Self::new(Op::Extract(low, self), width),
+        }
+    }
+
+    pub fn zero_extend(self, width: u32) -> Self {
+        assert!(width >= self.width());
+        if width == self.width() {
+            return self;
+        }
+        match self.as_constant() {
+            Some(value) => Self::constant(value, width),
+            None => Self::new(Op::ZeroExtend(self), width),
+        }
+    }
+
+    pub fn sign_extend(self, width: u32) -> Self {
+        assert!(width >= self.width());
+        if width == self.width() {
+            return self;
+        }
+        match self.as_constant() {
+            Some(value) => Self::constant(sign_extend(value, self.width()), width),
+            None => Self::new(Op::SignExtend(self), width),
+        }
+    }
+
+    /// self as the high part followed by low
+    pub fn concat(self, low: Self) -> Self {
+        let width = self.width() + low.width();
+        if let (Some(high), Some(low_value)) = (self.as_constant(), low.as_constant()) {
+            return Self::constant(high << low.width() | low_value, width);
+        }
+        // adjacent parts of the same expression
+        if let (Op::Extract(high_low, high_inner), Op::Extract(low_low, low_inner)) =
+            (&self.0.op, &low.0.op)
+        {
+            if Arc::ptr_eq(&high_inner.0, &low_inner.0) && *high_low == low_low + low.width() {
+                return low_inner.clone().extract(*low_low, width);
+            }
+        }
+        Self::new(Op::Concat(self, low), width)
+    }
+
+    /// self is the 1-bit condition
+    pub fn ite(self, then: Self, otherwise: Self) -> Self {
+        assert_eq!(self.width(), 1);
+        assert_eq!(then.width(), otherwise.width());
+        match self.as_constant() {
+            Some(1) => then,
+            Some(_) => otherwise,
+            None if then.same(&otherwise) => then,
+            None => {
+                let width = then.width();
+                Self::new(Op::Ite(self, then, otherwise), width)
+            }
+        }
+    }
+
+    /// value under the model, missing variables are zero
+    pub fn evaluate(&self, model: &Model) -> u64 {
+        self.evaluate_cached(model, &mut HashMap::new())
+    }
+
+    fn evaluate_cached(&self, model: &Model, cache: &mut HashMap<*const Node, u64>) -> u64 {
+        let key = Arc::as_ptr(&self.0);
+        if let Some(&value) = cache.get(&key) {
+            return value;
+        }
+        let width = self.width();
+        let value = match &self.0.op {
+            Op::Constant(value) => *value,
+            Op::Variable(variable) => model.value(*variable).unwrap_or(0),
+            Op::Not(inner) => !inner.evaluate_cached(model, cache),
+            Op::Binary(op, left, right) => evaluate_binary(
+                *op,
+                left.evaluate_cached(model, cache),
+                right.evaluate_cached(model, cache),
+                left.width(),
+            ),
+            Op::Extract(low, inner) => inner.evaluate_cached(model, cache) >> low,
+            Op::ZeroExtend(inner) => inner.evaluate_cached(model, cache),
+            Op::SignExtend(inner) => {
+                sign_extend(inner.evaluate_cached(model, cache), inner.width())
+            }
+            Op::Concat(high, low) => {
+                high.evaluate_cached(model, cache) << low.width()
+                    | low.evaluate_cached(model, cache)
+            }
+            Op::Ite(condition, then, otherwise) => {
+                if condition.evaluate_cached(model, cache) != 0 {
+                    then.evaluate_cached(model, cache)
+                } else {
+                    otherwise.evaluate_cached(model, cache)
+                }
+            }
+        } & mask(width);
+        cache.insert(key, value);
+        value
+    }
+
+    /// variables the expression depends on
+    pub fn variables(&self) -> BTreeSet<u32> {
+        let mut variables = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        let mut stack = vec![self];
+        while let Some(expr) = stack.pop() {
+            if !visited.insert(Arc::as_ptr(&expr.0)) {
+                continue;
+            }
+            match &expr.0.op {
+                Op::Constant(_) => {}
+                Op::Variable(variable) => {
+                    variables.insert(*variable);
+                }
+                Op::Not(inner)
+                | Op::Extract(_, inner)
+                | Op::ZeroExtend(inner)
+                | Op::SignExtend(inner) => stack.push(inner),
+                Op::Binary(_, left, right) | Op::Concat(left, right) => {
+                    stack.push(left);
+                    stack.push(right)
+                }
+                Op::Ite(condition, then, otherwise) => {
+                    stack.push(condition);
+                    stack.push(then);
+                    stack.push(otherwise)
+                }
+            }
+        }
+        variables
+    }
+
+    fn key(&self) -> *const Node {
+        Arc::as_ptr(&self.0)
+    }
+}
+
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0.op {
+            Op::Constant(value) => write!(f, "{value:#x}:{}", self.width()),
+            Op::Variable(variable) => write!(f, "v{variable}:{}", self.width()),
+            Op::Not(inner) => write!(f, "(not {inner:?})"),
+            Op::Binary(op, left, right) => write!(f, "({op:?} {left:?} {right:?})"),
+            Op::Extract(low, inner) => {
+                write!(f, "(extract {low} {} {inner:?})", self.width())
+            }
+            Op::ZeroExtend(inner) => write!(f, "(zext {} {inner:?})", self.width()),
+            Op::SignExtend(inner) => write!(f, "(sext {} {inner:?})", self.width()),
+            Op::Concat(high, low) => write!(f, "(concat {high:?} {low:?})"),
+            Op::Ite(condition, then, otherwise) => {
+                write!(f, "(ite {condition:?} {then:?} {otherwise:?})")
+            }
+        }
+    }
+}
+
+impl Not for Expr {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        let width = self.width();
+        match &self.0.op {
+            Op::Constant(value) => Self::constant(!value, width),
+            Op::Not(inner) => inner.clone(),
+            _ => Self::new(Op::Not(self), width),
+        }
+    }
+}
+
+impl Neg for Expr {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::constant(0, self.width()) - self
+    }
+}
+
+macro_rules! binary_operator {
+    ($trait:ident, $function:ident, $op:ident) => {
+        impl $trait for Expr {
+            type Output = Self;
+
+            fn $function(self, other: Self) -> Self {
+                Self::binary(BinaryOp::$op, self, other)
+            }
+        }
+    };
+}
+
+binary_operator!(Add, add, Add);
+binary_operator!(Sub, sub, Sub);
+binary_operator!(Mul, mul, Mul);
+binary_operator!(BitAnd, bitand, And);
+binary_operator!(BitOr, bitor, Or);
+binary_operator!(BitXor, bitxor, Xor);
+binary_operator!(Shl, shl, Shl);
+
+/// values of variables
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Model(BTreeMap<u32, u64>);
+
+impl Model {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn value(&self, variable: u32) -> Option<u64> {
+        self.0.get(&variable).copied()
+    }
+
+    pub fn set(&mut self, variable: u32, value: u64) {
+        self.0.insert(variable, value);
+    }
+
+    /// overrides the values of the variables in the other model
+    pub fn extend(&mut self, other: &Model) {
+        self.0.extend(&other.0);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u64)> + '_ {
+        self.0.iter().map(|(&variable, &value)| (variable, value))
+    }
+}
+
+/// decides constraints, 1-bit expressions which have to be 1
+#[derive(Debug, Clone)]
+pub enum Solver {
+    /// bit-blasts the constraints and solves them with the built-in SAT
+    /// solver, gives up after the specified number of conflicts
+    Builtin { conflict_limit: u64 },
+    /// runs an SMT-LIB 2 solver which reads the script from stdin (e.g. `z3
+    /// -in`)
+    Command {
+        program: PathBuf,
+        arguments: Vec<String>,
+    },
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Self::Builtin {
+            conflict_limit: 100_000,
+        }
+    }
+}
+
+impl Solver {
+    /// values for all variables satisfying the constraints, None if they are
+    /// unsatisfiable
+    pub fn solve(&self, constraints: &[Expr]) -> Result<Option<Model>> {
+        match self {
+            Self::Builtin { conflict_limit } => {
+                let mut blaster = blast::Blaster::new();
+                for constraint in constraints {
+                    assert_eq!(constraint.width(), 1);
+                    blaster.assert(constraint);
+                }
+                match blaster.solve(*conflict_limit) {
+                    Some(model) => {
+                        debug_assert!(constraints
+                            .iter()
+                            .all(|constraint| constraint.evaluate(&model) == 1));
+                        Ok(Some(model))
+                    }
+                    None if blaster.unknown() => Err(Error::Solver("conflict limit reached")),
+                    None => Ok(None),
+                }
+            }
+            Self::Command { program, arguments } => {
+                let mut child = Command::new(program)
+                    .args(arguments)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::null())
+                    .spawn()?;
+                child
+                    .stdin
+                    .take()
+                    .unwrap()
+                    .write_all(script(constraints).as_bytes())?;
+                let output = child.wait_with_output()?;
+                smtlib::parse(&String::from_utf8_lossy(&output.stdout))
+            }
+        }
+    }
+}
+
+fn mask(width: u32) -> u64 {
+    u64::MAX >> (64 - width)
+}
+
+fn sign_extend(value: u64, width: u32) -> u64 {
+    ((value << (64 - width)) as i64 >> (64 - width)) as u64
+}
+
+fn evaluate_binary(op: BinaryOp, a: u64, b: u64, width: u32) -> u64 {
+    let ones = mask(width);
+    let (a, b) = (a & ones, b & ones);
+    let value = match op {
+        BinaryOp::Add => a.wrapping_add(b),
+        BinaryOp::Sub => a.wrapping_sub(b),
+        BinaryOp::Mul => a.wrapping_mul(b),
+        BinaryOp::UDiv => a.checked_div(b).unwrap_or(ones),
+        BinaryOp::URem => a.checked_rem(b).unwrap_or(a),
+        BinaryOp::And => a & b,
+        BinaryOp::Or => a | b,
+        BinaryOp::Xor => a ^ b,
+        BinaryOp::Shl if b >= width as u64 => 0,
+        BinaryOp::Shl => a << b,
+        BinaryOp::LShr if b >= width as u64 => 0,
+        BinaryOp::LShr => a >> b,
+        BinaryOp::AShr => (sign_extend(a, width) as i64 >> b.min(63)) as u64,
+        BinaryOp::Eq => (a == b) as u64,
+        BinaryOp::Ult => (a < b) as u64,
+        BinaryOp::Slt => ((sign_extend(a, width) as i64) < sign_extend(b, width) as i64) as u64,
+    };
+    value & ones
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simplification() {
+        let x = Expr::variable(0, 8);
+        assert_eq!(
+            (Expr::constant(0xFF, 8) + Expr::constant(2, 8)).as_constant(),
+            Some(1)
+        );
+        assert_eq!((x.clone() ^ x.clone()).as_constant(), Some(0));
+        assert_eq!(x.clone().equal(x.clone()).as_constant(), Some(1));
+        assert!(!(x.clone() + Expr::constant(0, 8)).is_constant());
+        assert_eq!(
+            Expr::constant(0x1234, 16).extract(8, 8).as_constant(),
+            Some(0x12)
+        );
+        assert_eq!(
+            Expr::constant(0x80, 8).sign_extend(16).as_constant(),
+            Some(0xFF80)
+        );
+    }
+
+    #[test]
+    fn satisfiable() {
+        // 3 * x + 1 == 22 and y < x for 8-bit x and y
+        let x = Expr::variable(0, 8);
+        let y = Expr::variable(1, 8);
+        let constraints = [
+            (x.clone() * Expr::constant(3, 8) + Expr::constant(1, 8)).equal(Expr::constant(22, 8)),
+            y.clone().ult(x.clone()),
+            !y.clone().equal(Expr::constant(0, 8)),
+        ];
+        let model = Solver::default().solve(&constraints).unwrap().unwrap();
+        assert_eq!(model.value(0), Some(7));
+        assert!((1..7).contains(&model.value(1).unwrap()));
+        for constraint in &constraints {
+            assert_eq!(constraint.evaluate(&model), 1);
+        }
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        let x = Expr::variable(0, 32);
+        let solver = Solver::default();
+        // x < 5 and 10 < x
+        let constraints = [
+            x.clone().ult(Expr::constant(5, 32)),
+            Expr::constant(10, 32).ult(x.clone()),
+        ];
+        assert_eq!(solver.solve(&constraints).unwrap(), None);
+        // x * 2 is even
+        let constraints = [(x.clone() * Expr::constant(2, 32))
+            .extract(0, 1)
+            .equal(Expr::constant(1, 1))];
+        assert_eq!(solver.solve(&constraints).unwrap(), None);
+        assert_eq!(solver.solve(&[Expr::bool(false)]).unwrap(), None);
+        assert_eq!(solver.solve(&[]).unwrap(), Some(Model::new()));
+    }
+
+    #[test]
+    fn smtlib() {
+        let x = Expr::variable(3, 8);
+        let script = script(&[x.equal(Expr::constant(1, 8))]);
+        assert!(script.contains("(declare-fun v3 () (_ BitVec 8))"));
+        assert!(script.contains("(get-value (v3))"));
+        let model = smtlib::parse("sat\n((v3 #x01))\n").unwrap().unwrap();
+        assert_eq!(model.value(3), Some(1));
+        assert_eq!(smtlib::parse("unsat\n").unwrap(), None);
+        assert!(smtlib::parse("error").is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    sat::{Lit, Sat},
+    BinaryOp, Expr, Model, Node, Op,
+};
+
+/// bits of a quotient and remainder
+type Division = (Vec<Lit>, Vec<Lit>);
+
+/// translates expressions into clauses, one literal per bit starting with
+/// the lowest
+pub(super) struct Blaster {
+    sat: Sat,
+    true_lit: Lit,
+    cache: HashMap<*const Node, Vec<Lit>>,
+    /// quotient and remainder of each division
+    divisions: HashMap<(*const Node, *const Node), Division>,
+    variables: BTreeMap<u32, Vec<Lit>>,
+    unknown: bool,
+}
+
+impl Blaster {
+    pub(super) fn new() -> Self {
+        let mut sat = Sat::new();
+        let true_lit = Lit::new(sat.new_variable(), false);
+        sat.add_clause(&[true_lit]);
+        Self {
+            sat,
+            true_lit,
+            cache: HashMap::new(),
+            divisions: HashMap::new(),
+            variables: BTreeMap::new(),
+            unknown: false,
+        }
+    }
+
+    /// adds a 1-bit expression which has to be 1
+    pub(super) fn assert(&mut self, expr: &Expr) {
+        let bit = self.bits(expr)[0];
+        self.sat.add_clause(&[bit]);
+    }
+
+    /// a model if satisfiable, see [Blaster::unknown] otherwise
+    pub(super) fn solve(&mut self, conflict_limit: u64) -> Option<Model> {
+        match self.sat.solve(conflict_limit) {
+            Some(true) => {
+                let mut model = Model::new();
+                for (&variable, bits) in &self.variables {
+                    let value = bits
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, &bit)| self.sat.model_value(bit))
+                        .fold(0, |value, (i, _)| value | 1 << i);
+                    model.set(variable, value);
+                }
+                Some(model)
+            }
+            Some(false) => None,
+            None => {
+                self.unknown = true;
+                None
+            }
+        }
+    }
+
+    /// true if solving gave up
+    pub(super) fn unknown(&self) -> bool {
+        self.unknown
+    }
+
+    fn bits(&mut self, expr: &Expr) -> Vec<Lit> {
+        if let Some(bits) = self.cache.get(&expr.key()) {
+            return bits.clone();
+        }
+        let width = expr.width() as usize;
+        let bits = match expr.op() {
+            Op::Constant(value) => (0..width)
+                .map(|i| self.constant(value >> i & 1 != 0))
+                .collect(),
+            Op::Variable(variable) => {
+                let bits = self.variables.get(variable).cloned().unwrap_or_else(|| {
+                    let bits = self.fresh(width);
+                    self.variables.insert(*variable, bits.clone());
+                    bits
+                });
+                assert_eq!(bits.len(), width, "variable used with different widths");
+                bits
+            }
+            Op::Not(inner) => self.bits(inner).into_iter().map(|bit| !bit).collect(),
+            Op::Binary(op, left, right) => {
+                let a = self.bits(left);
+                let b = self.bits(right);
+                match op {
+                    BinaryOp::Add => self.add(&a, &b, self.constant(false)),
+                    BinaryOp::Sub => {
+                        let b: Vec<_> = b.iter().map(|&bit| !bit).collect();
+                        self.add(&a, &b, self.constant(true))
+                    }
+                    BinaryOp::Mul => self.mul(&a, &b),
+                    BinaryOp::UDiv => self.divide(left, right, &a, &b).0,
+                    BinaryOp::URem => self.divide(left, right, &a, &b).1,
+                    BinaryOp::And => a.iter().zip(&b).map(|(&a, &b)| self.and(a, b)).collect(),
+                    BinaryOp::Or => a.iter().zip(&b).map(|(&a, &b)| self.or(a, b)).collect(),
+                    BinaryOp::Xor => a.iter().zip(&b).map(|(&a, &b)| self.xor(a, b)).collect(),
+                    BinaryOp::Shl | BinaryOp::LShr | BinaryOp::AShr => self.shift(*op, &a, &b),
+                    BinaryOp::Eq => vec![self.equal(&a, &b)],
+                    BinaryOp::Ult => vec![self.less(&a, &b)],
+                    BinaryOp::Slt => {
+                        // flipping the sign bits turns it into an unsigned comparison
+                        let (mut a, mut b) = (a, b);
+                        let last = a.len() - 1;
+                        a[last] = !a[last];
+                        b[last] = !b[last];
+                        vec![self.less(&a, &b)]
+                    }
+                }
+            }
+            Op::Extract(low, inner) => {
+                let low = *low as usize;
+                self.bits(inner)[low..low + width].to_vec()
+            }
+            Op::ZeroExtend(inner) => {
+                let mut bits = self.bits(inner);
+                bits.resize(width, self.constant(false));
+                bits
+            }
+            Op::SignExtend(inner) => {
+                let mut bits = self.bits(inner);
+                let sign = *bits.last().unwrap();
+                bits.resize(width, sign);
+                bits
+            }
+            Op::Concat(high, low) => {
+                let mut bits = self.bits(low);
+                bits.extend(self.bits(high));
+                bits
+            }
+            Op::Ite(condition, then, otherwise) => {
+                let condition = self.bits(condition)[0];
+                let then = self.bits(then);
+                let otherwise = self.bits(otherwise);
+                then.iter()
+                    .zip(&otherwise)
+                    .map(|(&then, &otherwise)| self.mux(condition, then, otherwise))
+                    .collect()
+            }
+        };
+        self.cache.insert(expr.key(), bits.clone());
+        bits
+    }
+
+    fn constant(&self, value: bool) -> Lit {
+        if value {
+            self.true_lit
+        } else {
+            !self.true_lit
+        }
+    }
+
+    fn fresh(&mut self, width: usize) -> Vec<Lit> {
+        (0..width)
+            .map(|_| Lit::new(self.sat.new_variable(), false))
+            .collect()
+    }
+
+    fn and(&mut self, a: Lit, b: Lit) -> Lit {
+        let (true_lit, false_lit) = (self.constant(true), self.constant(false));
+        if a == false_lit || b == false_lit || a == !b {
+            return false_lit;
+        }
+        if a == true_lit || a == b {
+            return b;
+        }
+        if b == true_lit {
+            return a;
+        }
+        let c = self.fresh(1)[0];
+        self.sat.add_clause(&[!c, a]);
+        self.sat.add_clause(&[!c, b]);
+        self.sat.add_clause(&[c, !a, !b]);
+        c
+    }
+
+    fn or(&mut self, a: Lit, b: Lit) -> Lit {
+        !self.and(!a, !b)
+    }
+
+    fn xor(&mut self, a: Lit, b: Lit) -> Lit {
+        let true_lit = self.constant(true);
+        if a == !true_lit {
+            return b;
+        }
+        if b == !true_lit {
+            return a;
+        }
+        if a == true_lit {
+            return !b;
+        }
+        if b == true_lit {
+            return !a;
+        }
+        if a == b {
+            return !true_lit;
+        }
+        if a == !b {
+            return true_lit;
+        }
+        let c = self.fresh(1)[0];
+        self.sat.add_clause(&[!c, a, b]);
+        self.sat.add_clause(&[!c, !a, !b]);
+        self.sat.add_clause(&[c, !a, b]);
+        self.sat.add_clause(&[c, a, !b]);
+        c
+    }
+
+    /// then if the condition holds, otherwise else
+    fn mux(&mut self, condition: Lit, then: Lit, otherwise: Lit) -> Lit {
+        let true_lit = self.constant(true);
+        if condition == true_lit || then == otherwise {
+            return then;
+        }
+        if condition == !true_lit {
+            return otherwise;
+        }
+        let c = self.fresh(1)[0];
+        self.sat.add_clause(&[!condition, !then, c]);
+        self.sat.add_clause(&[!condition, then, !c]);
+        self.sat.add_clause(&[condition, !otherwise, c]);
+        self.sat.add_clause(&[condition, otherwise, !c]);
+        c
+    }
+
+    fn add(&mut self, a: &[Lit], b: &[Lit], mut carry: Lit) -> Vec<Lit> {
+        let mut sum = Vec::with_capacity(a.len());
+        for (&a, &b) in a.iter().zip(b) {
+            let half = self.xor(a, b);
+            sum.push(self.xor(half, carry));
+            let generate = self.and(a, b);
+            let propagate = self.and(half, carry);
+            carry = self.or(generate, propagate);
+        }
+        sum
+    }
+
+    /// shift and add, partial products beyond the width are dropped
+    fn mul(&mut self, a: &[Lit], b: &[Lit]) -> Vec<Lit> {
+        let false_lit = self.constant(false);
+        let mut product = vec![false_lit; a.len()];
+        for (i, &bit) in b.iter().enumerate() {
+            if bit == false_lit {
+                continue;
+            }
+            let partial: Vec<_> = a[..a.len() - i].iter().map(|&a| self.and(a, bit)).collect();
+            let sum = self.add(&product[i..], &partial, false_lit);
+            product[i..].copy_from_slice(&sum);
+        }
+        product
+    }
+
+    /// quotient and remainder as fresh bits constrained by
+    /// dividend = quotient * divisor + remainder with remainder < divisor
+    fn divide(&mut self, dividend: &Expr, divisor: &Expr, n: &[Lit], d: &[Lit]) -> Division {
+        let key = (dividend.key(), divisor.key());
+        if let Some(result) = self.divisions.get(&key) {
+            return result.clone();
+        }
+        let width = n.len();
+        let false_lit = self.constant(false);
+        let quotient = self.fresh(width);
+        let remainder = self.fresh(width);
+
+        // the product can't overflow twice the width
+        let extend = |bits: &[Lit]| {
+            let mut bits = bits.to_vec();
+            bits.resize(width * 2, false_lit);
+            bits
+        };
+        let product = self.mul(&extend(&quotient), &extend(d));
+        let sum = self.add(&product, &extend(&remainder), false_lit);
+        let exact = self.equal(&sum, &extend(n));
+        let smaller = self.less(&remainder, d);
+        let valid = self.and(exact, smaller);
+        let zero = self.equal(d, &vec![false_lit; width]);
+        let all_ones = self.equal(&quotient, &vec![!false_lit; width]);
+        let dividend_remains = self.equal(&remainder, n);
+        let by_zero = self.and(all_ones, dividend_remains);
+        self.sat.add_clause(&[zero, valid]);
+        self.sat.add_clause(&[!zero, by_zero]);
+
+        let result = (quotient, remainder);
+        self.divisions.insert(key, result.clone());
+        result
+    }
+
+    /// shift by a variable amount, one stage per bit of the amount
+    fn shift(&mut self, op: BinaryOp, a: &[Lit], amount: &[Lit]) -> Vec<Lit> {
+        let width = a.len();
+        let fill = match op {
+            BinaryOp::AShr => a[width - 1],
+            _ => self.constant(false),
+        };
+        let mut result = a.to_vec();
+        let mut overflow = self.constant(false);
+        for (stage, &bit) in amount.iter().enumerate() {
+            let distance = 1usize.checked_shl(stage as u32).unwrap_or(usize::MAX);
+            if distance >= width {
+                overflow = self.or(overflow, bit);
+                continue;
+            }
+            let shifted: Vec<_> = (0..width)
+                .map(|i| match op {
+                    BinaryOp::Shl if i >= distance => result[i - distance],
+                    BinaryOp::Shl => fill,
+                    _ if i + distance < width => result[i + distance],
+                    _ => fill,
+                })
+                .collect();
+            result = result
+                .iter()
+                .zip(&shifted)
+                .map(|(&keep, &shifted)| self.mux(bit, shifted, keep))
+                .collect();
+        }
+        result
+            .iter()
+            .map(|&bit| self.mux(overflow, fill, bit))
+            .collect()
+    }
+
+    fn equal(&mut self, a: &[Lit], b: &[Lit]) -> Lit {
+        let mut equal = self.constant(true);
+        for (&a, &b) in a.iter().zip(b) {
+            let same = !self.xor(a, b);
+            equal = self.and(equal, same);
+        }
+        equal
+    }
+
+    /// unsigned a < b
+    fn less(&mut self, a: &[Lit], b: &[Lit]) -> Lit {
+        let mut less = self.constant(false);
+        for (&a, &b) in a.iter().zip(b) {
+            // decided by the highest differing bit
+            let smaller = self.and(!a, b);
+            let same = !self.xor(a, b);
+            let keep = self.and(same, less);
+            less = self.or(smaller, keep);
+        }
+        less
+    }
+}
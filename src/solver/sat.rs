@@ -0,0 +1,384 @@
+use std::{collections::BTreeSet, ops::Not};
+
+/// variable or its negation
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(super) struct Lit(u32);
+
+impl Lit {
+    pub(super) fn new(variable: u32, negative: bool) -> Self {
+        Self(variable << 1 | negative as u32)
+    }
+
+    fn variable(self) -> usize {
+        (self.0 >> 1) as usize
+    }
+
+    fn negative(self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Not for Lit {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(self.0 ^ 1)
+    }
+}
+
+/// value of a variable or literal
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Value {
+    Unassigned,
+    True,
+    False,
+}
+
+/// conflict-driven clause learning SAT solver with two watched literals,
+/// first-UIP learning, activity-based decisions, phase saving and restarts
+#[derive(Default)]
+pub(super) struct Sat {
+    /// the first two literals of each clause are watched
+    clauses: Vec<Vec<Lit>>,
+    /// clauses watching each literal
+    watches: Vec<Vec<usize>>,
+    values: Vec<Value>,
+    levels: Vec<u32>,
+    /// clause which implied each variable, None for decisions
+    reasons: Vec<Option<usize>>,
+    trail: Vec<Lit>,
+    /// start of each decision level in the trail
+    trail_limits: Vec<usize>,
+    /// next trail entry to propagate
+    head: usize,
+    activities: Vec<f64>,
+    increment: f64,
+    /// unassigned variables by activity
+    order: BTreeSet<(u64, u32)>,
+    phases: Vec<bool>,
+    seen: Vec<bool>,
+    unsatisfiable: bool,
+}
+
+impl Sat {
+    pub(super) fn new() -> Self {
+        Self {
+            increment: 1.0,
+            ..Default::default()
+        }
+    }
+
+    pub(super) fn new_variable(&mut self) -> u32 {
+        let variable = self.values.len() as u32;
+        self.values.push(Value::Unassigned);
+        self.levels.push(0);
+        self.reasons.push(None);
+        self.activities.push(0.0);
+        self.phases.push(false);
+        self.seen.push(false);
+        self.watches.push(vec![]);
+        self.watches.push(vec![]);
+        self.order.insert((0, variable));
+        variable
+    }
+
+    /// value of the variable in the solution
+    pub(super) fn model_value(&self, lit: Lit) -> bool {
+        self.value(lit) == Value::True
+    }
+
+    /// adds a clause before solving
+    pub(super) fn add_clause(&mut self, lits: &[Lit]) {
+        if self.unsatisfiable {
+            return;
+        }
+        let mut clause = Vec::with_capacity(lits.len());
+        for &lit in lits {
+            match self.value(lit) {
+                Value::True => return,
+                Value::False => {}
+                Value::Unassigned => {
+                    // tautology
+                    if clause.contains(&!lit) {
+                        return;
+                    }
+                    if !clause.contains(&lit) {
+                        clause.push(lit)
+                    }
+                }
+            }
+        }
+        match clause.len() {
+            0 => self.unsatisfiable = true,
+            1 => {
+                self.assign(clause[0], None);
+                if self.propagate().is_some() {
+                    self.unsatisfiable = true
+                }
+            }
+            _ => {
+                self.attach(clause);
+            }
+        }
+    }
+
+    /// Some(true) if satisfiable, None if the conflict limit was reached
+    pub(super) fn solve(&mut self, conflict_limit: u64) -> Option<bool> {
+        if self.unsatisfiable {
+            return Some(false);
+        }
+        let mut conflicts = 0;
+        let mut restart = 0;
+        let mut restart_conflicts = 0;
+        loop {
+            if let Some(conflict) = self.propagate() {
+                conflicts += 1;
+                restart_conflicts += 1;
+                if self.trail_limits.is_empty() {
+                    self.unsatisfiable = true;
+                    return Some(false);
+                }
+                let (learnt, level) = self.analyze(conflict);
+                self.backtrack(level);
+                if learnt.len() == 1 {
+                    self.assign(learnt[0], None);
+                } else {
+                    let first = learnt[0];
+                    let clause = self.attach(learnt);
+                    self.assign(first, Some(clause));
+                }
+                self.increment /= 0.95;
+                if conflicts >= conflict_limit {
+                    self.backtrack(0);
+                    return None;
+                }
+                if restart_conflicts >= luby(restart) * 100 {
+                    restart += 1;
+                    restart_conflicts = 0;
+                    self.backtrack(0);
+                }
+            } else {
+                let Some(variable) = self.decide() else {
+                    return Some(true);
+                };
+                self.trail_limits.push(self.trail.len());
+                self.assign(Lit::new(variable, !self.phases[variable as usize]), None);
+            }
+        }
+    }
+
+    fn value(&self, lit: Lit) -> Value {
+        value(&self.values, lit)
+    }
+
+    fn attach(&mut self, clause: Vec<Lit>) -> usize {
+        let index = self.clauses.len();
+        self.watches[clause[0].index()].push(index);
+        self.watches[clause[1].index()].push(index);
+        self.clauses.push(clause);
+        index
+    }
+
+    fn assign(&mut self, lit: Lit, reason: Option<usize>) {
+        let variable = lit.variable();
+        self.values[variable] = if lit.negative() {
+            Value::False
+        } else {
+            Value::True
+        };
+        self.levels[variable] = self.trail_limits.len() as u32;
+        self.reasons[variable] = reason;
+        self.trail.push(lit);
+    }
+
+    /// returns the conflicting clause if any
+    fn propagate(&mut self) -> Option<usize> {
+        while self.head < self.trail.len() {
+            let lit = self.trail[self.head];
+            self.head += 1;
+            let false_lit = !lit;
+            let mut watchers = std::mem::take(&mut self.watches[false_lit.index()]);
+            let mut kept = 0;
+            let mut conflict = None;
+            let mut i = 0;
+            while i < watchers.len() {
+                let index = watchers[i];
+                i += 1;
+                let clause = &mut self.clauses[index];
+                if clause[0] == false_lit {
+                    clause.swap(0, 1);
+                }
+                let first = clause[0];
+                if value(&self.values, first) == Value::True {
+                    watchers[kept] = index;
+                    kept += 1;
+                    continue;
+                }
+                // look for another literal to watch
+                if let Some(k) =
+                    (2..clause.len()).find(|&k| value(&self.values, clause[k]) != Value::False)
+                {
+                    clause.swap(1, k);
+                    self.watches[clause[1].index()].push(index);
+                    continue;
+                }
+                watchers[kept] = index;
+                kept += 1;
+                if value(&self.values, first) == Value::False {
+                    conflict = Some(index);
+                    while i < watchers.len() {
+                        watchers[kept] = watchers[i];
+                        kept += 1;
+                        i += 1;
+                    }
+                } else {
+                    self.assign(first, Some(index));
+                }
+            }
+            watchers.truncate(kept);
+            self.watches[false_lit.index()] = watchers;
+            if conflict.is_some() {
+                self.head = self.trail.len();
+                return conflict;
+            }
+        }
+        None
+    }
+
+    /// learnt clause with the asserting literal first and the level to
+    /// backtrack to
+    fn analyze(&mut self, conflict: usize) -> (Vec<Lit>, usize) {
+        let level = self.trail_limits.len() as u32;
+        let mut learnt = vec![Lit(0)];
+        let mut pending = 0;
+        let mut clause = conflict;
+        let mut lit = None;
+        let mut index = self.trail.len();
+        loop {
+            let skip = lit.is_some() as usize;
+            for k in skip..self.clauses[clause].len() {
+                let other = self.clauses[clause][k];
+                let variable = other.variable();
+                if self.seen[variable] || self.levels[variable] == 0 {
+                    continue;
+                }
+                self.seen[variable] = true;
+                self.bump(variable);
+                if self.levels[variable] == level {
+                    pending += 1;
+                } else {
+                    learnt.push(other);
+                }
+            }
+            // next literal of the current level on the trail
+            loop {
+                index -= 1;
+                if self.seen[self.trail[index].variable()] {
+                    break;
+                }
+            }
+            let current = self.trail[index];
+            self.seen[current.variable()] = false;
+            pending -= 1;
+            lit = Some(current);
+            if pending == 0 {
+                break;
+            }
+            clause = self.reasons[current.variable()].unwrap();
+        }
+        learnt[0] = !lit.unwrap();
+        for other in &learnt[1..] {
+            self.seen[other.variable()] = false;
+        }
+
+        // the literal of the highest level is watched second
+        let mut backtrack = 0;
+        if learnt.len() > 1 {
+            let mut highest = 1;
+            for k in 2..learnt.len() {
+                if self.levels[learnt[k].variable()] > self.levels[learnt[highest].variable()] {
+                    highest = k;
+                }
+            }
+            learnt.swap(1, highest);
+            backtrack = self.levels[learnt[1].variable()] as usize;
+        }
+        (learnt, backtrack)
+    }
+
+    fn backtrack(&mut self, level: usize) {
+        if self.trail_limits.len() <= level {
+            return;
+        }
+        let start = self.trail_limits[level];
+        for lit in self.trail.drain(start..) {
+            let variable = lit.variable();
+            self.phases[variable] = !lit.negative();
+            self.values[variable] = Value::Unassigned;
+            self.reasons[variable] = None;
+            self.order
+                .insert((self.activities[variable].to_bits(), variable as u32));
+        }
+        self.trail_limits.truncate(level);
+        self.head = self.trail.len();
+    }
+
+    fn decide(&mut self) -> Option<u32> {
+        // assigned variables are reinserted when backtracking
+        while let Some((_, variable)) = self.order.pop_last() {
+            if self.values[variable as usize] == Value::Unassigned {
+                return Some(variable);
+            }
+        }
+        None
+    }
+
+    fn bump(&mut self, variable: usize) {
+        let old = (self.activities[variable].to_bits(), variable as u32);
+        let queued = self.order.remove(&old);
+        self.activities[variable] += self.increment;
+        if self.activities[variable] > 1e100 {
+            for activity in &mut self.activities {
+                *activity *= 1e-100;
+            }
+            self.increment *= 1e-100;
+            self.order = self
+                .order
+                .iter()
+                .map(|&(_, variable)| (self.activities[variable as usize].to_bits(), variable))
+                .collect();
+        }
+        if queued {
+            self.order
+                .insert((self.activities[variable].to_bits(), variable as u32));
+        }
+    }
+}
+
+fn value(values: &[Value], lit: Lit) -> Value {
+    match (values[lit.variable()], lit.negative()) {
+        (Value::Unassigned, _) => Value::Unassigned,
+        (Value::True, false) | (Value::False, true) => Value::True,
+        _ => Value::False,
+    }
+}
+
+/// 1, 1, 2, 1, 1, 2, 4, 1, ... starting at index 0
+fn luby(mut index: u64) -> u64 {
+    let mut size = 1;
+    let mut exponent = 0;
+    while size < index + 1 {
+        exponent += 1;
+        size = 2 * size + 1;
+    }
+    while size - 1 != index {
+        size = (size - 1) >> 1;
+        exponent -= 1;
+        index %= size;
+    }
+    1 << exponent
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use super::{BinaryOp, Expr, Model, Node, Op};
+use crate::{Error, Result};
+
+/// SMT-LIB 2 script asserting the constraints and querying the values of all
+/// variables, variable n is named vn
+pub fn script(constraints: &[Expr]) -> String {
+    let mut printer = Printer::default();
+    let names: Vec<_> = constraints
+        .iter()
+        .map(|constraint| printer.name(constraint))
+        .collect();
+
+    let mut script = String::from("(set-logic QF_BV)\n");
+    for (variable, width) in &printer.variables {
+        writeln!(script, "(declare-fun v{variable} () (_ BitVec {width}))").unwrap();
+    }
+    script.push_str(&printer.definitions);
+    for name in names {
+        writeln!(script, "(assert (= {name} #b1))").unwrap();
+    }
+    script.push_str("(check-sat)\n");
+    if !printer.variables.is_empty() {
+        script.push_str("(get-value (");
+        for (i, variable) in printer.variables.keys().enumerate() {
+            if i != 0 {
+                script.push(' ');
+            }
+            write!(script, "v{variable}").unwrap();
+        }
+        script.push_str("))\n");
+    }
+    script.push_str("(exit)\n");
+    script
+}
+
+/// parses the response to [script]
+pub(super) fn parse(output: &str) -> Result<Option<Model>> {
+    let tokens: Vec<_> = output
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect();
+    match tokens.first().map(String::as_str) {
+        Some("sat") => {}
+        Some("unsat") => return Ok(None),
+        Some("unknown") => return Err(Error::Solver("unknown")),
+        _ => return Err(Error::Solver("unexpected output")),
+    }
+
+    // pairs of name and value, the value is either #x.., #b.. or (_ bvN W)
+    let mut model = Model::new();
+    let mut i = 1;
+    while i < tokens.len() {
+        let Some(variable) = tokens[i]
+            .strip_prefix('v')
+            .and_then(|variable| variable.parse().ok())
+        else {
+            i += 1;
+            continue;
+        };
+        let value = match tokens.get(i + 1).map(String::as_str) {
+            Some(value) if value.starts_with("#x") => u64::from_str_radix(&value[2..], 16).ok(),
+            Some(value) if value.starts_with("#b") => u64::from_str_radix(&value[2..], 2).ok(),
+            Some("(") if tokens.get(i + 2).is_some_and(|token| token == "_") => tokens
+                .get(i + 3)
+                .and_then(|value| value.strip_prefix("bv"))
+                .and_then(|value| value.parse().ok()),
+            _ => None,
+        };
+        let value = value.ok_or(Error::Solver("unexpected value"))?;
+        model.set(variable, value);
+        i += 2;
+    }
+    Ok(Some(model))
+}
+
+/// defines every shared node once
+#[derive(Default)]
+struct Printer {
+    names: HashMap<*const Node, String>,
+    variables: BTreeMap<u32, u32>,
+    definitions: String,
+}
+
+impl Printer {
+    fn name(&mut self, expr: &Expr) -> String {
+        if let Some(name) = self.names.get(&expr.key()) {
+            return name.clone();
+        }
+        let width = expr.width();
+        let definition = match expr.op() {
+            Op::Constant(value) => format!("(_ bv{value} {width})"),
+            Op::Variable(variable) => {
+                self.variables.insert(*variable, width);
+                format!("v{variable}")
+            }
+            Op::Not(inner) => format!("(bvnot {})", self.name(inner)),
+            Op::Binary(op, left, right) => {
+                let (left, right) = (self.name(left), self.name(right));
+                let function = match op {
+                    BinaryOp::Add => "bvadd",
+                    BinaryOp::Sub => "bvsub",
+                    BinaryOp::Mul => "bvmul",
+                    BinaryOp::UDiv => "bvudiv",
+                    BinaryOp::URem => "bvurem",
+                    BinaryOp::And => "bvand",
+                    BinaryOp::Or => "bvor",
+                    BinaryOp::Xor => "bvxor",
+                    BinaryOp::Shl => "bvshl",
+                    BinaryOp::LShr => "bvlshr",
+                    BinaryOp::AShr => "bvashr",
+                    BinaryOp::Eq => "=",
+                    BinaryOp::Ult => "bvult",
+                    BinaryOp::Slt => "bvslt",
+                };
+                match op {
+                    BinaryOp::Eq | BinaryOp::Ult | BinaryOp::Slt => {
+                        format!("(ite ({function} {left} {right}) #b1 #b0)")
+                    }
+                    _ => format!("({function} {left} {right})"),
+                }
+            }
+            Op::Extract(low, inner) => format!(
+                "((_ extract {} {low}) {})",
+                low + width - 1,
+                self.name(inner)
+            ),
+            Op::ZeroExtend(inner) => format!(
+                "((_ zero_extend {}) {})",
+                width - inner.width(),
+                self.name(inner)
+            ),
+            Op::SignExtend(inner) => format!(
+                "((_ sign_extend {}) {})",
+                width - inner.width(),
+                self.name(inner)
+            ),
+            Op::Concat(high, low) => {
+                format!("(concat {} {})", self.name(high), self.name(low))
+            }
+            Op::Ite(condition, then, otherwise) => format!(
+                "(ite (= {} #b1) {} {})",
+                self.name(condition),
+                self.name(then),
+                self.name(otherwise)
+            ),
+        };
+        let name = match expr.op() {
+            Op::Constant(_) | Op::Variable(_) => definition,
+            _ => {
+                let name = format!("e{}", self.names.len());
+                writeln!(
+                    self.definitions,
+                    "(define-fun {name} () (_ BitVec {width}) {definition})"
+                )
+                .unwrap();
+                name
+            }
+        };
+        self.names.insert(expr.key(), name.clone());
+        name
+    }
+}
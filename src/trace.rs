@@ -0,0 +1,520 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    ops::Range,
+};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+#[cfg(windows)]
+use windows::Win32::System::Diagnostics::Debug::CONTEXT;
+
+use crate::{Error, Result};
+
+const MAGIC: &[u8; 4] = b"MBGT";
+const VERSION: u16 = 1;
+
+/// number of registers recorded per step: rax, rcx, rdx, rbx, rsp, rbp, rsi,
+/// rdi, r8-r15 (in the order of the emulator), rip, rflags, fs base and gs base
+pub const REGISTER_COUNT: usize = 20;
+
+pub const RIP: usize = 16;
+pub const RFLAGS: usize = 17;
+pub const FS_BASE: usize = 18;
+pub const GS_BASE: usize = 19;
+
+// record header: number of changed registers, accesses present, thread switch
+const CHANGED_REGISTERS: u8 = 0x1F;
+const HAS_ACCESSES: u8 = 0x20;
+const THREAD_SWITCH: u8 = 0x40;
+
+pub type Registers = [u64; REGISTER_COUNT];
+
+/// memory read or written by an instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: u64,
+    pub data: Vec<u8>,
+    pub write: bool,
+}
+
+impl MemoryAccess {
+    /// addresses covered by the access
+    pub fn range(&self) -> Range<u64> {
+        self.address..self.address + self.data.len() as u64
+    }
+}
+
+/// single executed instruction
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub thread: u32,
+    pub address: u64,
+    /// registers of the thread (except rip) which changed since its previous
+    /// step, the values are the ones before the instruction executed
+    pub registers: Vec<(u8, u64)>,
+    /// accesses of the instruction in order
+    pub accesses: Vec<MemoryAccess>,
+}
+
+/// records steps in a compact binary format, registers are stored as deltas
+/// to the previous step of the same thread and addresses as deltas to the
+/// previous address
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    /// registers of the previous step of each thread
+    registers: BTreeMap<u32, Registers>,
+    thread: Option<u32>,
+    address: u64,
+    access_address: u64,
+    count: u64,
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// writes the header
+    pub fn new(mut writer: W) -> Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_u16::<LE>(VERSION)?;
+        writer.write_u8(REGISTER_COUNT as u8)?;
+        Ok(Self {
+            writer,
+            registers: Default::default(),
+            thread: None,
+            address: 0,
+            access_address: 0,
+            count: 0,
+        })
+    }
+
+    /// records an instruction, the registers are the ones before and the
+    /// accesses the ones of its execution
+    pub fn record(
+        &mut self,
+        thread: u32,
+        registers: &Registers,
+        accesses: &[MemoryAccess],
+    ) -> Result<()> {
+        let previous = self.registers.entry(thread).or_insert([0; REGISTER_COUNT]);
+        let changed = (0..REGISTER_COUNT)
+            .filter(|&index| index != RIP && registers[index] != previous[index])
+            .collect::<Vec<_>>();
+
+        let mut header = changed.len() as u8;
+        if !accesses.is_empty() {
+            header |= HAS_ACCESSES;
+        }
+        if self.thread != Some(thread) {
+            header |= THREAD_SWITCH;
+        }
+        self.writer.write_u8(header)?;
+        if self.thread != Some(thread) {
+            write_varint(&mut self.writer, thread as u64)?;
+            self.thread = Some(thread);
+        }
+        write_signed(&mut self.writer, registers[RIP].wrapping_sub(self.address))?;
+        self.address = registers[RIP];
+        for index in changed {
+            self.writer.write_u8(index as u8)?;
+            write_signed(
+                &mut self.writer,
+                registers[index].wrapping_sub(previous[index]),
+            )?;
+        }
+        *previous = *registers;
+
+        if !accesses.is_empty() {
+            write_varint(&mut self.writer, accesses.len() as u64)?;
+            for access in accesses {
+                write_varint(
+                    &mut self.writer,
+                    (access.data.len() as u64) << 1 | access.write as u64,
+                )?;
+                write_signed(
+                    &mut self.writer,
+                    access.address.wrapping_sub(self.access_address),
+                )?;
+                self.access_address = access.address;
+                self.writer.write_all(&access.data)?;
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// number of recorded steps
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// flushes and returns the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// trace loaded for querying
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    steps: Vec<Step>,
+    /// steps by address
+    executions: BTreeMap<u64, Vec<usize>>,
+}
+
+impl Trace {
+    /// reads a trace written by [TraceWriter], a trace cut off at the end of
+    /// a step (e.g. the recording process crashed) is read up to that step
+    pub fn read(mut reader: impl Read) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Trace("bad magic"));
+        }
+        if reader.read_u16::<LE>()? != VERSION {
+            return Err(Error::Trace("unsupported version"));
+        }
+        if reader.read_u8()? as usize != REGISTER_COUNT {
+            return Err(Error::Trace("unsupported register count"));
+        }
+
+        let mut trace = Self::default();
+        let mut registers = BTreeMap::<u32, Registers>::new();
+        let mut thread = 0;
+        let mut address = 0u64;
+        let mut access_address = 0u64;
+        loop {
+            let header = match reader.read_u8() {
+                Ok(header) => header,
+                Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error.into()),
+            };
+            if header & THREAD_SWITCH != 0 {
+                thread = read_varint(&mut reader)? as u32;
+            }
+            address = address.wrapping_add(read_signed(&mut reader)?);
+            let thread_registers = registers.entry(thread).or_insert([0; REGISTER_COUNT]);
+            let mut changed = vec![];
+            for _ in 0..header & CHANGED_REGISTERS {
+                let index = reader.read_u8()?;
+                if index as usize >= REGISTER_COUNT || index as usize == RIP {
+                    return Err(Error::Trace("invalid register"));
+                }
+                let value =
+                    thread_registers[index as usize].wrapping_add(read_signed(&mut reader)?);
+                thread_registers[index as usize] = value;
+                changed.push((index, value));
+            }
+            thread_registers[RIP] = address;
+
+            let mut accesses = vec![];
+            if header & HAS_ACCESSES != 0 {
+                for _ in 0..read_varint(&mut reader)? {
+                    let size_and_kind = read_varint(&mut reader)?;
+                    access_address = access_address.wrapping_add(read_signed(&mut reader)?);
+                    let mut data = vec![0; (size_and_kind >> 1) as usize];
+                    reader.read_exact(&mut data)?;
+                    accesses.push(MemoryAccess {
+                        address: access_address,
+                        data,
+                        write: size_and_kind & 1 != 0,
+                    });
+                }
+            }
+
+            trace
+                .executions
+                .entry(address)
+                .or_default()
+                .push(trace.steps.len());
+            trace.steps.push(Step {
+                thread,
+                address,
+                registers: changed,
+                accesses,
+            });
+        }
+        Ok(trace)
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// all registers of the thread before the step executed
+    pub fn registers(&self, index: usize) -> Registers {
+        let thread = self.steps[index].thread;
+        let mut registers = [0; REGISTER_COUNT];
+        let mut known = [false; REGISTER_COUNT];
+        registers[RIP] = self.steps[index].address;
+        known[RIP] = true;
+        for step in self.steps[..=index]
+            .iter()
+            .rev()
+            .filter(|step| step.thread == thread)
+        {
+            for &(register, value) in &step.registers {
+                if !known[register as usize] {
+                    registers[register as usize] = value;
+                    known[register as usize] = true;
+                }
+            }
+            if known.iter().all(|&known| known) {
+                break;
+            }
+        }
+        registers
+    }
+
+    /// steps which executed the instruction at the address (e.g. the start of
+    /// a block), in order
+    pub fn executions(&self, address: u64) -> &[usize] {
+        self.executions
+            .get(&address)
+            .map_or(&[], |executions| executions.as_slice())
+    }
+
+    /// steps which executed an instruction in the range, in order
+    pub fn executions_in(&self, range: Range<u64>) -> Vec<usize> {
+        let mut executions = self
+            .executions
+            .range(range)
+            .flat_map(|(_, executions)| executions.iter().copied())
+            .collect::<Vec<_>>();
+        executions.sort_unstable();
+        executions
+    }
+
+    /// accesses overlapping the range, in order
+    pub fn accesses(&self, range: Range<u64>) -> impl Iterator<Item = (usize, &MemoryAccess)> {
+        self.steps
+            .iter()
+            .enumerate()
+            .flat_map(move |(index, step)| {
+                let range = range.clone();
+                step.accesses
+                    .iter()
+                    .filter(move |access| {
+                        let access_range = access.range();
+                        access_range.start < range.end && range.start < access_range.end
+                    })
+                    .map(move |access| (index, access))
+            })
+    }
+
+    /// last step before the specified one which wrote to the address
+    pub fn last_write(&self, address: u64, before: usize) -> Option<usize> {
+        self.steps[..before.min(self.steps.len())]
+            .iter()
+            .rposition(|step| {
+                step.accesses
+                    .iter()
+                    .any(|access| access.write && access.range().contains(&address))
+            })
+    }
+
+    /// value of the byte at the address before the step executed as far as
+    /// known from the accesses
+    pub fn value(&self, address: u64, before: usize) -> Option<u8> {
+        self.steps[..before.min(self.steps.len())]
+            .iter()
+            .rev()
+            .flat_map(|step| step.accesses.iter().rev())
+            .find(|access| access.range().contains(&address))
+            .map(|access| access.data[(address - access.address) as usize])
+    }
+}
+
+/// registers of a thread context captured while single-stepping (see
+/// [crate::process::Process::run_traced]), the segment bases are not part of
+/// the context and recorded as 0
+#[cfg(windows)]
+pub fn context_registers(context: &CONTEXT) -> Registers {
+    [
+        context.Rax,
+        context.Rcx,
+        context.Rdx,
+        context.Rbx,
+        context.Rsp,
+        context.Rbp,
+        context.Rsi,
+        context.Rdi,
+        context.R8,
+        context.R9,
+        context.R10,
+        context.R11,
+        context.R12,
+        context.R13,
+        context.R14,
+        context.R15,
+        context.Rip,
+        context.EFlags as u64,
+        0,
+        0,
+    ]
+}
+
+/// unsigned leb128
+fn write_varint(writer: &mut impl Write, mut value: u64) -> Result<()> {
+    loop {
+        let byte = value as u8 & 0x7F;
+        value >>= 7;
+        if value == 0 {
+            writer.write_u8(byte)?;
+            return Ok(());
+        }
+        writer.write_u8(byte | 0x80)?;
+    }
+}
+
+fn read_varint(reader: &mut impl Read) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = reader.read_u8()?;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::Trace("invalid varint"))
+}
+
+/// zigzag encoded, small negative deltas stay small
+fn write_signed(writer: &mut impl Write, value: u64) -> Result<()> {
+    let value = value as i64;
+    write_varint(writer, ((value << 1) ^ (value >> 63)) as u64)
+}
+
+fn read_signed(reader: &mut impl Read) -> Result<u64> {
+    let value = read_varint(reader)?;
+    Ok((value >> 1) ^ (value & 1).wrapping_neg())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAX: usize = 0;
+
+    fn registers(value: u64) -> Registers {
+        let mut registers = [value; REGISTER_COUNT];
+        registers[RIP] = 0x1000 + value;
+        registers
+    }
+
+    fn write(address: u64, data: &[u8]) -> MemoryAccess {
+        MemoryAccess {
+            address,
+            data: data.to_vec(),
+            write: true,
+        }
+    }
+
+    /// three steps on two threads, the registers of the third one decrease
+    fn trace() -> Vec<u8> {
+        let mut writer = TraceWriter::new(vec![]).unwrap();
+        writer
+            .record(1, &registers(5), &[write(0x100, &[1, 2])])
+            .unwrap();
+        writer
+            .record(
+                2,
+                &registers(7),
+                &[
+                    MemoryAccess {
+                        address: 0x101,
+                        data: vec![2],
+                        write: false,
+                    },
+                    write(0x80, &[3]),
+                ],
+            )
+            .unwrap();
+        writer
+            .record(1, &registers(3), &[write(0x101, &[4])])
+            .unwrap();
+        assert_eq!(writer.count(), 3);
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let trace = Trace::read(trace().as_slice()).unwrap();
+        assert_eq!(trace.len(), 3);
+        assert_eq!(
+            trace
+                .steps()
+                .iter()
+                .map(|step| (step.thread, step.address))
+                .collect::<Vec<_>>(),
+            [(1, 0x1005), (2, 0x1007), (1, 0x1003)]
+        );
+        for (index, value) in [(0, 5), (1, 7), (2, 3)] {
+            assert_eq!(trace.registers(index), registers(value));
+        }
+        assert_eq!(trace.steps()[1].accesses[1], write(0x80, &[3]));
+    }
+
+    #[test]
+    fn unchanged_registers() {
+        let mut writer = TraceWriter::new(vec![]).unwrap();
+        let mut registers = registers(1);
+        writer.record(1, &registers, &[]).unwrap();
+        registers[RIP] += 4;
+        registers[RAX] = 2;
+        writer.record(1, &registers, &[]).unwrap();
+        let trace = Trace::read(writer.finish().unwrap().as_slice()).unwrap();
+        // only rax is recorded for the second step
+        assert_eq!(trace.steps()[1].registers, [(RAX as u8, 2)]);
+        assert_eq!(trace.registers(1), registers);
+    }
+
+    #[test]
+    fn queries() {
+        let trace = Trace::read(trace().as_slice()).unwrap();
+        assert_eq!(trace.executions(0x1007), &[1]);
+        assert_eq!(trace.executions(0x2000), &[] as &[usize]);
+        assert_eq!(trace.executions_in(0x1000..0x1006), [0, 2]);
+        assert_eq!(
+            trace
+                .accesses(0x101..0x102)
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        assert_eq!(trace.last_write(0x101, 3), Some(2));
+        assert_eq!(trace.last_write(0x101, 2), Some(0));
+        assert_eq!(trace.last_write(0x102, 3), None);
+        assert_eq!(trace.value(0x101, 3), Some(4));
+        assert_eq!(trace.value(0x101, 2), Some(2));
+        assert_eq!(trace.value(0x100, 1), Some(1));
+        assert_eq!(trace.value(0x100, 0), None);
+    }
+
+    #[test]
+    fn truncated() {
+        let data = trace();
+        // cut off after the first step (header, rip, registers and access)
+        let mut writer = TraceWriter::new(vec![]).unwrap();
+        writer
+            .record(1, &registers(5), &[write(0x100, &[1, 2])])
+            .unwrap();
+        let first = writer.finish().unwrap().len();
+        assert_eq!(Trace::read(&data[..first]).unwrap().len(), 1);
+        // cut off inside a step
+        assert!(Trace::read(&data[..data.len() - 1]).is_err());
+
+        let mut data = data;
+        data[0] = b'X';
+        assert!(matches!(
+            Trace::read(data.as_slice()),
+            Err(Error::Trace("bad magic"))
+        ));
+    }
+}
@@ -0,0 +1,667 @@
+use std::{collections::BTreeMap, mem::size_of};
+
+use object::{
+    pe::{
+        ImageDataDirectory, ImageDosHeader, ImageFileHeader, ImageImportDescriptor,
+        ImageNtHeaders64, ImageSectionHeader, IMAGE_DIRECTORY_ENTRY_BOUND_IMPORT,
+        IMAGE_DIRECTORY_ENTRY_IAT, IMAGE_DIRECTORY_ENTRY_IMPORT, IMAGE_ORDINAL_FLAG64,
+        IMAGE_SCN_CNT_CODE, IMAGE_SCN_CNT_INITIALIZED_DATA, IMAGE_SCN_MEM_EXECUTE,
+        IMAGE_SCN_MEM_READ,
+    },
+    pod::{from_bytes_mut, slice_from_bytes_mut},
+    read::pe::ImageNtHeaders,
+    LittleEndian as LE,
+};
+
+use crate::{memory::Memory, module::Module, Error, Result};
+
+/// name of the section holding the rebuilt import directory
+pub const IMPORT_SECTION_NAME: &[u8; 8] = b".mbgimp\0";
+
+const PAGE_SIZE: usize = 0x1000;
+
+/// resolved slot of an import address table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub library: String,
+    /// function name or #ordinal
+    pub function: String,
+    /// address of the slot
+    pub thunk: usize,
+}
+
+/// reads a mapped image, pages which can't be read are zero-filled
+pub fn read_image(memory: &impl Memory, base: usize) -> Result<Vec<u8>> {
+    let headers = memory.read_vec(base, PAGE_SIZE)?;
+    let (nt_headers, _) = nt_headers(&headers)?;
+    let size = nt_headers.optional_header.size_of_image.get(LE) as usize;
+    let mut data = vec![0; size];
+    for (i, page) in data.chunks_mut(PAGE_SIZE).enumerate() {
+        let _ = memory.read(base + i * PAGE_SIZE, page);
+    }
+    Ok(data)
+}
+
+/// known functions by address to recognize import address tables, an
+/// address can be known under several names (e.g. forwarded exports)
+#[derive(Debug, Clone, Default)]
+pub struct Exports {
+    functions: BTreeMap<u64, Vec<(String, String)>>,
+}
+
+impl Exports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// exports of the modules, forwarded exports are resolved to the address
+    /// of their target if it is one of the modules
+    pub fn from_modules<'a>(modules: impl IntoIterator<Item = &'a Module>) -> Result<Self> {
+        let mut exports = Self::new();
+        let mut addresses = BTreeMap::new();
+        let mut forwards = vec![];
+        for module in modules {
+            let library = library_name(module.name());
+            for (function, address) in module.exports()? {
+                addresses.insert((library.clone(), function.clone()), address as u64);
+                exports.insert(address as u64, module.name(), &function);
+            }
+            for (function, target_library, target) in module.forwards()? {
+                forwards.push((
+                    module.name(),
+                    function,
+                    (library_name(&target_library), target),
+                ));
+            }
+        }
+
+        // forwards can be chained
+        let mut resolved = true;
+        while resolved {
+            resolved = false;
+            forwards.retain(|(library, function, target)| {
+                let Some(&address) = addresses.get(target) else {
+                    return true;
+                };
+                addresses.insert((library_name(library), function.clone()), address);
+                exports.insert(address, library, function);
+                resolved = true;
+                false
+            });
+        }
+        Ok(exports)
+    }
+
+    /// adds a name for the address
+    pub fn insert(&mut self, address: u64, library: &str, function: &str) {
+        let names = self.functions.entry(address).or_default();
+        let name = (library.to_owned(), function.to_owned());
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    /// names of the address in the order they were added
+    pub fn get(&self, address: u64) -> &[(String, String)] {
+        self.functions
+            .get(&address)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// searches the image for arrays of slots pointing to known functions,
+    /// slots of an address known under several names get the library which
+    /// the most of the following slots share, so forwarded functions are
+    /// grouped with the library they were imported from, ties go to the name
+    /// added last (forwards), the imports are ordered by address
+    pub fn find_imports(&self, data: &[u8], base: usize) -> Vec<Import> {
+        let slots: Vec<_> = data
+            .chunks_exact(8)
+            .map(|value| self.get(u64::from_le_bytes(value.try_into().unwrap())))
+            .collect();
+        let mut imports = vec![];
+        let mut i = 0;
+        while i < slots.len() {
+            if slots[i].is_empty() {
+                i += 1;
+                continue;
+            }
+            let shared = |library: &str| {
+                slots[i..]
+                    .iter()
+                    .take_while(|names| {
+                        names
+                            .iter()
+                            .any(|(other, _)| other.eq_ignore_ascii_case(library))
+                    })
+                    .count()
+            };
+            let mut library = &slots[i][0].0;
+            let mut count = shared(library);
+            for (other, _) in &slots[i][1..] {
+                let other_count = shared(other);
+                if other_count >= count {
+                    library = other;
+                    count = other_count;
+                }
+            }
+            for (j, names) in slots[i..i + count].iter().enumerate() {
+                let (library, function) = names
+                    .iter()
+                    .find(|(other, _)| other.eq_ignore_ascii_case(library))
+                    .unwrap();
+                imports.push(Import {
+                    library: library.clone(),
+                    function: function.clone(),
+                    thunk: base + (i + j) * 8,
+                });
+            }
+            i += count;
+        }
+        imports
+    }
+}
+
+/// dumps a module as a PE file, the import directory is rebuilt from the
+/// slots pointing to exports of the other modules (see [rebuild_imports])
+pub fn dump(memory: &impl Memory, module: &Module, modules: &[Module]) -> Result<Vec<u8>> {
+    let data = read_image(memory, module.base())?;
+    let others = modules.iter().filter(|other| other.base() != module.base());
+    let imports = Exports::from_modules(others)?.find_imports(&data, module.base());
+    rebuild_imports(data, module.base(), &imports)
+}
+
+/// turns a mapped image into a PE file with another entry point, see
+/// [rebuild_imports], the section of the entry point is made executable
+pub fn rebuild(
+    mut data: Vec<u8>,
+    base: usize,
+    entry_point: usize,
+    imports: &[Import],
+) -> Result<Vec<u8>> {
+    let entry_point = (entry_point - base) as u32;
+    let (nt_headers, nt_headers_offset) = nt_headers(&data)?;
+    let section_count = nt_headers.file_header.number_of_sections.get(LE) as usize;
+    let section_table_offset = section_table_offset(nt_headers, nt_headers_offset);
+    let (nt_headers, _) =
+        from_bytes_mut::<ImageNtHeaders64>(&mut data[nt_headers_offset..]).unwrap();
+    nt_headers
+        .optional_header
+        .address_of_entry_point
+        .set(LE, entry_point);
+    let (sections, _) = slice_from_bytes_mut::<ImageSectionHeader>(
+        &mut data[section_table_offset..],
+        section_count,
+    )
+    .map_err(|()| Error::Unsupported("truncated section table"))?;
+    for section in sections {
+        let address = section.virtual_address.get(LE);
+        let size = match section.virtual_size.get(LE) {
+            0 => section.size_of_raw_data.get(LE),
+            size => size,
+        };
+        if (address..address + size).contains(&entry_point) {
+            let characteristics = section.characteristics.get(LE);
+            section.characteristics.set(
+                LE,
+                characteristics | IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ,
+            );
+        }
+    }
+    rebuild_imports(data, base, imports)
+}
+
+/// turns a mapped image into a PE file: the raw data of each section is
+/// placed at its relative address (so the file is its own mapped image) and
+/// the import directory is rebuilt from the import slots in a new section,
+/// consecutive slots of the same library are kept in place as its import
+/// address table
+pub fn rebuild_imports(mut data: Vec<u8>, base: usize, imports: &[Import]) -> Result<Vec<u8>> {
+    let (nt_headers, nt_headers_offset) = nt_headers(&data)?;
+    let section_alignment = nt_headers.optional_header.section_alignment.get(LE) as usize;
+    let section_count = nt_headers.file_header.number_of_sections.get(LE) as usize;
+    let section_table_offset = section_table_offset(nt_headers, nt_headers_offset);
+    let image_size = data.len().next_multiple_of(section_alignment);
+    let section_table_end =
+        section_table_offset + (section_count + 1) * size_of::<ImageSectionHeader>();
+    let first_section = (0..section_count)
+        .map(|i| {
+            let offset = section_table_offset + i * size_of::<ImageSectionHeader>() + 12;
+            u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize
+        })
+        .min()
+        .unwrap_or(image_size);
+    if first_section < section_table_end {
+        return Err(Error::Unsupported("no room for another section header"));
+    }
+    data.resize(image_size, 0);
+
+    // realign the sections, the last one extends to the end of the image
+    data[section_table_end - size_of::<ImageSectionHeader>()..section_table_end].fill(0);
+    let (sections, _) = slice_from_bytes_mut::<ImageSectionHeader>(
+        &mut data[section_table_offset..],
+        section_count + 1,
+    )
+    .map_err(|()| Error::Unsupported("truncated section table"))?;
+    let mut section_addresses: Vec<_> = sections[..section_count]
+        .iter()
+        .map(|section| section.virtual_address.get(LE) as usize)
+        .collect();
+    section_addresses.push(image_size);
+    section_addresses.sort();
+    for section in &mut sections[..section_count] {
+        let address = section.virtual_address.get(LE) as usize;
+        let end = section_addresses[section_addresses.partition_point(|&next| next <= address)];
+        let size = (end - address) as u32;
+        section.virtual_size.set(LE, size);
+        section.pointer_to_raw_data.set(LE, address as u32);
+        section.size_of_raw_data.set(LE, size);
+        section.pointer_to_relocations.set(LE, 0);
+        section.number_of_relocations.set(LE, 0);
+    }
+
+    // descriptors, lookup tables, hints and names, and library names
+    let mut libraries: Vec<(String, Vec<&Import>)> = vec![];
+    let mut previous_thunk = None;
+    for import in imports {
+        match libraries.last_mut() {
+            Some((library, slots))
+                if library.eq_ignore_ascii_case(&import.library)
+                    && previous_thunk.map(|thunk| thunk + 8) == Some(import.thunk) =>
+            {
+                slots.push(import)
+            }
+            _ => libraries.push((import.library.clone(), vec![import])),
+        }
+        previous_thunk = Some(import.thunk);
+    }
+    let section_address = image_size;
+    let descriptors_size = (libraries.len() + 1) * size_of::<ImageImportDescriptor>();
+    let mut section = vec![0; descriptors_size];
+    let mut descriptors = vec![];
+    let mut names = BTreeMap::new();
+    for (library, slots) in &libraries {
+        // the names of the previous library leave the end unaligned
+        section.resize(section.len().next_multiple_of(8), 0);
+        let lookup_table = section_address + section.len();
+        section.resize(section.len() + (slots.len() + 1) * 8, 0);
+        for (i, import) in slots.iter().enumerate() {
+            let value = match import
+                .function
+                .strip_prefix('#')
+                .and_then(|ordinal| ordinal.parse::<u16>().ok())
+            {
+                Some(ordinal) => IMAGE_ORDINAL_FLAG64 | ordinal as u64,
+                None => {
+                    let name = *names.entry(import.function.clone()).or_insert_with(|| {
+                        section.resize(section.len().next_multiple_of(2), 0);
+                        let address = section_address + section.len();
+                        // hint
+                        section.extend_from_slice(&[0, 0]);
+                        section.extend_from_slice(import.function.as_bytes());
+                        section.push(0);
+                        address
+                    });
+                    name as u64
+                }
+            };
+            let offset = lookup_table - section_address + i * 8;
+            section[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        }
+        let name = section_address + section.len();
+        section.extend_from_slice(library.as_bytes());
+        section.push(0);
+        descriptors.push((lookup_table, name, slots[0].thunk - base));
+    }
+    for (i, &(lookup_table, name, first_thunk)) in descriptors.iter().enumerate() {
+        let (descriptor, _) = from_bytes_mut::<ImageImportDescriptor>(
+            &mut section[i * size_of::<ImageImportDescriptor>()..],
+        )
+        .unwrap();
+        descriptor.original_first_thunk.set(LE, lookup_table as u32);
+        descriptor.name.set(LE, name as u32);
+        descriptor.first_thunk.set(LE, first_thunk as u32);
+    }
+    let section_size = section.len().next_multiple_of(section_alignment);
+    section.resize(section_size, 0);
+
+    let new_section = &mut sections[section_count];
+    new_section.name = *IMPORT_SECTION_NAME;
+    new_section.virtual_size.set(LE, section_size as u32);
+    new_section.virtual_address.set(LE, section_address as u32);
+    new_section.size_of_raw_data.set(LE, section_size as u32);
+    new_section
+        .pointer_to_raw_data
+        .set(LE, section_address as u32);
+    new_section
+        .characteristics
+        .set(LE, IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ);
+
+    // headers
+    let (nt_headers, _) =
+        from_bytes_mut::<ImageNtHeaders64>(&mut data[nt_headers_offset..]).unwrap();
+    nt_headers
+        .file_header
+        .number_of_sections
+        .set(LE, section_count as u16 + 1);
+    let optional_header = &mut nt_headers.optional_header;
+    optional_header
+        .file_alignment
+        .set(LE, section_alignment as u32);
+    optional_header
+        .size_of_image
+        .set(LE, (image_size + section_size) as u32);
+    optional_header.check_sum.set(LE, 0);
+    let directory_count = optional_header.number_of_rva_and_sizes.get(LE) as usize;
+    let (directories, _) = slice_from_bytes_mut::<ImageDataDirectory>(
+        &mut data[nt_headers_offset + size_of::<ImageNtHeaders64>()..],
+        directory_count,
+    )
+    .unwrap();
+    let mut set_directory = |index: usize, address: usize, size: usize| {
+        if let Some(directory) = directories.get_mut(index) {
+            directory.virtual_address.set(LE, address as u32);
+            directory.size.set(LE, size as u32);
+        }
+    };
+    if imports.is_empty() {
+        set_directory(IMAGE_DIRECTORY_ENTRY_IMPORT, 0, 0);
+        set_directory(IMAGE_DIRECTORY_ENTRY_IAT, 0, 0);
+    } else {
+        set_directory(
+            IMAGE_DIRECTORY_ENTRY_IMPORT,
+            section_address,
+            descriptors_size,
+        );
+        let first_thunk = imports.iter().map(|import| import.thunk).min().unwrap() - base;
+        let last_thunk = imports.iter().map(|import| import.thunk).max().unwrap() - base;
+        set_directory(
+            IMAGE_DIRECTORY_ENTRY_IAT,
+            first_thunk,
+            last_thunk + 8 - first_thunk,
+        );
+    }
+    set_directory(IMAGE_DIRECTORY_ENTRY_BOUND_IMPORT, 0, 0);
+
+    data.extend_from_slice(&section);
+    Ok(data)
+}
+
+/// lowercase name of a library without extension
+fn library_name(library: &str) -> String {
+    let library = library.to_lowercase();
+    match library.strip_suffix(".dll") {
+        Some(library) => library.to_owned(),
+        None => library,
+    }
+}
+
+/// nt headers and their offset
+fn nt_headers(data: &[u8]) -> Result<(&ImageNtHeaders64, usize)> {
+    let dos_header = ImageDosHeader::parse(data)?;
+    let mut offset = dos_header.nt_headers_offset() as u64;
+    let nt_headers_offset = offset as usize;
+    let (nt_headers, _) = ImageNtHeaders64::parse(data, &mut offset)?;
+    Ok((nt_headers, nt_headers_offset))
+}
+
+fn section_table_offset(nt_headers: &ImageNtHeaders64, nt_headers_offset: usize) -> usize {
+    nt_headers_offset
+        + 4
+        + size_of::<ImageFileHeader>()
+        + nt_headers.file_header.size_of_optional_header.get(LE) as usize
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use object::{
+        pe::IMAGE_SCN_MEM_WRITE,
+        read::pe::{ImageOptionalHeader, PeFile64},
+    };
+
+    use super::*;
+    use crate::emulator::Emulator;
+
+    pub(crate) const BASE: usize = 0x1_4000_0000;
+
+    /// mapped image with a code section at 0x1000 and a data section at
+    /// 0x2000
+    pub(crate) fn image() -> Vec<u8> {
+        let mut data = vec![0; 0x3000];
+        let mut put = |offset: usize, value: &[u8]| {
+            data[offset..offset + value.len()].copy_from_slice(value);
+        };
+        put(0x00, b"MZ");
+        put(0x3C, &0x80u32.to_le_bytes());
+        put(0x80, b"PE\0\0");
+        // machine, number of sections and size of the optional header
+        put(0x84, &0x8664u16.to_le_bytes());
+        put(0x86, &2u16.to_le_bytes());
+        put(0x94, &0xF0u16.to_le_bytes());
+        // magic, entry point, image base, alignments, sizes and number of
+        // data directories
+        put(0x98, &0x20Bu16.to_le_bytes());
+        put(0xA8, &0x1000u32.to_le_bytes());
+        put(0xB0, &(BASE as u64).to_le_bytes());
+        put(0xB8, &0x1000u32.to_le_bytes());
+        put(0xBC, &0x200u32.to_le_bytes());
+        put(0xD0, &0x3000u32.to_le_bytes());
+        put(0xD4, &0x400u32.to_le_bytes());
+        put(0x104, &16u32.to_le_bytes());
+        for (i, (name, address, characteristics)) in [
+            (
+                b".text\0\0\0",
+                0x1000u32,
+                IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ,
+            ),
+            (
+                b".data\0\0\0",
+                0x2000,
+                IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE,
+            ),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let section = 0x188 + i * size_of::<ImageSectionHeader>();
+            put(section, name);
+            put(section + 8, &0x100u32.to_le_bytes());
+            put(section + 12, &address.to_le_bytes());
+            put(section + 36, &characteristics.to_le_bytes());
+        }
+        data
+    }
+
+    /// module whose exports are sorted by name, an export is either code at
+    /// 0x1000 + 0x10 * index or forwarded to library.function
+    fn module(name: &str, base: usize, exports: &[(&str, Option<&str>)]) -> Module {
+        let mut data = image();
+        let mut strings = 0x2100;
+        let mut string = |data: &mut Vec<u8>, value: &str| {
+            let address = strings;
+            data[address..address + value.len()].copy_from_slice(value.as_bytes());
+            strings += value.len() + 1;
+            address as u32
+        };
+        let put = |data: &mut Vec<u8>, offset: usize, value: u32| {
+            data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        // IMAGE_DIRECTORY_ENTRY_EXPORT
+        put(&mut data, 0x108, 0x2000);
+        put(&mut data, 0x10C, 0x200);
+        let library = string(&mut data, name);
+        for (offset, value) in [
+            (12, library),
+            (16, 1),
+            (20, exports.len() as u32),
+            (24, exports.len() as u32),
+            (28, 0x2040),
+            (32, 0x2080),
+            (36, 0x20C0),
+        ] {
+            put(&mut data, 0x2000 + offset, value);
+        }
+        for (i, (function, forward)) in exports.iter().enumerate() {
+            let address = match forward {
+                Some(forward) => string(&mut data, forward),
+                None => 0x1000 + i as u32 * 0x10,
+            };
+            put(&mut data, 0x2040 + i * 4, address);
+            let function = string(&mut data, function);
+            put(&mut data, 0x2080 + i * 4, function);
+            data[0x20C0 + i * 2..0x20C2 + i * 2].copy_from_slice(&(i as u16).to_le_bytes());
+        }
+        Module::from_image(name.to_owned(), base, data).unwrap()
+    }
+
+    fn import(library: &str, function: &str, thunk: usize) -> Import {
+        Import {
+            library: library.to_owned(),
+            function: function.to_owned(),
+            thunk,
+        }
+    }
+
+    #[test]
+    fn imports() {
+        let imports = [
+            import("kernel32.dll", "ExitProcess", BASE + 0x2000),
+            import("kernel32.dll", "Sleep", BASE + 0x2008),
+            // not adjacent to the other slots of the library
+            import("kernel32.dll", "ExitProcess", BASE + 0x2018),
+            import("user32.dll", "#5", BASE + 0x2020),
+        ];
+        let data = rebuild(image(), BASE, BASE + 0x2040, &imports).unwrap();
+        assert_eq!(data.len(), 0x4000);
+
+        let file = PeFile64::parse(&*data).unwrap();
+        let optional_header = file.nt_headers().optional_header();
+        assert_eq!(optional_header.address_of_entry_point(), 0x2040);
+        assert_eq!(optional_header.size_of_image(), 0x4000);
+        let sections = file.section_table();
+        assert_eq!(sections.len(), 3);
+        let data_section = sections.section(2).unwrap();
+        assert_eq!(data_section.pointer_to_raw_data.get(LE), 0x2000);
+        assert_eq!(data_section.size_of_raw_data.get(LE), 0x1000);
+        assert_ne!(
+            data_section.characteristics.get(LE) & IMAGE_SCN_MEM_EXECUTE,
+            0
+        );
+        let import_section = sections.section(3).unwrap();
+        assert_eq!(&import_section.name, IMPORT_SECTION_NAME);
+        assert_eq!(import_section.virtual_address.get(LE), 0x3000);
+        // the lookup tables follow the names of the previous library
+        let import_table = file.import_table().unwrap().unwrap();
+        let mut descriptors = import_table.descriptors().unwrap();
+        while let Some(descriptor) = descriptors.next().unwrap() {
+            assert_eq!(descriptor.original_first_thunk.get(LE) % 8, 0);
+        }
+
+        // the loader binds the rebuilt imports to the original slots
+        let mut emulator = Emulator::new();
+        let image = emulator.load("test.exe", &data).unwrap();
+        assert_eq!(image.base, BASE as u64);
+        assert_eq!(image.entry_point, BASE as u64 + 0x2040);
+        let bound: Vec<_> = image
+            .imports
+            .iter()
+            .map(|import| Import {
+                library: import.library.clone(),
+                function: import.function.clone(),
+                thunk: import.thunk as usize,
+            })
+            .collect();
+        assert_eq!(bound, imports);
+    }
+
+    #[test]
+    fn without_imports() {
+        let data = rebuild_imports(image(), BASE, &[]).unwrap();
+        let file = PeFile64::parse(&*data).unwrap();
+        assert!(file.import_table().unwrap().is_none());
+        assert_eq!(file.section_table().len(), 3);
+    }
+
+    #[test]
+    fn no_room_for_section() {
+        let mut data = image();
+        // the code section starts right after the section table
+        data[0x188 + 12..0x188 + 16].copy_from_slice(&0x1D8u32.to_le_bytes());
+        assert!(matches!(
+            rebuild_imports(data, BASE, &[]),
+            Err(Error::Unsupported("no room for another section header"))
+        ));
+        assert!(rebuild_imports(vec![0; 0x1000], BASE, &[]).is_err());
+    }
+
+    #[test]
+    fn exports() {
+        let kernel32 = module(
+            "KERNEL32.dll",
+            0x7FF8_0000_0000,
+            &[
+                ("ExitProcess", None),
+                ("HeapAlloc", Some("NTDLL.RtlAllocateHeap")),
+                ("Sleep", None),
+            ],
+        );
+        let ntdll = module("ntdll.dll", 0x7FF9_0000_0000, &[("RtlAllocateHeap", None)]);
+        assert_eq!(
+            kernel32.forwards().unwrap(),
+            [(
+                "HeapAlloc".to_owned(),
+                "NTDLL".to_owned(),
+                "RtlAllocateHeap".to_owned()
+            )]
+        );
+
+        let exports = Exports::from_modules([&kernel32, &ntdll]).unwrap();
+        let name = |library: &str, function: &str| (library.to_owned(), function.to_owned());
+        assert_eq!(
+            exports.get(0x7FF8_0000_1000),
+            [name("KERNEL32.dll", "ExitProcess")]
+        );
+        assert_eq!(
+            exports.get(0x7FF8_0000_1020),
+            [name("KERNEL32.dll", "Sleep")]
+        );
+        // forwarded exports are known by the address of their target
+        assert!(exports.get(0x7FF8_0000_1010).is_empty());
+        assert_eq!(
+            exports.get(0x7FF9_0000_1000),
+            [
+                name("ntdll.dll", "RtlAllocateHeap"),
+                name("KERNEL32.dll", "HeapAlloc")
+            ]
+        );
+    }
+
+    #[test]
+    fn find_imports() {
+        let mut exports = Exports::new();
+        exports.insert(0x1000, "kernel32.dll", "ExitProcess");
+        exports.insert(0x1010, "kernel32.dll", "Sleep");
+        exports.insert(0x2000, "ntdll.dll", "RtlAllocateHeap");
+        exports.insert(0x2000, "kernel32.dll", "HeapAlloc");
+        exports.insert(0x2000, "kernel32.dll", "HeapAlloc");
+        exports.insert(0x2010, "ntdll.dll", "NtClose");
+        assert_eq!(exports.get(0x2000).len(), 2);
+
+        let mut data = vec![];
+        for value in [0, 0x1010, 0x2000, 0x1000, 0x1234, 0x2000, 0x2010, 0] {
+            data.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+        // the forwarded function is grouped with the slots around it
+        assert_eq!(
+            exports.find_imports(&data, BASE),
+            [
+                import("kernel32.dll", "Sleep", BASE + 0x08),
+                import("kernel32.dll", "HeapAlloc", BASE + 0x10),
+                import("kernel32.dll", "ExitProcess", BASE + 0x18),
+                import("ntdll.dll", "RtlAllocateHeap", BASE + 0x28),
+                import("ntdll.dll", "NtClose", BASE + 0x30),
+            ]
+        );
+        assert!(exports.find_imports(&[0xFF; 0x20], BASE).is_empty());
+    }
+}